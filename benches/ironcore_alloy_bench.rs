@@ -5,13 +5,14 @@ use ironcore_alloy::standalone::config::{
     RotatableSecret, StandaloneConfiguration, StandaloneSecret, StandardSecrets, VectorSecret,
 };
 use ironcore_alloy::standard::StandardDocumentOps;
-use ironcore_alloy::vector::{PlaintextVector, VectorOps};
+use ironcore_alloy::vector::{PlaintextVector, PlaintextVectors, VectorOps};
 use ironcore_alloy::DerivationPath;
 use ironcore_alloy::{AlloyMetadata, Secret, SecretPath, Standalone, TenantId};
 use itertools::Itertools;
 use rand::rngs::ThreadRng;
 use rand::{Rng, RngCore};
 use rand_distr::{Alphanumeric, Uniform};
+use std::collections::HashMap;
 use tokio::runtime::Runtime;
 // // Test the function F with inputs of dimensionality 1,000 and 1,000,000
 // async fn test_function<F, Fut>(c: &mut Criterion, rng: &mut ThreadRng, name: &str, mut f: F)
@@ -79,6 +80,47 @@ fn benches(c: &mut Criterion) {
         )
     });
 
+    // Encrypt a batch of 1k-dimensional vectors sequentially, then with `encrypt_batch`, which runs on the crate's
+    // batch thread pool. Run with `RAYON_NUM_THREADS` set to different values to see how it scales across cores.
+    let batch: PlaintextVectors = (0..256)
+        .map(|i| {
+            (
+                format!("vector{i}"),
+                PlaintextVector {
+                    plaintext_vector: rng.clone().sample_iter(&range).take(1000).collect_vec(),
+                    secret_path: SecretPath("secret_path".to_string()),
+                    derivation_path: DerivationPath("derivation_path".to_string()),
+                },
+            )
+        })
+        .collect();
+    let runtime = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("encrypt batch of 256 d=1k");
+    group.bench_function("sequential", |b| {
+        b.to_async(&runtime).iter_batched(
+            || batch.clone(),
+            |vectors| async {
+                for vector in vectors.into_values() {
+                    sdk.vector().encrypt(vector, &metadata).await.unwrap();
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("parallel", |b| {
+        b.to_async(&runtime).iter_batched(
+            || batch.clone(),
+            |vectors| async {
+                sdk.vector()
+                    .encrypt_batch(vectors, &metadata)
+                    .await
+                    .unwrap()
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+
     fn random_word(rng: ThreadRng, length: usize) -> Vec<u8> {
         rng.sample_iter(&Alphanumeric)
             .take(length)
@@ -115,6 +157,29 @@ fn benches(c: &mut Criterion) {
             BatchSize::SmallInput,
         )
     });
+    // Many tokio tasks encrypting documents at once on a multi-threaded runtime. Before RNGs were sharded every
+    // field encryption waited on one global lock, so this measures how well standard encryption handles contention.
+    let document: HashMap<String, Vec<u8>> = (0..10)
+        .map(|i| (format!("field{i}"), random_word(rng.clone(), 100)))
+        .collect();
+    let multi_thread_runtime = tokio::runtime::Builder::new_multi_thread().build().unwrap();
+    c.bench_function("concurrent encrypt 64 tasks of 10 fields", |b| {
+        b.to_async(&multi_thread_runtime).iter(|| async {
+            let tasks = (0..64)
+                .map(|_| {
+                    let sdk = sdk.clone();
+                    let metadata = metadata.clone();
+                    let document = document.clone();
+                    tokio::spawn(async move {
+                        sdk.standard().encrypt(document, &metadata).await.unwrap()
+                    })
+                })
+                .collect_vec();
+            for task in tasks {
+                task.await.unwrap();
+            }
+        })
+    });
     // This test requires `computer_auth_hash` to be `pub`, so only
     // briefly un-comment and test, then re-comment.
     // {
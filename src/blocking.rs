@@ -0,0 +1,841 @@
+//! Blocking versions of the SDK, for callers that don't run an async runtime. Each trait here mirrors the async trait
+//! of the same name and blocks the calling thread until the operation finishes.
+//!
+//! Standalone operations never wait on anything, so `Standalone` runs them right on the calling thread without any
+//! runtime. `SaasShield` has to talk to the TSP, so it owns a tokio runtime that its clients run their requests on.
+//! Like other blocking clients, it panics if used from within an async context; use the async `SaasShield` there.
+
+use crate::deterministic::{
+    DeterministicRotateResult, EncryptedField, EncryptedFields,
+    GenerateQueryResult as DeterministicGenerateQueryResult, PlaintextCompoundField,
+    PlaintextCompoundFields, PlaintextField, PlaintextFields, TenantEncryptedFields,
+};
+use crate::errors::{AlloyError, ErrorKind};
+use crate::saas_shield::config::SaasShieldConfiguration;
+use crate::saas_shield::deterministic::SaasShieldDeterministicClient;
+use crate::saas_shield::security_events::SecurityEventQueueStats;
+use crate::saas_shield::sparse_vector::SaasShieldSparseVectorClient;
+use crate::saas_shield::standard::SaasShieldStandardClient;
+use crate::saas_shield::vector::SaasShieldVectorClient;
+use crate::saas_shield::SecurityEvent;
+use crate::standalone::config::StandaloneConfiguration;
+use crate::standalone::deterministic::StandaloneDeterministicClient;
+use crate::standalone::sparse_vector::StandaloneSparseVectorClient;
+use crate::standalone::standard::StandaloneStandardClient;
+use crate::standalone::standard_attached::StandaloneAttachedStandardClient;
+use crate::standalone::vector::StandaloneVectorClient;
+use crate::standard::{
+    EdekWithKeyIdHeader, EncryptedDocument, PlaintextDocument, PlaintextDocumentWithEdek,
+    RekeyEdeksBatchResult,
+};
+use crate::standard_attached::{EncryptedAttachedDocument, RekeyAttachedDocumentsBatchResult};
+use crate::vector::precision::{EncryptedTypedVector, PlaintextTypedVector, VectorPrecision};
+use crate::vector::sparse::{
+    EncryptedSparseVector, EncryptedSparseVectors, GenerateSparseQueryResult,
+    PlaintextSparseVector, PlaintextSparseVectors, SparseVectorRotateResult,
+};
+use crate::vector::{
+    EncryptedVector, EncryptedVectors, GenerateQueryResult as VectorGenerateQueryResult,
+    PlaintextVector, PlaintextVectors, TenantEncryptedVectors, VectorEncryptBatchResult,
+    VectorRotateResult,
+};
+use crate::{AlloyMetadata, DerivationPath, PlaintextBytes, SecretPath, TenantId};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// Wakes a thread parked in `block_on`.
+struct ThreadWaker(std::thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark()
+    }
+}
+
+/// Run `future` to completion on the current thread, parking it whenever the future is waiting. Only suitable for
+/// futures that don't need a runtime's IO or timers, like every Standalone operation.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+/// A client that runs the operations of the async client `C` to completion before returning. Get one from
+/// `Standalone` or `SaasShield` in this module.
+pub struct BlockingClient<C> {
+    client: Arc<C>,
+    runtime: Option<Arc<Runtime>>,
+}
+
+impl<C> Clone for BlockingClient<C> {
+    fn clone(&self) -> Self {
+        BlockingClient {
+            client: self.client.clone(),
+            runtime: self.runtime.clone(),
+        }
+    }
+}
+
+impl<C> BlockingClient<C> {
+    /// The async client, for use from async code.
+    pub fn inner(&self) -> &Arc<C> {
+        &self.client
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        match &self.runtime {
+            Some(runtime) => runtime.block_on(future),
+            None => block_on(future),
+        }
+    }
+}
+
+/// Blocking version of `crate::standard::StandardDocumentOps`.
+pub trait StandardDocumentOps {
+    /// See `crate::standard::StandardDocumentOps::encrypt`.
+    fn encrypt(
+        &self,
+        plaintext_document: PlaintextDocument,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedDocument, AlloyError>;
+    /// See `crate::standard::StandardDocumentOps::decrypt`.
+    fn decrypt(
+        &self,
+        encrypted_document: EncryptedDocument,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextDocument, AlloyError>;
+    /// See `crate::standard::StandardDocumentOps::rekey_edeks`.
+    fn rekey_edeks(
+        &self,
+        edeks: HashMap<String, EdekWithKeyIdHeader>,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<RekeyEdeksBatchResult, AlloyError>;
+    /// See `crate::standard::StandardDocumentOps::get_searchable_edek_prefix`.
+    fn get_searchable_edek_prefix(&self, id: i32) -> Vec<u8>;
+    /// See `crate::standard::StandardDocumentOps::encrypt_with_existing_edek`.
+    fn encrypt_with_existing_edek(
+        &self,
+        plaintext_document: PlaintextDocumentWithEdek,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedDocument, AlloyError>;
+}
+
+impl<C: crate::standard::StandardDocumentOps> StandardDocumentOps for BlockingClient<C> {
+    fn encrypt(
+        &self,
+        plaintext_document: PlaintextDocument,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedDocument, AlloyError> {
+        self.block_on(self.client.encrypt(plaintext_document, metadata))
+    }
+    fn decrypt(
+        &self,
+        encrypted_document: EncryptedDocument,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextDocument, AlloyError> {
+        self.block_on(self.client.decrypt(encrypted_document, metadata))
+    }
+    fn rekey_edeks(
+        &self,
+        edeks: HashMap<String, EdekWithKeyIdHeader>,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<RekeyEdeksBatchResult, AlloyError> {
+        self.block_on(self.client.rekey_edeks(edeks, metadata, new_tenant_id))
+    }
+    fn get_searchable_edek_prefix(&self, id: i32) -> Vec<u8> {
+        self.client.get_searchable_edek_prefix(id)
+    }
+    fn encrypt_with_existing_edek(
+        &self,
+        plaintext_document: PlaintextDocumentWithEdek,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedDocument, AlloyError> {
+        self.block_on(
+            self.client
+                .encrypt_with_existing_edek(plaintext_document, metadata),
+        )
+    }
+}
+
+/// Blocking version of `crate::standard_attached::StandardAttachedDocumentOps`.
+pub trait StandardAttachedDocumentOps {
+    /// See `crate::standard_attached::StandardAttachedDocumentOps::encrypt`.
+    fn encrypt(
+        &self,
+        plaintext_field: PlaintextBytes,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedAttachedDocument, AlloyError>;
+    /// See `crate::standard_attached::StandardAttachedDocumentOps::decrypt`.
+    fn decrypt(
+        &self,
+        attached_field: EncryptedAttachedDocument,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextBytes, AlloyError>;
+    /// See `crate::standard_attached::StandardAttachedDocumentOps::encrypt_with_existing_edek`.
+    fn encrypt_with_existing_edek(
+        &self,
+        plaintext_field: PlaintextBytes,
+        existing_document: EncryptedAttachedDocument,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedAttachedDocument, AlloyError>;
+    /// See `crate::standard_attached::StandardAttachedDocumentOps::get_searchable_edek_prefix`.
+    fn get_searchable_edek_prefix(&self, id: i32) -> Vec<u8>;
+    /// See `crate::standard_attached::StandardAttachedDocumentOps::rekey_documents`.
+    fn rekey_documents(
+        &self,
+        encrypted_documents: HashMap<String, EncryptedAttachedDocument>,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<RekeyAttachedDocumentsBatchResult, AlloyError>;
+}
+
+impl<C: crate::standard_attached::StandardAttachedDocumentOps> StandardAttachedDocumentOps
+    for BlockingClient<C>
+{
+    fn encrypt(
+        &self,
+        plaintext_field: PlaintextBytes,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedAttachedDocument, AlloyError> {
+        self.block_on(self.client.encrypt(plaintext_field, metadata))
+    }
+    fn decrypt(
+        &self,
+        attached_field: EncryptedAttachedDocument,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextBytes, AlloyError> {
+        self.block_on(self.client.decrypt(attached_field, metadata))
+    }
+    fn encrypt_with_existing_edek(
+        &self,
+        plaintext_field: PlaintextBytes,
+        existing_document: EncryptedAttachedDocument,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedAttachedDocument, AlloyError> {
+        self.block_on(self.client.encrypt_with_existing_edek(
+            plaintext_field,
+            existing_document,
+            metadata,
+        ))
+    }
+    fn get_searchable_edek_prefix(&self, id: i32) -> Vec<u8> {
+        self.block_on(self.client.get_searchable_edek_prefix(id))
+    }
+    fn rekey_documents(
+        &self,
+        encrypted_documents: HashMap<String, EncryptedAttachedDocument>,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<RekeyAttachedDocumentsBatchResult, AlloyError> {
+        self.block_on(
+            self.client
+                .rekey_documents(encrypted_documents, metadata, new_tenant_id),
+        )
+    }
+}
+
+/// Blocking version of `crate::deterministic::DeterministicFieldOps`.
+pub trait DeterministicFieldOps {
+    /// See `crate::deterministic::DeterministicFieldOps::encrypt`.
+    fn encrypt(
+        &self,
+        plaintext_field: PlaintextField,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedField, AlloyError>;
+    /// See `crate::deterministic::DeterministicFieldOps::decrypt`.
+    fn decrypt(
+        &self,
+        encrypted_field: EncryptedField,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextField, AlloyError>;
+    /// See `crate::deterministic::DeterministicFieldOps::generate_query_field_values`.
+    fn generate_query_field_values(
+        &self,
+        fields_to_query: PlaintextFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<DeterministicGenerateQueryResult, AlloyError>;
+    /// See `crate::deterministic::DeterministicFieldOps::rotate_fields`.
+    fn rotate_fields(
+        &self,
+        encrypted_fields: EncryptedFields,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<DeterministicRotateResult, AlloyError>;
+    /// See `crate::deterministic::DeterministicFieldOps::rotate_fields_multi_tenant`.
+    fn rotate_fields_multi_tenant(
+        &self,
+        encrypted_fields: TenantEncryptedFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<DeterministicRotateResult, AlloyError>;
+    /// See `crate::deterministic::DeterministicFieldOps::get_in_rotation_prefix`.
+    fn get_in_rotation_prefix(
+        &self,
+        secret_path: SecretPath,
+        derivation_path: DerivationPath,
+        metadata: &AlloyMetadata,
+    ) -> Result<Vec<u8>, AlloyError>;
+    /// See `crate::deterministic::DeterministicFieldOps::encrypt_compound`.
+    fn encrypt_compound(
+        &self,
+        plaintext_compound_field: PlaintextCompoundField,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedField, AlloyError>;
+    /// See `crate::deterministic::DeterministicFieldOps::decrypt_compound`.
+    fn decrypt_compound(
+        &self,
+        encrypted_field: EncryptedField,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextCompoundField, AlloyError>;
+    /// See `crate::deterministic::DeterministicFieldOps::generate_query_compound_field_values`.
+    fn generate_query_compound_field_values(
+        &self,
+        fields_to_query: PlaintextCompoundFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<DeterministicGenerateQueryResult, AlloyError>;
+}
+
+impl<C: crate::deterministic::DeterministicFieldOps> DeterministicFieldOps for BlockingClient<C> {
+    fn encrypt(
+        &self,
+        plaintext_field: PlaintextField,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedField, AlloyError> {
+        self.block_on(self.client.encrypt(plaintext_field, metadata))
+    }
+    fn decrypt(
+        &self,
+        encrypted_field: EncryptedField,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextField, AlloyError> {
+        self.block_on(self.client.decrypt(encrypted_field, metadata))
+    }
+    fn generate_query_field_values(
+        &self,
+        fields_to_query: PlaintextFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<DeterministicGenerateQueryResult, AlloyError> {
+        self.block_on(
+            self.client
+                .generate_query_field_values(fields_to_query, metadata),
+        )
+    }
+    fn rotate_fields(
+        &self,
+        encrypted_fields: EncryptedFields,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<DeterministicRotateResult, AlloyError> {
+        self.block_on(
+            self.client
+                .rotate_fields(encrypted_fields, metadata, new_tenant_id),
+        )
+    }
+    fn rotate_fields_multi_tenant(
+        &self,
+        encrypted_fields: TenantEncryptedFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<DeterministicRotateResult, AlloyError> {
+        self.block_on(
+            self.client
+                .rotate_fields_multi_tenant(encrypted_fields, metadata),
+        )
+    }
+    fn get_in_rotation_prefix(
+        &self,
+        secret_path: SecretPath,
+        derivation_path: DerivationPath,
+        metadata: &AlloyMetadata,
+    ) -> Result<Vec<u8>, AlloyError> {
+        self.block_on(
+            self.client
+                .get_in_rotation_prefix(secret_path, derivation_path, metadata),
+        )
+    }
+    fn encrypt_compound(
+        &self,
+        plaintext_compound_field: PlaintextCompoundField,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedField, AlloyError> {
+        self.block_on(
+            self.client
+                .encrypt_compound(plaintext_compound_field, metadata),
+        )
+    }
+    fn decrypt_compound(
+        &self,
+        encrypted_field: EncryptedField,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextCompoundField, AlloyError> {
+        self.block_on(self.client.decrypt_compound(encrypted_field, metadata))
+    }
+    fn generate_query_compound_field_values(
+        &self,
+        fields_to_query: PlaintextCompoundFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<DeterministicGenerateQueryResult, AlloyError> {
+        self.block_on(
+            self.client
+                .generate_query_compound_field_values(fields_to_query, metadata),
+        )
+    }
+}
+
+/// Blocking version of `crate::vector::VectorOps`.
+pub trait VectorOps {
+    /// See `crate::vector::VectorOps::encrypt`.
+    fn encrypt(
+        &self,
+        plaintext_vector: PlaintextVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedVector, AlloyError>;
+    /// See `crate::vector::VectorOps::encrypt_batch`.
+    fn encrypt_batch(
+        &self,
+        plaintext_vectors: PlaintextVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorEncryptBatchResult, AlloyError>;
+    /// See `crate::vector::VectorOps::decrypt`.
+    fn decrypt(
+        &self,
+        encrypted_vector: EncryptedVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextVector, AlloyError>;
+    /// See `crate::vector::VectorOps::encrypt_typed`.
+    fn encrypt_typed(
+        &self,
+        plaintext_vector: PlaintextTypedVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedTypedVector, AlloyError>;
+    /// See `crate::vector::VectorOps::decrypt_typed`.
+    fn decrypt_typed(
+        &self,
+        encrypted_vector: EncryptedTypedVector,
+        precision: VectorPrecision,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextTypedVector, AlloyError>;
+    /// See `crate::vector::VectorOps::generate_query_vectors`.
+    fn generate_query_vectors(
+        &self,
+        vectors_to_query: PlaintextVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorGenerateQueryResult, AlloyError>;
+    /// See `crate::vector::VectorOps::get_in_rotation_prefix`.
+    fn get_in_rotation_prefix(
+        &self,
+        secret_path: SecretPath,
+        derivation_path: DerivationPath,
+        metadata: &AlloyMetadata,
+    ) -> Result<Vec<u8>, AlloyError>;
+    /// See `crate::vector::VectorOps::rotate_vectors`.
+    fn rotate_vectors(
+        &self,
+        encrypted_vectors: EncryptedVectors,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<VectorRotateResult, AlloyError>;
+    /// See `crate::vector::VectorOps::rotate_vectors_multi_tenant`.
+    fn rotate_vectors_multi_tenant(
+        &self,
+        encrypted_vectors: TenantEncryptedVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorRotateResult, AlloyError>;
+}
+
+impl<C: crate::vector::VectorOps> VectorOps for BlockingClient<C> {
+    fn encrypt(
+        &self,
+        plaintext_vector: PlaintextVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedVector, AlloyError> {
+        self.block_on(self.client.encrypt(plaintext_vector, metadata))
+    }
+    fn encrypt_batch(
+        &self,
+        plaintext_vectors: PlaintextVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorEncryptBatchResult, AlloyError> {
+        self.block_on(self.client.encrypt_batch(plaintext_vectors, metadata))
+    }
+    fn decrypt(
+        &self,
+        encrypted_vector: EncryptedVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextVector, AlloyError> {
+        self.block_on(self.client.decrypt(encrypted_vector, metadata))
+    }
+    fn encrypt_typed(
+        &self,
+        plaintext_vector: PlaintextTypedVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedTypedVector, AlloyError> {
+        self.block_on(self.client.encrypt_typed(plaintext_vector, metadata))
+    }
+    fn decrypt_typed(
+        &self,
+        encrypted_vector: EncryptedTypedVector,
+        precision: VectorPrecision,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextTypedVector, AlloyError> {
+        self.block_on(
+            self.client
+                .decrypt_typed(encrypted_vector, precision, metadata),
+        )
+    }
+    fn generate_query_vectors(
+        &self,
+        vectors_to_query: PlaintextVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorGenerateQueryResult, AlloyError> {
+        self.block_on(
+            self.client
+                .generate_query_vectors(vectors_to_query, metadata),
+        )
+    }
+    fn get_in_rotation_prefix(
+        &self,
+        secret_path: SecretPath,
+        derivation_path: DerivationPath,
+        metadata: &AlloyMetadata,
+    ) -> Result<Vec<u8>, AlloyError> {
+        self.block_on(
+            self.client
+                .get_in_rotation_prefix(secret_path, derivation_path, metadata),
+        )
+    }
+    fn rotate_vectors(
+        &self,
+        encrypted_vectors: EncryptedVectors,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<VectorRotateResult, AlloyError> {
+        self.block_on(
+            self.client
+                .rotate_vectors(encrypted_vectors, metadata, new_tenant_id),
+        )
+    }
+    fn rotate_vectors_multi_tenant(
+        &self,
+        encrypted_vectors: TenantEncryptedVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorRotateResult, AlloyError> {
+        self.block_on(
+            self.client
+                .rotate_vectors_multi_tenant(encrypted_vectors, metadata),
+        )
+    }
+}
+
+/// Blocking version of `crate::vector::sparse::SparseVectorOps`.
+pub trait SparseVectorOps {
+    /// See `crate::vector::sparse::SparseVectorOps::encrypt`.
+    fn encrypt(
+        &self,
+        plaintext_vector: PlaintextSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedSparseVector, AlloyError>;
+    /// See `crate::vector::sparse::SparseVectorOps::decrypt`.
+    fn decrypt(
+        &self,
+        encrypted_vector: EncryptedSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextSparseVector, AlloyError>;
+    /// See `crate::vector::sparse::SparseVectorOps::generate_query_vectors`.
+    fn generate_query_vectors(
+        &self,
+        vectors_to_query: PlaintextSparseVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<GenerateSparseQueryResult, AlloyError>;
+    /// See `crate::vector::sparse::SparseVectorOps::get_in_rotation_prefix`.
+    fn get_in_rotation_prefix(
+        &self,
+        secret_path: SecretPath,
+        derivation_path: DerivationPath,
+        metadata: &AlloyMetadata,
+    ) -> Result<Vec<u8>, AlloyError>;
+    /// See `crate::vector::sparse::SparseVectorOps::rotate_vectors`.
+    fn rotate_vectors(
+        &self,
+        encrypted_vectors: EncryptedSparseVectors,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<SparseVectorRotateResult, AlloyError>;
+}
+
+impl<C: crate::vector::sparse::SparseVectorOps> SparseVectorOps for BlockingClient<C> {
+    fn encrypt(
+        &self,
+        plaintext_vector: PlaintextSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedSparseVector, AlloyError> {
+        self.block_on(self.client.encrypt(plaintext_vector, metadata))
+    }
+    fn decrypt(
+        &self,
+        encrypted_vector: EncryptedSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextSparseVector, AlloyError> {
+        self.block_on(self.client.decrypt(encrypted_vector, metadata))
+    }
+    fn generate_query_vectors(
+        &self,
+        vectors_to_query: PlaintextSparseVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<GenerateSparseQueryResult, AlloyError> {
+        self.block_on(
+            self.client
+                .generate_query_vectors(vectors_to_query, metadata),
+        )
+    }
+    fn get_in_rotation_prefix(
+        &self,
+        secret_path: SecretPath,
+        derivation_path: DerivationPath,
+        metadata: &AlloyMetadata,
+    ) -> Result<Vec<u8>, AlloyError> {
+        self.block_on(
+            self.client
+                .get_in_rotation_prefix(secret_path, derivation_path, metadata),
+        )
+    }
+    fn rotate_vectors(
+        &self,
+        encrypted_vectors: EncryptedSparseVectors,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<SparseVectorRotateResult, AlloyError> {
+        self.block_on(
+            self.client
+                .rotate_vectors(encrypted_vectors, metadata, new_tenant_id),
+        )
+    }
+}
+
+/// Blocking version of `crate::standalone::security_events::StandaloneSecurityEventOps`.
+pub trait StandaloneSecurityEventOps {
+    /// See `crate::standalone::security_events::StandaloneSecurityEventOps::log_security_event`.
+    fn log_security_event(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError>;
+}
+
+impl<C: crate::standalone::security_events::StandaloneSecurityEventOps> StandaloneSecurityEventOps
+    for BlockingClient<C>
+{
+    fn log_security_event(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError> {
+        self.block_on(
+            self.client
+                .log_security_event(event, metadata, event_time_millis),
+        )
+    }
+}
+
+/// Blocking version of `crate::saas_shield::SaasShieldSecurityEventOps`.
+pub trait SaasShieldSecurityEventOps {
+    /// See `crate::saas_shield::SaasShieldSecurityEventOps::log_security_event`.
+    fn log_security_event(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError>;
+}
+
+impl<C: crate::saas_shield::SaasShieldSecurityEventOps> SaasShieldSecurityEventOps
+    for BlockingClient<C>
+{
+    fn log_security_event(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError> {
+        self.block_on(
+            self.client
+                .log_security_event(event, metadata, event_time_millis),
+        )
+    }
+}
+
+/// Blocking version of `crate::Standalone`. Operations run on the calling thread.
+#[derive(Clone)]
+pub struct Standalone {
+    inner: Arc<crate::Standalone>,
+}
+
+impl Standalone {
+    pub fn new(config: &StandaloneConfiguration) -> Self {
+        Standalone {
+            inner: crate::Standalone::new(config),
+        }
+    }
+    pub fn standard(&self) -> BlockingClient<StandaloneStandardClient> {
+        self.client(self.inner.standard())
+    }
+    pub fn standard_attached(&self) -> BlockingClient<StandaloneAttachedStandardClient> {
+        self.client(self.inner.standard_attached())
+    }
+    pub fn deterministic(&self) -> BlockingClient<StandaloneDeterministicClient> {
+        self.client(self.inner.deterministic())
+    }
+    pub fn vector(&self) -> BlockingClient<StandaloneVectorClient> {
+        self.client(self.inner.vector())
+    }
+    pub fn sparse_vector(&self) -> BlockingClient<StandaloneSparseVectorClient> {
+        self.client(self.inner.sparse_vector())
+    }
+
+    fn client<C>(&self, client: Arc<C>) -> BlockingClient<C> {
+        BlockingClient {
+            client,
+            runtime: None,
+        }
+    }
+}
+
+/// Blocking version of `crate::SaasShield`. Owns the tokio runtime that requests to the TSP run on, which is shut
+/// down once this and every client taken from it are dropped.
+#[derive(Clone)]
+pub struct SaasShield {
+    inner: Arc<crate::SaasShield>,
+    runtime: Arc<Runtime>,
+}
+
+impl SaasShield {
+    pub fn new(config: &SaasShieldConfiguration) -> Result<Self, AlloyError> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("ironcore-alloy-blocking")
+            .build()
+            .map_err(|e| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::Other,
+                msg: format!("Couldn't start the runtime for blocking SaaS Shield clients: {e}"),
+            })?;
+        Ok(SaasShield {
+            inner: crate::SaasShield::new(config),
+            runtime: Arc::new(runtime),
+        })
+    }
+    pub fn standard(&self) -> BlockingClient<SaasShieldStandardClient> {
+        self.client(self.inner.standard())
+    }
+    pub fn deterministic(&self) -> BlockingClient<SaasShieldDeterministicClient> {
+        self.client(self.inner.deterministic())
+    }
+    pub fn vector(&self) -> BlockingClient<SaasShieldVectorClient> {
+        self.client(self.inner.vector())
+    }
+    pub fn sparse_vector(&self) -> BlockingClient<SaasShieldSparseVectorClient> {
+        self.client(self.inner.sparse_vector())
+    }
+    /// See `crate::SaasShield::security_event_stats`.
+    pub fn security_event_stats(&self) -> SecurityEventQueueStats {
+        self.inner.security_event_stats()
+    }
+    /// See `crate::SaasShield::flush_security_events`.
+    pub fn flush_security_events(&self, timeout: Duration) -> Result<(), AlloyError> {
+        self.runtime.block_on(
+            self.inner
+                .flush_security_events(timeout.as_millis().try_into().unwrap_or(u64::MAX)),
+        )
+    }
+
+    fn client<C>(&self, client: Arc<C>) -> BlockingClient<C> {
+        BlockingClient {
+            client,
+            runtime: Some(self.runtime.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::standalone::config::{StandaloneSecret, StandardSecrets};
+    use crate::Secret;
+
+    fn standalone() -> Standalone {
+        let secret = Secret::new([0u8; 32].to_vec()).unwrap();
+        let standard =
+            StandardSecrets::new(Some(1), vec![StandaloneSecret::new(1, secret)]).unwrap();
+        Standalone::new(&StandaloneConfiguration::new(
+            standard,
+            HashMap::new(),
+            HashMap::new(),
+        ))
+    }
+
+    // Plain tests, so there's no runtime around to lean on.
+    #[test]
+    fn standalone_roundtrips_without_a_runtime() {
+        let client = standalone().standard();
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        let document: PlaintextDocument = [("field".to_string(), vec![1, 2, 3])].into();
+        let encrypted = client.encrypt(document.clone(), &metadata).unwrap();
+        assert_eq!(client.decrypt(encrypted, &metadata).unwrap(), document);
+
+        let attached = standalone().standard_attached();
+        let plaintext: PlaintextBytes = vec![4, 5];
+        let encrypted = attached.encrypt(plaintext.clone(), &metadata).unwrap();
+        assert_eq!(attached.decrypt(encrypted, &metadata).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn standalone_errors_come_through() {
+        let client = standalone().deterministic();
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        let err = client
+            .encrypt(
+                PlaintextField {
+                    plaintext_field: vec![1],
+                    secret_path: SecretPath("path".to_string()),
+                    derivation_path: DerivationPath("path".to_string()),
+                },
+                &metadata,
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnknownSecretPath);
+    }
+
+    #[test]
+    fn saas_shield_runs_requests_on_its_own_runtime() {
+        // Nothing listens on port 1, so the request fails without a TSP, but only after actually being sent.
+        let config = SaasShieldConfiguration::new(
+            "http://127.0.0.1:1".to_string(),
+            "0WUaXesNgbTAuLwn".to_string(),
+            false,
+            None,
+        )
+        .unwrap();
+        let saas_shield = SaasShield::new(&config).unwrap();
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        let err = saas_shield
+            .standard()
+            .encrypt([].into(), &metadata)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TspUnavailable);
+        assert!(err.is_retryable());
+        saas_shield
+            .flush_security_events(Duration::from_millis(100))
+            .unwrap();
+    }
+}
@@ -1,5 +1,5 @@
 use crate::{
-    errors::AlloyError,
+    errors::{AlloyError, ErrorKind},
     util::{self, BatchResult},
     AlloyMetadata, DerivationPath, EncryptedBytes, FieldId, PlaintextBytes, Secret, SecretPath,
     TenantId,
@@ -9,16 +9,19 @@ use aes_siv::siv::Aes256Siv;
 use bytes::Bytes;
 use ironcore_documents::v5::key_id_header::KeyIdHeader;
 use std::collections::HashMap;
+#[cfg(feature = "uniffi")]
 use uniffi::custom_newtype;
 
-#[derive(Debug, Clone, uniffi::Record)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct EncryptedField {
     pub encrypted_field: EncryptedBytes,
     pub secret_path: SecretPath,
     pub derivation_path: DerivationPath,
 }
 
-#[derive(Debug, Clone, uniffi::Record)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct PlaintextField {
     pub plaintext_field: PlaintextBytes,
     pub secret_path: SecretPath,
@@ -28,7 +31,8 @@ pub type PlaintextFields = HashMap<FieldId, PlaintextField>;
 pub type EncryptedFields = HashMap<FieldId, EncryptedField>;
 pub type GenerateQueryResult = HashMap<FieldId, Vec<EncryptedField>>;
 
-#[derive(Debug, Clone, uniffi::Record)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct DeterministicRotateResult {
     pub successes: HashMap<FieldId, EncryptedField>,
     pub failures: HashMap<FieldId, AlloyError>,
@@ -43,9 +47,127 @@ impl From<BatchResult<EncryptedField>> for DeterministicRotateResult {
     }
 }
 
+impl From<DeterministicRotateResult> for BatchResult<EncryptedField> {
+    fn from(value: DeterministicRotateResult) -> Self {
+        Self {
+            successes: value.successes,
+            failures: value.failures,
+        }
+    }
+}
+
+/// An encrypted field along with the tenant it is currently encrypted to and the tenant it should be rotated to.
+/// If `new_tenant_id` is empty the field will be rotated to the current secret of `tenant_id`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct TenantEncryptedField {
+    pub encrypted_field: EncryptedField,
+    pub tenant_id: TenantId,
+    pub new_tenant_id: Option<TenantId>,
+}
+pub type TenantEncryptedFields = HashMap<FieldId, TenantEncryptedField>;
+
+/// A single named value that makes up part of a compound field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct CompoundFieldValue {
+    pub name: String,
+    pub value: PlaintextBytes,
+}
+
+/// An ordered list of named values that are encrypted together as one deterministic field.
+/// Each name and value is length-prefixed before encryption, so `("ab", "c")` and `("a", "bc")`
+/// produce different ciphertexts. The order of `values` is significant.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct PlaintextCompoundField {
+    pub values: Vec<CompoundFieldValue>,
+    pub secret_path: SecretPath,
+    pub derivation_path: DerivationPath,
+}
+pub type PlaintextCompoundFields = HashMap<FieldId, PlaintextCompoundField>;
+
+fn encode_length(length: usize) -> Result<[u8; 4], AlloyError> {
+    u32::try_from(length)
+        .map(u32::to_be_bytes)
+        .map_err(|_| AlloyError::InvalidInput {
+            kind: ErrorKind::Other,
+            msg: "Compound field components must be smaller than 4GB.".to_string(),
+        })
+}
+
+impl TryFrom<PlaintextCompoundField> for PlaintextField {
+    type Error = AlloyError;
+
+    /// Encode the compound values as `count || (name_len || name || value_len || value)*`, with
+    /// all lengths as 4 byte big endian integers.
+    fn try_from(compound: PlaintextCompoundField) -> Result<Self, Self::Error> {
+        let mut encoded = encode_length(compound.values.len())?.to_vec();
+        for CompoundFieldValue { name, value } in compound.values {
+            encoded.extend(encode_length(name.len())?);
+            encoded.extend(name.into_bytes());
+            encoded.extend(encode_length(value.len())?);
+            encoded.extend(value);
+        }
+        Ok(PlaintextField {
+            plaintext_field: encoded,
+            secret_path: compound.secret_path,
+            derivation_path: compound.derivation_path,
+        })
+    }
+}
+
+impl TryFrom<PlaintextField> for PlaintextCompoundField {
+    type Error = AlloyError;
+
+    fn try_from(field: PlaintextField) -> Result<Self, Self::Error> {
+        fn invalid() -> AlloyError {
+            AlloyError::DecryptError {
+                kind: ErrorKind::DecryptionFailed,
+                msg: "Decrypted field was not a valid compound field.".to_string(),
+            }
+        }
+        fn take<'a>(bytes: &mut &'a [u8], length: usize) -> Result<&'a [u8], AlloyError> {
+            if bytes.len() < length {
+                Err(invalid())
+            } else {
+                let (taken, rest) = bytes.split_at(length);
+                *bytes = rest;
+                Ok(taken)
+            }
+        }
+        fn take_length(bytes: &mut &[u8]) -> Result<usize, AlloyError> {
+            let length_bytes = take(bytes, 4)?;
+            Ok(u32::from_be_bytes(length_bytes.try_into().map_err(|_| invalid())?) as usize)
+        }
+
+        let mut remaining = field.plaintext_field.as_slice();
+        let count = take_length(&mut remaining)?;
+        let mut values = Vec::new();
+        for _ in 0..count {
+            let name_len = take_length(&mut remaining)?;
+            let name = String::from_utf8(take(&mut remaining, name_len)?.to_vec())
+                .map_err(|_| invalid())?;
+            let value_len = take_length(&mut remaining)?;
+            let value = take(&mut remaining, value_len)?.to_vec();
+            values.push(CompoundFieldValue { name, value });
+        }
+        if remaining.is_empty() {
+            Ok(PlaintextCompoundField {
+                values,
+                secret_path: field.secret_path,
+                derivation_path: field.derivation_path,
+            })
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
 /// Key used for deterministic operations.
 #[derive(Debug, Clone)]
 pub struct DeterministicEncryptionKey(pub Vec<u8>);
+#[cfg(feature = "uniffi")]
 custom_newtype!(DeterministicEncryptionKey, Vec<u8>);
 
 impl DeterministicEncryptionKey {
@@ -96,17 +218,45 @@ pub trait DeterministicFieldOps {
         metadata: &AlloyMetadata,
         new_tenant_id: Option<TenantId>,
     ) -> Result<DeterministicRotateResult, AlloyError>;
+    /// Re-encrypt already encrypted fields that may belong to many tenants. Each field carries the tenant ID it was
+    /// encrypted to and an optional tenant ID to rotate it to. Fields are grouped by the tenant they were encrypted to,
+    /// so that tenant's keys are derived once however many tenants its fields move to. The tenant ID in `metadata` is
+    /// ignored, but its other values are used for every group.
+    async fn rotate_fields_multi_tenant(
+        &self,
+        encrypted_fields: TenantEncryptedFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<DeterministicRotateResult, AlloyError>;
     /// Generate a prefix that could used to search a data store for fields encrypted using an identifier (KMS
     /// config id for SaaS Shield, secret id for Standalone). These bytes should be encoded into
-    /// a format matching the encoding in the data store. z85/ascii85 users should first pass these bytes through
-    /// `encode_prefix_z85` or `base85_prefix_padding`. Make sure you've read the documentation of those functions to
-    /// avoid pitfalls when encoding across byte boundaries.
+    /// a format matching the encoding in the data store. `encode_search_prefixes` does this for the common
+    /// encodings, including the z85/ascii85 pitfalls when encoding across byte boundaries.
     async fn get_in_rotation_prefix(
         &self,
         secret_path: SecretPath,
         derivation_path: DerivationPath,
         metadata: &AlloyMetadata,
     ) -> Result<Vec<u8>, AlloyError>;
+    /// Deterministically encrypt an ordered list of named values as a single field. The values are
+    /// length-prefixed before encryption so different splits of the same bytes never collide.
+    async fn encrypt_compound(
+        &self,
+        plaintext_compound_field: PlaintextCompoundField,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedField, AlloyError>;
+    /// Decrypt a field that was encrypted with `encrypt_compound` back into its named values.
+    async fn decrypt_compound(
+        &self,
+        encrypted_field: EncryptedField,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextCompoundField, AlloyError>;
+    /// Encrypt each compound field with any Current and InRotation keys for the provided secret path.
+    /// The resulting encrypted fields should be used in tandem when querying the data store.
+    async fn generate_query_compound_field_values(
+        &self,
+        fields_to_query: PlaintextCompoundFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<GenerateQueryResult, AlloyError>;
 }
 
 pub(crate) fn encrypt_internal(
@@ -116,6 +266,7 @@ pub(crate) fn encrypt_internal(
 ) -> Result<EncryptedField, AlloyError> {
     let current_derived_key_sized: [u8; 64] =
         key.0.try_into().map_err(|_| AlloyError::InvalidKey {
+            kind: ErrorKind::Other,
             msg: "The derived key was not 64 bytes.".to_string(),
         })?;
     let encrypted_bytes = deterministic_encrypt(
@@ -137,6 +288,7 @@ pub(crate) fn decrypt_internal(
     derivation_path: DerivationPath,
 ) -> Result<PlaintextField, AlloyError> {
     let sized_key: [u8; 64] = key.0.try_into().map_err(|_| AlloyError::InvalidKey {
+        kind: ErrorKind::Other,
         msg: "The derived key was not 64 bytes.".to_string(),
     })?;
     deterministic_decrypt(sized_key, &ciphertext).map(|res| PlaintextField {
@@ -158,7 +310,10 @@ fn deterministic_encrypt_core(
     let mut cipher = Aes256Siv::new(&key.into());
     cipher
         .encrypt([associated_data], plaintext)
-        .map_err(|e| AlloyError::EncryptError { msg: e.to_string() })
+        .map_err(|e| AlloyError::EncryptError {
+            kind: ErrorKind::EncryptionFailed,
+            msg: e.to_string(),
+        })
 }
 
 fn deterministic_decrypt(key: [u8; 64], ciphertext: &[u8]) -> Result<Vec<u8>, AlloyError> {
@@ -174,6 +329,7 @@ fn deterministic_decrypt_core(
     cipher
         .decrypt([associated_data], ciphertext)
         .map_err(|_| AlloyError::DecryptError {
+            kind: ErrorKind::DecryptionFailed,
             msg: "Failed deterministic decryption. Ensure the data and tenant ID are correct"
                 .to_string(),
         })
@@ -198,4 +354,48 @@ mod test {
         let decrypt_result = deterministic_decrypt_core(key, &encrypt_result, &ad).unwrap();
         assert_eq!(decrypt_result, plaintext);
     }
+
+    fn compound_field(values: &[(&str, &[u8])]) -> PlaintextCompoundField {
+        PlaintextCompoundField {
+            values: values
+                .iter()
+                .map(|(name, value)| CompoundFieldValue {
+                    name: name.to_string(),
+                    value: value.to_vec(),
+                })
+                .collect(),
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+        }
+    }
+
+    #[test]
+    fn compound_encoding_is_unambiguous() {
+        let first: PlaintextField = compound_field(&[("a", b"bc")]).try_into().unwrap();
+        let second: PlaintextField = compound_field(&[("ab", b"c")]).try_into().unwrap();
+        let third: PlaintextField = compound_field(&[("a", b"b"), ("", b"c")])
+            .try_into()
+            .unwrap();
+        assert_ne!(first.plaintext_field, second.plaintext_field);
+        assert_ne!(first.plaintext_field, third.plaintext_field);
+        assert_ne!(second.plaintext_field, third.plaintext_field);
+    }
+
+    #[test]
+    fn compound_encoding_roundtrip() {
+        let compound = compound_field(&[("last", b"Smith"), ("first", b""), ("dob", &[0, 1, 2])]);
+        let encoded: PlaintextField = compound.clone().try_into().unwrap();
+        let decoded: PlaintextCompoundField = encoded.try_into().unwrap();
+        assert_eq!(decoded.values, compound.values);
+        assert_eq!(decoded.secret_path, compound.secret_path);
+        assert_eq!(decoded.derivation_path, compound.derivation_path);
+    }
+
+    #[test]
+    fn compound_decoding_rejects_trailing_bytes() {
+        let mut encoded: PlaintextField = compound_field(&[("a", b"b")]).try_into().unwrap();
+        encoded.plaintext_field.push(0);
+        let result: Result<PlaintextCompoundField, _> = encoded.try_into();
+        assert!(result.is_err());
+    }
 }
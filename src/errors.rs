@@ -4,26 +4,42 @@ pub use crate::tenant_security_client::errors::{
 use crate::vector::crypto::{
     DecryptError as VectorDecryptError, EncryptError as VectorEncryptError,
 };
+use std::sync::Arc;
 
-/// Errors related to IronCore Alloy SDK
-#[derive(Debug, uniffi::Error, PartialEq, Eq, Clone)]
+/// Errors related to IronCore Alloy SDK. Besides the variant, each error carries an `ErrorKind` saying more precisely
+/// what went wrong, so callers don't need to inspect messages. Errors caused by another library's error keep it as
+/// their `source`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Error))]
 pub enum AlloyError {
     /// Error while loading configuration.
-    InvalidConfiguration { msg: String },
+    InvalidConfiguration { kind: ErrorKind, msg: String },
     /// Error with key used
-    InvalidKey { msg: String },
+    InvalidKey { kind: ErrorKind, msg: String },
     /// Error with user input
-    InvalidInput { msg: String },
+    InvalidInput { kind: ErrorKind, msg: String },
     /// Errors while encrypting
-    EncryptError { msg: String },
+    EncryptError { kind: ErrorKind, msg: String },
     /// Errors while decrypting
-    DecryptError { msg: String },
+    DecryptError { kind: ErrorKind, msg: String },
     /// Error when parsing encryption headers/metadata
-    ProtobufError { msg: String },
-    /// Error when making a request to the TSP
-    RequestError { msg: String },
+    ProtobufError {
+        kind: ErrorKind,
+        msg: String,
+        source: Option<Arc<ErrorSource>>,
+    },
+    /// Error when making a request to the TSP or writing to a security event sink
+    RequestError {
+        kind: ErrorKind,
+        msg: String,
+        source: Option<Arc<ErrorSource>>,
+    },
     /// Error converting request data to JSON
-    SerdeJsonError { msg: String },
+    SerdeJsonError {
+        kind: ErrorKind,
+        msg: String,
+        source: Option<Arc<ErrorSource>>,
+    },
     /// Error directly from the TSP. See https://ironcorelabs.com/docs/saas-shield/tenant-security-proxy/errors/
     /// for details about these error codes.
     TspError {
@@ -33,19 +49,163 @@ pub enum AlloyError {
         msg: String,
     },
 }
+
+/// What went wrong, in more detail than the `AlloyError` variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Nothing more specific than the `AlloyError` variant is known.
+    Other,
+    /// No secret with the key ID the data was encrypted with is configured.
+    UnknownSecretId,
+    /// No primary (or current) secret is configured to encrypt new data with.
+    MissingPrimarySecret,
+    /// The secret path isn't in the configuration.
+    UnknownSecretPath,
+    /// No approximation factor is configured for a vector secret path.
+    MissingApproximationFactor,
+    /// An encryption header or metadata couldn't be parsed.
+    MalformedHeader,
+    /// The header says the data was encrypted by a different kind of client or for a different kind of data.
+    HeaderTypeMismatch,
+    /// The signature over an EDEK or header didn't verify. Often means the wrong tenant or secret was used.
+    SignatureVerificationFailed,
+    /// The authentication hash of an encrypted vector didn't match. Often means the wrong tenant or secret was used.
+    AuthHashMismatch,
+    /// Decrypting the data failed, because it was corrupted or a different key was used.
+    DecryptionFailed,
+    /// Encrypting the data failed.
+    EncryptionFailed,
+    /// A vector value is too large to encrypt with the configured approximation factor.
+    VectorOverflow,
+    /// A vector's dimension doesn't match the dimension its secret path is locked to.
+    DimensionMismatch,
+    /// A vector was encrypted for a different embedding model than its secret path is locked to.
+    EmbeddingModelMismatch,
+    /// A vector contains a NaN or infinite value.
+    NonFiniteValue,
+    /// A vector isn't normalized as its secret path requires, or has a norm of 0 and can't be normalized.
+    NotNormalized,
+    /// A sparse vector has a different number of indices and values, or repeats an index.
+    MalformedSparseVector,
+    /// The options configured for a vector secret path aren't valid, or can't be used for the vector.
+    InvalidVectorOptions,
+    /// The TSP couldn't be reached, or didn't respond in time.
+    TspUnavailable,
+    /// The TSP's response couldn't be understood.
+    InvalidTspResponse,
+    /// Data couldn't be converted to or from JSON or protobuf.
+    Serialization,
+    /// A security event couldn't be recorded or queued.
+    SecurityEventNotRecorded,
+}
+
+/// The error from another library that caused an `AlloyError`, like the HTTP client's error for a failed TSP request.
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
+pub struct ErrorSource(Box<dyn std::error::Error + Send + Sync>);
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl ErrorSource {
+    /// Message of the underlying error.
+    pub fn message(&self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl ErrorSource {
+    pub(crate) fn new(error: impl std::error::Error + Send + Sync + 'static) -> Option<Arc<Self>> {
+        Some(Arc::new(ErrorSource(Box::new(error))))
+    }
+}
+
+impl std::fmt::Debug for ErrorSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::fmt::Display for ErrorSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl PartialEq for ErrorSource {
+    fn eq(&self, other: &Self) -> bool {
+        self.message() == other.message()
+    }
+}
+
+impl Eq for ErrorSource {}
+
+impl AlloyError {
+    /// Name of the variant, for reporting errors without their messages.
+    pub(crate) fn variant_name(&self) -> &'static str {
+        match self {
+            AlloyError::InvalidConfiguration { .. } => "InvalidConfiguration",
+            AlloyError::InvalidKey { .. } => "InvalidKey",
+            AlloyError::InvalidInput { .. } => "InvalidInput",
+            AlloyError::EncryptError { .. } => "EncryptError",
+            AlloyError::DecryptError { .. } => "DecryptError",
+            AlloyError::ProtobufError { .. } => "ProtobufError",
+            AlloyError::RequestError { .. } => "RequestError",
+            AlloyError::SerdeJsonError { .. } => "SerdeJsonError",
+            AlloyError::TspError { .. } => "TspError",
+        }
+    }
+
+    /// What went wrong, in more detail than the variant. Retryable TSP errors are `TspUnavailable`.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            AlloyError::InvalidConfiguration { kind, .. }
+            | AlloyError::InvalidKey { kind, .. }
+            | AlloyError::InvalidInput { kind, .. }
+            | AlloyError::EncryptError { kind, .. }
+            | AlloyError::DecryptError { kind, .. }
+            | AlloyError::ProtobufError { kind, .. }
+            | AlloyError::RequestError { kind, .. }
+            | AlloyError::SerdeJsonError { kind, .. } => *kind,
+            AlloyError::TspError { .. } if self.is_retryable() => ErrorKind::TspUnavailable,
+            AlloyError::TspError { .. } => ErrorKind::Other,
+        }
+    }
+
+    /// Whether retrying the operation might succeed, like when the TSP was unreachable or overloaded. Errors caused
+    /// by configuration, keys or the data itself will fail the same way again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AlloyError::TspError {
+                error, http_code, ..
+            } => {
+                *http_code >= 500
+                    || *http_code == 429
+                    || matches!(
+                        error,
+                        TenantSecurityProxyError::Kms {
+                            error: KmsError::KmsUnreachable | KmsError::KmsThrottled
+                        } | TenantSecurityProxyError::Service {
+                            error: ServiceError::UnknownError
+                        }
+                    )
+            }
+            _ => self.kind() == ErrorKind::TspUnavailable,
+        }
+    }
+}
 impl std::fmt::Display for AlloyError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            AlloyError::InvalidConfiguration { msg } => {
+            AlloyError::InvalidConfiguration { msg, .. } => {
                 write!(f, "Invalid configuration: '{msg}'")
             }
-            AlloyError::InvalidKey { msg } => write!(f, "Invalid key: '{msg}'"),
-            AlloyError::InvalidInput { msg } => write!(f, "Invalid input: '{msg}'"),
-            AlloyError::EncryptError { msg } => write!(f, "Encrypt error: '{msg}'"),
-            AlloyError::DecryptError { msg } => write!(f, "Decrypt error: '{msg}'"),
-            AlloyError::ProtobufError { msg } => write!(f, "Protobuf error: '{msg}'"),
-            AlloyError::RequestError { msg } => write!(f, "Request error: '{msg}'"),
-            AlloyError::SerdeJsonError { msg } => write!(f, "Serde JSON error: '{msg}'"),
+            AlloyError::InvalidKey { msg, .. } => write!(f, "Invalid key: '{msg}'"),
+            AlloyError::InvalidInput { msg, .. } => write!(f, "Invalid input: '{msg}'"),
+            AlloyError::EncryptError { msg, .. } => write!(f, "Encrypt error: '{msg}'"),
+            AlloyError::DecryptError { msg, .. } => write!(f, "Decrypt error: '{msg}'"),
+            AlloyError::ProtobufError { msg, .. } => write!(f, "Protobuf error: '{msg}'"),
+            AlloyError::RequestError { msg, .. } => write!(f, "Request error: '{msg}'"),
+            AlloyError::SerdeJsonError { msg, .. } => write!(f, "Serde JSON error: '{msg}'"),
             AlloyError::TspError {
                 error,
                 tsp_code,
@@ -61,8 +221,12 @@ impl std::fmt::Display for AlloyError {
 impl From<VectorEncryptError> for AlloyError {
     fn from(value: VectorEncryptError) -> Self {
         match value {
-            VectorEncryptError::InvalidKey(s) => Self::InvalidKey { msg: s },
+            VectorEncryptError::InvalidKey(s) => Self::InvalidKey {
+                kind: ErrorKind::Other,
+                msg: s,
+            },
             VectorEncryptError::OverflowError => Self::InvalidInput {
+                kind: ErrorKind::VectorOverflow,
                 msg: value.to_string(),
             },
         }
@@ -71,8 +235,12 @@ impl From<VectorEncryptError> for AlloyError {
 impl From<VectorDecryptError> for AlloyError {
     fn from(value: VectorDecryptError) -> Self {
         match value {
-            VectorDecryptError::InvalidKey(s) => Self::InvalidKey { msg: s },
+            VectorDecryptError::InvalidKey(s) => Self::InvalidKey {
+                kind: ErrorKind::Other,
+                msg: s,
+            },
             VectorDecryptError::InvalidAuthHash => Self::InvalidInput {
+                kind: ErrorKind::AuthHashMismatch,
                 msg: "Invalid authentication hash".to_string(),
             },
         }
@@ -81,41 +249,172 @@ impl From<VectorDecryptError> for AlloyError {
 impl From<ironcore_documents::Error> for AlloyError {
     fn from(value: ironcore_documents::Error) -> Self {
         match value {
+            ironcore_documents::Error::EdekTypeError(_)
+            | ironcore_documents::Error::PayloadTypeError(_) => AlloyError::InvalidInput {
+                kind: ErrorKind::HeaderTypeMismatch,
+                msg: value.to_string(),
+            },
             ironcore_documents::Error::EdocTooShort(_)
             | ironcore_documents::Error::HeaderParseErr(_)
             | ironcore_documents::Error::InvalidVersion(_)
             | ironcore_documents::Error::NoIronCoreMagic
             | ironcore_documents::Error::SpecifiedLengthTooLong(_)
             | ironcore_documents::Error::HeaderLengthOverflow(_)
-            | ironcore_documents::Error::EdekTypeError(_)
-            | ironcore_documents::Error::PayloadTypeError(_)
             | ironcore_documents::Error::KeyIdHeaderTooShort(_)
             | ironcore_documents::Error::KeyIdHeaderMalformed(_) => AlloyError::InvalidInput {
+                kind: ErrorKind::MalformedHeader,
                 msg: value.to_string(),
             },
-            ironcore_documents::Error::ProtoSerializationErr(msg) => {
-                AlloyError::ProtobufError { msg }
-            }
-            ironcore_documents::Error::EncryptError(msg) => AlloyError::EncryptError { msg },
-            ironcore_documents::Error::DecryptError(msg) => AlloyError::DecryptError { msg },
+            ironcore_documents::Error::ProtoSerializationErr(msg) => AlloyError::ProtobufError {
+                kind: ErrorKind::MalformedHeader,
+                msg,
+                source: None,
+            },
+            ironcore_documents::Error::EncryptError(msg) => AlloyError::EncryptError {
+                kind: ErrorKind::EncryptionFailed,
+                msg,
+            },
+            ironcore_documents::Error::DecryptError(msg) => AlloyError::DecryptError {
+                kind: ErrorKind::DecryptionFailed,
+                msg,
+            },
         }
     }
 }
 impl From<protobuf::Error> for AlloyError {
     fn from(value: protobuf::Error) -> Self {
         AlloyError::ProtobufError {
+            kind: ErrorKind::Serialization,
             msg: value.to_string(),
+            source: ErrorSource::new(value),
         }
     }
 }
 impl From<reqwest::Error> for AlloyError {
     fn from(e: reqwest::Error) -> Self {
-        Self::RequestError { msg: e.to_string() }
+        let kind = if e.is_connect() || e.is_timeout() {
+            ErrorKind::TspUnavailable
+        } else if e.is_decode() {
+            ErrorKind::InvalidTspResponse
+        } else {
+            ErrorKind::Other
+        };
+        Self::RequestError {
+            kind,
+            msg: e.to_string(),
+            source: ErrorSource::new(e),
+        }
     }
 }
 impl From<serde_json::Error> for AlloyError {
     fn from(e: serde_json::Error) -> Self {
-        Self::SerdeJsonError { msg: e.to_string() }
+        Self::SerdeJsonError {
+            kind: ErrorKind::Serialization,
+            msg: e.to_string(),
+            source: ErrorSource::new(e),
+        }
+    }
+}
+impl std::error::Error for AlloyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AlloyError::ProtobufError { source, .. }
+            | AlloyError::RequestError { source, .. }
+            | AlloyError::SerdeJsonError { source, .. } => source
+                .as_ref()
+                .map(|source| source.0.as_ref() as &(dyn std::error::Error + 'static)),
+            _ => None,
+        }
+    }
+}
+
+/// Whether retrying the operation that failed with `error` might succeed, like when the TSP was unreachable or
+/// overloaded. Errors caused by configuration, keys or the data itself will fail the same way again.
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+pub fn error_is_retryable(error: AlloyError) -> bool {
+    error.is_retryable()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::error::Error;
+
+    fn tsp_error(error: TenantSecurityProxyError, http_code: u16) -> AlloyError {
+        AlloyError::TspError {
+            msg: error.to_string(),
+            error,
+            http_code,
+            tsp_code: 0,
+        }
+    }
+
+    #[test]
+    fn retryable_errors() {
+        let unavailable = AlloyError::RequestError {
+            kind: ErrorKind::TspUnavailable,
+            msg: "connection refused".to_string(),
+            source: None,
+        };
+        assert!(unavailable.is_retryable());
+        assert!(error_is_retryable(unavailable));
+        let throttled = tsp_error(
+            TenantSecurityProxyError::Kms {
+                error: KmsError::KmsThrottled,
+            },
+            400,
+        );
+        assert!(throttled.is_retryable());
+        assert_eq!(throttled.kind(), ErrorKind::TspUnavailable);
+        assert!(tsp_error(
+            TenantSecurityProxyError::Service {
+                error: ServiceError::InvalidRequestBody,
+            },
+            503
+        )
+        .is_retryable());
+
+        let unauthorized = tsp_error(
+            TenantSecurityProxyError::Service {
+                error: ServiceError::UnauthorizedRequest,
+            },
+            401,
+        );
+        assert!(!unauthorized.is_retryable());
+        assert_eq!(unauthorized.kind(), ErrorKind::Other);
+        let auth_hash = AlloyError::from(VectorDecryptError::InvalidAuthHash);
+        assert!(!auth_hash.is_retryable());
+        assert_eq!(auth_hash.kind(), ErrorKind::AuthHashMismatch);
+    }
+
+    #[test]
+    fn header_errors_have_kinds() {
+        assert_eq!(
+            AlloyError::from(ironcore_documents::Error::NoIronCoreMagic).kind(),
+            ErrorKind::MalformedHeader
+        );
+        assert_eq!(
+            AlloyError::from(ironcore_documents::Error::EdekTypeError("bad".to_string())).kind(),
+            ErrorKind::HeaderTypeMismatch
+        );
+    }
+
+    #[test]
+    fn source_is_preserved() {
+        let json_error = serde_json::from_str::<u32>("nope").unwrap_err();
+        let message = json_error.to_string();
+        let error = AlloyError::from(json_error);
+        assert_eq!(error.kind(), ErrorKind::Serialization);
+        assert_eq!(error.source().unwrap().to_string(), message);
+        match error {
+            AlloyError::SerdeJsonError {
+                source: Some(source),
+                ..
+            } => assert_eq!(source.message(), message),
+            _ => panic!("Expected a SerdeJsonError with a source."),
+        }
+        assert!(AlloyError::from(VectorEncryptError::OverflowError)
+            .source()
+            .is_none());
     }
 }
-impl std::error::Error for AlloyError {}
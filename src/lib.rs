@@ -1,37 +1,65 @@
 #![allow(async_fn_in_trait)]
 
-use crate::errors::AlloyError;
+use crate::errors::{AlloyError, ErrorKind};
 use bytes::Bytes;
 use ironcore_documents::v5::key_id_header::{EdekType, KeyId, KeyIdHeader, PayloadType};
+use itertools::Itertools;
 use saas_shield::config::SaasShieldConfiguration;
 use saas_shield::deterministic::SaasShieldDeterministicClient;
+use saas_shield::security_events::{SecurityEventQueue, SecurityEventQueueStats};
+use saas_shield::sparse_vector::SaasShieldSparseVectorClient;
 use saas_shield::standard::SaasShieldStandardClient;
 use saas_shield::vector::SaasShieldVectorClient;
 use serde::{Deserialize, Serialize};
 use standalone::config::StandaloneConfiguration;
 use standalone::deterministic::StandaloneDeterministicClient;
+use standalone::sparse_vector::StandaloneSparseVectorClient;
 use standalone::standard::StandaloneStandardClient;
 use standalone::standard_attached::StandaloneAttachedStandardClient;
 use standalone::vector::StandaloneVectorClient;
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tenant_security_client::{RequestMetadata, RequestingId};
+#[cfg(feature = "uniffi")]
 use uniffi::custom_newtype;
 use vector::VectorEncryptionKey;
 
+/// Implements the constructors in the given `impl` block, exported as uniffi constructors when the `uniffi` feature is
+/// on. uniffi's `export` doesn't look through `cfg_attr`, so `uniffi::constructor` can't be made optional in place.
+/// The signatures are the same either way so the feature stays additive; Rust callers that want values rather than
+/// `Arc`s use the builders and `From<&Config>` impls instead.
+macro_rules! uniffi_constructors {
+    (impl $ty:ident { $($(#[$($attr:tt)*])* pub fn $name:ident($($args:tt)*) -> $ret:ty $body:block)* }) => {
+        #[cfg(feature = "uniffi")]
+        #[uniffi::export]
+        impl $ty {
+            $($(#[$($attr)*])* #[uniffi::constructor] pub fn $name($($args)*) -> $ret $body)*
+        }
+        #[cfg(not(feature = "uniffi"))]
+        impl $ty {
+            $($(#[$($attr)*])* pub fn $name($($args)*) -> $ret $body)*
+        }
+    };
+}
+
+pub mod blocking;
 pub mod deterministic;
 pub mod errors;
+pub mod metrics;
 pub mod saas_shield;
+pub mod serialization;
 pub mod standalone;
 pub mod standard;
 pub mod standard_attached;
+mod telemetry;
 mod tenant_security_client;
 mod util;
 pub mod vector;
 
 // add multi-lang scaffolding
 // proc macro defined
+#[cfg(feature = "uniffi")]
 uniffi::setup_scaffolding!();
 
 type FieldId = String;
@@ -40,16 +68,43 @@ type PlaintextBytes = Vec<u8>;
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Hash, Clone)]
 pub struct SecretPath(pub String);
+#[cfg(feature = "uniffi")]
 custom_newtype!(SecretPath, String);
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Hash, Clone)]
 pub struct DerivationPath(pub String);
+#[cfg(feature = "uniffi")]
 custom_newtype!(DerivationPath, String);
 
+impl From<&str> for SecretPath {
+    fn from(value: &str) -> Self {
+        Self(value.into())
+    }
+}
+
+impl From<String> for SecretPath {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for DerivationPath {
+    fn from(value: &str) -> Self {
+        Self(value.into())
+    }
+}
+
+impl From<String> for DerivationPath {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
 /// Holds metadata fields as part of an SDK call. Each encrypted value will have metadata that associates
 /// it to a tenant ID as well as optional fields for other arbitrary key/value pairs and a request ID to send to the Tenant Security Proxy.
 /// Only the tenant ID will be used in Standalone SDKs, which can be created easily with `new_simple()`.
-#[derive(Debug, Clone, Serialize, uniffi::Object)]
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 #[serde(rename_all(serialize = "camelCase"))]
 pub struct AlloyMetadata {
     tenant_id: TenantId,
@@ -61,7 +116,7 @@ pub struct AlloyMetadata {
     custom_fields: HashMap<String, String>,
 }
 
-#[uniffi::export]
+uniffi_constructors! {
 impl AlloyMetadata {
     /// Constructor for AlloyMetadata which contains the tenant's ID and other metadata to send to the
     /// Tenant Security Proxy.
@@ -75,7 +130,6 @@ impl AlloyMetadata {
     /// - `request_id`                    - Unique ID that ties host application request ID to tenant.
     /// - `other_data`                    - Additional String key/value pairs to add to metadata.
     #[allow(clippy::too_many_arguments)]
-    #[uniffi::constructor]
     pub fn new(
         tenant_id: TenantId,
         requesting_user_or_service_id: Option<String>,
@@ -101,7 +155,6 @@ impl AlloyMetadata {
     ///
     /// # Arguments
     /// - `tenant_id` - Unique ID of tenant that is performing the operation.
-    #[uniffi::constructor]
     pub fn new_simple(tenant_id: TenantId) -> Arc<Self> {
         Arc::new(Self {
             tenant_id,
@@ -114,6 +167,58 @@ impl AlloyMetadata {
         })
     }
 }
+}
+
+impl AlloyMetadata {
+    /// Start building metadata for `tenant_id`. Without any other fields, this is the same as `new_simple`.
+    pub fn builder(tenant_id: impl Into<TenantId>) -> AlloyMetadataBuilder {
+        AlloyMetadataBuilder(Self {
+            tenant_id: tenant_id.into(),
+            requesting_id: None,
+            data_label: None,
+            source_ip: None,
+            object_id: None,
+            request_id: None,
+            custom_fields: HashMap::new(),
+        })
+    }
+}
+
+/// Builds an `AlloyMetadata`, setting only the fields that are needed. Create one with `AlloyMetadata::builder`.
+/// See `AlloyMetadata::new` for what each field means.
+#[derive(Debug, Clone)]
+pub struct AlloyMetadataBuilder(AlloyMetadata);
+
+impl AlloyMetadataBuilder {
+    pub fn requesting_user_or_service_id(mut self, id: impl Into<String>) -> Self {
+        self.0.requesting_id = Some(id.into());
+        self
+    }
+    pub fn data_label(mut self, data_label: impl Into<String>) -> Self {
+        self.0.data_label = Some(data_label.into());
+        self
+    }
+    pub fn source_ip(mut self, source_ip: impl Into<String>) -> Self {
+        self.0.source_ip = Some(source_ip.into());
+        self
+    }
+    pub fn object_id(mut self, object_id: impl Into<String>) -> Self {
+        self.0.object_id = Some(object_id.into());
+        self
+    }
+    pub fn request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.0.request_id = Some(request_id.into());
+        self
+    }
+    /// Add a key/value pair to the additional metadata sent to the TSP.
+    pub fn other_data(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.0.custom_fields.insert(key.into(), value.into());
+        self
+    }
+    pub fn build(self) -> AlloyMetadata {
+        self.0
+    }
+}
 
 impl TryFrom<AlloyMetadata> for RequestMetadata {
     type Error = AlloyError;
@@ -125,7 +230,10 @@ impl TryFrom<AlloyMetadata> for RequestMetadata {
                     .requesting_id
                     .unwrap_or("IronCore Labs Alloy SDK".to_string()),
             )
-            .map_err(|e| AlloyError::InvalidConfiguration { msg: e.to_string() })?,
+            .map_err(|e| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::Other,
+                msg: e.to_string(),
+            })?,
             value.data_label,
             value.source_ip,
             value.object_id,
@@ -144,6 +252,7 @@ impl TryFrom<(AlloyMetadata, Option<i64>)> for RequestMetadata {
         let time_as_u64 = match event_time_millis {
             Some(time) if time >= 0 => Ok(time as u64),
             Some(_) => Err(AlloyError::InvalidInput {
+                kind: ErrorKind::Other,
                 msg: "millis times must be >= 0.".to_string(),
             }),
             None => Ok(SystemTime::now()
@@ -157,24 +266,34 @@ impl TryFrom<(AlloyMetadata, Option<i64>)> for RequestMetadata {
     }
 }
 // only make these top two publicly constructable to narrow public interface a bit
-#[derive(uniffi::Object)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct Standalone {
     standard: Arc<StandaloneStandardClient>,
     standard_attached: Arc<StandaloneAttachedStandardClient>,
     deterministic: Arc<StandaloneDeterministicClient>,
     vector: Arc<StandaloneVectorClient>,
+    sparse_vector: Arc<StandaloneSparseVectorClient>,
 }
-#[uniffi::export]
+uniffi_constructors! {
 impl Standalone {
-    #[uniffi::constructor]
     pub fn new(config: &StandaloneConfiguration) -> Arc<Self> {
-        Arc::new(Self {
+        Arc::new(Self::from(config))
+    }
+}
+}
+impl From<&StandaloneConfiguration> for Standalone {
+    fn from(config: &StandaloneConfiguration) -> Self {
+        Self {
             standard: Arc::new(StandaloneStandardClient::new(config.clone())),
             standard_attached: Arc::new(StandaloneAttachedStandardClient::new(config.clone())),
             deterministic: Arc::new(StandaloneDeterministicClient::new(config.clone())),
             vector: Arc::new(StandaloneVectorClient::new(config.clone())),
-        })
+            sparse_vector: Arc::new(StandaloneSparseVectorClient::new(config.clone())),
+        }
     }
+}
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl Standalone {
     pub fn standard(&self) -> Arc<StandaloneStandardClient> {
         self.standard.clone()
     }
@@ -187,30 +306,52 @@ impl Standalone {
     pub fn vector(&self) -> Arc<StandaloneVectorClient> {
         self.vector.clone()
     }
+    pub fn sparse_vector(&self) -> Arc<StandaloneSparseVectorClient> {
+        self.sparse_vector.clone()
+    }
 }
-#[derive(uniffi::Object)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct SaasShield {
     standard: Arc<SaasShieldStandardClient>,
     deterministic: Arc<SaasShieldDeterministicClient>,
     vector: Arc<SaasShieldVectorClient>,
+    sparse_vector: Arc<SaasShieldSparseVectorClient>,
+    security_events: Arc<SecurityEventQueue>,
 }
-#[uniffi::export]
+uniffi_constructors! {
 impl SaasShield {
-    #[uniffi::constructor]
     pub fn new(config: &SaasShieldConfiguration) -> Arc<Self> {
-        Arc::new(Self {
+        Arc::new(Self::from(config))
+    }
+}
+}
+impl From<&SaasShieldConfiguration> for SaasShield {
+    fn from(config: &SaasShieldConfiguration) -> Self {
+        Self {
             standard: Arc::new(SaasShieldStandardClient::new(
                 config.tenant_security_client.clone(),
+                config.security_events.clone(),
             )),
             deterministic: Arc::new(SaasShieldDeterministicClient::new(
                 config.tenant_security_client.clone(),
+                config.security_events.clone(),
             )),
             vector: Arc::new(SaasShieldVectorClient::new(
                 config.tenant_security_client.clone(),
-                config.approximation_factor,
+                config.approximation_factors.clone(),
+                config.security_events.clone(),
             )),
-        })
+            sparse_vector: Arc::new(SaasShieldSparseVectorClient::new(
+                config.tenant_security_client.clone(),
+                config.approximation_factors.clone(),
+                config.security_events.clone(),
+            )),
+            security_events: config.security_events.clone(),
+        }
     }
+}
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl SaasShield {
     pub fn standard(&self) -> Arc<SaasShieldStandardClient> {
         self.standard.clone()
     }
@@ -220,6 +361,23 @@ impl SaasShield {
     pub fn vector(&self) -> Arc<SaasShieldVectorClient> {
         self.vector.clone()
     }
+    pub fn sparse_vector(&self) -> Arc<SaasShieldSparseVectorClient> {
+        self.sparse_vector.clone()
+    }
+    /// Counts of the security events sent, or waiting to be sent, in the background.
+    pub fn security_event_stats(&self) -> SecurityEventQueueStats {
+        self.security_events.stats()
+    }
+}
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
+impl SaasShield {
+    /// Wait until all security events queued to be sent in the background have been sent or dropped. Call this before
+    /// shutting down so queued events aren't lost. Fails if that takes longer than `timeout_millis`.
+    pub async fn flush_security_events(&self, timeout_millis: u64) -> Result<(), AlloyError> {
+        self.security_events
+            .flush(Duration::from_millis(timeout_millis))
+            .await
+    }
 }
 
 /// This module exists to prevent leaking AlloyClient functions to the various client traits
@@ -237,6 +395,7 @@ pub(crate) mod alloy_client_trait {
         fn get_payload_type() -> PayloadType;
 
         fn create_key_id_header(key_id: u32) -> KeyIdHeader {
+            telemetry::record_key_id(key_id);
             KeyIdHeader {
                 key_id: KeyId(key_id),
                 edek_type: Self::get_edek_type(),
@@ -259,23 +418,25 @@ pub(crate) mod alloy_client_trait {
                 encrypted_bytes.into(),
             )
             .map_err(|_| AlloyError::InvalidInput {
+                kind: ErrorKind::MalformedHeader,
                 msg: "Encrypted header was invalid.".to_string(),
             })?;
             let expected_edek_type = Self::get_edek_type();
             let expected_payload_type = Self::get_payload_type();
             if edek_type == expected_edek_type && payload_type == expected_payload_type {
+                telemetry::record_key_id(key_id.0);
                 Ok((key_id, remaining_bytes))
             } else {
-                Err(AlloyError::InvalidInput{ msg:
-                    format!("The data indicated that this was not a {expected_edek_type} {expected_payload_type} wrapped value. Found: {edek_type}, {payload_type}"),
-            })
+                Err(AlloyError::InvalidInput{ kind: ErrorKind::HeaderTypeMismatch, msg:
+                    format!("The data indicated that this was not a {expected_edek_type} {expected_payload_type} wrapped value. Found: {edek_type}, {payload_type}"), })
             }
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct TenantId(pub String);
+#[cfg(feature = "uniffi")]
 custom_newtype!(TenantId, String);
 
 impl AsRef<[u8]> for TenantId {
@@ -290,6 +451,12 @@ impl From<&str> for TenantId {
     }
 }
 
+impl From<String> for TenantId {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
 /// Applies the padding required for base85 algorithms to produce consistent string when the bytes are encoded.
 /// If you're using a base85 encoding algorithm other than the directly supported z85, see our tests in `util.rs` for examples.
 pub fn base85_prefix_padding(prefix_bytes: &[u8]) -> Vec<u8> {
@@ -307,18 +474,69 @@ pub fn encode_prefix_z85(prefix_bytes: Vec<u8>) -> String {
     z85_string
 }
 
+/// String encodings supported by `encode_search_prefixes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum PrefixEncoding {
+    /// Standard base64, with or without padding.
+    Base64,
+    /// URL safe base64, with or without padding.
+    Base64Url,
+    /// ZeroMQ's base85. Encoded values must be at least 4 bytes longer than the prefix, which is always the case
+    /// for encrypted values, since z85 encodes a trailing partial chunk differently.
+    Z85,
+    /// Adobe/btoa style ascii85, without the `<~` and `~>` delimiters. Prepend `<~` to each prefix if your data
+    /// store keeps them. Both the `z` shorthand for zero chunks and its long form are covered.
+    Ascii85,
+    /// Lowercase hex. Use a case insensitive query if your data store might have uppercase hex.
+    Hex,
+}
+
+/// Encode the prefix bytes from `get_searchable_edek_prefix` or `get_in_rotation_prefix` into the string prefixes
+/// that values encrypted with them start with once they're encoded with `encoding`. A value matches if it starts
+/// with any one of the returned strings, so combine them with `OR` in LIKE or prefix queries.
+///
+/// Hex and base64 prefixes are exact. When the prefix bytes end partway through an encoded character, every
+/// character they could produce is returned. Base85 encodings mix all 4 bytes of a chunk into every character, so
+/// the z85 and ascii85 prefixes stop early, keeping one character per prefix byte in the final partial chunk. They
+/// match every value starting with the prefix bytes, but can also match a small number of values that don't.
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+pub fn encode_search_prefixes(prefix_bytes: Vec<u8>, encoding: PrefixEncoding) -> Vec<String> {
+    let prefixes = match encoding {
+        PrefixEncoding::Base64 => util::base64_search_prefixes(
+            &base64::engine::general_purpose::STANDARD_NO_PAD,
+            &prefix_bytes,
+        ),
+        PrefixEncoding::Base64Url => util::base64_search_prefixes(
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+            &prefix_bytes,
+        ),
+        PrefixEncoding::Z85 => util::base85_search_prefixes(
+            &prefix_bytes,
+            |digit| util::Z85_ALPHABET[digit as usize] as char,
+            false,
+        ),
+        PrefixEncoding::Ascii85 => {
+            util::base85_search_prefixes(&prefix_bytes, |digit| (digit as u8 + b'!') as char, true)
+        }
+        PrefixEncoding::Hex => vec![hex::encode(prefix_bytes)],
+    };
+    prefixes.into_iter().sorted().dedup().collect()
+}
+
 // Like an EncryptionKey but not used directly for encryption
-#[derive(Debug, Serialize, Clone, uniffi::Object)]
+#[derive(Debug, Serialize, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct Secret {
     pub(crate) secret: Vec<u8>,
 }
 
-#[uniffi::export]
+uniffi_constructors! {
 impl Secret {
-    #[uniffi::constructor]
     pub fn new(secret: Vec<u8>) -> Result<Arc<Self>, AlloyError> {
         if secret.len() < 32 {
             Err(AlloyError::InvalidConfiguration {
+                kind: ErrorKind::Other,
                 msg: "Secrets must be at least 32 cryptographically random bytes.".to_string(),
             })
         } else {
@@ -326,11 +544,13 @@ impl Secret {
         }
     }
 }
+}
 
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
     use itertools::Itertools;
+    use standalone::config::{RotatableSecret, StandaloneSecret, VectorSecret};
 
     pub fn get_metadata() -> Arc<AlloyMetadata> {
         AlloyMetadata::new_simple(TenantId("foo".to_string()))
@@ -381,4 +601,42 @@ pub(crate) mod tests {
         let result1 = base85_prefix_padding(&[1, 2, 3, 4, 5, 6]);
         assert_eq!(result1, [1, 2, 3, 4, 5, 6, 0, 0])
     }
+
+    #[test]
+    fn metadata_builder_sets_fields() {
+        let metadata = AlloyMetadata::builder("tenant")
+            .requesting_user_or_service_id("svc")
+            .data_label("PII")
+            .other_data("key", "value")
+            .build();
+        assert_eq!(metadata.tenant_id, TenantId("tenant".to_string()));
+        assert_eq!(metadata.requesting_id, Some("svc".to_string()));
+        assert_eq!(metadata.data_label, Some("PII".to_string()));
+        assert_eq!(metadata.source_ip, None);
+        assert_eq!(
+            metadata.custom_fields.get("key").map(String::as_str),
+            Some("value")
+        );
+    }
+
+    #[test]
+    fn standalone_configuration_builder_sets_secrets() {
+        let secret = Secret::new([1u8; 32].to_vec()).unwrap();
+        let config = StandaloneConfiguration::builder()
+            .deterministic_secret(
+                "path",
+                RotatableSecret::new(Some(StandaloneSecret::new(1, secret.clone())), None).unwrap(),
+            )
+            .vector_secret(
+                "path",
+                VectorSecret::new(
+                    1.1,
+                    RotatableSecret::new(Some(StandaloneSecret::new(2, secret)), None).unwrap(),
+                ),
+            )
+            .build();
+        assert!(config.deterministic.contains_key(&"path".into()));
+        assert!(config.vector.contains_key(&"path".into()));
+        assert_eq!(config.standard.primary_secret_id, None);
+    }
 }
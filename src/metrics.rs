@@ -0,0 +1,242 @@
+//! Metrics hooks for SDK operations and TSP requests. Install a `MetricsRecorder` with `set_metrics_recorder` to
+//! receive one `OperationMetrics` for every standard, deterministic and vector operation, and every request to the
+//! TSP. The recorder is global, so `Standalone` and `SaasShield` clients report the same way. With the `metrics`
+//! feature enabled, `MetricsCrateRecorder` reports them through the `metrics` crate.
+//!
+//! Tenant IDs and secret paths aren't included, both to keep label cardinality bounded and because they're
+//! identifying. Failures of individual items in batch operations are returned in the batch result instead of failing
+//! the operation, so they don't show up as operation failures.
+
+use crate::errors::AlloyError;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// What happened during one SDK operation or TSP request.
+#[derive(Debug, Clone, Copy)]
+pub struct OperationMetrics<'a> {
+    /// Name of the operation, like `vector.encrypt` or `tsp.request`. The same for every client kind.
+    pub operation: &'static str,
+    /// TSP endpoint for `tsp.request` operations.
+    pub endpoint: Option<&'a str>,
+    /// Number of items in batch operations.
+    pub batch_size: Option<usize>,
+    pub latency: Duration,
+    /// The error the operation failed with, if it failed.
+    pub error: Option<&'a AlloyError>,
+}
+
+impl OperationMetrics<'_> {
+    /// Name of the `AlloyError` variant the operation failed with.
+    pub fn error_variant(&self) -> Option<&'static str> {
+        self.error.map(AlloyError::variant_name)
+    }
+
+    /// TSP error code the operation failed with, if the failure came from the TSP.
+    pub fn tsp_error_code(&self) -> Option<u16> {
+        match self.error {
+            Some(AlloyError::TspError { tsp_code, .. }) => Some(*tsp_code),
+            _ => None,
+        }
+    }
+}
+
+/// Receives metrics for every SDK operation and TSP request once it completes. Called on the thread that ran the
+/// operation, so implementations should be quick and must not block.
+pub trait MetricsRecorder: Send + Sync {
+    fn record_operation(&self, metrics: &OperationMetrics<'_>);
+
+    /// Called when SaaS Shield security events queued to be sent in the background are dropped, because the queue
+    /// was full or the TSP wouldn't take them.
+    fn record_security_events_dropped(&self, _count: usize) {}
+}
+
+static RECORDER: RwLock<Option<Arc<dyn MetricsRecorder>>> = RwLock::new(None);
+
+/// Install `recorder` to receive metrics from all clients, replacing any recorder installed before.
+pub fn set_metrics_recorder(recorder: Arc<dyn MetricsRecorder>) {
+    *RECORDER.write().unwrap_or_else(|e| e.into_inner()) = Some(recorder);
+}
+
+/// Stop reporting metrics.
+pub fn clear_metrics_recorder() {
+    *RECORDER.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+pub(crate) fn current_recorder() -> Option<Arc<dyn MetricsRecorder>> {
+    RECORDER.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Reports metrics through the `metrics` crate, to whichever `metrics` recorder/exporter the application installed:
+/// - `alloy_operations_total` counter, labeled by `operation` and `outcome` (`success` or `failure`)
+/// - `alloy_operation_errors_total` counter, labeled by `operation`, `error` (the `AlloyError` variant) and
+///   `tsp_code` (empty unless the TSP returned the error)
+/// - `alloy_operation_duration_seconds` histogram, labeled by `operation`
+/// - `alloy_batch_size` histogram, labeled by `operation`, for batch operations
+/// - `alloy_tsp_request_duration_seconds` histogram, labeled by `endpoint`, for TSP requests
+/// - `alloy_security_events_dropped_total` counter of dropped security events
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MetricsCrateRecorder;
+
+#[cfg(feature = "metrics")]
+impl MetricsRecorder for MetricsCrateRecorder {
+    fn record_operation(&self, metrics: &OperationMetrics<'_>) {
+        let operation = metrics.operation;
+        let outcome = if metrics.error.is_some() {
+            "failure"
+        } else {
+            "success"
+        };
+        ::metrics::counter!("alloy_operations_total", "operation" => operation, "outcome" => outcome)
+            .increment(1);
+        if let Some(error) = metrics.error_variant() {
+            let tsp_code = metrics
+                .tsp_error_code()
+                .map(|code| code.to_string())
+                .unwrap_or_default();
+            ::metrics::counter!(
+                "alloy_operation_errors_total",
+                "operation" => operation,
+                "error" => error,
+                "tsp_code" => tsp_code
+            )
+            .increment(1);
+        }
+        ::metrics::histogram!("alloy_operation_duration_seconds", "operation" => operation)
+            .record(metrics.latency.as_secs_f64());
+        if let Some(batch_size) = metrics.batch_size {
+            ::metrics::histogram!("alloy_batch_size", "operation" => operation)
+                .record(batch_size as f64);
+        }
+        if let Some(endpoint) = metrics.endpoint {
+            ::metrics::histogram!("alloy_tsp_request_duration_seconds", "endpoint" => endpoint.to_string())
+                .record(metrics.latency.as_secs_f64());
+        }
+    }
+
+    fn record_security_events_dropped(&self, count: usize) {
+        ::metrics::counter!("alloy_security_events_dropped_total").increment(count as u64);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::TenantSecurityProxyError;
+    use crate::standalone::standard::test::new_client;
+    use crate::standard::StandardDocumentOps;
+    use crate::AlloyMetadata;
+    use std::sync::Mutex;
+
+    /// Operation, batch size and error variant of each recorded operation.
+    type Record = (&'static str, Option<usize>, Option<&'static str>);
+
+    #[derive(Default)]
+    struct Recorded(Mutex<Vec<Record>>);
+
+    impl MetricsRecorder for Recorded {
+        fn record_operation(&self, metrics: &OperationMetrics<'_>) {
+            self.0.lock().unwrap().push((
+                metrics.operation,
+                metrics.batch_size,
+                metrics.error_variant(),
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn operations_are_recorded() {
+        let recorded = Arc::new(Recorded::default());
+        set_metrics_recorder(recorded.clone());
+        let client = new_client(Some(1));
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        let encrypted = client.encrypt([].into(), &metadata).await.unwrap();
+        client
+            .rekey_edeks(
+                [("edek".to_string(), encrypted.edek.clone())].into(),
+                &metadata,
+                None,
+            )
+            .await
+            .unwrap();
+        let other_tenant = AlloyMetadata::new_simple("other".into());
+        assert!(client.decrypt(encrypted, &other_tenant).await.is_err());
+        clear_metrics_recorder();
+
+        // Other tests may run operations while the recorder is installed, so only look for these ones.
+        let recorded = recorded.0.lock().unwrap();
+        assert!(recorded.contains(&("standard.encrypt", None, None)));
+        assert!(recorded.contains(&("standard.rekey_edeks", Some(1), None)));
+        assert!(recorded
+            .iter()
+            .any(|(operation, _, error)| *operation == "standard.decrypt" && error.is_some()));
+    }
+
+    #[test]
+    fn tsp_error_code_is_exposed() {
+        let error = AlloyError::TspError {
+            error: TenantSecurityProxyError::code_to_error(204),
+            http_code: 400,
+            tsp_code: 204,
+            msg: "KMS wrap failed".to_string(),
+        };
+        let metrics = OperationMetrics {
+            operation: "tsp.request",
+            endpoint: Some("document/wrap"),
+            batch_size: None,
+            latency: Duration::from_millis(3),
+            error: Some(&error),
+        };
+        assert_eq!(metrics.error_variant(), Some("TspError"));
+        assert_eq!(metrics.tsp_error_code(), Some(204));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics_crate_recorder_reports_counters_and_histograms() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let error = AlloyError::InvalidInput {
+            kind: crate::errors::ErrorKind::Other,
+            msg: "bad".to_string(),
+        };
+        ::metrics::with_local_recorder(&recorder, || {
+            MetricsCrateRecorder.record_operation(&OperationMetrics {
+                operation: "vector.encrypt_batch",
+                endpoint: None,
+                batch_size: Some(7),
+                latency: Duration::from_millis(5),
+                error: None,
+            });
+            MetricsCrateRecorder.record_operation(&OperationMetrics {
+                operation: "tsp.request",
+                endpoint: Some("document/wrap"),
+                batch_size: None,
+                latency: Duration::from_millis(20),
+                error: Some(&error),
+            });
+        });
+        let snapshot = snapshotter.snapshot().into_vec();
+        let find = |name: &str| {
+            snapshot
+                .iter()
+                .filter(|(key, ..)| key.key().name() == name)
+                .map(|(key, _, _, value)| (key.key().labels().cloned().collect::<Vec<_>>(), value))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(find("alloy_operations_total").len(), 2);
+        let errors = find("alloy_operation_errors_total");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .0
+            .iter()
+            .any(|label| label.key() == "error" && label.value() == "InvalidInput"));
+        assert!(matches!(
+            find("alloy_batch_size")[0].1,
+            DebugValue::Histogram(values) if values.len() == 1 && values[0].into_inner() == 7.
+        ));
+        assert_eq!(find("alloy_tsp_request_duration_seconds").len(), 1);
+    }
+}
@@ -1,37 +1,401 @@
-use crate::errors::AlloyError;
+use crate::errors::{AlloyError, ErrorKind};
+use crate::saas_shield::security_events::{
+    AutomaticSecurityEvents, SecurityEventQueue, SecurityEventQueueOptions,
+};
 use crate::tenant_security_client::{ApiKey, TenantSecurityClient};
+use crate::vector::{VectorEncryptionParams, VectorSecretOptions};
+use crate::SecretPath;
+use std::collections::HashMap;
 use std::sync::Arc;
 
+/// Approximation factors and other options used for vector encryption, chosen by the secret path of each vector.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct VectorApproximationFactors {
+    pub(crate) by_secret_path: HashMap<SecretPath, f32>,
+    pub(crate) default: Option<f32>,
+    pub(crate) options_by_secret_path: HashMap<SecretPath, VectorSecretOptions>,
+}
+
+impl VectorApproximationFactors {
+    /// Get the approximation factor configured for `secret_path`, falling back to the default factor.
+    pub(crate) fn get(&self, secret_path: &SecretPath) -> Result<f32, AlloyError> {
+        self.by_secret_path
+            .get(secret_path)
+            .copied()
+            .or(self.default)
+            .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::MissingApproximationFactor,
+                msg: format!(
+                    "No approximation factor was configured for secret path `{}` and no default approximation factor was set.",
+                    secret_path.0
+                ),
+            })
+    }
+
+    /// Get the options configured for `secret_path`, or the default options if there aren't any.
+    pub(crate) fn options(&self, secret_path: &SecretPath) -> VectorSecretOptions {
+        self.options_by_secret_path
+            .get(secret_path)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Get everything needed to encrypt a vector with `secret_path`.
+    pub(crate) fn params(
+        &self,
+        secret_path: &SecretPath,
+    ) -> Result<VectorEncryptionParams, AlloyError> {
+        Ok(self
+            .options(secret_path)
+            .encryption_params(self.get(secret_path)?))
+    }
+}
+
 /// Configuration for the SaaS Shield SDKs. Sets the TSP domain/URI and API key to be used for SaaS Shield operations.
-#[derive(uniffi::Object)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct SaasShieldConfiguration {
-    // TODO: configuring this across the whole SDK probably isn't how we want to do it. If a vector was encrypted with
-    //       an approximation factor, you wouldn't be able to query against/for it reliably if the factor changed in
-    //       the future, or decrypt it. You'd have to manually know what factor the data you're looking for used and
-    //       create this config with the same one. Not do-able outside full re-indexing situations.
-    pub(crate) approximation_factor: Option<f32>,
+    // Note that if the factor for a secret path changes, vectors encrypted under the old factor can't be reliably
+    // queried or decrypted with this configuration. They should be rotated with the old configuration first.
+    pub(crate) approximation_factors: VectorApproximationFactors,
     pub(crate) tenant_security_client: Arc<TenantSecurityClient>,
+    pub(crate) security_events: Arc<SecurityEventQueue>,
 }
-#[uniffi::export]
+uniffi_constructors! {
 impl SaasShieldConfiguration {
-    #[uniffi::constructor]
+    /// Create a configuration that uses `approximation_factor` for vectors with any secret path.
     pub fn new(
         tsp_uri: String,
         api_key: String,
         accept_invalid_certs: bool,
         approximation_factor: Option<f32>,
     ) -> Result<Arc<Self>, AlloyError> {
+        Self::new_with_approximation_factors(
+            tsp_uri,
+            api_key,
+            accept_invalid_certs,
+            HashMap::new(),
+            approximation_factor,
+        )
+    }
+
+    /// Create a configuration with an approximation factor for each vector secret path. Vectors with a secret path
+    /// that isn't in `approximation_factors` will use `default_approximation_factor`, or fail if there isn't one.
+    pub fn new_with_approximation_factors(
+        tsp_uri: String,
+        api_key: String,
+        accept_invalid_certs: bool,
+        approximation_factors: HashMap<SecretPath, f32>,
+        default_approximation_factor: Option<f32>,
+    ) -> Result<Arc<Self>, AlloyError> {
+        Self::new_with_vector_options(
+            tsp_uri,
+            api_key,
+            accept_invalid_certs,
+            approximation_factors,
+            default_approximation_factor,
+            HashMap::new(),
+        )
+    }
+
+    /// Like `new_with_approximation_factors`, but also sets the other options for vector secret paths. Secret paths
+    /// that aren't in `vector_options` use the default `VectorSecretOptions`. Fails if any of the options are invalid.
+    pub fn new_with_vector_options(
+        tsp_uri: String,
+        api_key: String,
+        accept_invalid_certs: bool,
+        approximation_factors: HashMap<SecretPath, f32>,
+        default_approximation_factor: Option<f32>,
+        vector_options: HashMap<SecretPath, VectorSecretOptions>,
+    ) -> Result<Arc<Self>, AlloyError> {
+        Self::new_with_automatic_security_events(
+            tsp_uri,
+            api_key,
+            accept_invalid_certs,
+            approximation_factors,
+            default_approximation_factor,
+            vector_options,
+            AutomaticSecurityEvents::default(),
+        )
+    }
+
+    /// Like `new_with_vector_options`, but also chooses which security events clients log on their own after
+    /// standard, deterministic and vector operations. See `AutomaticSecurityEvents`.
+    pub fn new_with_automatic_security_events(
+        tsp_uri: String,
+        api_key: String,
+        accept_invalid_certs: bool,
+        approximation_factors: HashMap<SecretPath, f32>,
+        default_approximation_factor: Option<f32>,
+        vector_options: HashMap<SecretPath, VectorSecretOptions>,
+        automatic_security_events: AutomaticSecurityEvents,
+    ) -> Result<Arc<Self>, AlloyError> {
+        Self::new_with_security_event_queue(
+            tsp_uri,
+            api_key,
+            accept_invalid_certs,
+            approximation_factors,
+            default_approximation_factor,
+            vector_options,
+            automatic_security_events,
+            None,
+        )
+    }
+
+    /// Like `new_with_automatic_security_events`, but `log_security_event` also queues events to be sent in the
+    /// background instead of waiting for the TSP, buffered and retried as set by `security_event_queue`. Without it,
+    /// automatic events use the default `SecurityEventQueueOptions`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_security_event_queue(
+        tsp_uri: String,
+        api_key: String,
+        accept_invalid_certs: bool,
+        approximation_factors: HashMap<SecretPath, f32>,
+        default_approximation_factor: Option<f32>,
+        vector_options: HashMap<SecretPath, VectorSecretOptions>,
+        automatic_security_events: AutomaticSecurityEvents,
+        security_event_queue: Option<SecurityEventQueueOptions>,
+    ) -> Result<Arc<Self>, AlloyError> {
+        let mut builder = Self::builder(tsp_uri, api_key)
+            .accept_invalid_certs(accept_invalid_certs)
+            .automatic_security_events(automatic_security_events);
+        builder.approximation_factors = approximation_factors;
+        builder.default_approximation_factor = default_approximation_factor;
+        builder.vector_options = vector_options;
+        builder.security_event_queue = security_event_queue;
+        builder.build().map(Arc::new)
+    }
+}
+}
+
+impl SaasShieldConfiguration {
+    /// Start building a configuration for the TSP at `tsp_uri`.
+    pub fn builder(
+        tsp_uri: impl Into<String>,
+        api_key: impl Into<String>,
+    ) -> SaasShieldConfigurationBuilder {
+        SaasShieldConfigurationBuilder {
+            tsp_uri: tsp_uri.into(),
+            api_key: api_key.into(),
+            accept_invalid_certs: false,
+            approximation_factors: HashMap::new(),
+            default_approximation_factor: None,
+            vector_options: HashMap::new(),
+            automatic_security_events: AutomaticSecurityEvents::default(),
+            security_event_queue: None,
+        }
+    }
+}
+
+/// Builds a `SaasShieldConfiguration`, setting only the options that are needed. Create one with
+/// `SaasShieldConfiguration::builder`. See `SaasShieldConfiguration::new_with_security_event_queue` for what each
+/// option means.
+#[derive(Debug, Clone)]
+pub struct SaasShieldConfigurationBuilder {
+    tsp_uri: String,
+    api_key: String,
+    accept_invalid_certs: bool,
+    approximation_factors: HashMap<SecretPath, f32>,
+    default_approximation_factor: Option<f32>,
+    vector_options: HashMap<SecretPath, VectorSecretOptions>,
+    automatic_security_events: AutomaticSecurityEvents,
+    security_event_queue: Option<SecurityEventQueueOptions>,
+}
+
+impl SaasShieldConfigurationBuilder {
+    pub fn accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Approximation factor for vectors with `secret_path`.
+    pub fn approximation_factor(mut self, secret_path: impl Into<SecretPath>, factor: f32) -> Self {
+        self.approximation_factors
+            .insert(secret_path.into(), factor);
+        self
+    }
+
+    /// Approximation factor for vectors with secret paths that weren't given one with `approximation_factor`.
+    pub fn default_approximation_factor(mut self, factor: f32) -> Self {
+        self.default_approximation_factor = Some(factor);
+        self
+    }
+
+    /// Other options for vectors with `secret_path`.
+    pub fn vector_options(
+        mut self,
+        secret_path: impl Into<SecretPath>,
+        options: VectorSecretOptions,
+    ) -> Self {
+        self.vector_options.insert(secret_path.into(), options);
+        self
+    }
+
+    pub fn automatic_security_events(mut self, events: AutomaticSecurityEvents) -> Self {
+        self.automatic_security_events = events;
+        self
+    }
+
+    pub fn security_event_queue(mut self, options: SecurityEventQueueOptions) -> Self {
+        self.security_event_queue = Some(options);
+        self
+    }
+
+    /// Fails if the API key or any of the vector options aren't valid.
+    pub fn build(self) -> Result<SaasShieldConfiguration, AlloyError> {
+        for options in self.vector_options.values() {
+            options.validate()?;
+        }
         let reqwest_client = reqwest::Client::builder()
-            .danger_accept_invalid_certs(accept_invalid_certs)
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
             .build()
             .expect("Failed to create http client. This means there is a system misconfiguration.");
-        Ok(Arc::new(Self {
-            approximation_factor,
-            tenant_security_client: Arc::new(TenantSecurityClient::new(
-                tsp_uri,
-                ApiKey::try_from(api_key)?,
-                reqwest_client,
+        let tenant_security_client = Arc::new(TenantSecurityClient::new(
+            self.tsp_uri,
+            ApiKey::try_from(self.api_key)?,
+            reqwest_client,
+        ));
+        Ok(SaasShieldConfiguration {
+            approximation_factors: VectorApproximationFactors {
+                by_secret_path: self.approximation_factors,
+                default: self.default_approximation_factor,
+                options_by_secret_path: self.vector_options,
+            },
+            tenant_security_client: tenant_security_client.clone(),
+            security_events: Arc::new(SecurityEventQueue::new(
+                self.automatic_security_events,
+                self.security_event_queue,
+                tenant_security_client,
             )),
-        }))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vector::VectorEncryptionMode;
+    use assertables::*;
+
+    #[test]
+    fn approximation_factor_prefers_secret_path() {
+        let factors = VectorApproximationFactors {
+            by_secret_path: [(SecretPath("path".to_string()), 2.0)].into(),
+            default: Some(1.1),
+            ..Default::default()
+        };
+        assert_eq!(factors.get(&SecretPath("path".to_string())).unwrap(), 2.0);
+        assert_eq!(factors.get(&SecretPath("other".to_string())).unwrap(), 1.1);
+    }
+
+    #[test]
+    fn approximation_factor_missing_path_without_default() {
+        let factors = VectorApproximationFactors {
+            by_secret_path: [(SecretPath("path".to_string()), 2.0)].into(),
+            default: None,
+            ..Default::default()
+        };
+        let err = factors.get(&SecretPath("other".to_string())).unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidConfiguration { .. }));
+        assert_contains!(err.to_string(), "secret path `other`");
+    }
+
+    #[test]
+    fn params_default_to_scaled_without_blocks() {
+        let factors = VectorApproximationFactors {
+            default: Some(1.1),
+            options_by_secret_path: [(
+                SecretPath("path".to_string()),
+                VectorSecretOptions {
+                    mode: VectorEncryptionMode::QuantizationFriendly,
+                    matryoshka_block_size: Some(256),
+                    accept_quantization_friendly_leakage: true,
+                    ..Default::default()
+                },
+            )]
+            .into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            factors.params(&SecretPath("path".to_string())).unwrap(),
+            VectorEncryptionParams {
+                approximation_factor: 1.1,
+                mode: VectorEncryptionMode::QuantizationFriendly,
+                matryoshka_block_size: Some(256),
+                model_id: None,
+            }
+        );
+        assert_eq!(
+            factors.params(&SecretPath("other".to_string())).unwrap(),
+            VectorEncryptionParams::scaled(1.1)
+        );
+    }
+
+    #[test]
+    fn builder_collects_vector_settings() {
+        let config = SaasShieldConfiguration::builder("http://localhost:32804", "0WUaXesNgbTAuLwn")
+            .approximation_factor("path", 2.0)
+            .default_approximation_factor(1.1)
+            .build()
+            .unwrap();
+        assert_eq!(
+            config.approximation_factors.get(&"path".into()).unwrap(),
+            2.0
+        );
+        assert_eq!(
+            config.approximation_factors.get(&"other".into()).unwrap(),
+            1.1
+        );
+    }
+
+    #[test]
+    fn builder_requires_accepting_quantization_friendly_leakage() {
+        let builder = |accept_quantization_friendly_leakage| {
+            SaasShieldConfiguration::builder("http://localhost:32804", "0WUaXesNgbTAuLwn")
+                .vector_options(
+                    "path",
+                    VectorSecretOptions {
+                        mode: VectorEncryptionMode::QuantizationFriendly,
+                        accept_quantization_friendly_leakage,
+                        ..Default::default()
+                    },
+                )
+                .build()
+        };
+        let Err(err) = builder(false) else {
+            panic!("QuantizationFriendly mode should need to be accepted");
+        };
+        assert!(matches!(err, AlloyError::InvalidConfiguration { .. }));
+        assert!(builder(true).is_ok());
+    }
+
+    #[test]
+    fn new_with_vector_options_rejects_zero_block_size() {
+        let Err(err) = SaasShieldConfiguration::new_with_vector_options(
+            "http://localhost:32804".to_string(),
+            "0WUaXesNgbTAuLwn".to_string(),
+            false,
+            HashMap::new(),
+            None,
+            [(
+                SecretPath("path".to_string()),
+                VectorSecretOptions {
+                    matryoshka_block_size: Some(0),
+                    ..Default::default()
+                },
+            )]
+            .into(),
+        ) else {
+            panic!("a block size of zero should be rejected");
+        };
+        assert!(matches!(err, AlloyError::InvalidConfiguration { .. }));
+    }
+
+    #[test]
+    fn builder_rejects_empty_api_key() {
+        let Err(err) = SaasShieldConfiguration::builder("http://localhost:32804", "").build()
+        else {
+            panic!("empty API key should be rejected");
+        };
+        assert!(matches!(err, AlloyError::InvalidConfiguration { .. }));
     }
 }
@@ -1,31 +1,99 @@
 use super::{
     derive_keys_many_paths, get_in_rotation_prefix_internal, get_keys_for_rotation,
-    DeriveKeyChoice, RotationKeys, SaasShieldSecurityEventOps, SecurityEvent,
+    rotate_to_new_tenants, DataEvent, DeriveKeyChoice, RotationKeys, SaasShieldSecurityEventOps,
+    SecurityEvent,
 };
 
 use crate::deterministic::{
     decrypt_internal, encrypt_internal, DeterministicEncryptionKey, DeterministicFieldOps,
     DeterministicRotateResult, EncryptedField, EncryptedFields, GenerateQueryResult,
-    PlaintextField, PlaintextFields,
+    PlaintextCompoundField, PlaintextCompoundFields, PlaintextField, PlaintextFields,
+    TenantEncryptedFields,
 };
-use crate::errors::AlloyError;
+use crate::errors::{AlloyError, ErrorKind};
+use crate::saas_shield::security_events::SecurityEventQueue;
+use crate::telemetry::Operation;
 use crate::tenant_security_client::{DerivationType, SecretType, TenantSecurityClient};
-use crate::util::{check_rotation_no_op, collection_to_batch_result};
+use crate::util::{
+    check_rotation_no_op, collection_to_batch_result, rotate_grouped_by_tenant, BatchResult,
+};
 use crate::{alloy_client_trait::AlloyClient, AlloyMetadata, DerivationPath, SecretPath, TenantId};
 use ironcore_documents::v5::key_id_header::{EdekType, PayloadType};
 use itertools::Itertools;
 use std::sync::Arc;
 
-#[derive(uniffi::Object)]
+const KEY_ID_MISMATCH: &str =
+    "The key ID in the document header and on the key derived for decryption did not match";
+
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct SaasShieldDeterministicClient {
     tenant_security_client: Arc<TenantSecurityClient>,
+    security_events: Arc<SecurityEventQueue>,
 }
 impl SaasShieldDeterministicClient {
-    pub(crate) fn new(tenant_security_client: Arc<TenantSecurityClient>) -> Self {
+    pub(crate) fn new(
+        tenant_security_client: Arc<TenantSecurityClient>,
+        security_events: Arc<SecurityEventQueue>,
+    ) -> Self {
         Self {
             tenant_security_client: tenant_security_client.clone(),
+            security_events,
         }
     }
+
+    /// Re-encrypt each field with the current key of `new_tenant_id`. Fields that are already encrypted with that key
+    /// are returned unchanged.
+    fn rotate_fields_with_keys(
+        &self,
+        rotation_keys: &RotationKeys,
+        encrypted_fields: EncryptedFields,
+        metadata: &AlloyMetadata,
+        new_tenant_id: &TenantId,
+    ) -> BatchResult<EncryptedField> {
+        let reencrypt_field = |encrypted_field: EncryptedField| {
+            let (original_key_id, ciphertext) =
+                Self::decompose_key_id_header(encrypted_field.encrypted_field.clone())?;
+            let maybe_current_key_id = rotation_keys
+                .new_keys
+                .get_current(
+                    &encrypted_field.secret_path,
+                    &encrypted_field.derivation_path,
+                )
+                .map(|k| k.tenant_secret_id.0);
+            if check_rotation_no_op(
+                original_key_id,
+                &maybe_current_key_id,
+                new_tenant_id,
+                metadata,
+            ) {
+                Ok(encrypted_field)
+            } else {
+                let original_key = rotation_keys.original_keys.get_key_for_path(
+                    &encrypted_field.secret_path,
+                    &encrypted_field.derivation_path,
+                    DeriveKeyChoice::Specific(original_key_id),
+                )?;
+                let decrypted_field = decrypt_internal(
+                    DeterministicEncryptionKey(original_key.derived_key.0.clone()),
+                    ciphertext,
+                    encrypted_field.secret_path.clone(),
+                    encrypted_field.derivation_path.clone(),
+                )?;
+                let new_current_key = rotation_keys.new_keys.get_key_for_path(
+                    &encrypted_field.secret_path,
+                    &encrypted_field.derivation_path,
+                    DeriveKeyChoice::Current,
+                )?;
+                let key_id_header = Self::create_key_id_header(new_current_key.tenant_secret_id.0);
+                encrypt_internal(
+                    DeterministicEncryptionKey(new_current_key.derived_key.0.clone()),
+                    key_id_header,
+                    decrypted_field,
+                )
+            }
+        };
+        collection_to_batch_result(encrypted_fields, reencrypt_field)
+    }
 }
 
 impl AlloyClient for SaasShieldDeterministicClient {
@@ -38,7 +106,7 @@ impl AlloyClient for SaasShieldDeterministicClient {
     }
 }
 
-#[uniffi::export(async_runtime = "tokio")]
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
 impl DeterministicFieldOps for SaasShieldDeterministicClient {
     /// Encrypt a field with the provided metadata.
     /// Because the field is encrypted deterministically with each call, the result will be the same for repeated calls.
@@ -49,31 +117,40 @@ impl DeterministicFieldOps for SaasShieldDeterministicClient {
         plaintext_field: PlaintextField,
         metadata: &AlloyMetadata,
     ) -> Result<EncryptedField, AlloyError> {
-        let paths = [(
-            plaintext_field.secret_path.clone(),
-            [plaintext_field.derivation_path.clone()].into(),
-        )]
-        .into();
-        let derived_keys = self
-            .tenant_security_client
-            .tenant_key_derive(
-                paths,
-                &metadata.clone().try_into()?,
-                DerivationType::Sha512,
-                SecretType::Deterministic,
-            )
-            .await?;
-        let derived_key = derived_keys.get_key_for_path(
-            &plaintext_field.secret_path,
-            &plaintext_field.derivation_path,
-            DeriveKeyChoice::Current,
-        )?;
-        let key_id_header = Self::create_key_id_header(derived_key.tenant_secret_id.0);
-        encrypt_internal(
-            DeterministicEncryptionKey(derived_key.derived_key.0.clone()),
-            key_id_header,
-            plaintext_field,
-        )
+        let secret_path = plaintext_field.secret_path.0.clone();
+        let result = Operation::new("deterministic.encrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .secret_path(&secret_path)
+            .run(async move {
+                let paths = [(
+                    plaintext_field.secret_path.clone(),
+                    [plaintext_field.derivation_path.clone()].into(),
+                )]
+                .into();
+                let derived_keys = self
+                    .tenant_security_client
+                    .tenant_key_derive(
+                        paths,
+                        &metadata.clone().try_into()?,
+                        DerivationType::Sha512,
+                        SecretType::Deterministic,
+                    )
+                    .await?;
+                let derived_key = derived_keys.get_key_for_path(
+                    &plaintext_field.secret_path,
+                    &plaintext_field.derivation_path,
+                    DeriveKeyChoice::Current,
+                )?;
+                let key_id_header = Self::create_key_id_header(derived_key.tenant_secret_id.0);
+                encrypt_internal(
+                    DeterministicEncryptionKey(derived_key.derived_key.0.clone()),
+                    key_id_header,
+                    plaintext_field,
+                )
+            })
+            .await;
+        self.security_events
+            .after_operation(DataEvent::Encrypt, metadata, result)
     }
 
     /// Decrypt a field that was deterministically encrypted with the provided metadata.
@@ -82,40 +159,49 @@ impl DeterministicFieldOps for SaasShieldDeterministicClient {
         encrypted_field: EncryptedField,
         metadata: &AlloyMetadata,
     ) -> Result<PlaintextField, AlloyError> {
-        let (key_id, ciphertext) =
-            Self::decompose_key_id_header(encrypted_field.encrypted_field.clone())?;
-        let paths = [(
-            encrypted_field.secret_path.clone(),
-            [encrypted_field.derivation_path.clone()].into(),
-        )]
-        .into();
-        let derived_keys = self
-            .tenant_security_client
-            .tenant_key_derive(
-                paths,
-                &metadata.clone().try_into()?,
-                DerivationType::Sha512,
-                SecretType::Deterministic,
-            )
-            .await?;
-        let derived_key = derived_keys.get_key_for_path(
-            &encrypted_field.secret_path,
-            &encrypted_field.derivation_path,
-            DeriveKeyChoice::Specific(key_id),
-        )?;
-        if derived_key.tenant_secret_id.0 != key_id.0 {
-            Err(AlloyError::InvalidKey{ msg:
-                    "The key ID in the document header and on the key derived for decryption did not match"
-                        .to_string(),
-        })
-        } else {
-            decrypt_internal(
-                DeterministicEncryptionKey(derived_key.derived_key.0.clone()),
-                ciphertext,
-                encrypted_field.secret_path,
-                encrypted_field.derivation_path,
-            )
-        }
+        let secret_path = encrypted_field.secret_path.0.clone();
+        let result = Operation::new("deterministic.decrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .secret_path(&secret_path)
+            .run(async move {
+                let (key_id, ciphertext) =
+                    Self::decompose_key_id_header(encrypted_field.encrypted_field.clone())?;
+                let paths = [(
+                    encrypted_field.secret_path.clone(),
+                    [encrypted_field.derivation_path.clone()].into(),
+                )]
+                .into();
+                let derived_keys = self
+                    .tenant_security_client
+                    .tenant_key_derive(
+                        paths,
+                        &metadata.clone().try_into()?,
+                        DerivationType::Sha512,
+                        SecretType::Deterministic,
+                    )
+                    .await?;
+                let derived_key = derived_keys.get_key_for_path(
+                    &encrypted_field.secret_path,
+                    &encrypted_field.derivation_path,
+                    DeriveKeyChoice::Specific(key_id),
+                )?;
+                if derived_key.tenant_secret_id.0 != key_id.0 {
+                    Err(AlloyError::InvalidKey {
+                        kind: ErrorKind::DecryptionFailed,
+                        msg: KEY_ID_MISMATCH.to_string(),
+                    })
+                } else {
+                    decrypt_internal(
+                        DeterministicEncryptionKey(derived_key.derived_key.0.clone()),
+                        ciphertext,
+                        encrypted_field.secret_path,
+                        encrypted_field.derivation_path,
+                    )
+                }
+            })
+            .await;
+        self.security_events
+            .after_operation(DataEvent::Decrypt, metadata, result)
     }
 
     /// Encrypt each plaintext field with any Current and InRotation keys for the provided secret path.
@@ -125,41 +211,50 @@ impl DeterministicFieldOps for SaasShieldDeterministicClient {
         fields_to_query: PlaintextFields,
         metadata: &AlloyMetadata,
     ) -> Result<GenerateQueryResult, AlloyError> {
-        let paths = fields_to_query
-            .values()
-            .map(|field| (field.secret_path.clone(), field.derivation_path.clone()))
-            .collect_vec();
-        let all_keys = derive_keys_many_paths(
-            &self.tenant_security_client,
-            metadata,
-            paths,
-            SecretType::Deterministic,
-        )
-        .await?
-        .derived_keys;
-        fields_to_query
-            .into_iter()
-            .map(|(field_id, plaintext_field)| {
-                let keys = all_keys
-                    .get(&plaintext_field.secret_path)
-                    .and_then(|deriv| deriv.get(&plaintext_field.derivation_path))
-                    .ok_or(AlloyError::RequestError {
-                        msg: "Failed to derive keys for provided path using the TSP.".to_string(),
-                    })?;
-                keys.iter()
-                    .map(|derived_key| {
-                        let key_id_header =
-                            Self::create_key_id_header(derived_key.tenant_secret_id.0);
-                        encrypt_internal(
-                            DeterministicEncryptionKey(derived_key.derived_key.0.clone()),
-                            key_id_header,
-                            plaintext_field.clone(),
-                        )
+        Operation::new("deterministic.generate_query_field_values")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(fields_to_query.len())
+            .run(async move {
+                let paths = fields_to_query
+                    .values()
+                    .map(|field| (field.secret_path.clone(), field.derivation_path.clone()))
+                    .collect_vec();
+                let all_keys = derive_keys_many_paths(
+                    &self.tenant_security_client,
+                    metadata,
+                    paths,
+                    SecretType::Deterministic,
+                )
+                .await?
+                .derived_keys;
+                fields_to_query
+                    .into_iter()
+                    .map(|(field_id, plaintext_field)| {
+                        let keys = all_keys
+                            .get(&plaintext_field.secret_path)
+                            .and_then(|deriv| deriv.get(&plaintext_field.derivation_path))
+                            .ok_or(AlloyError::RequestError {
+                                kind: ErrorKind::InvalidTspResponse,
+                                msg: "Failed to derive keys for provided path using the TSP."
+                                    .to_string(),
+                                source: None,
+                            })?;
+                        keys.iter()
+                            .map(|derived_key| {
+                                let key_id_header =
+                                    Self::create_key_id_header(derived_key.tenant_secret_id.0);
+                                encrypt_internal(
+                                    DeterministicEncryptionKey(derived_key.derived_key.0.clone()),
+                                    key_id_header,
+                                    plaintext_field.clone(),
+                                )
+                            })
+                            .try_collect()
+                            .map(|enc| (field_id, enc))
                     })
-                    .try_collect()
-                    .map(|enc| (field_id, enc))
+                    .collect()
             })
-            .collect()
+            .await
     }
 
     /// Re-encrypt already encrypted fields with the Current key for the provided tenant. The `metadata` passed
@@ -171,71 +266,83 @@ impl DeterministicFieldOps for SaasShieldDeterministicClient {
         metadata: &AlloyMetadata,
         new_tenant_id: Option<TenantId>,
     ) -> Result<DeterministicRotateResult, AlloyError> {
-        let parsed_new_tenant_id = new_tenant_id.as_ref().unwrap_or(&metadata.tenant_id);
-        let paths = encrypted_fields
-            .values()
-            .map(|field| (field.secret_path.clone(), field.derivation_path.clone()))
-            .collect_vec();
-        let RotationKeys {
-            original_keys: original_tenant_keys,
-            new_keys: new_tenant_keys,
-        } = get_keys_for_rotation(
-            metadata,
-            parsed_new_tenant_id,
-            paths,
-            &self.tenant_security_client,
-            SecretType::Deterministic,
-        )
-        .await?;
-        let reencrypt_field = |encrypted_field: EncryptedField| {
-            let (original_key_id, ciphertext) =
-                Self::decompose_key_id_header(encrypted_field.encrypted_field.clone())?;
-            let maybe_current_key_id = new_tenant_keys
-                .get_current(
-                    &encrypted_field.secret_path,
-                    &encrypted_field.derivation_path,
+        Operation::new("deterministic.rotate_fields")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(encrypted_fields.len())
+            .run(async move {
+                let parsed_new_tenant_id = new_tenant_id.as_ref().unwrap_or(&metadata.tenant_id);
+                let paths = encrypted_fields
+                    .values()
+                    .map(|field| (field.secret_path.clone(), field.derivation_path.clone()))
+                    .collect_vec();
+                let rotation_keys = get_keys_for_rotation(
+                    metadata,
+                    parsed_new_tenant_id,
+                    paths,
+                    &self.tenant_security_client,
+                    SecretType::Deterministic,
                 )
-                .map(|k| k.tenant_secret_id.0);
-            if check_rotation_no_op(
-                original_key_id,
-                &maybe_current_key_id,
-                parsed_new_tenant_id,
-                metadata,
-            ) {
-                Ok(encrypted_field)
-            } else {
-                let original_key = original_tenant_keys.get_key_for_path(
-                    &encrypted_field.secret_path,
-                    &encrypted_field.derivation_path,
-                    DeriveKeyChoice::Specific(original_key_id),
-                )?;
-                let decrypted_field = decrypt_internal(
-                    DeterministicEncryptionKey(original_key.derived_key.0.clone()),
-                    ciphertext,
-                    encrypted_field.secret_path.clone(),
-                    encrypted_field.derivation_path.clone(),
-                )?;
-                let new_current_key = new_tenant_keys.get_key_for_path(
-                    &encrypted_field.secret_path,
-                    &encrypted_field.derivation_path,
-                    DeriveKeyChoice::Current,
-                )?;
-                let key_id_header = Self::create_key_id_header(new_current_key.tenant_secret_id.0);
-                encrypt_internal(
-                    DeterministicEncryptionKey(new_current_key.derived_key.0.clone()),
-                    key_id_header,
-                    decrypted_field,
+                .await?;
+                Ok(self
+                    .rotate_fields_with_keys(
+                        &rotation_keys,
+                        encrypted_fields,
+                        metadata,
+                        parsed_new_tenant_id,
+                    )
+                    .into())
+            })
+            .await
+    }
+
+    /// Re-encrypt already encrypted fields that may belong to many tenants. Each field carries the tenant ID it was
+    /// encrypted to and an optional tenant ID to rotate it to. Fields are grouped by the tenant they were encrypted to,
+    /// so that tenant's keys are derived once however many tenants its fields move to. The tenant ID in `metadata` is
+    /// ignored, but its other values are used for every group.
+    async fn rotate_fields_multi_tenant(
+        &self,
+        encrypted_fields: TenantEncryptedFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<DeterministicRotateResult, AlloyError> {
+        Operation::new("deterministic.rotate_fields_multi_tenant")
+            .batch_size(encrypted_fields.len())
+            .run(async move {
+                let items = encrypted_fields.into_iter().map(|(field_id, field)| {
+                    (
+                        field_id,
+                        (field.tenant_id, field.new_tenant_id, field.encrypted_field),
+                    )
+                });
+                Ok(
+                    rotate_grouped_by_tenant(items, metadata, |fields, metadata| async move {
+                        rotate_to_new_tenants(
+                            &self.tenant_security_client,
+                            fields,
+                            &metadata,
+                            SecretType::Deterministic,
+                            |field| (field.secret_path.clone(), field.derivation_path.clone()),
+                            |rotation_keys, fields, new_tenant_id| {
+                                self.rotate_fields_with_keys(
+                                    rotation_keys,
+                                    fields,
+                                    &metadata,
+                                    new_tenant_id,
+                                )
+                            },
+                        )
+                        .await
+                    })
+                    .await
+                    .into(),
                 )
-            }
-        };
-        Ok(collection_to_batch_result(encrypted_fields, reencrypt_field).into())
+            })
+            .await
     }
 
     /// Generate a prefix that could used to search a data store for fields encrypted using an identifier (KMS
     /// config id for SaaS Shield, secret id for Standalone). These bytes should be encoded into
-    /// a format matching the encoding in the data store. z85/ascii85 users should first pass these bytes through
-    /// `encode_prefix_z85` or `base85_prefix_padding`. Make sure you've read the documentation of those functions to
-    /// avoid pitfalls when encoding across byte boundaries.
+    /// a format matching the encoding in the data store. `encode_search_prefixes` does this for the common
+    /// encodings, including the z85/ascii85 pitfalls when encoding across byte boundaries.
     async fn get_in_rotation_prefix(
         &self,
         secret_path: SecretPath,
@@ -260,9 +367,44 @@ impl DeterministicFieldOps for SaasShieldDeterministicClient {
             Self::get_payload_type(),
         )
     }
+
+    /// Deterministically encrypt an ordered list of named values as a single field. The values are
+    /// length-prefixed before encryption so different splits of the same bytes never collide.
+    async fn encrypt_compound(
+        &self,
+        plaintext_compound_field: PlaintextCompoundField,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedField, AlloyError> {
+        self.encrypt(plaintext_compound_field.try_into()?, metadata)
+            .await
+    }
+
+    /// Decrypt a field that was encrypted with `encrypt_compound` back into its named values.
+    async fn decrypt_compound(
+        &self,
+        encrypted_field: EncryptedField,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextCompoundField, AlloyError> {
+        self.decrypt(encrypted_field, metadata).await?.try_into()
+    }
+
+    /// Encrypt each compound field with any Current and InRotation keys for the provided secret path.
+    /// The resulting encrypted fields should be used in tandem when querying the data store.
+    async fn generate_query_compound_field_values(
+        &self,
+        fields_to_query: PlaintextCompoundFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<GenerateQueryResult, AlloyError> {
+        let plaintext_fields = fields_to_query
+            .into_iter()
+            .map(|(field_id, compound_field)| Ok((field_id, compound_field.try_into()?)))
+            .collect::<Result<PlaintextFields, AlloyError>>()?;
+        self.generate_query_field_values(plaintext_fields, metadata)
+            .await
+    }
 }
 
-#[uniffi::export(async_runtime = "tokio")]
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
 impl SaasShieldSecurityEventOps for SaasShieldDeterministicClient {
     /// Log the security event `event` to the tenant's log sink.
     /// If the event time is unspecified the current time will be used.
@@ -272,9 +414,8 @@ impl SaasShieldSecurityEventOps for SaasShieldDeterministicClient {
         metadata: &AlloyMetadata,
         event_time_millis: Option<i64>,
     ) -> Result<(), AlloyError> {
-        let request_metadata = (metadata.clone(), event_time_millis).try_into()?;
-        self.tenant_security_client
-            .log_security_event(&event, &request_metadata)
+        self.security_events
+            .log(event, metadata, event_time_millis)
             .await
     }
 }
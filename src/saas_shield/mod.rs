@@ -1,16 +1,27 @@
+use crate::alloy_client_trait::AlloyClient;
 use crate::tenant_security_client::{
     DerivationType, DeriveKeyChoice, DerivedKey, KeyDeriveResponse, SecretType,
     TenantSecurityClient,
 };
-use crate::{errors::AlloyError, AlloyMetadata, VectorEncryptionKey};
-use crate::{DerivationPath, SecretPath, TenantId};
+use crate::util::{
+    check_rotation_no_op, collection_to_batch_result, merge_group_results, BatchResult,
+};
+use crate::vector::sparse::EncryptedSparseVector;
+use crate::vector::{get_vector_metadata, EncryptedVector, VectorId, VectorMetadata};
+use crate::{
+    errors::{AlloyError, ErrorKind},
+    AlloyMetadata, VectorEncryptionKey,
+};
+use crate::{DerivationPath, FieldId, SecretPath, TenantId};
 use convert_case::Casing;
 use ironcore_documents::v5::key_id_header::{EdekType, KeyId, KeyIdHeader, PayloadType};
 use itertools::Itertools;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 pub mod config;
 pub mod deterministic;
+pub mod security_events;
+pub mod sparse_vector;
 pub mod standard;
 pub mod standard_attached;
 pub mod vector;
@@ -26,7 +37,8 @@ pub trait SaasShieldSecurityEventOps {
     ) -> Result<(), AlloyError>;
 }
 
-#[derive(Debug, uniffi::Enum)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 pub enum SecurityEvent {
     Admin { event: AdminEvent },
     Data { event: DataEvent },
@@ -47,7 +59,8 @@ impl ToString for SecurityEvent {
     }
 }
 
-#[derive(Debug, uniffi::Enum)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 pub enum AdminEvent {
     Add,
     ChangePermissions,
@@ -61,7 +74,8 @@ impl ToString for AdminEvent {
     }
 }
 
-#[derive(Debug, uniffi::Enum)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 pub enum UserEvent {
     Add,
     Suspend,
@@ -88,7 +102,8 @@ impl ToString for UserEvent {
     }
 }
 
-#[derive(Debug, uniffi::Enum)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 pub enum DataEvent {
     Import,
     Export,
@@ -106,7 +121,8 @@ impl ToString for DataEvent {
     }
 }
 
-#[derive(Debug, uniffi::Enum)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 pub enum PeriodicEvent {
     EnforceRetentionPolicy,
     CreateBackup,
@@ -119,7 +135,8 @@ impl ToString for PeriodicEvent {
 }
 
 /// A custom event. The event must have a screaming snake case name and cannot start with an `_`.
-#[derive(Debug, uniffi::Record)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct CustomEvent {
     event_name: String,
 }
@@ -130,6 +147,7 @@ impl CustomEvent {
             regex::Regex::new("^[A-Z_]+$").expect("Regex compilation is a development error");
         if !regex.is_match(event_name) || event_name.starts_with('_') {
             Err(AlloyError::InvalidInput {
+                kind: ErrorKind::Other,
                 msg: "CustomEvents must be screaming snake case and cannot start with _"
                     .to_string(),
             })
@@ -208,14 +226,79 @@ pub(crate) async fn get_keys_for_rotation(
     })
 }
 
+/// Rotates one tenant's items to each of their new tenants, with `None` meaning the same tenant. The original
+/// tenant's keys are derived once for all of the items, then each new tenant's keys once for the items moving to it.
+/// If deriving a new tenant's keys fails only the items moving to that tenant fail.
+pub(crate) async fn rotate_to_new_tenants<T, U, P, R>(
+    tenant_security_client: &TenantSecurityClient,
+    items: HashMap<FieldId, (Option<TenantId>, T)>,
+    metadata: &AlloyMetadata,
+    secret_type: SecretType,
+    path: P,
+    rotate: R,
+) -> Result<BatchResult<U>, AlloyError>
+where
+    P: Fn(&T) -> (SecretPath, DerivationPath),
+    R: Fn(&RotationKeys, HashMap<FieldId, T>, &TenantId) -> BatchResult<U>,
+{
+    let original_keys = derive_keys_many_paths(
+        tenant_security_client,
+        metadata,
+        items.values().map(|(_, item)| path(item)).collect_vec(),
+        secret_type.clone(),
+    )
+    .await?;
+    let by_new_tenant = items
+        .into_iter()
+        .map(|(id, (new_tenant_id, item))| {
+            (
+                new_tenant_id.unwrap_or_else(|| metadata.tenant_id.clone()),
+                (id, item),
+            )
+        })
+        .into_group_map();
+    let mut group_results = Vec::with_capacity(by_new_tenant.len());
+    for (new_tenant_id, group) in by_new_tenant {
+        let ids = group.iter().map(|(id, _)| id.clone()).collect_vec();
+        let result: Result<_, AlloyError> = async {
+            let new_keys = if new_tenant_id == metadata.tenant_id {
+                original_keys.clone()
+            } else {
+                let new_metadata = AlloyMetadata {
+                    tenant_id: new_tenant_id.clone(),
+                    ..metadata.clone()
+                };
+                derive_keys_many_paths(
+                    tenant_security_client,
+                    &new_metadata,
+                    group.iter().map(|(_, item)| path(item)).collect_vec(),
+                    secret_type.clone(),
+                )
+                .await?
+            };
+            let rotation_keys = RotationKeys {
+                original_keys: original_keys.clone(),
+                new_keys,
+            };
+            Ok(rotate(
+                &rotation_keys,
+                group.into_iter().collect(),
+                &new_tenant_id,
+            ))
+        }
+        .await;
+        group_results.push((ids, result));
+    }
+    Ok(merge_group_results(group_results))
+}
+
 /// Converts a DerivedKey to an encryption Key (with scaling factor) and key ID
 fn derived_key_to_vector_encryption_key(
     derived_key: &DerivedKey,
 ) -> Result<(KeyId, VectorEncryptionKey), AlloyError> {
     let key = if derived_key.derived_key.len() < 35 {
-        Err(AlloyError::RequestError {msg:
-            "Derivation didn't return enough bytes. HMAC-SHA512 should always return 64 bytes, so the TSP is misbehaving.".to_string()
-        })
+        Err(AlloyError::RequestError { kind: ErrorKind::InvalidTspResponse, msg:
+            "Derivation didn't return enough bytes. HMAC-SHA512 should always return 64 bytes, so the TSP is misbehaving.".to_string(), source: None })
     } else {
         let key_bytes = &derived_key.derived_key.0[..];
         Ok(VectorEncryptionKey::unsafe_bytes_to_key(key_bytes))
@@ -223,6 +306,191 @@ fn derived_key_to_vector_encryption_key(
     Ok((KeyId(derived_key.tenant_secret_id.0), key))
 }
 
+/// Derives the vector key for a single secret path/derivation path combination. A `Specific` key that comes back with
+/// a different key ID is rejected, since it can't decrypt the vector.
+async fn derive_vector_key(
+    tenant_security_client: &TenantSecurityClient,
+    metadata: &AlloyMetadata,
+    secret_path: &SecretPath,
+    derivation_path: &DerivationPath,
+    derive_key_choice: DeriveKeyChoice,
+) -> Result<(KeyId, VectorEncryptionKey), AlloyError> {
+    let expected_key_id = match derive_key_choice {
+        DeriveKeyChoice::Specific(key_id) => Some(key_id),
+        _ => None,
+    };
+    let paths = [(secret_path.clone(), [derivation_path.clone()].into())].into();
+    let derived_keys = tenant_security_client
+        .tenant_key_derive(
+            paths,
+            &metadata.clone().try_into()?,
+            DerivationType::Sha512,
+            SecretType::Vector,
+        )
+        .await?;
+    let derived_key =
+        derived_keys.get_key_for_path(secret_path, derivation_path, derive_key_choice)?;
+    let (key_id, key) = derived_key_to_vector_encryption_key(derived_key)?;
+    if expected_key_id.is_some_and(|expected_key_id| expected_key_id != key_id) {
+        Err(AlloyError::InvalidKey {
+            kind: ErrorKind::DecryptionFailed,
+            msg: "The key ID in the paired ICL info and on the key derived for decryption did not match"
+                .to_string(),
+        })
+    } else {
+        Ok((key_id, key))
+    }
+}
+
+/// The parts of an encrypted dense or sparse vector that rotation needs.
+pub(crate) trait RotatableVector {
+    fn secret_path(&self) -> &SecretPath;
+    fn derivation_path(&self) -> &DerivationPath;
+    fn paired_icl_info(&self) -> &[u8];
+}
+
+impl RotatableVector for EncryptedVector {
+    fn secret_path(&self) -> &SecretPath {
+        &self.secret_path
+    }
+    fn derivation_path(&self) -> &DerivationPath {
+        &self.derivation_path
+    }
+    fn paired_icl_info(&self) -> &[u8] {
+        &self.paired_icl_info
+    }
+}
+
+impl RotatableVector for EncryptedSparseVector {
+    fn secret_path(&self) -> &SecretPath {
+        &self.secret_path
+    }
+    fn derivation_path(&self) -> &DerivationPath {
+        &self.derivation_path
+    }
+    fn paired_icl_info(&self) -> &[u8] {
+        &self.paired_icl_info
+    }
+}
+
+/// What the dense and sparse vector clients do differently when rotating vectors. Deriving the keys, skipping
+/// vectors that are already current and choosing the key to decrypt and encrypt with are shared.
+pub(crate) trait VectorRotation: AlloyClient {
+    type Encrypted: RotatableVector;
+    type Plaintext;
+
+    /// Whether `encrypted_vector` was encrypted with the approximation factor and options currently configured for
+    /// its secret path.
+    fn is_configured(
+        &self,
+        encrypted_vector: &Self::Encrypted,
+        vector_metadata: &VectorMetadata,
+    ) -> Result<bool, AlloyError>;
+
+    /// Decrypt a vector with the key it was encrypted with.
+    fn decrypt_with_key(
+        &self,
+        encrypted_vector: Self::Encrypted,
+        vector_metadata: VectorMetadata,
+        key: &VectorEncryptionKey,
+    ) -> Result<Self::Plaintext, AlloyError>;
+
+    /// Encrypt a vector with the current key of the tenant it's being rotated to.
+    fn encrypt_with_key(
+        &self,
+        plaintext_vector: Self::Plaintext,
+        key_id: KeyId,
+        key: &VectorEncryptionKey,
+    ) -> Result<Self::Encrypted, AlloyError>;
+}
+
+/// Derive the keys to rotate `encrypted_vectors` from the tenant in `metadata` to `new_tenant_id` (or the same
+/// tenant) and rotate them with `rotate_vectors_with_keys`.
+pub(crate) async fn rotate_vectors_internal<C: VectorRotation>(
+    client: &C,
+    tenant_security_client: &TenantSecurityClient,
+    encrypted_vectors: HashMap<VectorId, C::Encrypted>,
+    metadata: &AlloyMetadata,
+    new_tenant_id: Option<TenantId>,
+) -> Result<BatchResult<C::Encrypted>, AlloyError> {
+    let parsed_new_tenant_id = new_tenant_id.as_ref().unwrap_or(&metadata.tenant_id);
+    let paths = encrypted_vectors
+        .values()
+        .map(|vector| {
+            (
+                vector.secret_path().clone(),
+                vector.derivation_path().clone(),
+            )
+        })
+        .collect_vec();
+    let rotation_keys = get_keys_for_rotation(
+        metadata,
+        parsed_new_tenant_id,
+        paths,
+        tenant_security_client,
+        SecretType::Vector,
+    )
+    .await?;
+    Ok(rotate_vectors_with_keys(
+        client,
+        &rotation_keys,
+        encrypted_vectors,
+        metadata,
+        parsed_new_tenant_id,
+    ))
+}
+
+/// Re-encrypt each vector with the current key of `new_tenant_id`. Vectors that are already encrypted with that key
+/// and the configured approximation factor and options are returned unchanged.
+pub(crate) fn rotate_vectors_with_keys<C: VectorRotation>(
+    client: &C,
+    rotation_keys: &RotationKeys,
+    encrypted_vectors: HashMap<VectorId, C::Encrypted>,
+    metadata: &AlloyMetadata,
+    new_tenant_id: &TenantId,
+) -> BatchResult<C::Encrypted> {
+    let reencrypt_vector = |encrypted_vector: C::Encrypted| {
+        let (original_key_id, icl_metadata_bytes) =
+            C::decompose_key_id_header(encrypted_vector.paired_icl_info().to_vec())?;
+        let vector_metadata = get_vector_metadata(&icl_metadata_bytes)?;
+        let maybe_current_key_id = rotation_keys
+            .new_keys
+            .get_current(
+                encrypted_vector.secret_path(),
+                encrypted_vector.derivation_path(),
+            )
+            .map(|k| k.tenant_secret_id.0);
+        if check_rotation_no_op(
+            original_key_id,
+            &maybe_current_key_id,
+            new_tenant_id,
+            metadata,
+        ) && client.is_configured(&encrypted_vector, &vector_metadata)?
+        {
+            Ok(encrypted_vector)
+        } else {
+            let original_derived_key = rotation_keys.original_keys.get_key_for_path(
+                encrypted_vector.secret_path(),
+                encrypted_vector.derivation_path(),
+                DeriveKeyChoice::Specific(original_key_id),
+            )?;
+            let (_, original_vector_key) =
+                derived_key_to_vector_encryption_key(original_derived_key)?;
+            let new_derived_key = rotation_keys.new_keys.get_key_for_path(
+                encrypted_vector.secret_path(),
+                encrypted_vector.derivation_path(),
+                DeriveKeyChoice::Current,
+            )?;
+            let (new_key_id, new_vector_key) =
+                derived_key_to_vector_encryption_key(new_derived_key)?;
+            let decrypted_vector =
+                client.decrypt_with_key(encrypted_vector, vector_metadata, &original_vector_key)?;
+            client.encrypt_with_key(decrypted_vector, new_key_id, &new_vector_key)
+        }
+    };
+    collection_to_batch_result(encrypted_vectors, reencrypt_vector)
+}
+
 fn get_in_rotation_prefix_internal(
     derived_keys: &KeyDeriveResponse,
     secret_path: SecretPath,
@@ -243,11 +511,35 @@ fn get_in_rotation_prefix_internal(
 }
 
 #[cfg(test)]
-mod test {
+pub(crate) mod test {
     use super::*;
     use crate::tenant_security_client::TenantSecretAssignmentId;
     use base64_type::Base64;
-    use std::collections::HashMap;
+
+    /// Vector keys for the `secret`/`deriv` path with each of `key_ids`, as the TSP would derive them for one tenant.
+    /// Each key's bytes are its ID, so the same ID always gives the same key.
+    pub(crate) fn vector_keys(key_ids: &[u32], current_id: u32) -> KeyDeriveResponse {
+        let keys = key_ids
+            .iter()
+            .map(|id| DerivedKey {
+                derived_key: Base64(vec![*id as u8; 64]),
+                tenant_secret_id: TenantSecretAssignmentId(*id),
+                current: *id == current_id,
+            })
+            .collect();
+        KeyDeriveResponse {
+            has_primary_config: true,
+            derived_keys: [("secret".into(), [("deriv".into(), keys)].into())].into(),
+        }
+    }
+
+    /// Keys for rotating vectors within one tenant from any of `key_ids` to `current_id`.
+    pub(crate) fn vector_rotation_keys(key_ids: &[u32], current_id: u32) -> RotationKeys {
+        RotationKeys {
+            original_keys: vector_keys(key_ids, current_id),
+            new_keys: vector_keys(key_ids, current_id),
+        }
+    }
 
     // helper function to create the nested hashmaps. Groups by the secret path string and the derivation path string creating the
     // derivation keys inside as it goes.
@@ -394,6 +686,7 @@ mod test {
         assert_eq!(
             CustomEvent::create("_THIS_FAILS").unwrap_err(),
             AlloyError::InvalidInput {
+                kind: ErrorKind::Other,
                 msg: "CustomEvents must be screaming snake case and cannot start with _"
                     .to_string()
             }
@@ -402,6 +695,7 @@ mod test {
         assert_eq!(
             CustomEvent::create("thisAlso").unwrap_err(),
             AlloyError::InvalidInput {
+                kind: ErrorKind::Other,
                 msg: "CustomEvents must be screaming snake case and cannot start with _"
                     .to_string()
             }
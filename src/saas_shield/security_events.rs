@@ -0,0 +1,675 @@
+use crate::errors::{AlloyError, ErrorKind, KmsError, ServiceError, TenantSecurityProxyError};
+use crate::metrics;
+use crate::saas_shield::{DataEvent, SecurityEvent};
+use crate::tenant_security_client::{RequestMetadata, TenantSecurityClient};
+use crate::AlloyMetadata;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+use tokio::sync::{watch, Notify};
+
+/// Most events taken out of the buffer at once.
+const MAX_EVENTS_TAKEN: usize = 100;
+/// Most requests logging events to the TSP at the same time. The TSP takes one event per request.
+const MAX_CONCURRENT_SENDS: usize = 8;
+/// Longest wait between retries of an event.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Which security events SaaS Shield clients log to the tenant's log sink on their own, after standard, deterministic
+/// and vector operations. Events are sent in the background one request per event, a few at a time, and failing to
+/// send them never fails the operation. Events logged this way use the operation's metadata and the time the
+/// operation finished.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct AutomaticSecurityEvents {
+    /// Log `DataEvent::Encrypt` after each successful encrypt.
+    pub encrypt: bool,
+    /// Log `DataEvent::Decrypt` after each successful decrypt.
+    pub decrypt: bool,
+    /// Log `DataEvent::DenyAccess` when the TSP refuses an operation access to the tenant's keys.
+    pub deny_access: bool,
+}
+
+/// How security events are buffered before being sent to the TSP in the background.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct SecurityEventQueueOptions {
+    /// Most events held in memory waiting to be sent. Further events are spilled to `spill_path`, or dropped if there
+    /// isn't one.
+    pub capacity: u32,
+    /// Times to retry sending an event after a transient failure before dropping it.
+    pub max_retries: u32,
+    /// Wait before the first retry of an event. Doubles with each retry, up to 30 seconds.
+    pub initial_backoff_millis: u64,
+    /// File that events are appended to while the in-memory buffer is full. They're sent once there's room again,
+    /// including events left in the file by a previous process.
+    pub spill_path: Option<String>,
+}
+
+impl Default for SecurityEventQueueOptions {
+    fn default() -> Self {
+        SecurityEventQueueOptions {
+            capacity: 10_000,
+            max_retries: 5,
+            initial_backoff_millis: 100,
+            spill_path: None,
+        }
+    }
+}
+
+/// Counts of the security events that have gone through the background queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct SecurityEventQueueStats {
+    /// Events buffered in memory, spilled to disk or being sent.
+    pub pending: u64,
+    /// Events the TSP accepted.
+    pub sent: u64,
+    /// Events dropped because the queue was full or they couldn't be sent.
+    pub dropped: u64,
+    /// Events written to the spill file.
+    pub spilled: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct QueuedEvent {
+    event: String,
+    metadata: RequestMetadata,
+}
+
+#[derive(Debug, Default)]
+struct Buffer {
+    events: VecDeque<QueuedEvent>,
+    /// Events in the spill file.
+    spilled: usize,
+    /// Events taken off the buffer that are being sent.
+    in_flight: usize,
+    shutdown: bool,
+}
+
+impl Buffer {
+    fn pending(&self) -> usize {
+        self.events.len() + self.spilled + self.in_flight
+    }
+}
+
+/// State shared between the queue and its background thread.
+struct Shared {
+    options: SecurityEventQueueOptions,
+    tenant_security_client: Arc<TenantSecurityClient>,
+    buffer: Mutex<Buffer>,
+    wake: Notify,
+    pending: watch::Sender<usize>,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    spilled: AtomicU64,
+}
+
+/// Sends the security events of one `SaasShieldConfiguration` to the TSP. Events are queued without blocking and sent
+/// by a background thread, which is started with the first event and stops once the queue is dropped and empty.
+pub(crate) struct SecurityEventQueue {
+    policy: AutomaticSecurityEvents,
+    /// Whether `log_security_event` goes through the queue instead of calling the TSP directly.
+    queue_logged_events: bool,
+    shared: Arc<Shared>,
+    /// `false` if the background thread couldn't be started.
+    started: OnceLock<bool>,
+}
+
+impl SecurityEventQueue {
+    /// Create a queue for `policy`'s automatic events. If `queue_options` are provided, events logged with
+    /// `log_security_event` are queued too.
+    pub(crate) fn new(
+        policy: AutomaticSecurityEvents,
+        queue_options: Option<SecurityEventQueueOptions>,
+        tenant_security_client: Arc<TenantSecurityClient>,
+    ) -> Self {
+        let queue_logged_events = queue_options.is_some();
+        let options = queue_options.unwrap_or_default();
+        let leftover_spilled = options
+            .spill_path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .map(|contents| contents.lines().count())
+            .unwrap_or(0);
+        let queue = SecurityEventQueue {
+            policy,
+            queue_logged_events,
+            shared: Arc::new(Shared {
+                options,
+                tenant_security_client,
+                buffer: Mutex::new(Buffer {
+                    spilled: leftover_spilled,
+                    ..Default::default()
+                }),
+                wake: Notify::new(),
+                pending: watch::Sender::new(leftover_spilled),
+                sent: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+                spilled: AtomicU64::new(0),
+            }),
+            started: OnceLock::new(),
+        };
+        if leftover_spilled > 0 {
+            queue.start();
+        }
+        queue
+    }
+
+    /// Queue the event `policy` calls for after an operation that would log `event` if it succeeded, if any. The
+    /// operation's result is returned unchanged.
+    pub(crate) fn after_operation<T>(
+        &self,
+        event: DataEvent,
+        metadata: &AlloyMetadata,
+        result: Result<T, AlloyError>,
+    ) -> Result<T, AlloyError> {
+        let wanted = match (&result, &event) {
+            (Ok(_), DataEvent::Encrypt) => self.policy.encrypt,
+            (Ok(_), DataEvent::Decrypt) => self.policy.decrypt,
+            (Ok(_), _) => false,
+            (Err(e), _) => self.policy.deny_access && is_access_denied(e),
+        };
+        if wanted {
+            let event = if result.is_ok() {
+                event
+            } else {
+                DataEvent::DenyAccess
+            };
+            if let Ok(request_metadata) = (metadata.clone(), None).try_into() {
+                let _ = self.push(QueuedEvent {
+                    event: SecurityEvent::Data { event }.to_string(),
+                    metadata: request_metadata,
+                });
+            }
+        }
+        result
+    }
+
+    /// Log `event` to the tenant's log sink, through the queue if it was configured for logged events.
+    pub(crate) async fn log(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError> {
+        let request_metadata = (metadata.clone(), event_time_millis).try_into()?;
+        if self.queue_logged_events {
+            self.push(QueuedEvent {
+                event: event.to_string(),
+                metadata: request_metadata,
+            })
+        } else {
+            self.shared
+                .tenant_security_client
+                .log_security_event(&event, &request_metadata)
+                .await
+        }
+    }
+
+    pub(crate) fn stats(&self) -> SecurityEventQueueStats {
+        SecurityEventQueueStats {
+            pending: *self.shared.pending.borrow() as u64,
+            sent: self.shared.sent.load(Ordering::Relaxed),
+            dropped: self.shared.dropped.load(Ordering::Relaxed),
+            spilled: self.shared.spilled.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Wait until every queued event has been sent or dropped, failing if that takes longer than `timeout`.
+    pub(crate) async fn flush(&self, timeout: Duration) -> Result<(), AlloyError> {
+        let mut pending = self.shared.pending.subscribe();
+        if *pending.borrow() == 0 {
+            return Ok(());
+        }
+        self.start();
+        tokio::time::timeout(timeout, pending.wait_for(|pending| *pending == 0))
+            .await
+            .map(|_| ())
+            .map_err(|_| AlloyError::RequestError {
+                kind: ErrorKind::SecurityEventNotRecorded,
+                msg: format!(
+                    "Timed out flushing security events with {} still pending.",
+                    *self.shared.pending.borrow()
+                ),
+                source: None,
+            })
+    }
+
+    fn push(&self, queued: QueuedEvent) -> Result<(), AlloyError> {
+        if !self.start() {
+            self.shared.record_dropped(1);
+            return Err(AlloyError::RequestError {
+                kind: ErrorKind::SecurityEventNotRecorded,
+                msg: "The security event queue couldn't be started.".to_string(),
+                source: None,
+            });
+        }
+        let mut buffer = self.shared.lock_buffer();
+        let result = if buffer.events.len() < self.shared.options.capacity as usize {
+            buffer.events.push_back(queued);
+            Ok(())
+        } else {
+            self.shared.spill(&mut buffer, &queued)
+        };
+        if result.is_err() {
+            self.shared.record_dropped(1);
+        }
+        self.shared.pending.send_replace(buffer.pending());
+        drop(buffer);
+        self.shared.wake.notify_one();
+        result
+    }
+
+    /// Start the background thread if it isn't running yet, returning whether it's running. It uses its own runtime so
+    /// events keep flowing no matter which async runtime (if any) the caller uses.
+    fn start(&self) -> bool {
+        *self.started.get_or_init(|| {
+            let shared = self.shared.clone();
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .and_then(|runtime| {
+                    std::thread::Builder::new()
+                        .name("alloy-security-events".to_string())
+                        .spawn(move || runtime.block_on(shared.run()))
+                })
+                .is_ok()
+        })
+    }
+}
+
+impl Drop for SecurityEventQueue {
+    /// Let the background thread finish sending the queued events, then stop.
+    fn drop(&mut self) {
+        self.shared.lock_buffer().shutdown = true;
+        self.shared.wake.notify_one();
+    }
+}
+
+impl Shared {
+    fn lock_buffer(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record_dropped(&self, count: usize) {
+        self.dropped.fetch_add(count as u64, Ordering::Relaxed);
+        if let Some(recorder) = metrics::current_recorder() {
+            recorder.record_security_events_dropped(count);
+        }
+    }
+
+    async fn run(self: Arc<Self>) {
+        loop {
+            let events = {
+                let mut buffer = self.lock_buffer();
+                if buffer.events.is_empty() && buffer.spilled > 0 {
+                    self.unspill(&mut buffer);
+                    self.pending.send_replace(buffer.pending());
+                }
+                let count = buffer.events.len().min(MAX_EVENTS_TAKEN);
+                let events = buffer.events.drain(..count).collect::<Vec<_>>();
+                if events.is_empty() && buffer.shutdown {
+                    return;
+                }
+                buffer.in_flight += events.len();
+                events
+            };
+            if events.is_empty() {
+                self.wake.notified().await;
+                continue;
+            }
+            stream::iter(&events)
+                .for_each_concurrent(MAX_CONCURRENT_SENDS, |queued| self.send(queued))
+                .await;
+            let mut buffer = self.lock_buffer();
+            buffer.in_flight -= events.len();
+            self.pending.send_replace(buffer.pending());
+        }
+    }
+
+    /// Send one event, retrying transient failures with exponential backoff.
+    async fn send(&self, queued: &QueuedEvent) {
+        let mut backoff = Duration::from_millis(self.options.initial_backoff_millis);
+        let mut retries = 0;
+        loop {
+            match self
+                .tenant_security_client
+                .log_security_event_text(&queued.event, &queued.metadata)
+                .await
+            {
+                Ok(()) => {
+                    self.sent.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                Err(e) if retries < self.options.max_retries && e.is_retryable() => {
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    retries += 1;
+                }
+                Err(_) => {
+                    self.record_dropped(1);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn spill(&self, buffer: &mut Buffer, queued: &QueuedEvent) -> Result<(), AlloyError> {
+        let path = self
+            .options
+            .spill_path
+            .as_ref()
+            .ok_or_else(|| AlloyError::RequestError {
+                kind: ErrorKind::SecurityEventNotRecorded,
+                msg: "The security event queue is full.".to_string(),
+                source: None,
+            })?;
+        let mut line = serde_json::to_vec(queued)?;
+        line.push(b'\n');
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(&line))
+            .map_err(|e| AlloyError::RequestError {
+                kind: ErrorKind::SecurityEventNotRecorded,
+                msg: format!("Couldn't spill security event to `{path}`: {e}"),
+                source: None,
+            })?;
+        buffer.spilled += 1;
+        self.spilled.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Move as many spilled events as fit back into the in-memory buffer.
+    fn unspill(&self, buffer: &mut Buffer) {
+        let Some(path) = &self.options.spill_path else {
+            return;
+        };
+        let capacity = self.options.capacity.max(1) as usize;
+        let result = std::fs::read_to_string(path).and_then(|contents| {
+            let lines = contents.lines().collect::<Vec<_>>();
+            let (loaded, rest) = lines.split_at(lines.len().min(capacity));
+            std::fs::write(
+                path,
+                rest.iter()
+                    .flat_map(|line| [line, "\n"])
+                    .collect::<String>(),
+            )?;
+            Ok((
+                loaded
+                    .iter()
+                    .map(|line| serde_json::from_str(line))
+                    .collect::<Vec<_>>(),
+                rest.len(),
+            ))
+        });
+        match result {
+            Ok((loaded, rest)) => {
+                let unreadable = loaded.iter().filter(|event| event.is_err()).count();
+                buffer.events.extend(loaded.into_iter().flatten());
+                buffer.spilled = rest;
+                if unreadable > 0 {
+                    self.record_dropped(unreadable);
+                }
+            }
+            Err(_) => {
+                self.record_dropped(buffer.spilled);
+                buffer.spilled = 0;
+            }
+        }
+    }
+}
+
+/// Whether `error` means the TSP refused access to the tenant's keys, rather than something going wrong.
+fn is_access_denied(error: &AlloyError) -> bool {
+    matches!(
+        error,
+        AlloyError::TspError {
+            error: TenantSecurityProxyError::Service {
+                error: ServiceError::UnauthorizedRequest
+            } | TenantSecurityProxyError::Kms {
+                error: KmsError::UnknownTenantOrNoActiveKmsConfigurations
+                    | KmsError::KmsConfigurationDisabled
+                    | KmsError::KmsAuthorizationFailed
+            },
+            ..
+        }
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tenant_security_client::ApiKey;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::mpsc::Receiver;
+
+    /// Start a fake TSP that fails the first `failures` requests with a 503 and accepts the rest, returning its
+    /// address and the bodies of the requests it accepted.
+    fn fake_tsp(failures: usize) -> (String, Receiver<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for (i, stream) in listener.incoming().enumerate() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let response: &[u8] = if i < failures {
+                    b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                } else {
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\nConnection: close\r\n\r\nnull"
+                };
+                reader.get_mut().write_all(response).unwrap();
+                if i >= failures {
+                    let _ = sender.send(serde_json::from_slice(&body).unwrap());
+                }
+            }
+        });
+        (address, receiver)
+    }
+
+    fn queue(
+        address: String,
+        policy: AutomaticSecurityEvents,
+        options: Option<SecurityEventQueueOptions>,
+    ) -> SecurityEventQueue {
+        let tenant_security_client = TenantSecurityClient::new(
+            address,
+            ApiKey::try_from("0WUaXesNgbTAuLwn".to_string()).unwrap(),
+            reqwest::Client::new(),
+        );
+        SecurityEventQueue::new(policy, options, Arc::new(tenant_security_client))
+    }
+
+    fn login() -> SecurityEvent {
+        SecurityEvent::User {
+            event: crate::saas_shield::UserEvent::Login,
+        }
+    }
+
+    #[test]
+    fn events_follow_the_policy() {
+        let (address, requests) = fake_tsp(0);
+        let queue = queue(
+            address,
+            AutomaticSecurityEvents {
+                encrypt: false,
+                decrypt: true,
+                deny_access: true,
+            },
+            None,
+        );
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        let denied = AlloyError::TspError {
+            error: TenantSecurityProxyError::code_to_error(101),
+            http_code: 403,
+            tsp_code: 101,
+            msg: "Unauthorized".to_string(),
+        };
+
+        assert_eq!(
+            queue
+                .after_operation(DataEvent::Encrypt, &metadata, Ok(1))
+                .unwrap(),
+            1
+        );
+        queue
+            .after_operation(DataEvent::Decrypt, &metadata, Ok(()))
+            .unwrap();
+        assert!(queue
+            .after_operation::<()>(DataEvent::Decrypt, &metadata, Err(denied))
+            .is_err());
+        assert!(queue
+            .after_operation::<()>(
+                DataEvent::Decrypt,
+                &metadata,
+                Err(AlloyError::InvalidInput {
+                    kind: ErrorKind::Other,
+                    msg: "bad".to_string()
+                })
+            )
+            .is_err());
+
+        let mut events = (0..2)
+            .map(|_| requests.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect::<Vec<_>>();
+        events.sort_by_key(|request| request["event"].as_str().unwrap().to_string());
+        assert_eq!(events[0]["event"], "DATA_DECRYPT");
+        assert_eq!(events[1]["event"], "DATA_DENY_ACCESS");
+        assert_eq!(events[0]["tenantId"], "tenant");
+        assert!(requests.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    #[test]
+    fn nothing_is_sent_by_default() {
+        let queue = queue(
+            "http://127.0.0.1:1".to_string(),
+            AutomaticSecurityEvents::default(),
+            None,
+        );
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        queue
+            .after_operation(DataEvent::Encrypt, &metadata, Ok(()))
+            .unwrap();
+        assert!(queue.started.get().is_none());
+    }
+
+    #[tokio::test]
+    async fn logged_events_are_retried_and_flushed() {
+        let (address, requests) = fake_tsp(2);
+        let queue = queue(
+            address,
+            AutomaticSecurityEvents::default(),
+            Some(SecurityEventQueueOptions {
+                initial_backoff_millis: 1,
+                ..Default::default()
+            }),
+        );
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        queue.log(login(), &metadata, Some(5)).await.unwrap();
+        queue.flush(Duration::from_secs(10)).await.unwrap();
+
+        let request = requests.try_recv().unwrap();
+        assert_eq!(request["event"], "USER_LOGIN");
+        assert_eq!(request["timestampMillis"], 5);
+        assert_eq!(
+            queue.stats(),
+            SecurityEventQueueStats {
+                pending: 0,
+                sent: 1,
+                dropped: 0,
+                spilled: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn events_are_dropped_when_the_queue_is_full_or_retries_run_out() {
+        let queue = queue(
+            "http://127.0.0.1:1".to_string(),
+            AutomaticSecurityEvents::default(),
+            Some(SecurityEventQueueOptions {
+                capacity: 1,
+                max_retries: 1,
+                initial_backoff_millis: 1,
+                spill_path: None,
+            }),
+        );
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        let results = [
+            queue.log(login(), &metadata, None).await,
+            queue.log(login(), &metadata, None).await,
+        ];
+        // The second event may or may not find the buffer full, depending on whether the first was taken off yet.
+        assert!(results[0].is_ok());
+        queue.flush(Duration::from_secs(10)).await.unwrap();
+        let stats = queue.stats();
+        assert_eq!(stats.sent, 0);
+        assert_eq!(stats.dropped, 2);
+    }
+
+    #[tokio::test]
+    async fn spilled_events_are_sent_later() {
+        let path = std::env::temp_dir().join(format!(
+            "alloy-security-event-spill-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let options = SecurityEventQueueOptions {
+            spill_path: Some(path.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let spilling = queue(
+            "http://127.0.0.1:1".to_string(),
+            AutomaticSecurityEvents::default(),
+            Some(options.clone()),
+        );
+        let queued = QueuedEvent {
+            event: login().to_string(),
+            metadata: (
+                AlloyMetadata::new_simple("tenant".into()).as_ref().clone(),
+                None,
+            )
+                .try_into()
+                .unwrap(),
+        };
+        for _ in 0..2 {
+            let mut buffer = spilling.shared.lock_buffer();
+            spilling.shared.spill(&mut buffer, &queued).unwrap();
+        }
+        assert_eq!(spilling.stats().spilled, 2);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+        drop(spilling);
+
+        // A new queue picks up the spilled events and sends them.
+        let (address, requests) = fake_tsp(0);
+        let queue = queue(address, AutomaticSecurityEvents::default(), Some(options));
+        queue.flush(Duration::from_secs(10)).await.unwrap();
+        assert_eq!(queue.stats().sent, 2);
+        assert_eq!(requests.try_iter().count(), 2);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,415 @@
+use super::{
+    config::VectorApproximationFactors, derive_keys_many_paths, derive_vector_key,
+    derived_key_to_vector_encryption_key, get_in_rotation_prefix_internal, rotate_vectors_internal,
+    DataEvent, DeriveKeyChoice, SaasShieldSecurityEventOps, SecurityEvent, VectorRotation,
+};
+use crate::alloy_client_trait::AlloyClient;
+use crate::errors::{AlloyError, ErrorKind};
+use crate::saas_shield::security_events::SecurityEventQueue;
+use crate::telemetry::Operation;
+use crate::tenant_security_client::{DerivationType, SecretType, TenantSecurityClient};
+use crate::util::{get_rng, OurReseedingRng, ShardedRng};
+use crate::vector::sparse::{
+    decrypt_sparse_internal, encrypt_sparse_internal, EncryptedSparseVector,
+    EncryptedSparseVectors, GenerateSparseQueryResult, PlaintextSparseVector,
+    PlaintextSparseVectors, SparseVectorOps, SparseVectorRotateResult,
+};
+use crate::vector::{get_vector_metadata, VectorMetadata};
+use crate::{AlloyMetadata, DerivationPath, SecretPath, TenantId, VectorEncryptionKey};
+use ironcore_documents::v5::key_id_header::{EdekType, KeyId, PayloadType};
+use itertools::Itertools;
+use std::sync::Arc;
+
+/// Encrypts sparse embeddings using vector secrets from the TSP.
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
+pub struct SaasShieldSparseVectorClient {
+    approximation_factors: VectorApproximationFactors,
+    tenant_security_client: Arc<TenantSecurityClient>,
+    rng: Arc<ShardedRng<OurReseedingRng>>,
+    security_events: Arc<SecurityEventQueue>,
+}
+
+impl SaasShieldSparseVectorClient {
+    pub(crate) fn new(
+        client: Arc<TenantSecurityClient>,
+        approximation_factors: VectorApproximationFactors,
+        security_events: Arc<SecurityEventQueue>,
+    ) -> Self {
+        SaasShieldSparseVectorClient {
+            approximation_factors,
+            tenant_security_client: client.clone(),
+            rng: crate::util::create_reseeding_rng(),
+            security_events,
+        }
+    }
+}
+
+impl VectorRotation for SaasShieldSparseVectorClient {
+    type Encrypted = EncryptedSparseVector;
+    type Plaintext = PlaintextSparseVector;
+
+    /// Sparse vectors are always encrypted in `Scaled` mode without Matryoshka blocks, so only the approximation
+    /// factor can change.
+    fn is_configured(
+        &self,
+        encrypted_vector: &EncryptedSparseVector,
+        vector_metadata: &VectorMetadata,
+    ) -> Result<bool, AlloyError> {
+        let approximation_factor = self
+            .approximation_factors
+            .get(&encrypted_vector.secret_path)?;
+        Ok(vector_metadata.approximation_factor == Some(approximation_factor))
+    }
+
+    fn decrypt_with_key(
+        &self,
+        encrypted_vector: EncryptedSparseVector,
+        vector_metadata: VectorMetadata,
+        key: &VectorEncryptionKey,
+    ) -> Result<PlaintextSparseVector, AlloyError> {
+        // Sparse vectors always record their approximation factor.
+        let approximation_factor = vector_metadata.approximation_factor.map_or_else(
+            || {
+                self.approximation_factors
+                    .get(&encrypted_vector.secret_path)
+            },
+            Ok,
+        )?;
+        decrypt_sparse_internal(approximation_factor, key, encrypted_vector, vector_metadata)
+    }
+
+    fn encrypt_with_key(
+        &self,
+        plaintext_vector: PlaintextSparseVector,
+        key_id: KeyId,
+        key: &VectorEncryptionKey,
+    ) -> Result<EncryptedSparseVector, AlloyError> {
+        self.approximation_factors
+            .options(&plaintext_vector.secret_path)
+            .check_sparse(&plaintext_vector.secret_path)?;
+        encrypt_sparse_internal(
+            self.approximation_factors
+                .get(&plaintext_vector.secret_path)?,
+            key,
+            key_id,
+            Self::get_edek_type(),
+            plaintext_vector,
+            &mut *get_rng(&self.rng),
+        )
+    }
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
+impl SparseVectorOps for SaasShieldSparseVectorClient {
+    /// Encrypt a sparse embedding with the provided metadata. Indices are hidden with a keyed permutation and values
+    /// are encrypted like dense embedding values, using the approximation factor configured for the secret path.
+    /// The same tenant ID must be provided in the metadata when decrypting the embedding.
+    async fn encrypt(
+        &self,
+        plaintext_vector: PlaintextSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedSparseVector, AlloyError> {
+        let secret_path = plaintext_vector.secret_path.0.clone();
+        let result = Operation::new("sparse_vector.encrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .secret_path(&secret_path)
+            .run(async move {
+                let (key_id, key) = derive_vector_key(
+                    &self.tenant_security_client,
+                    metadata,
+                    &plaintext_vector.secret_path,
+                    &plaintext_vector.derivation_path,
+                    DeriveKeyChoice::Current,
+                )
+                .await?;
+                self.encrypt_with_key(plaintext_vector, key_id, &key)
+            })
+            .await;
+        self.security_events
+            .after_operation(DataEvent::Encrypt, metadata, result)
+    }
+
+    /// Decrypt a sparse embedding that was encrypted with the provided metadata. The result is sorted by index.
+    async fn decrypt(
+        &self,
+        encrypted_vector: EncryptedSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextSparseVector, AlloyError> {
+        let secret_path = encrypted_vector.secret_path.0.clone();
+        let result = Operation::new("sparse_vector.decrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .secret_path(&secret_path)
+            .run(async move {
+                let (key_id, icl_metadata_bytes) =
+                    Self::decompose_key_id_header(encrypted_vector.paired_icl_info.clone())?;
+                let vector_metadata = get_vector_metadata(&icl_metadata_bytes)?;
+                let (_, key) = derive_vector_key(
+                    &self.tenant_security_client,
+                    metadata,
+                    &encrypted_vector.secret_path,
+                    &encrypted_vector.derivation_path,
+                    DeriveKeyChoice::Specific(key_id),
+                )
+                .await?;
+                self.decrypt_with_key(encrypted_vector, vector_metadata, &key)
+            })
+            .await;
+        self.security_events
+            .after_operation(DataEvent::Decrypt, metadata, result)
+    }
+
+    /// Encrypt each sparse embedding with any Current and InRotation keys for the provided secret path.
+    /// The resulting encrypted vectors should be used in tandem when querying the vector database.
+    async fn generate_query_vectors(
+        &self,
+        vectors_to_query: PlaintextSparseVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<GenerateSparseQueryResult, AlloyError> {
+        Operation::new("sparse_vector.generate_query_vectors")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(vectors_to_query.len())
+            .run(async move {
+                let paths = vectors_to_query
+                    .values()
+                    .map(|vector| (vector.secret_path.clone(), vector.derivation_path.clone()))
+                    .collect_vec();
+                let all_keys = derive_keys_many_paths(
+                    &self.tenant_security_client,
+                    metadata,
+                    paths,
+                    SecretType::Vector,
+                )
+                .await?
+                .derived_keys;
+                vectors_to_query
+                    .into_iter()
+                    .map(|(vector_id, plaintext_vector)| {
+                        let keys = all_keys
+                            .get(&plaintext_vector.secret_path)
+                            .and_then(|deriv| deriv.get(&plaintext_vector.derivation_path))
+                            .ok_or(AlloyError::RequestError {
+                                kind: ErrorKind::InvalidTspResponse,
+                                msg: "Failed to derive keys for provided path using the TSP."
+                                    .to_string(),
+                                source: None,
+                            })?;
+                        keys.iter()
+                            .map(|derived_key| {
+                                let (key_id, key) =
+                                    derived_key_to_vector_encryption_key(derived_key)?;
+                                self.encrypt_with_key(plaintext_vector.clone(), key_id, &key)
+                            })
+                            .try_collect()
+                            .map(|enc| (vector_id, enc))
+                    })
+                    .collect()
+            })
+            .await
+    }
+
+    /// Get the byte prefix for the InRotation secret corresponding to this secret_path/derivation_path.
+    /// Pass the result to `encode_search_prefixes` to get the string prefixes to search an encoded datastore with.
+    async fn get_in_rotation_prefix(
+        &self,
+        secret_path: SecretPath,
+        derivation_path: DerivationPath,
+        metadata: &AlloyMetadata,
+    ) -> Result<Vec<u8>, AlloyError> {
+        let paths = [(secret_path.clone(), [derivation_path.clone()].into())].into();
+        let derived_keys = self
+            .tenant_security_client
+            .tenant_key_derive(
+                paths,
+                &metadata.clone().try_into()?,
+                DerivationType::Sha512,
+                SecretType::Vector,
+            )
+            .await?;
+        get_in_rotation_prefix_internal(
+            &derived_keys,
+            secret_path,
+            derivation_path,
+            Self::get_edek_type(),
+            Self::get_payload_type(),
+        )
+    }
+
+    /// Rotates sparse vectors from the in-rotation secret for their secret path to the current secret, or from one
+    /// tenant ID to a new one. Like dense vectors, they are also re-encrypted if their recorded approximation factor
+    /// doesn't match the configured one.
+    /// The same lossiness warning as dense vector rotation applies to the values.
+    async fn rotate_vectors(
+        &self,
+        encrypted_vectors: EncryptedSparseVectors,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<SparseVectorRotateResult, AlloyError> {
+        Operation::new("sparse_vector.rotate_vectors")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(encrypted_vectors.len())
+            .run(async move {
+                rotate_vectors_internal(
+                    self,
+                    &self.tenant_security_client,
+                    encrypted_vectors,
+                    metadata,
+                    new_tenant_id,
+                )
+                .await
+                .map(SparseVectorRotateResult::from)
+            })
+            .await
+    }
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
+impl SaasShieldSecurityEventOps for SaasShieldSparseVectorClient {
+    /// Log the security event `event` to the tenant's log sink.
+    /// If the event time is unspecified the current time will be used.
+    async fn log_security_event(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError> {
+        self.security_events
+            .log(event, metadata, event_time_millis)
+            .await
+    }
+}
+
+impl AlloyClient for SaasShieldSparseVectorClient {
+    fn get_edek_type() -> EdekType {
+        EdekType::SaasShield
+    }
+    fn get_payload_type() -> PayloadType {
+        PayloadType::VectorMetadata
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::saas_shield::rotate_vectors_with_keys;
+    use crate::saas_shield::test::{vector_keys, vector_rotation_keys};
+    use crate::SaasShieldConfiguration;
+    use approx::assert_abs_diff_eq;
+
+    fn get_client() -> SaasShieldSparseVectorClient {
+        let config = SaasShieldConfiguration::builder("http://localhost:7777", "0WUaXesNgbTAuLwn")
+            .default_approximation_factor(1.5)
+            .build()
+            .unwrap();
+        SaasShieldSparseVectorClient::new(
+            config.tenant_security_client,
+            config.approximation_factors,
+            config.security_events,
+        )
+    }
+
+    fn get_metadata() -> Arc<AlloyMetadata> {
+        AlloyMetadata::new_simple(TenantId("tenant".to_string()))
+    }
+
+    fn key(id: u32) -> (KeyId, VectorEncryptionKey) {
+        let derived_keys = vector_keys(&[id], id);
+        let derived_key = derived_keys
+            .get_key_for_path(
+                &"secret".into(),
+                &"deriv".into(),
+                DeriveKeyChoice::Specific(KeyId(id)),
+            )
+            .unwrap();
+        derived_key_to_vector_encryption_key(derived_key).unwrap()
+    }
+
+    fn encrypt_with_key_id(
+        client: &SaasShieldSparseVectorClient,
+        id: u32,
+    ) -> EncryptedSparseVector {
+        let (key_id, key) = key(id);
+        client
+            .encrypt_with_key(
+                PlaintextSparseVector {
+                    indices: vec![2045, 7, 30000],
+                    values: vec![0.5, 0.25, 0.75],
+                    secret_path: "secret".into(),
+                    derivation_path: "deriv".into(),
+                },
+                key_id,
+                &key,
+            )
+            .unwrap()
+    }
+
+    fn decrypt_with_key_id(
+        client: &SaasShieldSparseVectorClient,
+        id: u32,
+        encrypted: EncryptedSparseVector,
+    ) -> Result<PlaintextSparseVector, AlloyError> {
+        let (key_id, icl_metadata_bytes) = SaasShieldSparseVectorClient::decompose_key_id_header(
+            encrypted.paired_icl_info.clone(),
+        )?;
+        assert_eq!(key_id, KeyId(id));
+        let vector_metadata = get_vector_metadata(&icl_metadata_bytes)?;
+        client.decrypt_with_key(encrypted, vector_metadata, &key(id).1)
+    }
+
+    #[test]
+    fn rotate_moves_to_current_key() {
+        let client = get_client();
+        let encrypted = encrypt_with_key_id(&client, 1);
+        let mut result = rotate_vectors_with_keys(
+            &client,
+            &vector_rotation_keys(&[1, 2], 2),
+            [("vector".to_string(), encrypted)].into(),
+            &get_metadata(),
+            &get_metadata().tenant_id,
+        );
+        assert!(result.failures.is_empty());
+        let rotated = result.successes.remove("vector").unwrap();
+        let decrypted = decrypt_with_key_id(&client, 2, rotated).unwrap();
+        assert_eq!(decrypted.indices, vec![7, 2045, 30000]);
+        assert_abs_diff_eq!(
+            &decrypted.values[..],
+            &[0.25, 0.5, 0.75][..],
+            epsilon = 1e-4
+        );
+    }
+
+    #[test]
+    fn rotate_is_no_op_for_current_key() {
+        let client = get_client();
+        let encrypted = encrypt_with_key_id(&client, 2);
+        let mut result = rotate_vectors_with_keys(
+            &client,
+            &vector_rotation_keys(&[1, 2], 2),
+            [("vector".to_string(), encrypted.clone())].into(),
+            &get_metadata(),
+            &get_metadata().tenant_id,
+        );
+        let rotated = result.successes.remove("vector").unwrap();
+        assert_eq!(rotated.values, encrypted.values);
+        assert_eq!(rotated.paired_icl_info, encrypted.paired_icl_info);
+    }
+
+    #[test]
+    fn rejects_wrong_indices_hash() {
+        let client = get_client();
+        let mut encrypted = encrypt_with_key_id(&client, 1);
+        encrypted.indices[0] ^= 1;
+        let err = decrypt_with_key_id(&client, 1, encrypted.clone()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AuthHashMismatch);
+        let result = rotate_vectors_with_keys(
+            &client,
+            &vector_rotation_keys(&[1, 2], 2),
+            [("vector".to_string(), encrypted)].into(),
+            &get_metadata(),
+            &get_metadata().tenant_id,
+        );
+        assert_eq!(
+            result.failures.get("vector").map(AlloyError::kind),
+            Some(ErrorKind::AuthHashMismatch)
+        );
+    }
+}
@@ -1,13 +1,15 @@
-use crate::errors::AlloyError;
+use crate::errors::{AlloyError, ErrorKind};
+use crate::saas_shield::security_events::SecurityEventQueue;
 use crate::standard::{
     decrypt_document_core, encrypt_document_core, encrypt_map, verify_sig, EdekWithKeyIdHeader,
     EncryptedDocument, PlaintextDocument, PlaintextDocumentWithEdek, RekeyEdeksBatchResult,
     StandardDocumentOps,
 };
+use crate::telemetry::{record_key_id, Operation};
 use crate::tenant_security_client::{
     RequestMetadata, TenantSecurityClient, UnwrapKeyResponse, WrapKeyResponse,
 };
-use crate::util::{collection_to_batch_result, v4_proto_from_bytes, OurReseedingRng};
+use crate::util::{collection_to_batch_result, v4_proto_from_bytes, OurReseedingRng, ShardedRng};
 use crate::TenantId;
 use crate::{alloy_client_trait::AlloyClient, AlloyMetadata};
 use bytes::Bytes;
@@ -24,14 +26,15 @@ use protobuf::Message;
 use rand::{CryptoRng, RngCore};
 use std::collections::HashMap;
 use std::convert::identity;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
-use super::{SaasShieldSecurityEventOps, SecurityEvent};
+use super::{DataEvent, SaasShieldSecurityEventOps, SecurityEvent};
 
-#[derive(uniffi::Object)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct SaasShieldStandardClient {
     tenant_security_client: Arc<TenantSecurityClient>,
-    rng: Arc<Mutex<OurReseedingRng>>,
+    rng: Arc<ShardedRng<OurReseedingRng>>,
+    security_events: Arc<SecurityEventQueue>,
 }
 
 // Standard SaaS Shield edeks could be V3 if they originated in old TSCs or V4 if they originated from Cloaked Search.
@@ -73,15 +76,19 @@ impl EdekParts {
 }
 
 impl SaasShieldStandardClient {
-    pub(crate) fn new(tenant_security_client: Arc<TenantSecurityClient>) -> Self {
+    pub(crate) fn new(
+        tenant_security_client: Arc<TenantSecurityClient>,
+        security_events: Arc<SecurityEventQueue>,
+    ) -> Self {
         SaasShieldStandardClient {
             tenant_security_client,
             rng: crate::util::create_reseeding_rng(),
+            security_events,
         }
     }
 
     fn encrypt_document<R: RngCore + CryptoRng>(
-        rng: Arc<Mutex<R>>,
+        rng: Arc<ShardedRng<R>>,
         tsc_edek: Vec<u8>,
         dek: EncryptionKey,
         tenant_id: TenantId,
@@ -151,12 +158,12 @@ impl SaasShieldStandardClient {
                 let expected_edek_type = Self::get_edek_type();
                 let expected_payload_type = Self::get_payload_type();
                 if edek_type == expected_edek_type && payload_type == expected_payload_type {
+                    record_key_id(key_id.0);
                     let v4_document_header = v4_proto_from_bytes(remaining_bytes)?;
                     Ok(EdekParts::V5(key_id, v4_document_header))
                 } else {
-                    Err(AlloyError::InvalidInput{ msg:
-                format!("The data indicated that this was not a {expected_edek_type} {expected_payload_type} wrapped value. Found: {edek_type}, {payload_type}"),
-                })
+                    Err(AlloyError::InvalidInput{ kind: ErrorKind::HeaderTypeMismatch, msg:
+                format!("The data indicated that this was not a {expected_edek_type} {expected_payload_type} wrapped value. Found: {edek_type}, {payload_type}"), })
                 }
             }
             // This is the case where the value did not have a key id header. This means it's either a v4 or v3.
@@ -181,7 +188,7 @@ impl AlloyClient for SaasShieldStandardClient {
     }
 }
 
-#[uniffi::export(async_runtime = "tokio")]
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
 impl StandardDocumentOps for SaasShieldStandardClient {
     /// Encrypt a document with the provided metadata. The document must be a map from field identifiers to plaintext
     /// bytes, and the same metadata must be provided when decrypting the document.
@@ -197,22 +204,29 @@ impl StandardDocumentOps for SaasShieldStandardClient {
         plaintext_document: PlaintextDocument,
         metadata: &AlloyMetadata,
     ) -> Result<EncryptedDocument, AlloyError> {
-        let request_metadata = metadata.clone().try_into()?;
-        let WrapKeyResponse {
-            dek,
-            edek: tsc_edek,
-        } = self
-            .tenant_security_client
-            .wrap_key(&request_metadata)
-            .await?;
-        let enc_key = tsc_dek_to_encryption_key(dek.0)?;
-        Self::encrypt_document(
-            self.rng.clone(),
-            tsc_edek.0,
-            enc_key,
-            metadata.tenant_id.clone(),
-            plaintext_document,
-        )
+        let result = Operation::new("standard.encrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .run(async move {
+                let request_metadata = metadata.clone().try_into()?;
+                let WrapKeyResponse {
+                    dek,
+                    edek: tsc_edek,
+                } = self
+                    .tenant_security_client
+                    .wrap_key(&request_metadata)
+                    .await?;
+                let enc_key = tsc_dek_to_encryption_key(dek.0)?;
+                Self::encrypt_document(
+                    self.rng.clone(),
+                    tsc_edek.0,
+                    enc_key,
+                    metadata.tenant_id.clone(),
+                    plaintext_document,
+                )
+            })
+            .await;
+        self.security_events
+            .after_operation(DataEvent::Encrypt, metadata, result)
     }
 
     /// Decrypt a document that was encrypted with the provided metadata. The document must have been encrypted with one
@@ -223,16 +237,23 @@ impl StandardDocumentOps for SaasShieldStandardClient {
         encrypted_document: EncryptedDocument,
         metadata: &AlloyMetadata,
     ) -> Result<PlaintextDocument, AlloyError> {
-        let request_metadata = metadata.clone().try_into()?;
-        let edek_parts = Self::decompose_edek_header(encrypted_document.edek)?;
-        let edek = edek_parts.get_edek_bytes()?;
-        let UnwrapKeyResponse { dek } = self
-            .tenant_security_client
-            .unwrap_key(edek, &request_metadata)
-            .await?;
-        let enc_key = tsc_dek_to_encryption_key(dek.0)?;
-        edek_parts.validate_signature(enc_key)?;
-        decrypt_document_core(encrypted_document.document, enc_key)
+        let result = Operation::new("standard.decrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .run(async move {
+                let request_metadata = metadata.clone().try_into()?;
+                let edek_parts = Self::decompose_edek_header(encrypted_document.edek)?;
+                let edek = edek_parts.get_edek_bytes()?;
+                let UnwrapKeyResponse { dek } = self
+                    .tenant_security_client
+                    .unwrap_key(edek, &request_metadata)
+                    .await?;
+                let enc_key = tsc_dek_to_encryption_key(dek.0)?;
+                edek_parts.validate_signature(enc_key)?;
+                decrypt_document_core(encrypted_document.document, enc_key)
+            })
+            .await;
+        self.security_events
+            .after_operation(DataEvent::Decrypt, metadata, result)
     }
 
     /// Decrypt the provided EDEKs and re-encrypt them using the tenant's current key. If `new_tenant_id` is `None`,
@@ -244,14 +265,20 @@ impl StandardDocumentOps for SaasShieldStandardClient {
         metadata: &AlloyMetadata,
         new_tenant_id: Option<TenantId>,
     ) -> Result<RekeyEdeksBatchResult, AlloyError> {
-        let parsed_new_tenant_id = new_tenant_id.as_ref().unwrap_or(&metadata.tenant_id);
-        let request_metadata = metadata.clone().try_into()?;
-        let tsp_responses = join_all(edeks.into_iter().map(|(id, edek)| {
-            self.rekey_edek_core(edek, parsed_new_tenant_id, &request_metadata)
-                .map(|res| (id, res))
-        }))
-        .await;
-        Ok(collection_to_batch_result(tsp_responses, identity).into())
+        Operation::new("standard.rekey_edeks")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(edeks.len())
+            .run(async move {
+                let parsed_new_tenant_id = new_tenant_id.as_ref().unwrap_or(&metadata.tenant_id);
+                let request_metadata = metadata.clone().try_into()?;
+                let tsp_responses = join_all(edeks.into_iter().map(|(id, edek)| {
+                    self.rekey_edek_core(edek, parsed_new_tenant_id, &request_metadata)
+                        .map(|res| (id, res))
+                }))
+                .await;
+                Ok(collection_to_batch_result(tsp_responses, identity).into())
+            })
+            .await
     }
 
     /// Encrypt a document with the provided metadata. The document must be a map from field identifiers to plaintext
@@ -263,23 +290,30 @@ impl StandardDocumentOps for SaasShieldStandardClient {
         plaintext_document: PlaintextDocumentWithEdek,
         metadata: &AlloyMetadata,
     ) -> Result<EncryptedDocument, AlloyError> {
-        let request_metadata = metadata.clone().try_into()?;
-        let edek_parts = Self::decompose_edek_header(plaintext_document.edek.clone())?;
-        let edek = edek_parts.get_edek_bytes()?;
-        let UnwrapKeyResponse { dek } = self
-            .tenant_security_client
-            .unwrap_key(edek, &request_metadata)
-            .await?;
-        let enc_key = tsc_dek_to_encryption_key(dek.0)?;
-        edek_parts.validate_signature(enc_key)?;
-        Ok(EncryptedDocument {
-            document: encrypt_map(plaintext_document.document, self.rng.clone(), enc_key)?,
-            edek: plaintext_document.edek,
-        })
+        let result = Operation::new("standard.encrypt_with_existing_edek")
+            .tenant_id(&metadata.tenant_id.0)
+            .run(async move {
+                let request_metadata = metadata.clone().try_into()?;
+                let edek_parts = Self::decompose_edek_header(plaintext_document.edek.clone())?;
+                let edek = edek_parts.get_edek_bytes()?;
+                let UnwrapKeyResponse { dek } = self
+                    .tenant_security_client
+                    .unwrap_key(edek, &request_metadata)
+                    .await?;
+                let enc_key = tsc_dek_to_encryption_key(dek.0)?;
+                edek_parts.validate_signature(enc_key)?;
+                Ok(EncryptedDocument {
+                    document: encrypt_map(plaintext_document.document, self.rng.clone(), enc_key)?,
+                    edek: plaintext_document.edek,
+                })
+            })
+            .await;
+        self.security_events
+            .after_operation(DataEvent::Encrypt, metadata, result)
     }
 }
 
-#[uniffi::export(async_runtime = "tokio")]
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
 impl SaasShieldSecurityEventOps for SaasShieldStandardClient {
     /// Log the security event `event` to the tenant's log sink.
     /// If the event time is unspecified the current time will be used.
@@ -289,9 +323,8 @@ impl SaasShieldSecurityEventOps for SaasShieldStandardClient {
         metadata: &AlloyMetadata,
         event_time_millis: Option<i64>,
     ) -> Result<(), AlloyError> {
-        let request_metadata = (metadata.clone(), event_time_millis).try_into()?;
-        self.tenant_security_client
-            .log_security_event(&event, &request_metadata)
+        self.security_events
+            .log(event, metadata, event_time_millis)
             .await
     }
 }
@@ -301,6 +334,7 @@ fn find_cmk_edek(edeks: &[EdekWrapper]) -> Result<&EncryptedDek, AlloyError> {
     let cmk_edek = maybe_edek_wrapper
         .map(|edek| edek.cmk_edek())
         .ok_or_else(|| AlloyError::DecryptError {
+            kind: ErrorKind::MalformedHeader,
             msg: "No Saas Shield EDEK found.".to_string(),
         })?;
     Ok(cmk_edek)
@@ -337,6 +371,7 @@ fn fix_encrypted_dek(
 
 fn tsc_dek_to_encryption_key(dek: Vec<u8>) -> Result<EncryptionKey, AlloyError> {
     let bytes: [u8; 32] = dek.try_into().map_err(|_| AlloyError::InvalidKey {
+        kind: ErrorKind::Other,
         msg: "Invalid DEK".to_string(),
     })?;
     Ok(EncryptionKey(bytes))
@@ -394,7 +429,7 @@ mod test {
 
         assert!(matches!(
             SaasShieldStandardClient::decompose_edek_header(encrypted_doc.edek).unwrap_err(),
-            AlloyError::InvalidInput { msg: _ }
+            AlloyError::InvalidInput { .. }
         ));
     }
 
@@ -2,19 +2,21 @@ use crate::{
     errors::AlloyError,
     standard::StandardDocumentOps,
     standard_attached::{
-        decrypt_core, encrypt_core, EncryptedAttachedDocument, StandardAttachedDocumentOps,
+        decrypt_core, encrypt_core, encrypt_with_existing_edek_core, rekey_documents_core,
+        EncryptedAttachedDocument, RekeyAttachedDocumentsBatchResult, StandardAttachedDocumentOps,
     },
-    AlloyMetadata, PlaintextBytes,
+    AlloyMetadata, PlaintextBytes, TenantId,
 };
+use std::collections::HashMap;
 
 use super::{standard::SaasShieldStandardClient, SaasShieldSecurityEventOps, SecurityEvent};
 
-#[derive(uniffi::Object)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct SaasShieldStandardAttachedClient {
     standard_client: SaasShieldStandardClient,
 }
 
-#[uniffi::export(async_runtime = "tokio")]
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
 impl StandardAttachedDocumentOps for SaasShieldStandardAttachedClient {
     /// Encrypt a field with the provided metadata.
     /// A DEK (document encryption key) will be generated and encrypted using a derived key.
@@ -37,18 +39,54 @@ impl StandardAttachedDocumentOps for SaasShieldStandardAttachedClient {
         decrypt_core(&self.standard_client, attached_field, metadata).await
     }
 
+    /// Encrypt a field with the provided metadata, reusing the EDEK from `existing_document`.
+    /// The existing EDEK will be decrypted and used to encrypt the new field, so no new DEK is generated. This is
+    /// useful when updating a document, as the result keeps the same key ID and EDEK as the original.
+    async fn encrypt_with_existing_edek(
+        &self,
+        plaintext_field: PlaintextBytes,
+        existing_document: EncryptedAttachedDocument,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedAttachedDocument, AlloyError> {
+        encrypt_with_existing_edek_core(
+            &self.standard_client,
+            plaintext_field,
+            existing_document,
+            metadata,
+        )
+        .await
+    }
+
     /// Generate a prefix that could used to search a data store for documents encrypted using an identifier (KMS
     /// config id for SaaS Shield, secret id for Standalone). These bytes should be encoded into
-    /// a format matching the encoding in the data store. z85/ascii85 users should first pass these bytes through
-    /// `encode_prefix_z85` or `base85_prefix_padding`. Make sure you've read the documentation of those functions to
-    /// avoid pitfalls when encoding across byte boundaries.
+    /// a format matching the encoding in the data store. `encode_search_prefixes` does this for the common
+    /// encodings, including the z85/ascii85 pitfalls when encoding across byte boundaries.
     /// Note that this will not work for matching values that don't use our key_id_header format, such as cloaked search.
     async fn get_searchable_edek_prefix(&self, id: i32) -> Vec<u8> {
         self.standard_client.get_searchable_edek_prefix(id)
     }
+
+    /// Decrypt each EDEK on the front of the attached documents and re-encrypt it to the current secret or a new
+    /// tenant without touching the encrypted payloads. `metadata` must contain the tenant ID that the documents were
+    /// originally encrypted to. If `new_tenant_id` is empty, the EDEKs will be rekeyed to the same tenant's current
+    /// secret. Legacy V4 attached documents will be converted to the V5 attached layout.
+    async fn rekey_documents(
+        &self,
+        encrypted_documents: HashMap<String, EncryptedAttachedDocument>,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<RekeyAttachedDocumentsBatchResult, AlloyError> {
+        rekey_documents_core(
+            &self.standard_client,
+            encrypted_documents,
+            metadata,
+            new_tenant_id,
+        )
+        .await
+    }
 }
 
-#[uniffi::export(async_runtime = "tokio")]
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
 impl SaasShieldSecurityEventOps for SaasShieldStandardAttachedClient {
     /// Log the security event `event` to the tenant's log sink.
     /// If the event time is unspecified the current time will be used.
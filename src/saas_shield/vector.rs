@@ -1,65 +1,90 @@
 use super::{
-    derive_keys_many_paths, derived_key_to_vector_encryption_key, get_in_rotation_prefix_internal,
-    get_keys_for_rotation, DeriveKeyChoice, RotationKeys, SaasShieldSecurityEventOps,
-    SecurityEvent,
+    config::VectorApproximationFactors, derive_keys_many_paths, derive_vector_key,
+    derived_key_to_vector_encryption_key, get_in_rotation_prefix_internal, rotate_to_new_tenants,
+    rotate_vectors_internal, rotate_vectors_with_keys, DataEvent, DeriveKeyChoice,
+    SaasShieldSecurityEventOps, SecurityEvent, VectorRotation,
 };
 use crate::alloy_client_trait::AlloyClient;
-use crate::errors::AlloyError;
+use crate::errors::{AlloyError, ErrorKind};
+use crate::saas_shield::security_events::SecurityEventQueue;
+use crate::telemetry::Operation;
 use crate::tenant_security_client::{DerivationType, SecretType, TenantSecurityClient};
-use crate::util::{check_rotation_no_op, collection_to_batch_result, get_rng, OurReseedingRng};
+use crate::util::{
+    get_rng, parallel_collection_to_batch_result, rotate_grouped_by_tenant, OurReseedingRng,
+    ShardedRng,
+};
+use crate::vector::precision::{EncryptedTypedVector, PlaintextTypedVector, VectorPrecision};
 use crate::vector::{
-    decrypt_internal, encrypt_internal, EncryptedVector, EncryptedVectors, GenerateQueryResult,
-    PlaintextVector, PlaintextVectors, VectorOps, VectorRotateResult,
+    decrypt_internal, encrypt_internal, get_vector_metadata, EncryptedVector, EncryptedVectors,
+    GenerateQueryResult, PlaintextVector, PlaintextVectors, TenantEncryptedVectors,
+    VectorEncryptBatchResult, VectorMetadata, VectorOps, VectorRotateResult,
 };
 use crate::{AlloyMetadata, DerivationPath, SecretPath, TenantId, VectorEncryptionKey};
 use ironcore_documents::v5::key_id_header::{EdekType, KeyId, PayloadType};
 use itertools::Itertools;
-use std::sync::{Arc, Mutex};
+use rand::{CryptoRng, RngCore};
+use std::sync::Arc;
 
-#[derive(uniffi::Object)]
+#[derive(Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct SaasShieldVectorClient {
-    approximation_factor: Option<f32>,
+    approximation_factors: VectorApproximationFactors,
     tenant_security_client: Arc<TenantSecurityClient>,
-    rng: Arc<Mutex<OurReseedingRng>>,
+    rng: Arc<ShardedRng<OurReseedingRng>>,
+    security_events: Arc<SecurityEventQueue>,
 }
 
 impl SaasShieldVectorClient {
     pub(crate) fn new(
         client: Arc<TenantSecurityClient>,
-        approximation_factor: Option<f32>,
+        approximation_factors: VectorApproximationFactors,
+        security_events: Arc<SecurityEventQueue>,
     ) -> Self {
         SaasShieldVectorClient {
-            approximation_factor,
+            approximation_factors,
             tenant_security_client: client.clone(),
             rng: crate::util::create_reseeding_rng(),
+            security_events,
         }
     }
 
-    /// Encrypts a plaintext vector with the provided key/ID
-    fn encrypt_core(
+    /// The approximation factor recorded in the vector metadata, or the configured one for metadata that predates
+    /// recording it.
+    fn get_decrypt_approximation_factor(
+        &self,
+        vector_metadata: &VectorMetadata,
+        secret_path: &SecretPath,
+    ) -> Result<f32, AlloyError> {
+        vector_metadata
+            .approximation_factor
+            .map_or_else(|| self.approximation_factors.get(secret_path), Ok)
+    }
+
+    /// Validates a plaintext vector and encrypts it with the provided key/ID
+    fn encrypt_core<R: RngCore + CryptoRng>(
         &self,
         key: &VectorEncryptionKey,
         key_id: KeyId,
         plaintext_vector: PlaintextVector,
+        rng: &mut R,
     ) -> Result<EncryptedVector, AlloyError> {
-        let approximation_factor =
-            self.approximation_factor
-                .ok_or_else(|| AlloyError::InvalidConfiguration {
-                    msg: "`approximation_factor` was not set in the vector configuration."
-                        .to_string(),
-                })?;
+        let plaintext_vector = self
+            .approximation_factors
+            .options(&plaintext_vector.secret_path)
+            .prepare_vector(plaintext_vector)?;
         encrypt_internal(
-            approximation_factor,
+            self.approximation_factors
+                .params(&plaintext_vector.secret_path)?,
             key,
             key_id,
             Self::get_edek_type(),
             plaintext_vector,
-            &mut *get_rng(&self.rng),
+            rng,
         )
     }
 }
 
-#[uniffi::export(async_runtime = "tokio")]
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
 impl SaasShieldSecurityEventOps for SaasShieldVectorClient {
     /// Log the security event `event` to the tenant's log sink.
     /// If the event time is unspecified the current time will be used.
@@ -69,9 +94,8 @@ impl SaasShieldSecurityEventOps for SaasShieldVectorClient {
         metadata: &AlloyMetadata,
         event_time_millis: Option<i64>,
     ) -> Result<(), AlloyError> {
-        let request_metadata = (metadata.clone(), event_time_millis).try_into()?;
-        self.tenant_security_client
-            .log_security_event(&event, &request_metadata)
+        self.security_events
+            .log(event, metadata, event_time_millis)
             .await
     }
 }
@@ -85,37 +109,123 @@ impl AlloyClient for SaasShieldVectorClient {
     }
 }
 
-#[uniffi::export(async_runtime = "tokio")]
+impl VectorRotation for SaasShieldVectorClient {
+    type Encrypted = EncryptedVector;
+    type Plaintext = PlaintextVector;
+
+    fn is_configured(
+        &self,
+        encrypted_vector: &EncryptedVector,
+        vector_metadata: &VectorMetadata,
+    ) -> Result<bool, AlloyError> {
+        let params = self
+            .approximation_factors
+            .params(&encrypted_vector.secret_path)?;
+        Ok(vector_metadata.was_encrypted_with(&params))
+    }
+
+    fn decrypt_with_key(
+        &self,
+        encrypted_vector: EncryptedVector,
+        vector_metadata: VectorMetadata,
+        key: &VectorEncryptionKey,
+    ) -> Result<PlaintextVector, AlloyError> {
+        self.approximation_factors
+            .options(&encrypted_vector.secret_path)
+            .check_encrypted(&encrypted_vector, &vector_metadata)?;
+        let approximation_factor =
+            self.get_decrypt_approximation_factor(&vector_metadata, &encrypted_vector.secret_path)?;
+        decrypt_internal(approximation_factor, key, encrypted_vector, vector_metadata)
+    }
+
+    fn encrypt_with_key(
+        &self,
+        plaintext_vector: PlaintextVector,
+        key_id: KeyId,
+        key: &VectorEncryptionKey,
+    ) -> Result<EncryptedVector, AlloyError> {
+        self.encrypt_core(key, key_id, plaintext_vector, &mut *get_rng(&self.rng))
+    }
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
 impl VectorOps for SaasShieldVectorClient {
     /// Encrypt a vector embedding with the provided metadata. The provided embedding is assumed to be normalized
-    /// and its values will be shuffled as part of the encryption.
+    /// and its values will be shuffled as part of the encryption. It is first checked and preprocessed according to
+    /// the `VectorValidation` configured for its secret path, and rejected with `InvalidInput` if that fails.
     /// The same tenant ID must be provided in the metadata when decrypting the embedding.
     async fn encrypt(
         &self,
         plaintext_vector: PlaintextVector,
         metadata: &AlloyMetadata,
     ) -> Result<EncryptedVector, AlloyError> {
-        let paths = [(
-            plaintext_vector.secret_path.clone(),
-            [plaintext_vector.derivation_path.clone()].into(),
-        )]
-        .into();
-        let derived_keys = self
-            .tenant_security_client
-            .tenant_key_derive(
-                paths,
-                &metadata.clone().try_into()?,
-                DerivationType::Sha512,
-                SecretType::Vector,
-            )
-            .await?;
-        let derived_key = derived_keys.get_key_for_path(
-            &plaintext_vector.secret_path,
-            &plaintext_vector.derivation_path,
-            DeriveKeyChoice::Current,
-        )?;
-        let (key_id, key) = derived_key_to_vector_encryption_key(derived_key)?;
-        self.encrypt_core(&key, key_id, plaintext_vector)
+        let secret_path = plaintext_vector.secret_path.0.clone();
+        let result = Operation::new("vector.encrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .secret_path(&secret_path)
+            .run(async move {
+                let (key_id, key) = derive_vector_key(
+                    &self.tenant_security_client,
+                    metadata,
+                    &plaintext_vector.secret_path,
+                    &plaintext_vector.derivation_path,
+                    DeriveKeyChoice::Current,
+                )
+                .await?;
+                self.encrypt_core(&key, key_id, plaintext_vector, &mut *get_rng(&self.rng))
+            })
+            .await;
+        self.security_events
+            .after_operation(DataEvent::Encrypt, metadata, result)
+    }
+
+    /// Encrypt a batch of vector embeddings with the provided metadata. Vectors are encrypted in parallel on a rayon
+    /// thread pool owned by this crate, separate from rayon's global pool and any pool the caller runs on. That makes
+    /// this much faster than calling `encrypt` in a loop for large batches or high-dimensional embeddings. The pool
+    /// has a thread per core unless `RAYON_NUM_THREADS` says otherwise. The calling task waits for the pool without
+    /// blocking its executor thread, but a large batch keeps every pool thread busy until it's done, which competes
+    /// with other CPU work in the process.
+    /// Vectors that fail to encrypt are returned in `failures` keyed by their ID.
+    async fn encrypt_batch(
+        &self,
+        plaintext_vectors: PlaintextVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorEncryptBatchResult, AlloyError> {
+        let result = Operation::new("vector.encrypt_batch")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(plaintext_vectors.len())
+            .run(async move {
+                let paths = plaintext_vectors
+                    .values()
+                    .map(|vector| (vector.secret_path.clone(), vector.derivation_path.clone()))
+                    .collect_vec();
+                let derived_keys = derive_keys_many_paths(
+                    &self.tenant_security_client,
+                    metadata,
+                    paths,
+                    SecretType::Vector,
+                )
+                .await?;
+                let client = self.clone();
+                Ok(parallel_collection_to_batch_result(
+                    plaintext_vectors,
+                    self.rng.clone(),
+                    move |vector, rng| {
+                        let derived_key = derived_keys.get_key_for_path(
+                            &vector.secret_path,
+                            &vector.derivation_path,
+                            DeriveKeyChoice::Current,
+                        )?;
+                        let (key_id, key) = derived_key_to_vector_encryption_key(derived_key)?;
+                        client.encrypt_core(&key, key_id, vector, rng)
+                    },
+                )
+                .await
+                .into())
+            })
+            .await;
+        self.security_events
+            .after_operation(DataEvent::Encrypt, metadata, result)
     }
 
     /// Decrypt a vector embedding that was encrypted with the provided metadata. The values of the embedding will
@@ -125,48 +235,52 @@ impl VectorOps for SaasShieldVectorClient {
         encrypted_vector: EncryptedVector,
         metadata: &AlloyMetadata,
     ) -> Result<PlaintextVector, AlloyError> {
-        let approximation_factor =
-            self.approximation_factor
-                .ok_or_else(|| AlloyError::InvalidConfiguration {
-                    msg: "`approximation_factor` was not set in the vector configuration."
-                        .to_string(),
-                })?;
-        let (key_id, icl_metadata_bytes) =
-            Self::decompose_key_id_header(encrypted_vector.paired_icl_info.clone())?;
+        let secret_path = encrypted_vector.secret_path.0.clone();
+        let result = Operation::new("vector.decrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .secret_path(&secret_path)
+            .run(async move {
+                let (key_id, icl_metadata_bytes) =
+                    Self::decompose_key_id_header(encrypted_vector.paired_icl_info.clone())?;
+                let vector_metadata = get_vector_metadata(&icl_metadata_bytes)?;
+                let (_, key) = derive_vector_key(
+                    &self.tenant_security_client,
+                    metadata,
+                    &encrypted_vector.secret_path,
+                    &encrypted_vector.derivation_path,
+                    DeriveKeyChoice::Specific(key_id),
+                )
+                .await?;
+                self.decrypt_with_key(encrypted_vector, vector_metadata, &key)
+            })
+            .await;
+        self.security_events
+            .after_operation(DataEvent::Decrypt, metadata, result)
+    }
 
-        let paths = [(
-            encrypted_vector.secret_path.clone(),
-            [encrypted_vector.derivation_path.clone()].into(),
-        )]
-        .into();
-        let derived_keys = self
-            .tenant_security_client
-            .tenant_key_derive(
-                paths,
-                &metadata.clone().try_into()?,
-                DerivationType::Sha512,
-                SecretType::Vector,
-            )
-            .await?;
-        let derived_key = derived_keys.get_key_for_path(
-            &encrypted_vector.secret_path,
-            &encrypted_vector.derivation_path,
-            DeriveKeyChoice::Specific(key_id),
-        )?;
-        let (derived_key_id, key) = derived_key_to_vector_encryption_key(derived_key)?;
-        if derived_key_id != key_id {
-            Err(AlloyError::InvalidKey{ msg:
-                    "The key ID in the paired ICL info and on the key derived for decryption did not match"
-                        .to_string(),
-        })
-        } else {
-            decrypt_internal(
-                approximation_factor,
-                &key,
-                encrypted_vector,
-                icl_metadata_bytes,
-            )
-        }
+    /// Encrypt a vector embedding given in any supported precision. Encryption runs on `f32` values, so `f64`
+    /// embeddings are rounded to `f32` first. The encrypted vector is `F64` for `F64` embeddings and `F32` otherwise;
+    /// see `EncryptedTypedVector` for why half precision ciphertexts aren't possible.
+    async fn encrypt_typed(
+        &self,
+        plaintext_vector: PlaintextTypedVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedTypedVector, AlloyError> {
+        let precision = plaintext_vector.plaintext_vector.precision();
+        let encrypted = self.encrypt(plaintext_vector.try_into()?, metadata).await?;
+        Ok(EncryptedTypedVector::from_encrypted(encrypted, precision))
+    }
+
+    /// Decrypt a vector embedding that was encrypted with `encrypt_typed` (or `encrypt`) and return it in `precision`.
+    /// Fails if a decrypted value doesn't fit in `precision`.
+    async fn decrypt_typed(
+        &self,
+        encrypted_vector: EncryptedTypedVector,
+        precision: VectorPrecision,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextTypedVector, AlloyError> {
+        let decrypted = self.decrypt(encrypted_vector.try_into()?, metadata).await?;
+        PlaintextTypedVector::from_decrypted(decrypted, precision)
     }
 
     /// Encrypt each plaintext vector with any Current and InRotation keys for the provided secret path.
@@ -176,36 +290,51 @@ impl VectorOps for SaasShieldVectorClient {
         vectors_to_query: PlaintextVectors,
         metadata: &AlloyMetadata,
     ) -> Result<GenerateQueryResult, AlloyError> {
-        let paths = vectors_to_query
-            .values()
-            .map(|vector| (vector.secret_path.clone(), vector.derivation_path.clone()))
-            .collect_vec();
-        let all_keys = derive_keys_many_paths(
-            &self.tenant_security_client,
-            metadata,
-            paths,
-            SecretType::Vector,
-        )
-        .await?
-        .derived_keys;
-        vectors_to_query
-            .into_iter()
-            .map(|(vector_id, plaintext_vector)| {
-                let keys = all_keys
-                    .get(&plaintext_vector.secret_path)
-                    .and_then(|deriv| deriv.get(&plaintext_vector.derivation_path))
-                    .ok_or(AlloyError::RequestError {
-                        msg: "Failed to derive keys for provided path using the TSP.".to_string(),
-                    })?;
-                keys.iter()
-                    .map(|derived_key| {
-                        let (key_id, key) = derived_key_to_vector_encryption_key(derived_key)?;
-                        self.encrypt_core(&key, key_id, plaintext_vector.clone())
+        Operation::new("vector.generate_query_vectors")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(vectors_to_query.len())
+            .run(async move {
+                let paths = vectors_to_query
+                    .values()
+                    .map(|vector| (vector.secret_path.clone(), vector.derivation_path.clone()))
+                    .collect_vec();
+                let all_keys = derive_keys_many_paths(
+                    &self.tenant_security_client,
+                    metadata,
+                    paths,
+                    SecretType::Vector,
+                )
+                .await?
+                .derived_keys;
+                vectors_to_query
+                    .into_iter()
+                    .map(|(vector_id, plaintext_vector)| {
+                        let keys = all_keys
+                            .get(&plaintext_vector.secret_path)
+                            .and_then(|deriv| deriv.get(&plaintext_vector.derivation_path))
+                            .ok_or(AlloyError::RequestError {
+                                kind: ErrorKind::InvalidTspResponse,
+                                msg: "Failed to derive keys for provided path using the TSP."
+                                    .to_string(),
+                                source: None,
+                            })?;
+                        keys.iter()
+                            .map(|derived_key| {
+                                let (key_id, key) =
+                                    derived_key_to_vector_encryption_key(derived_key)?;
+                                self.encrypt_core(
+                                    &key,
+                                    key_id,
+                                    plaintext_vector.clone(),
+                                    &mut *get_rng(&self.rng),
+                                )
+                            })
+                            .try_collect()
+                            .map(|enc| (vector_id, enc))
                     })
-                    .try_collect()
-                    .map(|enc| (vector_id, enc))
+                    .collect()
             })
-            .collect()
+            .await
     }
 
     async fn rotate_vectors(
@@ -214,81 +343,75 @@ impl VectorOps for SaasShieldVectorClient {
         metadata: &AlloyMetadata,
         new_tenant_id: Option<TenantId>,
     ) -> Result<VectorRotateResult, AlloyError> {
-        let approximation_factor =
-            self.approximation_factor
-                .ok_or_else(|| AlloyError::InvalidConfiguration {
-                    msg: "`approximation_factor` was not set in the vector configuration."
-                        .to_string(),
-                })?;
-        let parsed_new_tenant_id = new_tenant_id.as_ref().unwrap_or(&metadata.tenant_id);
-        let paths = encrypted_vectors
-            .values()
-            .map(|field| (field.secret_path.clone(), field.derivation_path.clone()))
-            .collect_vec();
-        let RotationKeys {
-            original_keys: original_tenant_keys,
-            new_keys: new_tenant_keys,
-        } = get_keys_for_rotation(
-            metadata,
-            parsed_new_tenant_id,
-            paths,
-            &self.tenant_security_client,
-            SecretType::Vector,
-        )
-        .await?;
-        let reencrypt_vector = |encrypted_vector: EncryptedVector| {
-            let (original_key_id, icl_metadata_bytes) =
-                Self::decompose_key_id_header(encrypted_vector.paired_icl_info.clone())?;
-            let maybe_current_key_id = new_tenant_keys
-                .get_current(
-                    &encrypted_vector.secret_path,
-                    &encrypted_vector.derivation_path,
+        Operation::new("vector.rotate_vectors")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(encrypted_vectors.len())
+            .run(async move {
+                rotate_vectors_internal(
+                    self,
+                    &self.tenant_security_client,
+                    encrypted_vectors,
+                    metadata,
+                    new_tenant_id,
                 )
-                .map(|k| k.tenant_secret_id.0);
-            if check_rotation_no_op(
-                original_key_id,
-                &maybe_current_key_id,
-                parsed_new_tenant_id,
-                metadata,
-            ) {
-                Ok(encrypted_vector)
-            } else {
-                let original_derived_key = original_tenant_keys.get_key_for_path(
-                    &encrypted_vector.secret_path,
-                    &encrypted_vector.derivation_path,
-                    DeriveKeyChoice::Specific(original_key_id),
-                )?;
-                let (_, original_vector_key) =
-                    derived_key_to_vector_encryption_key(original_derived_key)?;
-                let decrypted_vector = decrypt_internal(
-                    approximation_factor,
-                    &original_vector_key,
-                    encrypted_vector,
-                    icl_metadata_bytes,
-                )?;
-                let new_derived_key = new_tenant_keys.get_key_for_path(
-                    &decrypted_vector.secret_path,
-                    &decrypted_vector.derivation_path,
-                    DeriveKeyChoice::Current,
-                )?;
-                let (new_key_id, new_vector_key) =
-                    derived_key_to_vector_encryption_key(new_derived_key)?;
-                encrypt_internal(
-                    approximation_factor,
-                    &new_vector_key,
-                    new_key_id,
-                    Self::get_edek_type(),
-                    decrypted_vector,
-                    &mut *get_rng(&self.rng),
+                .await
+                .map(VectorRotateResult::from)
+            })
+            .await
+    }
+
+    /// Rotates vectors that may belong to many tenants. Each vector carries the tenant ID it was encrypted to and an
+    /// optional tenant ID to rotate it to. Vectors are grouped by the tenant they were encrypted to, so that tenant's
+    /// keys are derived once however many tenants its vectors move to. The tenant ID in `metadata` is ignored, but its
+    /// other values are used for every group.
+    /// The same lossiness warning as `rotate_vectors` applies.
+    async fn rotate_vectors_multi_tenant(
+        &self,
+        encrypted_vectors: TenantEncryptedVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorRotateResult, AlloyError> {
+        Operation::new("vector.rotate_vectors_multi_tenant")
+            .batch_size(encrypted_vectors.len())
+            .run(async move {
+                let items = encrypted_vectors.into_iter().map(|(vector_id, vector)| {
+                    (
+                        vector_id,
+                        (
+                            vector.tenant_id,
+                            vector.new_tenant_id,
+                            vector.encrypted_vector,
+                        ),
+                    )
+                });
+                Ok(
+                    rotate_grouped_by_tenant(items, metadata, |vectors, metadata| async move {
+                        rotate_to_new_tenants(
+                            &self.tenant_security_client,
+                            vectors,
+                            &metadata,
+                            SecretType::Vector,
+                            |vector| (vector.secret_path.clone(), vector.derivation_path.clone()),
+                            |rotation_keys, vectors, new_tenant_id| {
+                                rotate_vectors_with_keys(
+                                    self,
+                                    rotation_keys,
+                                    vectors,
+                                    &metadata,
+                                    new_tenant_id,
+                                )
+                            },
+                        )
+                        .await
+                    })
+                    .await
+                    .into(),
                 )
-            }
-        };
-        Ok(collection_to_batch_result(encrypted_vectors, reencrypt_vector).into())
+            })
+            .await
     }
 
     /// Get the byte prefix for the InRotation secret corresponding to this secret_path/derivation_path.
-    /// Note that if you use z85 or ascii85 encoding, the result of this function should be passed to `base85_compat_prefix_bytes`
-    /// before searching your datastore.
+    /// Pass the result to `encode_search_prefixes` to get the string prefixes to search an encoded datastore with.
     async fn get_in_rotation_prefix(
         &self,
         secret_path: SecretPath,
@@ -314,3 +437,97 @@ impl VectorOps for SaasShieldVectorClient {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::saas_shield::rotate_vectors_with_keys;
+    use crate::saas_shield::test::{vector_keys, vector_rotation_keys};
+    use crate::SaasShieldConfiguration;
+    use approx::assert_abs_diff_eq;
+
+    fn get_client(approximation_factor: f32) -> SaasShieldVectorClient {
+        let config = SaasShieldConfiguration::builder("http://localhost:7777", "0WUaXesNgbTAuLwn")
+            .default_approximation_factor(approximation_factor)
+            .build()
+            .unwrap();
+        SaasShieldVectorClient::new(
+            config.tenant_security_client,
+            config.approximation_factors,
+            config.security_events,
+        )
+    }
+
+    fn get_metadata() -> Arc<AlloyMetadata> {
+        AlloyMetadata::new_simple(TenantId("tenant".to_string()))
+    }
+
+    fn key(id: u32) -> (KeyId, VectorEncryptionKey) {
+        let derived_keys = vector_keys(&[id], id);
+        let derived_key = derived_keys
+            .get_key_for_path(
+                &"secret".into(),
+                &"deriv".into(),
+                DeriveKeyChoice::Specific(KeyId(id)),
+            )
+            .unwrap();
+        derived_key_to_vector_encryption_key(derived_key).unwrap()
+    }
+
+    fn encrypt_with_key_id(client: &SaasShieldVectorClient, id: u32) -> EncryptedVector {
+        let (key_id, key) = key(id);
+        client
+            .encrypt_with_key(
+                PlaintextVector {
+                    plaintext_vector: vec![0.1, -0.2, 0.3],
+                    secret_path: "secret".into(),
+                    derivation_path: "deriv".into(),
+                },
+                key_id,
+                &key,
+            )
+            .unwrap()
+    }
+
+    fn rotate(client: &SaasShieldVectorClient, encrypted: EncryptedVector) -> EncryptedVector {
+        let mut result = rotate_vectors_with_keys(
+            client,
+            &vector_rotation_keys(&[1, 2], 2),
+            [("vector".to_string(), encrypted)].into(),
+            &get_metadata(),
+            &get_metadata().tenant_id,
+        );
+        assert!(result.failures.is_empty());
+        result.successes.remove("vector").unwrap()
+    }
+
+    #[test]
+    fn rotate_moves_to_current_key() {
+        let client = get_client(1.5);
+        let rotated = rotate(&client, encrypt_with_key_id(&client, 1));
+        let (key_id, icl_metadata_bytes) =
+            SaasShieldVectorClient::decompose_key_id_header(rotated.paired_icl_info.clone())
+                .unwrap();
+        assert_eq!(key_id, KeyId(2));
+        let vector_metadata = get_vector_metadata(&icl_metadata_bytes).unwrap();
+        let decrypted = client
+            .decrypt_with_key(rotated, vector_metadata, &key(2).1)
+            .unwrap();
+        assert_abs_diff_eq!(
+            &decrypted.plaintext_vector[..],
+            &[0.1, -0.2, 0.3][..],
+            epsilon = 1e-4
+        );
+    }
+
+    #[test]
+    fn rotate_is_no_op_only_with_current_key_and_factor() {
+        let client = get_client(1.5);
+        let encrypted = encrypt_with_key_id(&client, 2);
+        let rotated = rotate(&client, encrypted.clone());
+        assert_eq!(rotated.encrypted_vector, encrypted.encrypted_vector);
+
+        let rotated = rotate(&get_client(2.0), encrypted.clone());
+        assert_ne!(rotated.encrypted_vector, encrypted.encrypted_vector);
+    }
+}
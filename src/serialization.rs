@@ -0,0 +1,450 @@
+//! Serde support for the encrypted types, so they can be stored or sent over the wire in one stable shape instead of
+//! each service inventing its own.
+//!
+//! Every type serializes to a map with a `version` (currently 1) and the `encoding` used for all of its byte fields,
+//! followed by its own fields in camelCase. The plain `Serialize` implementations use base64; wrap a value in
+//! `WithEncoding` to pick another encoding. Deserialization reads the encoding back from the value itself, so it
+//! doesn't need to be told which one was used.
+//!
+//! Encrypted vector values are written as their big-endian `f32` bytes rather than as numbers. The vector's auth hash
+//! covers those exact bytes, and numeric formats can't carry every one of them (JSON has no NaN or infinities, for
+//! example).
+
+use crate::deterministic::EncryptedField;
+use crate::errors::{AlloyError, ErrorKind};
+use crate::standard::{EdekWithKeyIdHeader, EncryptedDocument};
+use crate::standard_attached::EncryptedAttachedDocument;
+use crate::vector::EncryptedVector;
+use crate::{DerivationPath, FieldId, SecretPath};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+
+/// Version written to, and required of, every serialized value.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Text encoding used for the byte fields of serialized values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteEncoding {
+    /// Standard base64 with padding.
+    #[default]
+    Base64,
+    /// URL safe base64 without padding.
+    Base64Url,
+    /// ZeroMQ's base85.
+    Z85,
+    /// Lowercase hex.
+    Hex,
+}
+
+impl ByteEncoding {
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            ByteEncoding::Base64 => STANDARD.encode(bytes),
+            ByteEncoding::Base64Url => URL_SAFE_NO_PAD.encode(bytes),
+            ByteEncoding::Z85 => z85::encode(bytes),
+            ByteEncoding::Hex => hex::encode(bytes),
+        }
+    }
+
+    pub fn decode(&self, encoded: &str) -> Result<Vec<u8>, AlloyError> {
+        let result = match self {
+            ByteEncoding::Base64 => STANDARD.decode(encoded).map_err(|e| e.to_string()),
+            ByteEncoding::Base64Url => URL_SAFE_NO_PAD.decode(encoded).map_err(|e| e.to_string()),
+            ByteEncoding::Z85 => z85::decode(encoded).map_err(|e| e.to_string()),
+            ByteEncoding::Hex => hex::decode(encoded).map_err(|e| e.to_string()),
+        };
+        result.map_err(|e| AlloyError::InvalidInput {
+            kind: ErrorKind::Serialization,
+            msg: format!("Invalid {self:?} encoded bytes: {e}"),
+        })
+    }
+}
+
+/// Serializes the wrapped value with its byte fields in the given encoding, e.g.
+/// `serde_json::to_string(&WithEncoding(&encrypted_document, ByteEncoding::Hex))`.
+#[derive(Debug, Clone, Copy)]
+pub struct WithEncoding<'a, T>(pub &'a T, pub ByteEncoding);
+
+/// Conversion between an encrypted type and its serialized shape.
+trait Wire: Sized {
+    type Wire: Serialize + for<'de> Deserialize<'de>;
+
+    fn to_wire(&self, encoding: ByteEncoding) -> Self::Wire;
+    fn from_wire(wire: Self::Wire) -> Result<Self, AlloyError>;
+}
+
+fn check_version(version: u32) -> Result<(), AlloyError> {
+    if version == SCHEMA_VERSION {
+        Ok(())
+    } else {
+        Err(AlloyError::InvalidInput {
+            kind: ErrorKind::Serialization,
+            msg: format!(
+                "Serialized value has schema version {version}, but only version {SCHEMA_VERSION} is supported."
+            ),
+        })
+    }
+}
+
+macro_rules! impl_serde_via_wire {
+    ($($t:ty),*) => {$(
+        impl Serialize for $t {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                WithEncoding(self, ByteEncoding::default()).serialize(serializer)
+            }
+        }
+
+        impl Serialize for WithEncoding<'_, $t> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                self.0.to_wire(self.1).serialize(serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $t {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let wire = <$t as Wire>::Wire::deserialize(deserializer)?;
+                Self::from_wire(wire).map_err(D::Error::custom)
+            }
+        }
+    )*};
+}
+
+impl_serde_via_wire!(
+    EdekWithKeyIdHeader,
+    EncryptedDocument,
+    EncryptedAttachedDocument,
+    EncryptedField,
+    EncryptedVector
+);
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EdekWire {
+    version: u32,
+    encoding: ByteEncoding,
+    edek: String,
+}
+
+impl Wire for EdekWithKeyIdHeader {
+    type Wire = EdekWire;
+
+    fn to_wire(&self, encoding: ByteEncoding) -> EdekWire {
+        EdekWire {
+            version: SCHEMA_VERSION,
+            encoding,
+            edek: encoding.encode(&self.0),
+        }
+    }
+
+    fn from_wire(wire: EdekWire) -> Result<Self, AlloyError> {
+        check_version(wire.version)?;
+        Ok(EdekWithKeyIdHeader(wire.encoding.decode(&wire.edek)?))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EncryptedDocumentWire {
+    version: u32,
+    encoding: ByteEncoding,
+    edek: String,
+    document: HashMap<FieldId, String>,
+}
+
+impl Wire for EncryptedDocument {
+    type Wire = EncryptedDocumentWire;
+
+    fn to_wire(&self, encoding: ByteEncoding) -> EncryptedDocumentWire {
+        EncryptedDocumentWire {
+            version: SCHEMA_VERSION,
+            encoding,
+            edek: encoding.encode(&self.edek.0),
+            document: self
+                .document
+                .iter()
+                .map(|(field_id, bytes)| (field_id.clone(), encoding.encode(bytes)))
+                .collect(),
+        }
+    }
+
+    fn from_wire(wire: EncryptedDocumentWire) -> Result<Self, AlloyError> {
+        check_version(wire.version)?;
+        Ok(EncryptedDocument {
+            edek: EdekWithKeyIdHeader(wire.encoding.decode(&wire.edek)?),
+            document: wire
+                .document
+                .into_iter()
+                .map(|(field_id, encoded)| Ok((field_id, wire.encoding.decode(&encoded)?)))
+                .collect::<Result<_, AlloyError>>()?,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EncryptedAttachedDocumentWire {
+    version: u32,
+    encoding: ByteEncoding,
+    document: String,
+}
+
+impl Wire for EncryptedAttachedDocument {
+    type Wire = EncryptedAttachedDocumentWire;
+
+    fn to_wire(&self, encoding: ByteEncoding) -> EncryptedAttachedDocumentWire {
+        EncryptedAttachedDocumentWire {
+            version: SCHEMA_VERSION,
+            encoding,
+            document: encoding.encode(&self.0),
+        }
+    }
+
+    fn from_wire(wire: EncryptedAttachedDocumentWire) -> Result<Self, AlloyError> {
+        check_version(wire.version)?;
+        Ok(EncryptedAttachedDocument(
+            wire.encoding.decode(&wire.document)?,
+        ))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EncryptedFieldWire {
+    version: u32,
+    encoding: ByteEncoding,
+    encrypted_field: String,
+    secret_path: SecretPath,
+    derivation_path: DerivationPath,
+}
+
+impl Wire for EncryptedField {
+    type Wire = EncryptedFieldWire;
+
+    fn to_wire(&self, encoding: ByteEncoding) -> EncryptedFieldWire {
+        EncryptedFieldWire {
+            version: SCHEMA_VERSION,
+            encoding,
+            encrypted_field: encoding.encode(&self.encrypted_field),
+            secret_path: self.secret_path.clone(),
+            derivation_path: self.derivation_path.clone(),
+        }
+    }
+
+    fn from_wire(wire: EncryptedFieldWire) -> Result<Self, AlloyError> {
+        check_version(wire.version)?;
+        Ok(EncryptedField {
+            encrypted_field: wire.encoding.decode(&wire.encrypted_field)?,
+            secret_path: wire.secret_path,
+            derivation_path: wire.derivation_path,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EncryptedVectorWire {
+    version: u32,
+    encoding: ByteEncoding,
+    /// Big-endian bytes of each `f32` value.
+    encrypted_vector: String,
+    secret_path: SecretPath,
+    derivation_path: DerivationPath,
+    paired_icl_info: String,
+}
+
+impl Wire for EncryptedVector {
+    type Wire = EncryptedVectorWire;
+
+    fn to_wire(&self, encoding: ByteEncoding) -> EncryptedVectorWire {
+        let vector_bytes = self
+            .encrypted_vector
+            .iter()
+            .flat_map(|value| value.to_be_bytes())
+            .collect::<Vec<_>>();
+        EncryptedVectorWire {
+            version: SCHEMA_VERSION,
+            encoding,
+            encrypted_vector: encoding.encode(&vector_bytes),
+            secret_path: self.secret_path.clone(),
+            derivation_path: self.derivation_path.clone(),
+            paired_icl_info: encoding.encode(&self.paired_icl_info),
+        }
+    }
+
+    fn from_wire(wire: EncryptedVectorWire) -> Result<Self, AlloyError> {
+        check_version(wire.version)?;
+        let vector_bytes = wire.encoding.decode(&wire.encrypted_vector)?;
+        if vector_bytes.len() % 4 != 0 {
+            Err(AlloyError::InvalidInput {
+                kind: ErrorKind::Serialization,
+                msg: format!(
+                    "Encrypted vector has {} bytes, which isn't a whole number of f32 values.",
+                    vector_bytes.len()
+                ),
+            })?
+        }
+        Ok(EncryptedVector {
+            encrypted_vector: vector_bytes
+                .chunks_exact(4)
+                .map(|chunk| f32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+                .collect(),
+            secret_path: wire.secret_path,
+            derivation_path: wire.derivation_path,
+            paired_icl_info: wire.encoding.decode(&wire.paired_icl_info)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::util::compute_auth_hash;
+    use crate::vector::{EncryptionKey, ScalingFactor, VectorEncryptionKey};
+    use assertables::*;
+    use proptest::prelude::*;
+
+    fn arb_encoding() -> impl Strategy<Value = ByteEncoding> {
+        prop_oneof![
+            Just(ByteEncoding::Base64),
+            Just(ByteEncoding::Base64Url),
+            Just(ByteEncoding::Z85),
+            Just(ByteEncoding::Hex),
+        ]
+    }
+
+    /// Any bit pattern, including NaNs with payloads, infinities, subnormals and negative zero.
+    fn arb_f32() -> impl Strategy<Value = f32> {
+        prop_oneof![
+            any::<u32>().prop_map(f32::from_bits),
+            Just(-0.),
+            Just(f32::NAN),
+            Just(f32::INFINITY),
+            Just(f32::NEG_INFINITY),
+            Just(f32::MIN_POSITIVE / 2.),
+        ]
+    }
+
+    fn roundtrip<T>(value: &T, encoding: ByteEncoding) -> T
+    where
+        T: for<'de> Deserialize<'de>,
+        for<'a> WithEncoding<'a, T>: Serialize,
+    {
+        let json = serde_json::to_string(&WithEncoding(value, encoding)).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn encoding_roundtrip(bytes: Vec<u8>, encoding in arb_encoding()) {
+            prop_assert_eq!(encoding.decode(&encoding.encode(&bytes)).unwrap(), bytes);
+        }
+
+        #[test]
+        fn edek_and_attached_document_roundtrip(bytes: Vec<u8>, encoding in arb_encoding()) {
+            let edek = EdekWithKeyIdHeader(bytes.clone());
+            prop_assert_eq!(roundtrip(&edek, encoding), edek);
+            let document = EncryptedAttachedDocument(bytes.clone());
+            prop_assert_eq!(roundtrip(&document, encoding).0, bytes);
+        }
+
+        #[test]
+        fn encrypted_document_roundtrip(
+            edek: Vec<u8>,
+            document: HashMap<String, Vec<u8>>,
+            encoding in arb_encoding(),
+        ) {
+            let encrypted = EncryptedDocument { edek: EdekWithKeyIdHeader(edek), document };
+            let result = roundtrip(&encrypted, encoding);
+            prop_assert_eq!(result.edek, encrypted.edek);
+            prop_assert_eq!(result.document, encrypted.document);
+        }
+
+        #[test]
+        fn encrypted_field_roundtrip(
+            encrypted_field: Vec<u8>,
+            secret_path: String,
+            derivation_path: String,
+            encoding in arb_encoding(),
+        ) {
+            let field = EncryptedField {
+                encrypted_field,
+                secret_path: SecretPath(secret_path),
+                derivation_path: DerivationPath(derivation_path),
+            };
+            let result = roundtrip(&field, encoding);
+            prop_assert_eq!(result.encrypted_field, field.encrypted_field);
+            prop_assert_eq!(result.secret_path, field.secret_path);
+            prop_assert_eq!(result.derivation_path, field.derivation_path);
+        }
+
+        #[test]
+        fn encrypted_vector_roundtrip_is_bit_exact(
+            values in proptest::collection::vec(arb_f32(), 0..64),
+            paired_icl_info: Vec<u8>,
+            key: [u8; 32],
+            encoding in arb_encoding(),
+        ) {
+            let vector = EncryptedVector {
+                encrypted_vector: values,
+                secret_path: SecretPath("secret_path".to_string()),
+                derivation_path: DerivationPath("deriv_path".to_string()),
+                paired_icl_info,
+            };
+            let result = roundtrip(&vector, encoding);
+            let bits = |vector: &EncryptedVector| {
+                vector.encrypted_vector.iter().map(|value| value.to_bits()).collect::<Vec<_>>()
+            };
+            prop_assert_eq!(bits(&result), bits(&vector));
+            prop_assert_eq!(&result.paired_icl_info, &vector.paired_icl_info);
+            let key = VectorEncryptionKey {
+                scaling_factor: ScalingFactor(1.),
+                key: EncryptionKey(key.to_vec()),
+            };
+            prop_assert_eq!(
+                compute_auth_hash(&key, &2.5, [0u8; 12], result.encrypted_vector.iter()),
+                compute_auth_hash(&key, &2.5, [0u8; 12], vector.encrypted_vector.iter())
+            );
+        }
+    }
+
+    #[test]
+    fn default_serialization_is_versioned_base64() {
+        let json = serde_json::to_value(EdekWithKeyIdHeader(vec![1, 2, 3])).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"version": 1, "encoding": "base64", "edek": "AQID"})
+        );
+        let json = serde_json::to_value(WithEncoding(
+            &EdekWithKeyIdHeader(vec![1, 2, 3]),
+            ByteEncoding::Hex,
+        ))
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"version": 1, "encoding": "hex", "edek": "010203"})
+        );
+    }
+
+    #[test]
+    fn unsupported_version_is_rejected() {
+        let err = serde_json::from_str::<EdekWithKeyIdHeader>(
+            r#"{"version": 2, "encoding": "base64", "edek": "AQID"}"#,
+        )
+        .unwrap_err();
+        assert_contains!(err.to_string(), "schema version 2");
+    }
+
+    #[test]
+    fn partial_f32_is_rejected() {
+        let err = serde_json::from_str::<EncryptedVector>(
+            r#"{"version": 1, "encoding": "hex", "encryptedVector": "010203", "secretPath": "s",
+                "derivationPath": "d", "pairedIclInfo": ""}"#,
+        )
+        .unwrap_err();
+        assert_contains!(err.to_string(), "3 bytes");
+    }
+}
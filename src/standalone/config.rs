@@ -1,17 +1,22 @@
-use crate::{errors::AlloyError, Secret, SecretPath};
+use super::security_events::{SecurityEventLogger, SecurityEventSink};
+use crate::{
+    errors::{AlloyError, ErrorKind},
+    vector::{VectorEncryptionMode, VectorEncryptionParams, VectorSecretOptions},
+    Secret, SecretPath,
+};
 use ironcore_documents::v5::key_id_header::KeyId;
 use std::{collections::HashMap, sync::Arc};
 
 /// A secret used by standalone mode to derive encryption keys.
-#[derive(Debug, uniffi::Object)]
+#[derive(Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct StandaloneSecret {
     pub(crate) id: u32,
     pub(crate) secret: Arc<Secret>,
 }
-#[uniffi::export]
+uniffi_constructors! {
 impl StandaloneSecret {
     /// Create a standalone secret. The secret needs to be cryptographically random bytes.
-    #[uniffi::constructor]
     pub fn new(id: i32, secret: Arc<Secret>) -> Arc<Self> {
         Arc::new(StandaloneSecret {
             id: id as u32,
@@ -19,19 +24,20 @@ impl StandaloneSecret {
         })
     }
 }
+}
 /// A collection of secrets for standalone standard mode used to derive encryption keys.
 /// The primary secret id is used to look up the primary secret, which will be used for encrypting new documents.
 /// The rest of the secrets will only be used to decrypt existing documents when encountered.
-#[derive(Debug, uniffi::Object)]
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct StandardSecrets {
     pub(crate) primary_secret_id: Option<u32>,
     pub(crate) secrets: HashMap<u32, Secret>,
 }
-#[uniffi::export]
+uniffi_constructors! {
 impl StandardSecrets {
     /// Create a collection of standard secrets.
     /// This will error if secret ids aren't unique or the primary secret id isn't in the secrets list.
-    #[uniffi::constructor]
     pub fn new(
         primary_secret_id: Option<i32>,
         secrets: Vec<Arc<StandaloneSecret>>,
@@ -40,6 +46,7 @@ impl StandardSecrets {
 
         if secrets.iter().any(|secret| secret.id == 0) {
             return Err(AlloyError::InvalidKey {
+                kind: ErrorKind::Other,
                 msg: "Secret ids must be greater than 0".to_string(),
             });
         }
@@ -53,6 +60,7 @@ impl StandardSecrets {
                 .is_some()
             {
                 return Err(AlloyError::InvalidKey {
+                    kind: ErrorKind::Other,
                     msg: format!(
                         "Duplicate secret id encountered while initializing Standalone mode: {}",
                         standalone_secret.id
@@ -65,6 +73,7 @@ impl StandardSecrets {
         if let Some(id) = primary_secret_id {
             if internal_secrets.get(&(id as u32)).is_none() {
                 return Err(AlloyError::InvalidKey {
+                    kind: ErrorKind::Other,
                     msg: format!("Primary secret id not found in provided secrets: {id}"),
                 });
             }
@@ -76,26 +85,28 @@ impl StandardSecrets {
         }))
     }
 }
+}
 
 /// A single secret that allows for rotation within a secret path.
 /// Used for Deterministic and Vector operations.
-#[derive(Debug, uniffi::Object)]
+#[derive(Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct RotatableSecret {
     pub(crate) current_secret: Option<Arc<StandaloneSecret>>,
     pub(crate) in_rotation_secret: Option<Arc<StandaloneSecret>>,
 }
 
-#[uniffi::export]
+uniffi_constructors! {
 impl RotatableSecret {
     /// Create a rotating secret. This will error if both secrets are unset. If no secret for a path is desired, leave
     /// that path out of the configuration entirely instead.
-    #[uniffi::constructor]
     pub fn new(
         current_secret: Option<Arc<StandaloneSecret>>,
         in_rotation_secret: Option<Arc<StandaloneSecret>>,
     ) -> Result<Arc<Self>, AlloyError> {
         if current_secret.is_none() && in_rotation_secret.is_none() {
             Err(AlloyError::InvalidKey {
+                kind: ErrorKind::Other,
                 msg: "Cannot create a RotatingSecret with no secrets.".to_string(),
             })
         } else {
@@ -106,6 +117,7 @@ impl RotatableSecret {
         }
     }
 }
+}
 
 // This impl is for non-uniffi functions
 impl RotatableSecret {
@@ -118,39 +130,82 @@ impl RotatableSecret {
     }
 }
 
-#[derive(Debug, uniffi::Object)]
+#[derive(Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct VectorSecret {
     pub(crate) approximation_factor: f32,
     pub(crate) secret: Arc<RotatableSecret>,
+    pub(crate) options: VectorSecretOptions,
 }
-#[uniffi::export]
+uniffi_constructors! {
 impl VectorSecret {
     /// The approximation factor should be chosen in a way that balances security with search performance.
     /// A higher approximation factor is more secure, but introduces more variance into encrypted embeddings,
     /// possibly leading to degraded performance. A lower bound for the approximation factor to start with is `sqrt(M)`,
     /// where M is the absolute value of the largest data point in the input embeddings.
-    #[uniffi::constructor]
+    /// `calibrate_approximation_factors` can be used to compare candidate factors against a sample of your data.
     pub fn new(approximation_factor: f32, secret: Arc<RotatableSecret>) -> Arc<Self> {
         Arc::new(Self {
             approximation_factor,
             secret,
+            options: VectorSecretOptions::default(),
         })
     }
+
+    /// Create a vector secret that encrypts in `mode`. See `VectorEncryptionMode` for the tradeoffs. Like the
+    /// approximation factor, the mode can't change without rotating the vectors encrypted under this secret.
+    /// Fails for `QuantizationFriendly`, which needs `new_with_options` to acknowledge its leakage.
+    pub fn new_with_mode(
+        approximation_factor: f32,
+        secret: Arc<RotatableSecret>,
+        mode: VectorEncryptionMode,
+    ) -> Result<Arc<Self>, AlloyError> {
+        Self::new_with_options(
+            approximation_factor,
+            secret,
+            VectorSecretOptions {
+                mode,
+                ..Default::default()
+            },
+        )
+    }
+
+    /// Create a vector secret with any of the options in `VectorSecretOptions`. Fails if the options are invalid.
+    pub fn new_with_options(
+        approximation_factor: f32,
+        secret: Arc<RotatableSecret>,
+        options: VectorSecretOptions,
+    ) -> Result<Arc<Self>, AlloyError> {
+        options.validate()?;
+        Ok(Arc::new(Self {
+            approximation_factor,
+            secret,
+            options,
+        }))
+    }
+}
+}
+
+impl VectorSecret {
+    pub(crate) fn encryption_params(&self) -> VectorEncryptionParams {
+        self.options.encryption_params(self.approximation_factor)
+    }
 }
 
 /// Configuration for the standalone SDKs. Sets secrets and secret paths for the different SDK operations.
 /// If usage of only one set of SDK operations is desired the others can be left as empty objects, and will error if
 /// called in that state. If you want to share a secret between multiple SDK modes, you'll need to create secrets in each
 /// mode that share the same secret bytes.
-#[derive(Debug, uniffi::Object, Clone)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct StandaloneConfiguration {
     pub(crate) standard: Arc<StandardSecrets>,
     pub(crate) deterministic: Arc<HashMap<SecretPath, Arc<RotatableSecret>>>,
     pub(crate) vector: Arc<HashMap<SecretPath, Arc<VectorSecret>>>,
+    pub(crate) security_events: SecurityEventLogger,
 }
-#[uniffi::export]
+uniffi_constructors! {
 impl StandaloneConfiguration {
-    #[uniffi::constructor]
     pub fn new(
         standard: Arc<StandardSecrets>,
         deterministic: HashMap<SecretPath, Arc<RotatableSecret>>,
@@ -160,6 +215,88 @@ impl StandaloneConfiguration {
             standard,
             deterministic: Arc::new(deterministic),
             vector: Arc::new(vector),
+            security_events: SecurityEventLogger::default(),
+        })
+    }
+
+    /// Create a configuration whose clients log security events to `sink`. If `automatic_data_events` is true,
+    /// a `DataEvent::Encrypt` or `DataEvent::Decrypt` event is logged after every successful encrypt or decrypt.
+    pub fn new_with_security_events(
+        standard: Arc<StandardSecrets>,
+        deterministic: HashMap<SecretPath, Arc<RotatableSecret>>,
+        vector: HashMap<SecretPath, Arc<VectorSecret>>,
+        sink: Arc<dyn SecurityEventSink>,
+        automatic_data_events: bool,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            standard,
+            deterministic: Arc::new(deterministic),
+            vector: Arc::new(vector),
+            security_events: SecurityEventLogger::new(sink, automatic_data_events),
         })
     }
 }
+}
+
+impl StandaloneConfiguration {
+    /// Start building a configuration. Any SDK modes that aren't given secrets are left empty, and will error if used.
+    pub fn builder() -> StandaloneConfigurationBuilder {
+        StandaloneConfigurationBuilder::default()
+    }
+}
+
+/// Builds a `StandaloneConfiguration` one secret at a time. Create one with `StandaloneConfiguration::builder()`.
+#[derive(Debug, Default)]
+pub struct StandaloneConfigurationBuilder {
+    standard: Option<Arc<StandardSecrets>>,
+    deterministic: HashMap<SecretPath, Arc<RotatableSecret>>,
+    vector: HashMap<SecretPath, Arc<VectorSecret>>,
+    security_events: SecurityEventLogger,
+}
+
+impl StandaloneConfigurationBuilder {
+    /// Secrets used for standard and standard attached encryption.
+    pub fn standard_secrets(mut self, secrets: impl Into<Arc<StandardSecrets>>) -> Self {
+        self.standard = Some(secrets.into());
+        self
+    }
+
+    /// Secret used for deterministic encryption of fields with `secret_path`.
+    pub fn deterministic_secret(
+        mut self,
+        secret_path: impl Into<SecretPath>,
+        secret: impl Into<Arc<RotatableSecret>>,
+    ) -> Self {
+        self.deterministic.insert(secret_path.into(), secret.into());
+        self
+    }
+
+    /// Secret used for dense and sparse vector encryption of vectors with `secret_path`.
+    pub fn vector_secret(
+        mut self,
+        secret_path: impl Into<SecretPath>,
+        secret: impl Into<Arc<VectorSecret>>,
+    ) -> Self {
+        self.vector.insert(secret_path.into(), secret.into());
+        self
+    }
+
+    /// Log security events to `sink`. See `StandaloneConfiguration::new_with_security_events`.
+    pub fn security_event_sink(
+        mut self,
+        sink: Arc<dyn SecurityEventSink>,
+        automatic_data_events: bool,
+    ) -> Self {
+        self.security_events = SecurityEventLogger::new(sink, automatic_data_events);
+        self
+    }
+
+    pub fn build(self) -> StandaloneConfiguration {
+        StandaloneConfiguration {
+            standard: self.standard.unwrap_or_default(),
+            deterministic: Arc::new(self.deterministic),
+            vector: Arc::new(self.vector),
+            security_events: self.security_events,
+        }
+    }
+}
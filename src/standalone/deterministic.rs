@@ -2,10 +2,14 @@ use super::config::RotatableSecret;
 use crate::deterministic::{
     decrypt_internal, encrypt_internal, DeterministicEncryptionKey, DeterministicFieldOps,
     DeterministicRotateResult, EncryptedField, EncryptedFields, GenerateQueryResult,
-    PlaintextField, PlaintextFields,
+    PlaintextCompoundField, PlaintextCompoundFields, PlaintextField, PlaintextFields,
+    TenantEncryptedFields,
 };
-use crate::errors::AlloyError;
-use crate::util::{check_rotation_no_op, collection_to_batch_result};
+use crate::errors::{AlloyError, ErrorKind};
+use crate::saas_shield::{DataEvent, SecurityEvent};
+use crate::standalone::security_events::{SecurityEventLogger, StandaloneSecurityEventOps};
+use crate::telemetry::Operation;
+use crate::util::{check_rotation_no_op, collection_to_batch_result, rotate_grouped_by_tenant};
 use crate::{
     alloy_client_trait::AlloyClient, AlloyMetadata, DerivationPath, SecretPath,
     StandaloneConfiguration, TenantId,
@@ -15,37 +19,44 @@ use itertools::Itertools;
 use std::collections::HashMap;
 use std::sync::Arc;
 
-#[derive(uniffi::Object)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct StandaloneDeterministicClient {
     config: Arc<HashMap<SecretPath, Arc<RotatableSecret>>>,
+    security_events: SecurityEventLogger,
 }
 impl StandaloneDeterministicClient {
     pub(crate) fn new(config: StandaloneConfiguration) -> Self {
         StandaloneDeterministicClient {
             config: config.deterministic,
+            security_events: config.security_events,
         }
     }
 
+    fn get_secret(&self, secret_path: &SecretPath) -> Result<&Arc<RotatableSecret>, AlloyError> {
+        self.config
+            .get(secret_path)
+            .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::UnknownSecretPath,
+                msg: format!(
+                    "Provided secret path `{}` does not exist in the deterministic configuration.",
+                    secret_path.0
+                ),
+            })
+    }
+
     /// Synchronous version of `encrypt` that takes TenantId instead of full AlloyMetadata
     fn encrypt_sync(
         &self,
         plaintext_field: PlaintextField,
         tenant_id: &TenantId,
     ) -> Result<EncryptedField, AlloyError> {
-        let secret = self
-            .config
-            .get(&plaintext_field.secret_path)
-            .ok_or_else(|| AlloyError::InvalidConfiguration {
-                msg: format!(
-                    "Provided secret path `{}` does not exist in the deterministic configuration.",
-                    &plaintext_field.secret_path.0
-                ),
-            })?;
+        let secret = self.get_secret(&plaintext_field.secret_path)?;
         let current_secret =
             secret
                 .current_secret
                 .as_ref()
                 .ok_or_else(|| AlloyError::InvalidConfiguration {
+                    kind: ErrorKind::MissingPrimarySecret,
                     msg: "No current secret exists in the deterministic configuration".to_string(),
                 })?;
         let key = DeterministicEncryptionKey::derive_from_secret(
@@ -65,19 +76,12 @@ impl StandaloneDeterministicClient {
     ) -> Result<PlaintextField, AlloyError> {
         let (key_id, ciphertext) =
             Self::decompose_key_id_header(encrypted_field.encrypted_field.clone())?;
-        let secret = self
-            .config
-            .get(&encrypted_field.secret_path)
-            .ok_or_else(|| AlloyError::InvalidConfiguration {
-                msg: format!(
-                    "Provided secret path `{}` does not exist in the deterministic configuration.",
-                    &encrypted_field.secret_path.0
-                ),
-            })?;
+        let secret = self.get_secret(&encrypted_field.secret_path)?;
         let standalone_secret =
             secret
                 .get_secret_with_id(&key_id)
                 .ok_or_else(|| AlloyError::InvalidConfiguration {
+                    kind: ErrorKind::UnknownSecretId,
                     msg: format!(
                         "Secret with key ID `{}` does not exist in the deterministic configuration",
                         key_id.0
@@ -95,6 +99,65 @@ impl StandaloneDeterministicClient {
             encrypted_field.derivation_path,
         )
     }
+
+    /// Re-encrypt a field to the current secret for its secret path and `new_tenant_id`, unless it's already
+    /// encrypted that way.
+    fn rotate_field(
+        &self,
+        encrypted_field: EncryptedField,
+        metadata: &AlloyMetadata,
+        new_tenant_id: &TenantId,
+    ) -> Result<EncryptedField, AlloyError> {
+        let (key_id, _) = Self::decompose_key_id_header(encrypted_field.encrypted_field.clone())?;
+        let maybe_new_secret = &self
+            .get_secret(&encrypted_field.secret_path)?
+            .current_secret;
+        if check_rotation_no_op(
+            key_id,
+            &maybe_new_secret.as_ref().map(|k| k.id),
+            new_tenant_id,
+            metadata,
+        ) {
+            Ok(encrypted_field)
+        } else {
+            self.decrypt_sync(encrypted_field, &metadata.tenant_id)
+                .and_then(|decrypted_field| self.encrypt_sync(decrypted_field, new_tenant_id))
+        }
+    }
+
+    /// Encrypt a field with the current and in-rotation secrets for its secret path, for querying.
+    fn query_field_values_sync(
+        &self,
+        plaintext_field: PlaintextField,
+        tenant_id: &TenantId,
+    ) -> Result<Vec<EncryptedField>, AlloyError> {
+        let RotatableSecret {
+            current_secret,
+            in_rotation_secret,
+        } = self.get_secret(&plaintext_field.secret_path)?.as_ref();
+        if current_secret.is_none() && in_rotation_secret.is_none() {
+            Err(AlloyError::InvalidConfiguration {
+                kind: ErrorKind::UnknownSecretPath,
+                msg: format!(
+                    "No secrets exist in the deterministic configuration for secret path `{}`.",
+                    plaintext_field.secret_path.0
+                ),
+            })?;
+        }
+        current_secret
+            .iter()
+            .chain(in_rotation_secret)
+            .map(|standalone_secret| {
+                let key = DeterministicEncryptionKey::derive_from_secret(
+                    standalone_secret.secret.as_ref(),
+                    tenant_id,
+                    &plaintext_field.derivation_path,
+                );
+                let key_id_header = Self::create_key_id_header(standalone_secret.id);
+                encrypt_internal(key, key_id_header, plaintext_field.clone())
+            })
+            .try_collect()
+    }
 }
 
 impl AlloyClient for StandaloneDeterministicClient {
@@ -107,7 +170,7 @@ impl AlloyClient for StandaloneDeterministicClient {
     }
 }
 
-#[uniffi::export]
+#[cfg_attr(feature = "uniffi", uniffi::export)]
 impl DeterministicFieldOps for StandaloneDeterministicClient {
     /// Encrypt a field with the provided metadata.
     /// Because the field is encrypted deterministically with each call, the result will be the same for repeated calls.
@@ -118,7 +181,14 @@ impl DeterministicFieldOps for StandaloneDeterministicClient {
         plaintext_field: PlaintextField,
         metadata: &AlloyMetadata,
     ) -> Result<EncryptedField, AlloyError> {
-        self.encrypt_sync(plaintext_field, &metadata.tenant_id)
+        let secret_path = plaintext_field.secret_path.0.clone();
+        let result = Operation::new("deterministic.encrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .secret_path(&secret_path)
+            .run(async move { self.encrypt_sync(plaintext_field, &metadata.tenant_id) })
+            .await;
+        self.security_events
+            .after_data_operation(DataEvent::Encrypt, metadata, result)
     }
 
     /// Decrypt a field that was deterministically encrypted with the provided metadata.
@@ -127,7 +197,14 @@ impl DeterministicFieldOps for StandaloneDeterministicClient {
         encrypted_field: EncryptedField,
         metadata: &AlloyMetadata,
     ) -> Result<PlaintextField, AlloyError> {
-        self.decrypt_sync(encrypted_field, &metadata.tenant_id)
+        let secret_path = encrypted_field.secret_path.0.clone();
+        let result = Operation::new("deterministic.decrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .secret_path(&secret_path)
+            .run(async move { self.decrypt_sync(encrypted_field, &metadata.tenant_id) })
+            .await;
+        self.security_events
+            .after_data_operation(DataEvent::Decrypt, metadata, result)
     }
 
     /// Encrypt each plaintext field with any Current and InRotation keys for the provided secret path.
@@ -137,48 +214,19 @@ impl DeterministicFieldOps for StandaloneDeterministicClient {
         fields_to_query: PlaintextFields,
         metadata: &AlloyMetadata,
     ) -> Result<GenerateQueryResult, AlloyError> {
-        fields_to_query
-            .into_iter()
-            .map(|(field_id, plaintext_field)| {
-                let secret = self
-                    .config
-                    .get(&plaintext_field.secret_path)
-                    .ok_or_else(|| {
-                        AlloyError::InvalidConfiguration{msg: format!(
-                            "Provided secret path `{}` does not exist in the deterministic configuration.",
-                            &plaintext_field.secret_path.0
-                        )}
-                    })?;
-                let RotatableSecret {
-                    current_secret,
-                    in_rotation_secret,
-                } = secret.as_ref();
-                if current_secret.is_none() && in_rotation_secret.is_none() {
-                    Err(AlloyError::InvalidConfiguration{msg: format!(
-                        "No secrets exist in the deterministic configuration for secret path `{}`.",
-                        plaintext_field.secret_path.0
-                    )})?;
-                }
-                current_secret
-                    .iter()
-                    .chain(in_rotation_secret)
-                    .map(|standalone_secret| {
-                        let key = DeterministicEncryptionKey::derive_from_secret(
-                            standalone_secret.secret.as_ref(),
-                            &metadata.tenant_id,
-                            &plaintext_field.derivation_path,
-                        );
-                        let key_id_header = Self::create_key_id_header(standalone_secret.id);
-                        encrypt_internal(
-                            key,
-                            key_id_header,
-                            plaintext_field.clone(),
-                        )
+        Operation::new("deterministic.generate_query_field_values")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(fields_to_query.len())
+            .run(async move {
+                fields_to_query
+                    .into_iter()
+                    .map(|(field_id, plaintext_field)| {
+                        self.query_field_values_sync(plaintext_field, &metadata.tenant_id)
+                            .map(|enc| (field_id, enc))
                     })
                     .try_collect()
-                    .map(|enc| (field_id, enc))
             })
-            .try_collect()
+            .await
     }
 
     /// Re-encrypt already encrypted fields with the Current key for the provided tenant. The `metadata` passed
@@ -190,41 +238,62 @@ impl DeterministicFieldOps for StandaloneDeterministicClient {
         metadata: &AlloyMetadata,
         new_tenant_id: Option<TenantId>,
     ) -> Result<DeterministicRotateResult, AlloyError> {
-        let parsed_new_tenant_id = new_tenant_id.as_ref().unwrap_or(&metadata.tenant_id);
-        let reencrypt_field = |encrypted_field: EncryptedField| {
-            let (key_id, _) =
-                Self::decompose_key_id_header(encrypted_field.encrypted_field.clone())?;
-            let maybe_new_secret = &self
-                .config
-                .get(&encrypted_field.secret_path)
-                .ok_or_else(|| {
-                    AlloyError::InvalidConfiguration{msg: format!(
-                        "Provided secret path `{}` does not exist in the deterministic configuration.",
-                        &encrypted_field.secret_path.0
-                    )}
-                })?.current_secret;
-            if check_rotation_no_op(
-                key_id,
-                &maybe_new_secret.as_ref().map(|k| k.id),
-                parsed_new_tenant_id,
-                metadata,
-            ) {
-                Ok(encrypted_field)
-            } else {
-                self.decrypt_sync(encrypted_field, &metadata.tenant_id)
-                    .and_then(|decrypted_field| {
-                        self.encrypt_sync(decrypted_field, parsed_new_tenant_id)
+        Operation::new("deterministic.rotate_fields")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(encrypted_fields.len())
+            .run(async move {
+                let parsed_new_tenant_id = new_tenant_id.as_ref().unwrap_or(&metadata.tenant_id);
+                Ok(
+                    collection_to_batch_result(encrypted_fields, |encrypted_field| {
+                        self.rotate_field(encrypted_field, metadata, parsed_new_tenant_id)
                     })
-            }
-        };
-        Ok(collection_to_batch_result(encrypted_fields, reencrypt_field).into())
+                    .into(),
+                )
+            })
+            .await
+    }
+
+    /// Re-encrypt already encrypted fields that may belong to many tenants. Each field carries the tenant ID it was
+    /// encrypted to and an optional tenant ID to rotate it to. The tenant ID in `metadata` is ignored, but its other
+    /// values are used for every field.
+    async fn rotate_fields_multi_tenant(
+        &self,
+        encrypted_fields: TenantEncryptedFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<DeterministicRotateResult, AlloyError> {
+        Operation::new("deterministic.rotate_fields_multi_tenant")
+            .batch_size(encrypted_fields.len())
+            .run(async move {
+                let items = encrypted_fields.into_iter().map(|(field_id, field)| {
+                    (
+                        field_id,
+                        (field.tenant_id, field.new_tenant_id, field.encrypted_field),
+                    )
+                });
+                Ok(
+                    rotate_grouped_by_tenant(items, metadata, |fields, metadata| async move {
+                        Ok(collection_to_batch_result(
+                            fields,
+                            |(new_tenant_id, encrypted_field)| {
+                                self.rotate_field(
+                                    encrypted_field,
+                                    &metadata,
+                                    new_tenant_id.as_ref().unwrap_or(&metadata.tenant_id),
+                                )
+                            },
+                        ))
+                    })
+                    .await
+                    .into(),
+                )
+            })
+            .await
     }
 
     /// Generate a prefix that could used to search a data store for fields encrypted using an identifier (KMS
     /// config id for SaaS Shield, secret id for Standalone). These bytes should be encoded into
-    /// a format matching the encoding in the data store. z85/ascii85 users should first pass these bytes through
-    /// `encode_prefix_z85` or `base85_prefix_padding`. Make sure you've read the documentation of those functions to
-    /// avoid pitfalls when encoding across byte boundaries.
+    /// a format matching the encoding in the data store. `encode_search_prefixes` does this for the common
+    /// encodings, including the z85/ascii85 pitfalls when encoding across byte boundaries.
     async fn get_in_rotation_prefix(
         &self,
         secret_path: SecretPath,
@@ -235,6 +304,7 @@ impl DeterministicFieldOps for StandaloneDeterministicClient {
             self.config
                 .get(&secret_path)
                 .ok_or_else(|| AlloyError::InvalidConfiguration {
+                    kind: ErrorKind::UnknownSecretPath,
                     msg: format!(
                 "Provided secret path `{}` does not exist in the deterministic configuration.",
                 &secret_path.0
@@ -245,6 +315,7 @@ impl DeterministicFieldOps for StandaloneDeterministicClient {
                 .in_rotation_secret
                 .as_ref()
                 .ok_or_else(|| AlloyError::InvalidConfiguration {
+                    kind: ErrorKind::MissingPrimarySecret,
                     msg: format!(
                 "There is no in-rotation secret for path `{}` in the deterministic configuration.",
                 secret_path.0
@@ -256,13 +327,72 @@ impl DeterministicFieldOps for StandaloneDeterministicClient {
                 .into(),
         )
     }
+
+    /// Deterministically encrypt an ordered list of named values as a single field. The values are
+    /// length-prefixed before encryption so different splits of the same bytes never collide.
+    async fn encrypt_compound(
+        &self,
+        plaintext_compound_field: PlaintextCompoundField,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedField, AlloyError> {
+        let result = plaintext_compound_field
+            .try_into()
+            .and_then(|field| self.encrypt_sync(field, &metadata.tenant_id));
+        self.security_events
+            .after_data_operation(DataEvent::Encrypt, metadata, result)
+    }
+
+    /// Decrypt a field that was encrypted with `encrypt_compound` back into its named values.
+    async fn decrypt_compound(
+        &self,
+        encrypted_field: EncryptedField,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextCompoundField, AlloyError> {
+        let result = self
+            .decrypt_sync(encrypted_field, &metadata.tenant_id)
+            .and_then(PlaintextCompoundField::try_from);
+        self.security_events
+            .after_data_operation(DataEvent::Decrypt, metadata, result)
+    }
+
+    /// Encrypt each compound field with any Current and InRotation keys for the provided secret path.
+    /// The resulting encrypted fields should be used in tandem when querying the data store.
+    async fn generate_query_compound_field_values(
+        &self,
+        fields_to_query: PlaintextCompoundFields,
+        metadata: &AlloyMetadata,
+    ) -> Result<GenerateQueryResult, AlloyError> {
+        let plaintext_fields = fields_to_query
+            .into_iter()
+            .map(|(field_id, compound_field)| Ok((field_id, compound_field.try_into()?)))
+            .collect::<Result<PlaintextFields, AlloyError>>()?;
+        self.generate_query_field_values(plaintext_fields, metadata)
+            .await
+    }
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl StandaloneSecurityEventOps for StandaloneDeterministicClient {
+    /// Log the security event `event` to the configured `SecurityEventSink`.
+    /// If the event time is unspecified the current time will be used.
+    async fn log_security_event(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError> {
+        self.security_events.log(event, metadata, event_time_millis)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::{
-        standalone::config::StandaloneSecret, tests::get_metadata, DerivationPath, Secret,
+        deterministic::{CompoundFieldValue, TenantEncryptedField},
+        standalone::config::StandaloneSecret,
+        tests::get_metadata,
+        DerivationPath, Secret,
     };
     use assertables::*;
     use hex_literal::hex;
@@ -291,6 +421,7 @@ mod test {
                 )]
                 .into(),
             ),
+            security_events: SecurityEventLogger::default(),
         }
     }
 
@@ -310,6 +441,104 @@ mod test {
         assert_eq!(decrypted.plaintext_field, field.plaintext_field);
     }
 
+    #[tokio::test]
+    async fn encrypt_compound_deterministic_roundtrip() {
+        let client = get_default_client();
+        let values = vec![
+            CompoundFieldValue {
+                name: "last".to_string(),
+                value: b"Smith".to_vec(),
+            },
+            CompoundFieldValue {
+                name: "first".to_string(),
+                value: b"Jo".to_vec(),
+            },
+        ];
+        let field = PlaintextCompoundField {
+            values: values.clone(),
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+        };
+        let encrypted = client
+            .encrypt_compound(field.clone(), &get_metadata())
+            .await
+            .unwrap();
+        let query = client
+            .generate_query_compound_field_values(
+                [("field".to_string(), field)].into(),
+                &get_metadata(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(query["field"][0].encrypted_field, encrypted.encrypted_field);
+        let decrypted = client
+            .decrypt_compound(encrypted, &get_metadata())
+            .await
+            .unwrap();
+        assert_eq!(decrypted.values, values);
+    }
+
+    #[tokio::test]
+    async fn rotate_fields_multi_tenant_roundtrip() {
+        let client = get_default_client();
+        let field = PlaintextField {
+            plaintext_field: vec![1, 2, 3],
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+        };
+        let tenant_a = TenantId("tenant_a".to_string());
+        let tenant_b = TenantId("tenant_b".to_string());
+        let encrypted_a = client
+            .encrypt(field.clone(), &AlloyMetadata::new_simple(tenant_a.clone()))
+            .await
+            .unwrap();
+        let encrypted_b = client
+            .encrypt(field.clone(), &AlloyMetadata::new_simple(tenant_b.clone()))
+            .await
+            .unwrap();
+        let fields = [
+            (
+                "a".to_string(),
+                TenantEncryptedField {
+                    encrypted_field: encrypted_a,
+                    tenant_id: tenant_a.clone(),
+                    new_tenant_id: Some(tenant_b.clone()),
+                },
+            ),
+            (
+                "b".to_string(),
+                TenantEncryptedField {
+                    encrypted_field: encrypted_b.clone(),
+                    tenant_id: tenant_b.clone(),
+                    new_tenant_id: None,
+                },
+            ),
+            (
+                "wrong_tenant".to_string(),
+                TenantEncryptedField {
+                    encrypted_field: encrypted_b.clone(),
+                    tenant_id: tenant_a,
+                    new_tenant_id: Some(tenant_b),
+                },
+            ),
+        ]
+        .into();
+        let result = client
+            .rotate_fields_multi_tenant(fields, &get_metadata())
+            .await
+            .unwrap();
+        assert_eq!(result.successes.len(), 2);
+        assert_eq!(
+            result.successes["a"].encrypted_field,
+            encrypted_b.encrypted_field
+        );
+        assert_eq!(
+            result.successes["b"].encrypted_field,
+            encrypted_b.encrypted_field
+        );
+        assert!(result.failures.contains_key("wrong_tenant"));
+    }
+
     #[tokio::test]
     async fn document_deterministic_decrypt_known_bytes() {
         let client = get_default_client();
@@ -1,5 +1,7 @@
 pub mod config;
 pub mod deterministic;
+pub mod security_events;
+pub mod sparse_vector;
 pub mod standard;
 pub mod standard_attached;
 pub mod vector;
@@ -0,0 +1,373 @@
+use crate::errors::{AlloyError, ErrorKind};
+use crate::saas_shield::{DataEvent, SecurityEvent};
+use crate::AlloyMetadata;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Destination for the security events logged by Standalone clients, which have no TSP to send them to. Implement
+/// this to forward events to your own audit log, or use `JsonLinesSecurityEventSink` or `InMemorySecurityEventSink`.
+///
+/// Sinks are called inline, on the thread of the operation that logs the event, and that operation doesn't finish
+/// until `record_event` returns. Async callers' executor threads are blocked for that long too, so a sink that does
+/// slow I/O should hand events off to a queue or background thread of its own.
+#[cfg_attr(feature = "uniffi", uniffi::export(with_foreign))]
+pub trait SecurityEventSink: Send + Sync {
+    /// Record that `event` happened at `event_time_millis` (milliseconds since the Unix epoch) for the tenant and
+    /// request described by `metadata`.
+    fn record_event(
+        &self,
+        event: SecurityEvent,
+        metadata: Arc<AlloyMetadata>,
+        event_time_millis: i64,
+    ) -> Result<(), AlloyError>;
+}
+
+pub trait StandaloneSecurityEventOps {
+    /// Log the security event `event` to the configured `SecurityEventSink`.
+    /// If the event time is unspecified the current time will be used.
+    async fn log_security_event(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError>;
+}
+
+/// The security event sink a Standalone client logs to, if any.
+#[derive(Clone, Default)]
+pub(crate) struct SecurityEventLogger {
+    sink: Option<Arc<dyn SecurityEventSink>>,
+    automatic_data_events: bool,
+}
+
+impl std::fmt::Debug for SecurityEventLogger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecurityEventLogger")
+            .field("has_sink", &self.sink.is_some())
+            .field("automatic_data_events", &self.automatic_data_events)
+            .finish()
+    }
+}
+
+impl SecurityEventLogger {
+    pub(crate) fn new(sink: Arc<dyn SecurityEventSink>, automatic_data_events: bool) -> Self {
+        SecurityEventLogger {
+            sink: Some(sink),
+            automatic_data_events,
+        }
+    }
+
+    pub(crate) fn log(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError> {
+        let sink = self
+            .sink
+            .as_ref()
+            .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::SecurityEventNotRecorded,
+                msg: "No security event sink was provided in the Standalone configuration."
+                    .to_string(),
+            })?;
+        let event_time_millis = event_time_millis.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as i64)
+                .unwrap_or_default()
+        });
+        sink.record_event(event, Arc::new(metadata.clone()), event_time_millis)
+    }
+
+    /// Log `event` after a successful operation if automatic data events are on. The operation's result is returned
+    /// unchanged; failing to log the event doesn't fail the operation.
+    pub(crate) fn after_data_operation<T>(
+        &self,
+        event: DataEvent,
+        metadata: &AlloyMetadata,
+        result: Result<T, AlloyError>,
+    ) -> Result<T, AlloyError> {
+        if self.automatic_data_events && result.is_ok() {
+            let _ = self.log(SecurityEvent::Data { event }, metadata, None);
+        }
+        result
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonLinesEvent<'a> {
+    event: String,
+    event_time_millis: i64,
+    #[serde(flatten)]
+    metadata: &'a AlloyMetadata,
+}
+
+/// Appends each security event to a file as one line of JSON, with the event name (like `DATA_ENCRYPT`), the event
+/// time and the fields of its `AlloyMetadata`. Each event is written and flushed before `record_event` returns, so
+/// every logged operation waits on a blocking file write, one at a time across all clients sharing the sink.
+#[derive(Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
+pub struct JsonLinesSecurityEventSink {
+    file: Mutex<File>,
+}
+
+uniffi_constructors! {
+impl JsonLinesSecurityEventSink {
+    /// Open `path` for appending, creating it if it doesn't exist.
+    pub fn new(path: String) -> Result<Arc<Self>, AlloyError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::Other,
+                msg: format!("Couldn't open security event log `{path}`: {e}"),
+            })?;
+        Ok(Arc::new(JsonLinesSecurityEventSink {
+            file: Mutex::new(file),
+        }))
+    }
+}
+}
+
+impl SecurityEventSink for JsonLinesSecurityEventSink {
+    fn record_event(
+        &self,
+        event: SecurityEvent,
+        metadata: Arc<AlloyMetadata>,
+        event_time_millis: i64,
+    ) -> Result<(), AlloyError> {
+        let mut line = serde_json::to_vec(&JsonLinesEvent {
+            event: event.to_string(),
+            event_time_millis,
+            metadata: &metadata,
+        })?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(&line)
+            .and_then(|_| file.flush())
+            .map_err(|e| AlloyError::RequestError {
+                kind: ErrorKind::SecurityEventNotRecorded,
+                msg: format!("Couldn't write security event: {e}"),
+                source: None,
+            })
+    }
+}
+
+/// A security event recorded by `InMemorySecurityEventSink`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct LoggedSecurityEvent {
+    pub event: SecurityEvent,
+    pub metadata: Arc<AlloyMetadata>,
+    pub event_time_millis: i64,
+}
+
+/// Keeps security events in memory, mostly useful for tests.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
+pub struct InMemorySecurityEventSink {
+    events: Mutex<Vec<LoggedSecurityEvent>>,
+}
+
+uniffi_constructors! {
+impl InMemorySecurityEventSink {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+}
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl InMemorySecurityEventSink {
+    /// All events recorded so far, oldest first.
+    pub fn events(&self) -> Vec<LoggedSecurityEvent> {
+        self.events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn clear(&self) {
+        self.events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear()
+    }
+}
+
+impl SecurityEventSink for InMemorySecurityEventSink {
+    fn record_event(
+        &self,
+        event: SecurityEvent,
+        metadata: Arc<AlloyMetadata>,
+        event_time_millis: i64,
+    ) -> Result<(), AlloyError> {
+        self.events
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(LoggedSecurityEvent {
+                event,
+                metadata,
+                event_time_millis,
+            });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::deterministic::{DeterministicFieldOps, PlaintextField};
+    use crate::saas_shield::UserEvent;
+    use crate::standalone::config::{StandaloneConfiguration, StandaloneSecret, StandardSecrets};
+    use crate::standard::StandardDocumentOps;
+    use crate::{DerivationPath, Secret, SecretPath, Standalone};
+    use std::collections::HashMap;
+
+    fn standalone(
+        sink: Arc<dyn SecurityEventSink>,
+        automatic_data_events: bool,
+    ) -> Arc<Standalone> {
+        let secret = Secret::new([0u8; 32].to_vec()).unwrap();
+        let standard =
+            StandardSecrets::new(Some(1), vec![StandaloneSecret::new(1, secret)]).unwrap();
+        Standalone::new(&StandaloneConfiguration::new_with_security_events(
+            standard,
+            HashMap::new(),
+            HashMap::new(),
+            sink,
+            automatic_data_events,
+        ))
+    }
+
+    #[tokio::test]
+    async fn automatic_data_events_are_logged_on_success() {
+        let sink = InMemorySecurityEventSink::new();
+        let client = standalone(sink.clone(), true).standard();
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        let encrypted = client.encrypt([].into(), &metadata).await.unwrap();
+        client.decrypt(encrypted.clone(), &metadata).await.unwrap();
+        let other_tenant = AlloyMetadata::new_simple("other".into());
+        assert!(client.decrypt(encrypted, &other_tenant).await.is_err());
+        // Deterministic fails because no secret path is configured, so nothing is logged.
+        assert!(standalone(sink.clone(), true)
+            .deterministic()
+            .encrypt(
+                PlaintextField {
+                    plaintext_field: vec![1],
+                    secret_path: SecretPath("path".to_string()),
+                    derivation_path: DerivationPath("path".to_string()),
+                },
+                &metadata,
+            )
+            .await
+            .is_err());
+
+        let events = sink.events();
+        assert_eq!(
+            events.iter().map(|e| e.event.clone()).collect::<Vec<_>>(),
+            vec![
+                SecurityEvent::Data {
+                    event: DataEvent::Encrypt
+                },
+                SecurityEvent::Data {
+                    event: DataEvent::Decrypt
+                },
+            ]
+        );
+        assert_eq!(events[0].metadata.tenant_id.0, "tenant");
+        assert!(events[0].event_time_millis > 0);
+    }
+
+    #[tokio::test]
+    async fn data_events_are_opt_in() {
+        let sink = InMemorySecurityEventSink::new();
+        let client = standalone(sink.clone(), false).standard();
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        client.encrypt([].into(), &metadata).await.unwrap();
+        client
+            .log_security_event(
+                SecurityEvent::User {
+                    event: UserEvent::Login,
+                },
+                &metadata,
+                Some(12),
+            )
+            .await
+            .unwrap();
+        let events = sink.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_time_millis, 12);
+        sink.clear();
+        assert!(sink.events().is_empty());
+    }
+
+    #[tokio::test]
+    async fn logging_without_a_sink_fails() {
+        let logger = SecurityEventLogger::default();
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        let result = logger.log(
+            SecurityEvent::Data {
+                event: DataEvent::Export,
+            },
+            &metadata,
+            None,
+        );
+        assert!(matches!(
+            result,
+            Err(AlloyError::InvalidConfiguration { .. })
+        ));
+        // The operation's result still comes through unchanged.
+        assert_eq!(
+            logger
+                .after_data_operation(DataEvent::Encrypt, &metadata, Ok(1))
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn json_lines_sink_appends_one_object_per_event() {
+        let path = std::env::temp_dir().join(format!(
+            "alloy-security-events-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let sink = JsonLinesSecurityEventSink::new(path.to_string_lossy().into_owned()).unwrap();
+        let metadata = AlloyMetadata::new_simple("tenant".into());
+        sink.record_event(
+            SecurityEvent::Data {
+                event: DataEvent::Encrypt,
+            },
+            metadata.clone(),
+            1,
+        )
+        .unwrap();
+        sink.record_event(
+            SecurityEvent::Admin {
+                event: crate::saas_shield::AdminEvent::Add,
+            },
+            metadata,
+            2,
+        )
+        .unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines = contents
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "DATA_ENCRYPT");
+        assert_eq!(lines[0]["eventTimeMillis"], 1);
+        assert_eq!(lines[0]["tenantId"], "tenant");
+        assert_eq!(lines[1]["event"], "ADMIN_ADD");
+    }
+}
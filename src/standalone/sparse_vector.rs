@@ -0,0 +1,581 @@
+use super::config::VectorSecret;
+use crate::errors::{AlloyError, ErrorKind};
+use crate::saas_shield::{DataEvent, SecurityEvent};
+use crate::standalone::config::RotatableSecret;
+use crate::standalone::security_events::{SecurityEventLogger, StandaloneSecurityEventOps};
+use crate::telemetry::Operation;
+use crate::util::{collection_to_batch_result, get_rng, ShardedRng};
+use crate::vector::get_vector_metadata;
+use crate::vector::sparse::{
+    decrypt_sparse_internal, encrypt_sparse_internal, EncryptedSparseVector,
+    EncryptedSparseVectors, GenerateSparseQueryResult, PlaintextSparseVector,
+    PlaintextSparseVectors, SparseVectorOps, SparseVectorRotateResult,
+};
+use crate::{
+    alloy_client_trait::AlloyClient, AlloyMetadata, DerivationPath, SecretPath,
+    StandaloneConfiguration, TenantId, VectorEncryptionKey,
+};
+use ironcore_documents::v5::key_id_header::{EdekType, KeyId, PayloadType};
+use itertools::Itertools;
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Encrypts sparse embeddings using the vector secrets from the configuration.
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
+pub struct StandaloneSparseVectorClient {
+    config: Arc<HashMap<SecretPath, Arc<VectorSecret>>>,
+    rng: Arc<ShardedRng<ChaCha20Rng>>,
+    security_events: SecurityEventLogger,
+}
+impl StandaloneSparseVectorClient {
+    pub(crate) fn new(config: StandaloneConfiguration) -> Self {
+        Self {
+            config: config.vector.clone(),
+            rng: Arc::new(ShardedRng::new(ChaCha20Rng::from_entropy)),
+            security_events: config.security_events.clone(),
+        }
+    }
+
+    fn get_vector_secret(
+        &self,
+        secret_path: &SecretPath,
+    ) -> Result<&Arc<VectorSecret>, AlloyError> {
+        self.config
+            .get(secret_path)
+            .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::UnknownSecretPath,
+                msg: format!(
+                    "Provided secret path `{}` does not exist in the vector configuration.",
+                    &secret_path.0
+                ),
+            })
+    }
+
+    fn encrypt_sync(
+        &self,
+        plaintext_vector: PlaintextSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedSparseVector, AlloyError> {
+        let vector_secret = self.get_vector_secret(&plaintext_vector.secret_path)?;
+        vector_secret
+            .options
+            .check_sparse(&plaintext_vector.secret_path)?;
+        let standalone_secret = vector_secret
+            .secret
+            .current_secret
+            .as_ref()
+            .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::MissingPrimarySecret,
+                msg: "No current secret exists in the vector configuration".to_string(),
+            })?;
+        let key = VectorEncryptionKey::derive_from_secret(
+            standalone_secret.secret.as_ref(),
+            &metadata.tenant_id,
+            &plaintext_vector.derivation_path,
+        );
+        encrypt_sparse_internal(
+            vector_secret.approximation_factor,
+            &key,
+            KeyId(standalone_secret.id),
+            Self::get_edek_type(),
+            plaintext_vector,
+            &mut *get_rng(&self.rng),
+        )
+    }
+
+    fn decrypt_sync(
+        &self,
+        encrypted_vector: EncryptedSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextSparseVector, AlloyError> {
+        let (key_id, icl_metadata_bytes) =
+            Self::decompose_key_id_header(encrypted_vector.paired_icl_info.clone())?;
+        let vector_secret = self.get_vector_secret(&encrypted_vector.secret_path)?;
+        let standalone_secret = vector_secret
+            .secret
+            .get_secret_with_id(&key_id)
+            .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::UnknownSecretId,
+                msg: format!(
+                    "Secret with key ID `{}` does not exist in the vector configuration",
+                    key_id.0
+                ),
+            })?;
+        let key = VectorEncryptionKey::derive_from_secret(
+            standalone_secret.secret.as_ref(),
+            &metadata.tenant_id,
+            &encrypted_vector.derivation_path,
+        );
+        let vector_metadata = get_vector_metadata(&icl_metadata_bytes)?;
+        decrypt_sparse_internal(
+            vector_metadata
+                .approximation_factor
+                .unwrap_or(vector_secret.approximation_factor),
+            &key,
+            encrypted_vector,
+            vector_metadata,
+        )
+    }
+
+    /// Rotation goes through the sync encrypt and decrypt paths so it doesn't log data events for every vector it
+    /// re-encrypts.
+    fn rotate_vector(
+        &self,
+        encrypted_vector: EncryptedSparseVector,
+        metadata: &AlloyMetadata,
+        new_metadata: &AlloyMetadata,
+    ) -> Result<EncryptedSparseVector, AlloyError> {
+        let (original_key_id, icl_metadata_bytes) =
+            Self::decompose_key_id_header(encrypted_vector.paired_icl_info.clone())?;
+        let vector_metadata = get_vector_metadata(&icl_metadata_bytes)?;
+        let vector_secret = self.get_vector_secret(&encrypted_vector.secret_path)?;
+        let standalone_secret = vector_secret
+            .secret
+            .current_secret
+            .as_ref()
+            .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::MissingPrimarySecret,
+                msg: "No current secret exists in the vector configuration".to_string(),
+            })?;
+        if original_key_id.0 == standalone_secret.id
+            && metadata.tenant_id == new_metadata.tenant_id
+            && vector_metadata.approximation_factor == Some(vector_secret.approximation_factor)
+        {
+            Ok(encrypted_vector)
+        } else {
+            self.decrypt_sync(encrypted_vector, metadata)
+                .and_then(|decrypted_vector| self.encrypt_sync(decrypted_vector, new_metadata))
+        }
+    }
+}
+
+impl AlloyClient for StandaloneSparseVectorClient {
+    fn get_edek_type() -> EdekType {
+        EdekType::Standalone
+    }
+
+    fn get_payload_type() -> PayloadType {
+        PayloadType::VectorMetadata
+    }
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl SparseVectorOps for StandaloneSparseVectorClient {
+    /// Encrypt a sparse embedding with the provided metadata. Indices are hidden with a keyed permutation and values
+    /// are encrypted like dense embedding values, using the approximation factor configured for the secret path.
+    /// The same tenant ID must be provided in the metadata when decrypting the embedding.
+    async fn encrypt(
+        &self,
+        plaintext_vector: PlaintextSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedSparseVector, AlloyError> {
+        let secret_path = plaintext_vector.secret_path.0.clone();
+        let result = Operation::new("sparse_vector.encrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .secret_path(&secret_path)
+            .run(async move { self.encrypt_sync(plaintext_vector, metadata) })
+            .await;
+        self.security_events
+            .after_data_operation(DataEvent::Encrypt, metadata, result)
+    }
+
+    /// Decrypt a sparse embedding that was encrypted with the provided metadata. The result is sorted by index.
+    async fn decrypt(
+        &self,
+        encrypted_vector: EncryptedSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextSparseVector, AlloyError> {
+        let secret_path = encrypted_vector.secret_path.0.clone();
+        let result = Operation::new("sparse_vector.decrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .secret_path(&secret_path)
+            .run(async move { self.decrypt_sync(encrypted_vector, metadata) })
+            .await;
+        self.security_events
+            .after_data_operation(DataEvent::Decrypt, metadata, result)
+    }
+
+    /// Encrypt each sparse embedding with any Current and InRotation keys for the provided secret path.
+    /// The resulting encrypted vectors should be used in tandem when querying the vector database.
+    async fn generate_query_vectors(
+        &self,
+        vectors_to_query: PlaintextSparseVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<GenerateSparseQueryResult, AlloyError> {
+        Operation::new("sparse_vector.generate_query_vectors")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(vectors_to_query.len())
+            .run(async move {
+                vectors_to_query
+                    .into_iter()
+                    .map(|(vector_id, plaintext_vector)| {
+                        let vector_secret =
+                            self.get_vector_secret(&plaintext_vector.secret_path)?;
+                        vector_secret
+                            .options
+                            .check_sparse(&plaintext_vector.secret_path)?;
+                        let RotatableSecret {
+                            current_secret,
+                            in_rotation_secret,
+                        } = vector_secret.secret.as_ref();
+                        if current_secret.is_none() && in_rotation_secret.is_none() {
+                            Err(AlloyError::InvalidConfiguration {
+                                kind: ErrorKind::UnknownSecretPath,
+                                msg: format!(
+                            "No secrets exist in the vector configuration for secret path `{}`.",
+                            plaintext_vector.secret_path.0
+                        ),
+                            })?;
+                        }
+                        current_secret
+                            .iter()
+                            .chain(in_rotation_secret)
+                            .map(|standalone_secret| {
+                                let key = VectorEncryptionKey::derive_from_secret(
+                                    standalone_secret.secret.as_ref(),
+                                    &metadata.tenant_id,
+                                    &plaintext_vector.derivation_path,
+                                );
+                                encrypt_sparse_internal(
+                                    vector_secret.approximation_factor,
+                                    &key,
+                                    KeyId(standalone_secret.id),
+                                    Self::get_edek_type(),
+                                    plaintext_vector.clone(),
+                                    &mut *get_rng(&self.rng),
+                                )
+                            })
+                            .try_collect()
+                            .map(|enc| (vector_id, enc))
+                    })
+                    .try_collect()
+            })
+            .await
+    }
+
+    /// Get the byte prefix for the InRotation secret corresponding to this secret_path.
+    /// Pass the result to `encode_search_prefixes` to get the string prefixes to search an encoded datastore with.
+    /// Note: The derivation_path and metadata are not actually required for this function and can be passed any value.
+    async fn get_in_rotation_prefix(
+        &self,
+        secret_path: SecretPath,
+        _derivation_path: DerivationPath,
+        _metadata: &AlloyMetadata,
+    ) -> Result<Vec<u8>, AlloyError> {
+        let in_rotation_secret = self
+            .get_vector_secret(&secret_path)?
+            .secret
+            .in_rotation_secret
+            .as_ref()
+            .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::MissingPrimarySecret,
+                msg: "There is no in-rotation secret in the vector configuration.".to_string(),
+            })?;
+        let key_id_header = Self::create_key_id_header(in_rotation_secret.id);
+        Ok(
+            ironcore_documents::v5::key_id_header::get_prefix_bytes_for_search(key_id_header)
+                .into(),
+        )
+    }
+
+    /// Rotates sparse vectors from the in-rotation secret for their secret path to the current secret, or from one
+    /// tenant ID to a new one. Like dense vectors, they are also re-encrypted if their recorded approximation factor
+    /// doesn't match the configured one.
+    /// The same lossiness warning as dense vector rotation applies to the values.
+    async fn rotate_vectors(
+        &self,
+        encrypted_vectors: EncryptedSparseVectors,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<SparseVectorRotateResult, AlloyError> {
+        Operation::new("sparse_vector.rotate_vectors")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(encrypted_vectors.len())
+            .run(async move {
+                let new_metadata = match new_tenant_id {
+                    None => metadata.clone(),
+                    Some(tenant_id) => AlloyMetadata {
+                        tenant_id: tenant_id.clone(),
+                        ..metadata.clone()
+                    },
+                };
+                Ok(
+                    collection_to_batch_result(encrypted_vectors, |encrypted_vector| {
+                        self.rotate_vector(encrypted_vector, metadata, &new_metadata)
+                    })
+                    .into(),
+                )
+            })
+            .await
+    }
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl StandaloneSecurityEventOps for StandaloneSparseVectorClient {
+    /// Log the security event `event` to the configured `SecurityEventSink`.
+    /// If the event time is unspecified the current time will be used.
+    async fn log_security_event(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError> {
+        self.security_events.log(event, metadata, event_time_millis)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::standalone::config::{StandaloneSecret, StandardSecrets};
+    use crate::standalone::security_events::InMemorySecurityEventSink;
+    use crate::vector::{PlaintextVector, VectorEncryptionMode, VectorOps, VectorSecretOptions};
+    use crate::{standalone::vector::StandaloneVectorClient, Secret};
+    use approx::assert_abs_diff_eq;
+
+    fn secret(id: u32, byte: u8) -> Arc<StandaloneSecret> {
+        Arc::new(StandaloneSecret {
+            id,
+            secret: Arc::new(Secret {
+                secret: vec![byte; 32],
+            }),
+        })
+    }
+
+    fn get_client(
+        current_secret: Option<Arc<StandaloneSecret>>,
+        in_rotation_secret: Option<Arc<StandaloneSecret>>,
+    ) -> StandaloneSparseVectorClient {
+        let vector_secret = VectorSecret {
+            approximation_factor: 4.0f32,
+            secret: Arc::new(RotatableSecret {
+                current_secret,
+                in_rotation_secret,
+            }),
+            options: VectorSecretOptions::default(),
+        };
+        StandaloneSparseVectorClient {
+            rng: Arc::new(ShardedRng::single(ChaCha20Rng::seed_from_u64(1u64))),
+            config: Arc::new(
+                [(
+                    SecretPath("secret_path".to_string()),
+                    Arc::new(vector_secret),
+                )]
+                .into(),
+            ),
+            security_events: SecurityEventLogger::default(),
+        }
+    }
+
+    fn get_metadata() -> Arc<AlloyMetadata> {
+        AlloyMetadata::new_simple(TenantId("foo".to_string()))
+    }
+
+    fn get_plaintext() -> PlaintextSparseVector {
+        PlaintextSparseVector {
+            indices: vec![2045, 7, 30000],
+            values: vec![1.5, 0.25, 0.75],
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn encrypt_decrypt_roundtrip() {
+        let client = get_client(Some(secret(1, 1)), None);
+        let encrypted = client
+            .encrypt(get_plaintext(), &get_metadata())
+            .await
+            .unwrap();
+        let decrypted = client.decrypt(encrypted, &get_metadata()).await.unwrap();
+        assert_eq!(decrypted.indices, vec![7, 2045, 30000]);
+        assert_abs_diff_eq!(
+            &decrypted.values[..],
+            &[0.25, 1.5, 0.75][..],
+            epsilon = 1e-5
+        );
+    }
+
+    #[tokio::test]
+    async fn decrypt_with_wrong_tenant_fails() {
+        let client = get_client(Some(secret(1, 1)), None);
+        let encrypted = client
+            .encrypt(get_plaintext(), &get_metadata())
+            .await
+            .unwrap();
+        let err = client
+            .decrypt(
+                encrypted,
+                &AlloyMetadata::new_simple(TenantId("bar".to_string())),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidInput { .. }));
+    }
+
+    #[tokio::test]
+    async fn encrypt_rejects_quantization_friendly_mode() {
+        let mut client = get_client(Some(secret(1, 1)), None);
+        let vector_secret = VectorSecret {
+            approximation_factor: 4.0f32,
+            secret: client.config[&SecretPath("secret_path".to_string())]
+                .secret
+                .clone(),
+            options: VectorSecretOptions {
+                mode: VectorEncryptionMode::QuantizationFriendly,
+                accept_quantization_friendly_leakage: true,
+                ..Default::default()
+            },
+        };
+        client.config = Arc::new(
+            [(
+                SecretPath("secret_path".to_string()),
+                Arc::new(vector_secret),
+            )]
+            .into(),
+        );
+        let err = client
+            .encrypt(get_plaintext(), &get_metadata())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidConfiguration { .. }));
+        assert_eq!(err.kind(), ErrorKind::InvalidVectorOptions);
+        let err = client
+            .generate_query_vectors(
+                [("query".to_string(), get_plaintext())].into(),
+                &get_metadata(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidConfiguration { .. }));
+    }
+
+    #[tokio::test]
+    async fn dense_client_rejects_sparse_vectors() {
+        let client = get_client(Some(secret(1, 1)), None);
+        let encrypted = client
+            .encrypt(get_plaintext(), &get_metadata())
+            .await
+            .unwrap();
+        let dense_client = StandaloneVectorClient::new(StandaloneConfiguration {
+            standard: StandardSecrets::new(None, vec![]).unwrap(),
+            deterministic: Arc::new(HashMap::new()),
+            vector: client.config.clone(),
+            security_events: SecurityEventLogger::default(),
+        });
+        let err = dense_client
+            .decrypt(
+                crate::vector::EncryptedVector {
+                    encrypted_vector: encrypted.values,
+                    secret_path: encrypted.secret_path,
+                    derivation_path: encrypted.derivation_path,
+                    paired_icl_info: encrypted.paired_icl_info,
+                },
+                &get_metadata(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AlloyError::DecryptError { .. }));
+        // and the dense client's vectors aren't sparse vectors
+        let dense = dense_client
+            .encrypt(
+                PlaintextVector {
+                    plaintext_vector: vec![0.5],
+                    secret_path: SecretPath("secret_path".to_string()),
+                    derivation_path: DerivationPath("deriv_path".to_string()),
+                },
+                &get_metadata(),
+            )
+            .await
+            .unwrap();
+        let err = client
+            .decrypt(
+                EncryptedSparseVector {
+                    indices: vec![0],
+                    values: dense.encrypted_vector,
+                    secret_path: dense.secret_path,
+                    derivation_path: dense.derivation_path,
+                    paired_icl_info: dense.paired_icl_info,
+                },
+                &get_metadata(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AlloyError::DecryptError { .. }));
+    }
+
+    #[tokio::test]
+    async fn generate_query_vectors_uses_both_secrets() {
+        let client = get_client(Some(secret(2, 2)), Some(secret(1, 1)));
+        let result = client
+            .generate_query_vectors(
+                [("query".to_string(), get_plaintext())].into(),
+                &get_metadata(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result["query"].len(), 2);
+        assert_ne!(result["query"][0].indices, result["query"][1].indices);
+    }
+
+    #[tokio::test]
+    async fn rotate_roundtrip() {
+        let old_client = get_client(Some(secret(1, 1)), None);
+        let encrypted = old_client
+            .encrypt(get_plaintext(), &get_metadata())
+            .await
+            .unwrap();
+        let new_client = get_client(Some(secret(2, 2)), Some(secret(1, 1)));
+        let new_tenant = TenantId("bar".to_string());
+        let rotated = new_client
+            .rotate_vectors(
+                [("vector".to_string(), encrypted)].into(),
+                &get_metadata(),
+                Some(new_tenant.clone()),
+            )
+            .await
+            .unwrap();
+        assert!(rotated.failures.is_empty());
+        let rotated_vector = rotated.successes["vector"].clone();
+        let (key_id, _) = StandaloneSparseVectorClient::decompose_key_id_header(
+            rotated_vector.paired_icl_info.clone(),
+        )
+        .unwrap();
+        assert_eq!(key_id, KeyId(2));
+        let decrypted = new_client
+            .decrypt(rotated_vector, &AlloyMetadata::new_simple(new_tenant))
+            .await
+            .unwrap();
+        assert_eq!(decrypted.indices, vec![7, 2045, 30000]);
+        assert_abs_diff_eq!(
+            &decrypted.values[..],
+            &[0.25, 1.5, 0.75][..],
+            epsilon = 1e-4
+        );
+    }
+
+    #[tokio::test]
+    async fn rotate_logs_no_data_events() {
+        let encrypted = get_client(Some(secret(1, 1)), None)
+            .encrypt(get_plaintext(), &get_metadata())
+            .await
+            .unwrap();
+        let sink = InMemorySecurityEventSink::new();
+        let new_client = StandaloneSparseVectorClient {
+            security_events: SecurityEventLogger::new(sink.clone(), true),
+            ..get_client(Some(secret(2, 2)), Some(secret(1, 1)))
+        };
+        let rotated = new_client
+            .rotate_vectors(
+                [("vector".to_string(), encrypted)].into(),
+                &get_metadata(),
+                Some(TenantId("bar".to_string())),
+            )
+            .await
+            .unwrap();
+        assert!(rotated.failures.is_empty());
+        assert!(sink.events().is_empty());
+    }
+}
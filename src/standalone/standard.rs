@@ -1,12 +1,15 @@
 // Standard standalone works for V4 and V5 documents. There is no suport for V3 since Standalone wasn't
 // available in V3.
-use crate::errors::AlloyError;
+use crate::errors::{AlloyError, ErrorKind};
+use crate::saas_shield::{DataEvent, SecurityEvent};
+use crate::standalone::security_events::{SecurityEventLogger, StandaloneSecurityEventOps};
 use crate::standard::{
     decrypt_document_core, encrypt_document_core, encrypt_map, verify_sig, EdekWithKeyIdHeader,
     EncryptedDocument, PlaintextDocument, PlaintextDocumentWithEdek, RekeyEdeksBatchResult,
     StandardDocumentOps,
 };
-use crate::util::{collection_to_batch_result, get_rng, hash256, OurReseedingRng};
+use crate::telemetry::Operation;
+use crate::util::{collection_to_batch_result, get_rng, hash256, OurReseedingRng, ShardedRng};
 use crate::{alloy_client_trait::AlloyClient, AlloyMetadata, Secret, TenantId};
 use ironcore_documents::aes::EncryptionKey;
 use ironcore_documents::v5::key_id_header::{EdekType, KeyId, PayloadType};
@@ -16,20 +19,22 @@ use rand::{CryptoRng, RngCore};
 use ring::digest::SHA256_OUTPUT_LEN;
 use ring::hkdf;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
 use super::config::{StandaloneConfiguration, StandardSecrets};
 
-#[derive(uniffi::Object)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct StandaloneStandardClient {
     config: Arc<StandardSecrets>,
-    rng: Arc<Mutex<OurReseedingRng>>,
+    rng: Arc<ShardedRng<OurReseedingRng>>,
+    security_events: SecurityEventLogger,
 }
 impl StandaloneStandardClient {
     pub(crate) fn new(config: StandaloneConfiguration) -> Self {
         Self {
             config: config.standard.clone(),
             rng: crate::util::create_reseeding_rng(),
+            security_events: config.security_events.clone(),
         }
     }
 
@@ -38,6 +43,7 @@ impl StandaloneStandardClient {
             self.config
                 .primary_secret_id
                 .ok_or_else(|| AlloyError::InvalidConfiguration {
+                    kind: ErrorKind::MissingPrimarySecret,
                     msg: "No primary secret exists in the standard configuration".to_string(),
                 })?;
         self.config
@@ -45,6 +51,7 @@ impl StandaloneStandardClient {
             .get(&primary_secret_id)
             .map(|secret| (primary_secret_id, secret))
             .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::MissingPrimarySecret,
                 msg: "Primary secret id not found in secrets map".to_string(),
             })
     }
@@ -55,7 +62,7 @@ impl StandaloneStandardClient {
         incoming_key: &[u8],
         document: HashMap<String, U>,
         key_id: KeyId,
-        rng: Arc<Mutex<R>>,
+        rng: Arc<ShardedRng<R>>,
         tenant_id: &TenantId,
     ) -> Result<EncryptedDocument, AlloyError> {
         let per_tenant_kek = derive_aes_encryption_key(&incoming_key, tenant_id);
@@ -91,6 +98,7 @@ impl StandaloneStandardClient {
                 config_secrets
                     .get(&key_id.0)
                     .ok_or_else(|| AlloyError::InvalidConfiguration {
+                        kind: ErrorKind::UnknownSecretId,
                         msg: format!(
                             "Provided secret id `{}` does not exist in the standard configuration.",
                             &key_id.0
@@ -120,6 +128,7 @@ impl StandaloneStandardClient {
             .map(attempt_decrypt)
             .find_or_first(|result| result.is_ok())
             .unwrap_or(Err(AlloyError::InvalidConfiguration {
+                kind: ErrorKind::UnknownSecretId,
                 msg: "No secret could be found to decrypt".to_string(),
             }))
     }
@@ -135,7 +144,7 @@ impl AlloyClient for StandaloneStandardClient {
     }
 }
 
-#[uniffi::export]
+#[cfg_attr(feature = "uniffi", uniffi::export)]
 impl StandardDocumentOps for StandaloneStandardClient {
     /// Encrypt a document with the provided metadata. The document must be a map from field identifiers to plaintext
     /// bytes, and the same metadata must be provided when decrypting the document.
@@ -151,15 +160,22 @@ impl StandardDocumentOps for StandaloneStandardClient {
         plaintext_document: PlaintextDocument,
         metadata: &AlloyMetadata,
     ) -> Result<EncryptedDocument, AlloyError> {
-        let (secret_id, secret) = self.get_current_secret_and_id()?;
-        let encrypted_doc = Self::encrypt_document(
-            &secret.secret,
-            plaintext_document,
-            KeyId(secret_id),
-            self.rng.clone(),
-            &metadata.tenant_id,
-        )?;
-        Ok(encrypted_doc)
+        let result = Operation::new("standard.encrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .run(async move {
+                let (secret_id, secret) = self.get_current_secret_and_id()?;
+                let encrypted_doc = Self::encrypt_document(
+                    &secret.secret,
+                    plaintext_document,
+                    KeyId(secret_id),
+                    self.rng.clone(),
+                    &metadata.tenant_id,
+                )?;
+                Ok(encrypted_doc)
+            })
+            .await;
+        self.security_events
+            .after_data_operation(DataEvent::Encrypt, metadata, result)
     }
 
     /// Decrypt a document that was encrypted with the provided metadata. The document must have been encrypted with one
@@ -170,12 +186,19 @@ impl StandardDocumentOps for StandaloneStandardClient {
         encrypted_document: EncryptedDocument,
         metadata: &AlloyMetadata,
     ) -> Result<PlaintextDocument, AlloyError> {
-        let dek = Self::decrypt_document_dek(
-            encrypted_document.edek,
-            &self.config.secrets,
-            &metadata.tenant_id,
-        )?;
-        decrypt_document_core(encrypted_document.document, dek)
+        let result = Operation::new("standard.decrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .run(async move {
+                let dek = Self::decrypt_document_dek(
+                    encrypted_document.edek,
+                    &self.config.secrets,
+                    &metadata.tenant_id,
+                )?;
+                decrypt_document_core(encrypted_document.document, dek)
+            })
+            .await;
+        self.security_events
+            .after_data_operation(DataEvent::Decrypt, metadata, result)
     }
 
     /// Decrypt the provided EDEKs and re-encrypt them using the tenant's current key. If `new_tenant_id` is `None`,
@@ -187,24 +210,34 @@ impl StandardDocumentOps for StandaloneStandardClient {
         metadata: &AlloyMetadata,
         new_tenant_id: Option<TenantId>,
     ) -> Result<RekeyEdeksBatchResult, AlloyError> {
-        let parsed_new_tenant_id = new_tenant_id.as_ref().unwrap_or(&metadata.tenant_id);
-        let rekey_edek = |edek: EdekWithKeyIdHeader| {
-            let dek = Self::decrypt_document_dek(edek, &self.config.secrets, &metadata.tenant_id)?;
-            let (current_secret_id, current_secret) = self.get_current_secret_and_id()?;
-            let encryption_key =
-                derive_aes_encryption_key(&current_secret.secret, parsed_new_tenant_id);
-            let (_, v4_doc) = v5::aes::generate_aes_edek_and_sign(
-                &mut *get_rng(&self.rng),
-                encryption_key,
-                Some(dek),
-                current_secret_id.to_string().as_str(),
-            )?;
-            Ok(EdekWithKeyIdHeader::new(
-                Self::create_key_id_header(current_secret_id),
-                v4_doc,
-            ))
-        };
-        Ok(collection_to_batch_result(edeks, rekey_edek).into())
+        Operation::new("standard.rekey_edeks")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(edeks.len())
+            .run(async move {
+                let parsed_new_tenant_id = new_tenant_id.as_ref().unwrap_or(&metadata.tenant_id);
+                let rekey_edek = |edek: EdekWithKeyIdHeader| {
+                    let dek = Self::decrypt_document_dek(
+                        edek,
+                        &self.config.secrets,
+                        &metadata.tenant_id,
+                    )?;
+                    let (current_secret_id, current_secret) = self.get_current_secret_and_id()?;
+                    let encryption_key =
+                        derive_aes_encryption_key(&current_secret.secret, parsed_new_tenant_id);
+                    let (_, v4_doc) = v5::aes::generate_aes_edek_and_sign(
+                        &mut *get_rng(&self.rng),
+                        encryption_key,
+                        Some(dek),
+                        current_secret_id.to_string().as_str(),
+                    )?;
+                    Ok(EdekWithKeyIdHeader::new(
+                        Self::create_key_id_header(current_secret_id),
+                        v4_doc,
+                    ))
+                };
+                Ok(collection_to_batch_result(edeks, rekey_edek).into())
+            })
+            .await
     }
 
     /// Encrypt a document with the provided metadata. The document must be a map from field identifiers to plaintext
@@ -216,19 +249,26 @@ impl StandardDocumentOps for StandaloneStandardClient {
         plaintext_document: PlaintextDocumentWithEdek,
         metadata: &AlloyMetadata,
     ) -> Result<EncryptedDocument, AlloyError> {
-        let dek = Self::decrypt_document_dek(
-            plaintext_document.edek.clone(),
-            &self.config.secrets,
-            &metadata.tenant_id,
-        )?;
-
-        let encrypted_document: HashMap<_, _> =
-            encrypt_map(plaintext_document.document, self.rng.clone(), dek)?;
-
-        Ok(EncryptedDocument {
-            edek: plaintext_document.edek,
-            document: encrypted_document,
-        })
+        let result = Operation::new("standard.encrypt_with_existing_edek")
+            .tenant_id(&metadata.tenant_id.0)
+            .run(async move {
+                let dek = Self::decrypt_document_dek(
+                    plaintext_document.edek.clone(),
+                    &self.config.secrets,
+                    &metadata.tenant_id,
+                )?;
+
+                let encrypted_document: HashMap<_, _> =
+                    encrypt_map(plaintext_document.document, self.rng.clone(), dek)?;
+
+                Ok(EncryptedDocument {
+                    edek: plaintext_document.edek,
+                    document: encrypted_document,
+                })
+            })
+            .await;
+        self.security_events
+            .after_data_operation(DataEvent::Encrypt, metadata, result)
     }
 }
 
@@ -247,6 +287,7 @@ pub(crate) fn decrypt_aes_edek(
     let aes_edek = maybe_edek_wrapper
         .map(|edek| edek.aes_256_gcm_edek())
         .ok_or_else(|| AlloyError::DecryptError {
+            kind: ErrorKind::MalformedHeader,
             msg: "No AES EDEK found.".to_string(),
         })?;
     let aes_dek = v5::aes::decrypt_aes_edek(kek, aes_edek)?;
@@ -284,6 +325,20 @@ fn derive_aes_encryption_key_legacy<K: AsRef<[u8]>>(
     }
 }
 
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl StandaloneSecurityEventOps for StandaloneStandardClient {
+    /// Log the security event `event` to the configured `SecurityEventSink`.
+    /// If the event time is unspecified the current time will be used.
+    async fn log_security_event(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError> {
+        self.security_events.log(event, metadata, event_time_millis)
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod test {
     use super::*;
@@ -323,6 +378,7 @@ pub(crate) mod test {
                 .into(),
             }),
             rng: create_test_seeded_rng(100),
+            security_events: SecurityEventLogger::default(),
         }
     }
     #[tokio::test]
@@ -405,6 +461,7 @@ pub(crate) mod test {
                 .into(),
             }),
             rng: create_test_seeded_rng(100),
+            security_events: SecurityEventLogger::default(),
         };
         let metadata = AlloyMetadata::new_simple(TenantId("foo".to_string()));
         let document: HashMap<_, _> = [("hi".to_string(), vec![1, 2, 3])].into();
@@ -453,6 +510,7 @@ pub(crate) mod test {
         assert_eq!(
             error,
             AlloyError::InvalidConfiguration {
+                kind: ErrorKind::UnknownSecretId,
                 msg: "Provided secret id `4` does not exist in the standard configuration."
                     .to_string()
             }
@@ -469,6 +527,7 @@ pub(crate) mod test {
         assert_eq!(
             error,
             AlloyError::InvalidConfiguration {
+                kind: ErrorKind::MissingPrimarySecret,
                 msg: "No primary secret exists in the standard configuration".to_string()
             }
         );
@@ -485,6 +544,7 @@ pub(crate) mod test {
         assert_eq!(
             error,
             AlloyError::InvalidConfiguration {
+                kind: ErrorKind::MissingPrimarySecret,
                 msg: "Primary secret id not found in secrets map".to_string()
             }
         );
@@ -578,12 +638,9 @@ pub(crate) mod test {
             .into(),
         };
         let error = client.decrypt(encrypted, &metadata).await.unwrap_err();
-        assert_eq!(
-            error,
-            AlloyError::ProtobufError {
-                msg: "Unexpected EOF".to_string()
-            }
-        );
+        assert_eq!(error.to_string(), "Protobuf error: 'Unexpected EOF'");
+        assert_eq!(error.kind(), ErrorKind::Serialization);
+        assert!(std::error::Error::source(&error).is_some());
         Ok(())
     }
 
@@ -598,10 +655,9 @@ pub(crate) mod test {
         let error = client.decrypt(encrypted, &metadata).await.unwrap_err();
         assert_eq!(
             error,
-            AlloyError::InvalidInput{ msg:
+            AlloyError::InvalidInput{ kind: ErrorKind::HeaderTypeMismatch, msg:
                 "The data indicated that this was not a Standalone Standard EDEK wrapped value. Found: SaaS Shield, Deterministic Field"
-                    .to_string()
-            }
+                    .to_string() }
         );
         Ok(())
     }
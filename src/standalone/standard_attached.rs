@@ -1,14 +1,20 @@
-use super::{config::StandaloneConfiguration, standard::StandaloneStandardClient};
+use super::{
+    config::StandaloneConfiguration, security_events::StandaloneSecurityEventOps,
+    standard::StandaloneStandardClient,
+};
 use crate::{
     errors::AlloyError,
+    saas_shield::SecurityEvent,
     standard::StandardDocumentOps,
     standard_attached::{
-        decrypt_core, encrypt_core, EncryptedAttachedDocument, StandardAttachedDocumentOps,
+        decrypt_core, encrypt_core, encrypt_with_existing_edek_core, rekey_documents_core,
+        EncryptedAttachedDocument, RekeyAttachedDocumentsBatchResult, StandardAttachedDocumentOps,
     },
-    AlloyMetadata, PlaintextBytes,
+    AlloyMetadata, PlaintextBytes, TenantId,
 };
+use std::collections::HashMap;
 
-#[derive(uniffi::Object)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct StandaloneAttachedStandardClient {
     standard_client: StandaloneStandardClient,
 }
@@ -21,7 +27,7 @@ impl StandaloneAttachedStandardClient {
     }
 }
 
-#[uniffi::export(async_runtime = "tokio")]
+#[cfg_attr(feature = "uniffi", uniffi::export(async_runtime = "tokio"))]
 impl StandardAttachedDocumentOps for StandaloneAttachedStandardClient {
     /// Encrypt a field with the provided metadata.
     /// A DEK (document encryption key) will be generated and encrypted using a derived key.
@@ -44,20 +50,74 @@ impl StandardAttachedDocumentOps for StandaloneAttachedStandardClient {
         decrypt_core(&self.standard_client, encrypted_field, metadata).await
     }
 
+    /// Encrypt a field with the provided metadata, reusing the EDEK from `existing_document`.
+    /// The existing EDEK will be decrypted and used to encrypt the new field, so no new DEK is generated. This is
+    /// useful when updating a document, as the result keeps the same key ID and EDEK as the original.
+    async fn encrypt_with_existing_edek(
+        &self,
+        plaintext_field: PlaintextBytes,
+        existing_document: EncryptedAttachedDocument,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedAttachedDocument, AlloyError> {
+        encrypt_with_existing_edek_core(
+            &self.standard_client,
+            plaintext_field,
+            existing_document,
+            metadata,
+        )
+        .await
+    }
+
     /// Generate a prefix that could used to search a data store for documents encrypted using an identifier (KMS
     /// config id for SaaS Shield, secret id for Standalone). These bytes should be encoded into
-    /// a format matching the encoding in the data store. z85/ascii85 users should first pass these bytes through
-    /// `encode_prefix_z85` or `base85_prefix_padding`. Make sure you've read the documentation of those functions to
-    /// avoid pitfalls when encoding across byte boundaries.
+    /// a format matching the encoding in the data store. `encode_search_prefixes` does this for the common
+    /// encodings, including the z85/ascii85 pitfalls when encoding across byte boundaries.
     /// Note that this will not work for matching values that don't use our key_id_header format, such as cloaked search.
     async fn get_searchable_edek_prefix(&self, id: i32) -> Vec<u8> {
         self.standard_client.get_searchable_edek_prefix(id)
     }
+
+    /// Decrypt each EDEK on the front of the attached documents and re-encrypt it to the current secret or a new
+    /// tenant without touching the encrypted payloads. `metadata` must contain the tenant ID that the documents were
+    /// originally encrypted to. If `new_tenant_id` is empty, the EDEKs will be rekeyed to the same tenant's current
+    /// secret. Legacy V4 attached documents will be converted to the V5 attached layout.
+    async fn rekey_documents(
+        &self,
+        encrypted_documents: HashMap<String, EncryptedAttachedDocument>,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<RekeyAttachedDocumentsBatchResult, AlloyError> {
+        rekey_documents_core(
+            &self.standard_client,
+            encrypted_documents,
+            metadata,
+            new_tenant_id,
+        )
+        .await
+    }
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl StandaloneSecurityEventOps for StandaloneAttachedStandardClient {
+    /// Log the security event `event` to the configured `SecurityEventSink`.
+    /// If the event time is unspecified the current time will be used.
+    async fn log_security_event(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError> {
+        self.standard_client
+            .log_security_event(event, metadata, event_time_millis)
+            .await
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use bytes::Bytes;
+    use ironcore_documents::v5::{attached::AttachedDocument, key_id_header::KeyId};
     fn default_client() -> StandaloneAttachedStandardClient {
         new_client(Some(1))
     }
@@ -153,7 +213,7 @@ mod test {
             .decrypt(EncryptedAttachedDocument(encrypted.to_vec()), &metadata)
             .await
             .unwrap_err();
-        assert!(matches!(err, AlloyError::DecryptError { msg: _ }))
+        assert!(matches!(err, AlloyError::DecryptError { .. }))
     }
 
     #[tokio::test]
@@ -166,4 +226,99 @@ mod test {
         let result = client.decrypt(encrypted, &metadata).await.unwrap();
         assert_eq!(result, plaintext);
     }
+
+    #[tokio::test]
+    async fn test_encrypt_with_existing_edek_roundtrip() {
+        let metadata = AlloyMetadata::new_simple(crate::TenantId("tenant".to_string()));
+        let client = default_client();
+        let encrypted = client.encrypt(vec![1u8; 10], &metadata).await.unwrap();
+        let original_parts = AttachedDocument::try_from(Bytes::from(encrypted.0.clone())).unwrap();
+        let updated = client
+            .encrypt_with_existing_edek(vec![2u8; 20], encrypted, &metadata)
+            .await
+            .unwrap();
+        let updated_parts = AttachedDocument::try_from(Bytes::from(updated.0.clone())).unwrap();
+        assert_eq!(updated_parts.key_id_header, original_parts.key_id_header);
+        assert_eq!(updated_parts.edek, original_parts.edek);
+        let result = client.decrypt(updated, &metadata).await.unwrap();
+        assert_eq!(result, vec![2u8; 20]);
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_with_existing_edek_wrong_tenant() {
+        let metadata = AlloyMetadata::new_simple(crate::TenantId("tenant".to_string()));
+        let client = default_client();
+        let encrypted = client.encrypt(vec![1u8; 10], &metadata).await.unwrap();
+        let err = client
+            .encrypt_with_existing_edek(
+                vec![2u8; 20],
+                encrypted,
+                &AlloyMetadata::new_simple(crate::TenantId("other_tenant".to_string())),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AlloyError::DecryptError { .. }))
+    }
+
+    #[tokio::test]
+    async fn test_rekey_documents_roundtrip() {
+        let metadata = AlloyMetadata::new_simple(crate::TenantId("tenant".to_string()));
+        let new_tenant_id = crate::TenantId("new_tenant".to_string());
+        let client = default_client();
+        let encrypted = client.encrypt(vec![1u8; 10], &metadata).await.unwrap();
+        let original_parts = AttachedDocument::try_from(Bytes::from(encrypted.0.clone())).unwrap();
+        let rotated_client = new_client(Some(2));
+        let mut result = rotated_client
+            .rekey_documents(
+                [
+                    ("doc".to_string(), encrypted),
+                    ("bad".to_string(), EncryptedAttachedDocument(vec![0, 1, 2])),
+                ]
+                .into(),
+                &metadata,
+                Some(new_tenant_id.clone()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.failures.len(), 1);
+        assert!(result.failures.contains_key("bad"));
+        let rekeyed = result.successes.remove("doc").unwrap();
+        let rekeyed_parts = AttachedDocument::try_from(Bytes::from(rekeyed.0.clone())).unwrap();
+        assert_eq!(rekeyed_parts.key_id_header.key_id, KeyId(2));
+        assert_eq!(rekeyed_parts.edoc, original_parts.edoc);
+        let new_metadata = AlloyMetadata::new_simple(new_tenant_id);
+        let result = rotated_client
+            .decrypt(rekeyed.clone(), &new_metadata)
+            .await
+            .unwrap();
+        assert_eq!(result, vec![1u8; 10]);
+        client.decrypt(rekeyed, &metadata).await.unwrap_err();
+    }
+
+    #[tokio::test]
+    async fn test_rekey_documents_converts_v4() {
+        let metadata = AlloyMetadata::new_simple(crate::TenantId("tenant".to_string()));
+        let client = default_client();
+        let encrypted = client.encrypt(vec![1u8; 10], &metadata).await.unwrap();
+        let AttachedDocument { edek, edoc, .. } =
+            AttachedDocument::try_from(Bytes::from(encrypted.0)).unwrap();
+        let v4_document =
+            ironcore_documents::v4::attached::encode_attached_edoc(edek, edoc.clone())
+                .unwrap()
+                .to_vec();
+        let mut result = client
+            .rekey_documents(
+                [("doc".to_string(), EncryptedAttachedDocument(v4_document))].into(),
+                &metadata,
+                None,
+            )
+            .await
+            .unwrap();
+        let rekeyed = result.successes.remove("doc").unwrap();
+        let rekeyed_parts = AttachedDocument::try_from(Bytes::from(rekeyed.0.clone())).unwrap();
+        assert_eq!(rekeyed_parts.key_id_header.key_id, KeyId(1));
+        assert_eq!(rekeyed_parts.edoc, edoc);
+        let result = client.decrypt(rekeyed, &metadata).await.unwrap();
+        assert_eq!(result, vec![1u8; 10]);
+    }
 }
@@ -1,99 +1,56 @@
 use super::config::VectorSecret;
-use crate::errors::AlloyError;
+use crate::errors::{AlloyError, ErrorKind};
+use crate::saas_shield::{DataEvent, SecurityEvent};
 use crate::standalone::config::RotatableSecret;
-use crate::util::{collection_to_batch_result, get_rng};
+use crate::standalone::security_events::{SecurityEventLogger, StandaloneSecurityEventOps};
+use crate::telemetry::Operation;
+use crate::util::{
+    collection_to_batch_result, get_rng, parallel_collection_to_batch_result,
+    rotate_grouped_by_tenant, ShardedRng,
+};
+use crate::vector::precision::{EncryptedTypedVector, PlaintextTypedVector, VectorPrecision};
 use crate::vector::{
-    decrypt_internal, encrypt_internal, EncryptedVector, EncryptedVectors, GenerateQueryResult,
-    PlaintextVector, PlaintextVectors, VectorEncryptionKey, VectorOps, VectorRotateResult,
+    decrypt_internal, encrypt_internal, get_vector_metadata, EncryptedVector, EncryptedVectors,
+    GenerateQueryResult, PlaintextVector, PlaintextVectors, TenantEncryptedVectors,
+    VectorEncryptBatchResult, VectorEncryptionKey, VectorOps, VectorRotateResult,
 };
 use crate::{
     alloy_client_trait::AlloyClient, AlloyMetadata, DerivationPath, SecretPath,
     StandaloneConfiguration, TenantId,
 };
-use futures::future::{join_all, FutureExt, TryFutureExt};
 use ironcore_documents::v5::key_id_header::{EdekType, KeyId, PayloadType};
 use itertools::Itertools;
-use rand::SeedableRng;
+use rand::{CryptoRng, RngCore, SeedableRng};
 use rand_chacha::ChaCha20Rng;
 use std::collections::HashMap;
-use std::convert::identity;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
-#[derive(uniffi::Object)]
+#[derive(Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Object))]
 pub struct StandaloneVectorClient {
     config: Arc<HashMap<SecretPath, Arc<VectorSecret>>>,
-    rng: Arc<Mutex<ChaCha20Rng>>,
+    rng: Arc<ShardedRng<ChaCha20Rng>>,
+    security_events: SecurityEventLogger,
 }
 impl StandaloneVectorClient {
     pub(crate) fn new(config: StandaloneConfiguration) -> Self {
         Self {
             config: config.vector.clone(),
-            rng: Arc::new(Mutex::new(ChaCha20Rng::from_entropy())),
+            rng: Arc::new(ShardedRng::new(ChaCha20Rng::from_entropy)),
+            security_events: config.security_events.clone(),
         }
     }
-    pub(crate) async fn rotate_vector(
-        &self,
-        encrypted_vector: EncryptedVector,
-        metadata: &AlloyMetadata,
-        new_metadata: &AlloyMetadata,
-    ) -> Result<EncryptedVector, AlloyError> {
-        let (original_key_id, _) =
-            Self::decompose_key_id_header(encrypted_vector.paired_icl_info.clone())?;
-
-        // check if we have a current secret for this path before doing significant work
-        // and that the current secret isn't the one this was encrypted with
-        let vector_secret = self
-            .config
-            .get(&encrypted_vector.secret_path)
-            .ok_or_else(|| AlloyError::InvalidConfiguration {
-                msg: format!(
-                    "Provided secret path `{}` does not exist in the vector configuration.",
-                    &encrypted_vector.secret_path.0
-                ),
-            })?;
-        let standalone_secret = vector_secret
-            .secret
-            .current_secret
-            .as_ref()
-            .ok_or_else(|| AlloyError::InvalidConfiguration {
-                msg: "No current secret exists in the vector configuration".to_string(),
-            })?;
-
-        if original_key_id.0 == standalone_secret.id && metadata.tenant_id == new_metadata.tenant_id
-        {
-            Ok(encrypted_vector)
-        } else {
-            self.decrypt(encrypted_vector, metadata)
-                .and_then(|decrypted_vector| self.encrypt(decrypted_vector, new_metadata))
-                .await
-        }
-    }
-}
-
-impl AlloyClient for StandaloneVectorClient {
-    fn get_edek_type() -> EdekType {
-        EdekType::Standalone
-    }
-
-    fn get_payload_type() -> PayloadType {
-        PayloadType::VectorMetadata
-    }
-}
-
-#[uniffi::export]
-impl VectorOps for StandaloneVectorClient {
-    /// Encrypt a vector embedding with the provided metadata. The provided embedding is assumed to be normalized
-    /// and its values will be shuffled as part of the encryption.
-    /// The same tenant ID must be provided in the metadata when decrypting the embedding.
-    async fn encrypt(
+    fn encrypt_sync<R: RngCore + CryptoRng>(
         &self,
         plaintext_vector: PlaintextVector,
         metadata: &AlloyMetadata,
+        rng: &mut R,
     ) -> Result<EncryptedVector, AlloyError> {
         let vector_secret = self
             .config
             .get(&plaintext_vector.secret_path)
             .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::UnknownSecretPath,
                 msg: format!(
                     "Provided secret path `{}` does not exist in the vector configuration.",
                     &plaintext_vector.secret_path.0
@@ -104,26 +61,26 @@ impl VectorOps for StandaloneVectorClient {
             .current_secret
             .as_ref()
             .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::MissingPrimarySecret,
                 msg: "No current secret exists in the vector configuration".to_string(),
             })?;
+        let plaintext_vector = vector_secret.options.prepare_vector(plaintext_vector)?;
         let key = VectorEncryptionKey::derive_from_secret(
             standalone_secret.secret.as_ref(),
             &metadata.tenant_id,
             &plaintext_vector.derivation_path,
         );
         encrypt_internal(
-            vector_secret.approximation_factor,
+            vector_secret.encryption_params(),
             &key,
             KeyId(standalone_secret.id),
             Self::get_edek_type(),
             plaintext_vector,
-            &mut *get_rng(&self.rng),
+            rng,
         )
     }
 
-    /// Decrypt a vector embedding that was encrypted with the provided metadata. The values of the embedding will
-    /// be unshuffled to their original positions during decryption.
-    async fn decrypt(
+    fn decrypt_sync(
         &self,
         encrypted_vector: EncryptedVector,
         metadata: &AlloyMetadata,
@@ -134,6 +91,7 @@ impl VectorOps for StandaloneVectorClient {
             .config
             .get(&encrypted_vector.secret_path)
             .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::UnknownSecretPath,
                 msg: format!(
                     "Provided secret path `{}` does not exist in the vector configuration.",
                     &encrypted_vector.secret_path.0
@@ -143,6 +101,7 @@ impl VectorOps for StandaloneVectorClient {
             .secret
             .get_secret_with_id(&key_id)
             .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::UnknownSecretId,
                 msg: format!(
                     "Secret with key ID `{}` does not exist in the vector configuration",
                     key_id.0
@@ -153,14 +112,176 @@ impl VectorOps for StandaloneVectorClient {
             &metadata.tenant_id,
             &encrypted_vector.derivation_path,
         );
+        let vector_metadata = get_vector_metadata(&icl_metadata_bytes)?;
+        vector_secret
+            .options
+            .check_encrypted(&encrypted_vector, &vector_metadata)?;
         decrypt_internal(
-            vector_secret.approximation_factor,
+            vector_metadata
+                .approximation_factor
+                .unwrap_or(vector_secret.approximation_factor),
             &key,
             encrypted_vector,
-            icl_metadata_bytes,
+            vector_metadata,
         )
     }
 
+    /// Rotation goes through the sync encrypt and decrypt paths so it doesn't log data events or start nested
+    /// telemetry operations for every vector it re-encrypts.
+    pub(crate) fn rotate_vector(
+        &self,
+        encrypted_vector: EncryptedVector,
+        metadata: &AlloyMetadata,
+        new_metadata: &AlloyMetadata,
+    ) -> Result<EncryptedVector, AlloyError> {
+        let (original_key_id, icl_metadata_bytes) =
+            Self::decompose_key_id_header(encrypted_vector.paired_icl_info.clone())?;
+        let vector_metadata = get_vector_metadata(&icl_metadata_bytes)?;
+
+        // check if we have a current secret for this path before doing significant work
+        // and that the current secret isn't the one this was encrypted with
+        let vector_secret = self
+            .config
+            .get(&encrypted_vector.secret_path)
+            .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::UnknownSecretPath,
+                msg: format!(
+                    "Provided secret path `{}` does not exist in the vector configuration.",
+                    &encrypted_vector.secret_path.0
+                ),
+            })?;
+        let standalone_secret = vector_secret
+            .secret
+            .current_secret
+            .as_ref()
+            .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::MissingPrimarySecret,
+                msg: "No current secret exists in the vector configuration".to_string(),
+            })?;
+
+        // Vectors also need to be re-encrypted if the configured approximation factor, encryption mode, or Matryoshka
+        // block size has changed since they were encrypted, or if their metadata doesn't record the factor yet.
+        if original_key_id.0 == standalone_secret.id
+            && metadata.tenant_id == new_metadata.tenant_id
+            && vector_metadata.was_encrypted_with(&vector_secret.encryption_params())
+        {
+            Ok(encrypted_vector)
+        } else {
+            self.decrypt_sync(encrypted_vector, metadata)
+                .and_then(|decrypted_vector| {
+                    self.encrypt_sync(decrypted_vector, new_metadata, &mut *get_rng(&self.rng))
+                })
+        }
+    }
+}
+
+impl AlloyClient for StandaloneVectorClient {
+    fn get_edek_type() -> EdekType {
+        EdekType::Standalone
+    }
+
+    fn get_payload_type() -> PayloadType {
+        PayloadType::VectorMetadata
+    }
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl VectorOps for StandaloneVectorClient {
+    /// Encrypt a vector embedding with the provided metadata. The provided embedding is assumed to be normalized
+    /// and its values will be shuffled as part of the encryption. It is first checked and preprocessed according to
+    /// the `VectorValidation` configured for its secret path, and rejected with `InvalidInput` if that fails.
+    /// The same tenant ID must be provided in the metadata when decrypting the embedding.
+    async fn encrypt(
+        &self,
+        plaintext_vector: PlaintextVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedVector, AlloyError> {
+        let secret_path = plaintext_vector.secret_path.0.clone();
+        let result =
+            Operation::new("vector.encrypt")
+                .tenant_id(&metadata.tenant_id.0)
+                .secret_path(&secret_path)
+                .run(async move {
+                    self.encrypt_sync(plaintext_vector, metadata, &mut *get_rng(&self.rng))
+                })
+                .await;
+        self.security_events
+            .after_data_operation(DataEvent::Encrypt, metadata, result)
+    }
+
+    /// Encrypt a batch of vector embeddings with the provided metadata. Vectors are encrypted in parallel on a rayon
+    /// thread pool owned by this crate, separate from rayon's global pool and any pool the caller runs on. That makes
+    /// this much faster than calling `encrypt` in a loop for large batches or high-dimensional embeddings. The pool
+    /// has a thread per core unless `RAYON_NUM_THREADS` says otherwise. The calling task waits for the pool without
+    /// blocking its executor thread, but a large batch keeps every pool thread busy until it's done, which competes
+    /// with other CPU work in the process.
+    /// Vectors that fail to encrypt are returned in `failures` keyed by their ID.
+    async fn encrypt_batch(
+        &self,
+        plaintext_vectors: PlaintextVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorEncryptBatchResult, AlloyError> {
+        let result = Operation::new("vector.encrypt_batch")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(plaintext_vectors.len())
+            .run(async move {
+                let client = self.clone();
+                let metadata = metadata.clone();
+                Ok(parallel_collection_to_batch_result(
+                    plaintext_vectors,
+                    self.rng.clone(),
+                    move |vector, rng| client.encrypt_sync(vector, &metadata, rng),
+                )
+                .await
+                .into())
+            })
+            .await;
+        self.security_events
+            .after_data_operation(DataEvent::Encrypt, metadata, result)
+    }
+
+    /// Decrypt a vector embedding that was encrypted with the provided metadata. The values of the embedding will
+    /// be unshuffled to their original positions during decryption.
+    async fn decrypt(
+        &self,
+        encrypted_vector: EncryptedVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextVector, AlloyError> {
+        let secret_path = encrypted_vector.secret_path.0.clone();
+        let result = Operation::new("vector.decrypt")
+            .tenant_id(&metadata.tenant_id.0)
+            .secret_path(&secret_path)
+            .run(async move { self.decrypt_sync(encrypted_vector, metadata) })
+            .await;
+        self.security_events
+            .after_data_operation(DataEvent::Decrypt, metadata, result)
+    }
+
+    /// Encrypt a vector embedding given in any supported precision. Encryption runs on `f32` values, so `f64`
+    /// embeddings are rounded to `f32` first. The encrypted vector is `F64` for `F64` embeddings and `F32` otherwise;
+    /// see `EncryptedTypedVector` for why half precision ciphertexts aren't possible.
+    async fn encrypt_typed(
+        &self,
+        plaintext_vector: PlaintextTypedVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedTypedVector, AlloyError> {
+        let precision = plaintext_vector.plaintext_vector.precision();
+        let encrypted = self.encrypt(plaintext_vector.try_into()?, metadata).await?;
+        Ok(EncryptedTypedVector::from_encrypted(encrypted, precision))
+    }
+
+    /// Decrypt a vector embedding that was encrypted with `encrypt_typed` (or `encrypt`) and return it in `precision`.
+    /// Fails if a decrypted value doesn't fit in `precision`.
+    async fn decrypt_typed(
+        &self,
+        encrypted_vector: EncryptedTypedVector,
+        precision: VectorPrecision,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextTypedVector, AlloyError> {
+        let decrypted = self.decrypt(encrypted_vector.try_into()?, metadata).await?;
+        PlaintextTypedVector::from_decrypted(decrypted, precision)
+    }
+
     /// Encrypt each plaintext vector with any Current and InRotation keys for the provided secret path.
     /// The resulting encrypted vectors should be used in tandem when querying the vector database.
     async fn generate_query_vectors(
@@ -168,58 +289,67 @@ impl VectorOps for StandaloneVectorClient {
         vectors_to_query: PlaintextVectors,
         metadata: &AlloyMetadata,
     ) -> Result<GenerateQueryResult, AlloyError> {
-        vectors_to_query
-            .into_iter()
-            .map(|(vector_id, plaintext_vector)| {
-                let vector_secret =
-                    self.config
-                        .get(&plaintext_vector.secret_path)
-                        .ok_or_else(|| AlloyError::InvalidConfiguration {
-                            msg: format!(
+        Operation::new("vector.generate_query_vectors")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(vectors_to_query.len())
+            .run(async move {
+                vectors_to_query
+                    .into_iter()
+                    .map(|(vector_id, plaintext_vector)| {
+                        let vector_secret = self
+                            .config
+                            .get(&plaintext_vector.secret_path)
+                            .ok_or_else(|| AlloyError::InvalidConfiguration {
+                                kind: ErrorKind::UnknownSecretPath,
+                                msg: format!(
                             "Provided secret path `{}` does not exist in the vector configuration.",
                             &plaintext_vector.secret_path.0
                         ),
-                        })?;
-                let RotatableSecret {
-                    current_secret,
-                    in_rotation_secret,
-                } = vector_secret.secret.as_ref();
-                if current_secret.is_none() && in_rotation_secret.is_none() {
-                    Err(AlloyError::InvalidConfiguration {
-                        msg: format!(
+                            })?;
+                        let RotatableSecret {
+                            current_secret,
+                            in_rotation_secret,
+                        } = vector_secret.secret.as_ref();
+                        if current_secret.is_none() && in_rotation_secret.is_none() {
+                            Err(AlloyError::InvalidConfiguration {
+                                kind: ErrorKind::UnknownSecretPath,
+                                msg: format!(
                             "No secrets exist in the vector configuration for secret path `{}`.",
                             plaintext_vector.secret_path.0
                         ),
-                    })?;
-                }
-                current_secret
-                    .iter()
-                    .chain(in_rotation_secret)
-                    .map(|standalone_secret| {
-                        let key = VectorEncryptionKey::derive_from_secret(
-                            standalone_secret.secret.as_ref(),
-                            &metadata.tenant_id,
-                            &plaintext_vector.derivation_path,
-                        );
-                        encrypt_internal(
-                            vector_secret.approximation_factor,
-                            &key,
-                            KeyId(standalone_secret.id),
-                            StandaloneVectorClient::get_edek_type(),
-                            plaintext_vector.clone(),
-                            &mut *get_rng(&self.rng),
-                        )
+                            })?;
+                        }
+                        let plaintext_vector =
+                            vector_secret.options.prepare_vector(plaintext_vector)?;
+                        current_secret
+                            .iter()
+                            .chain(in_rotation_secret)
+                            .map(|standalone_secret| {
+                                let key = VectorEncryptionKey::derive_from_secret(
+                                    standalone_secret.secret.as_ref(),
+                                    &metadata.tenant_id,
+                                    &plaintext_vector.derivation_path,
+                                );
+                                encrypt_internal(
+                                    vector_secret.encryption_params(),
+                                    &key,
+                                    KeyId(standalone_secret.id),
+                                    StandaloneVectorClient::get_edek_type(),
+                                    plaintext_vector.clone(),
+                                    &mut *get_rng(&self.rng),
+                                )
+                            })
+                            .try_collect()
+                            .map(|enc| (vector_id, enc))
                     })
                     .try_collect()
-                    .map(|enc| (vector_id, enc))
             })
-            .try_collect()
+            .await
     }
 
     // TODO: Discuss if we want to make this function less consistent to avoid passing useless values.
     /// Get the byte prefix for the InRotation secret corresponding to this secret_path.
-    /// Note that if you use z85 or ascii85 encoding, the result of this function should be passed to `base85_compat_prefix_bytes`
-    /// before searching your datastore.
+    /// Pass the result to `encode_search_prefixes` to get the string prefixes to search an encoded datastore with.
     /// Note: The derivation_path and metadata are not actually required for this function and can be passed any value.
     async fn get_in_rotation_prefix(
         &self,
@@ -231,6 +361,7 @@ impl VectorOps for StandaloneVectorClient {
             self.config
                 .get(&secret_path)
                 .ok_or_else(|| AlloyError::InvalidConfiguration {
+                    kind: ErrorKind::UnknownSecretPath,
                     msg: format!(
                         "Provided secret path `{}` does not exist in the vector configuration.",
                         &secret_path.0
@@ -241,6 +372,7 @@ impl VectorOps for StandaloneVectorClient {
             .in_rotation_secret
             .as_ref()
             .ok_or_else(|| AlloyError::InvalidConfiguration {
+                kind: ErrorKind::MissingPrimarySecret,
                 msg: "There is no in-rotation secret in the vector configuration.".to_string(),
             })?;
         let key_id_header = Self::create_key_id_header(in_rotation_secret.id);
@@ -253,6 +385,10 @@ impl VectorOps for StandaloneVectorClient {
     /// Rotates vectors from the in-rotation secret for their secret path to the current secret.
     /// This can also be used to rotate data from one tenant ID to a new one, which most useful when a tenant is
     /// internally migrated.
+    /// Vectors are also re-encrypted if the approximation factor recorded in their metadata doesn't match the one
+    /// currently configured for their secret path, which migrates them to the new factor. Vectors encrypted before
+    /// the factor was recorded are decrypted with the configured factor and re-encrypted to record it. Vectors are
+    /// likewise migrated to the encryption mode and Matryoshka block size configured for their secret path.
     ///
     /// WARNINGS:
     ///     * this involves decrypting then encrypting vectors. Since the vectors are full of floating point numbers,
@@ -266,30 +402,97 @@ impl VectorOps for StandaloneVectorClient {
         metadata: &AlloyMetadata,
         new_tenant_id: Option<TenantId>,
     ) -> Result<VectorRotateResult, AlloyError> {
-        let new_metadata = match new_tenant_id {
-            None => metadata.clone(),
-            Some(tenant_id) => AlloyMetadata {
-                tenant_id: tenant_id.clone(),
-                ..metadata.clone()
-            },
-        };
-        let attempts: Vec<_> = join_all(encrypted_vectors.into_iter().map(
-            |(vector_id, encrypted_vector)| {
-                self.rotate_vector(encrypted_vector, metadata, &new_metadata)
-                    .map(|rotated_vector| (vector_id, rotated_vector))
-            },
-        ))
-        .await;
-        Ok(collection_to_batch_result(attempts, identity).into())
+        Operation::new("vector.rotate_vectors")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(encrypted_vectors.len())
+            .run(async move {
+                let new_metadata = match new_tenant_id {
+                    None => metadata.clone(),
+                    Some(tenant_id) => AlloyMetadata {
+                        tenant_id: tenant_id.clone(),
+                        ..metadata.clone()
+                    },
+                };
+                Ok(
+                    collection_to_batch_result(encrypted_vectors, |encrypted_vector| {
+                        self.rotate_vector(encrypted_vector, metadata, &new_metadata)
+                    })
+                    .into(),
+                )
+            })
+            .await
+    }
+
+    /// Rotates vectors that may belong to many tenants. Each vector carries the tenant ID it was encrypted to and an
+    /// optional tenant ID to rotate it to. The tenant ID in `metadata` is ignored, but its other values are used for
+    /// every vector.
+    /// The same lossiness warning as `rotate_vectors` applies.
+    async fn rotate_vectors_multi_tenant(
+        &self,
+        encrypted_vectors: TenantEncryptedVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorRotateResult, AlloyError> {
+        Operation::new("vector.rotate_vectors_multi_tenant")
+            .batch_size(encrypted_vectors.len())
+            .run(async move {
+                let items = encrypted_vectors.into_iter().map(|(vector_id, vector)| {
+                    (
+                        vector_id,
+                        (
+                            vector.tenant_id,
+                            vector.new_tenant_id,
+                            vector.encrypted_vector,
+                        ),
+                    )
+                });
+                Ok(
+                    rotate_grouped_by_tenant(items, metadata, |vectors, metadata| async move {
+                        Ok(collection_to_batch_result(
+                            vectors,
+                            |(new_tenant_id, encrypted_vector)| {
+                                let new_metadata = AlloyMetadata {
+                                    tenant_id: new_tenant_id
+                                        .unwrap_or_else(|| metadata.tenant_id.clone()),
+                                    ..metadata.clone()
+                                };
+                                self.rotate_vector(encrypted_vector, &metadata, &new_metadata)
+                            },
+                        ))
+                    })
+                    .await
+                    .into(),
+                )
+            })
+            .await
+    }
+}
+
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+impl StandaloneSecurityEventOps for StandaloneVectorClient {
+    /// Log the security event `event` to the configured `SecurityEventSink`.
+    /// If the event time is unspecified the current time will be used.
+    async fn log_security_event(
+        &self,
+        event: SecurityEvent,
+        metadata: &AlloyMetadata,
+        event_time_millis: Option<i64>,
+    ) -> Result<(), AlloyError> {
+        self.security_events.log(event, metadata, event_time_millis)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::standalone::security_events::InMemorySecurityEventSink;
+    use crate::vector::precision::TypedVectorValues;
+    use crate::vector::validation::{VectorNormalization, VectorValidation};
+    use crate::vector::{
+        EmbeddingModel, TenantEncryptedVector, VectorEncryptionMode, VectorSecretOptions,
+    };
     use crate::TenantId;
     use crate::{standalone::config::StandaloneSecret, Secret};
-    use approx::assert_ulps_eq;
+    use approx::{assert_abs_diff_eq, assert_ulps_eq};
 
     fn get_default_client() -> StandaloneVectorClient {
         let k = rand_chacha::ChaCha20Rng::seed_from_u64(1u64);
@@ -311,10 +514,11 @@ mod test {
         let vector_secret = VectorSecret {
             approximation_factor: 4.0f32,
             secret: Arc::new(rotatable_secret),
+            options: VectorSecretOptions::default(),
         };
 
         StandaloneVectorClient {
-            rng: Arc::new(Mutex::new(k)),
+            rng: Arc::new(ShardedRng::single(k)),
             config: Arc::new(
                 [(
                     SecretPath("secret_path".to_string()),
@@ -322,6 +526,58 @@ mod test {
                 )]
                 .into(),
             ),
+            security_events: SecurityEventLogger::default(),
+        }
+    }
+
+    fn with_approximation_factor(
+        client: StandaloneVectorClient,
+        approximation_factor: f32,
+    ) -> StandaloneVectorClient {
+        let config = client
+            .config
+            .iter()
+            .map(|(path, vector_secret)| {
+                (
+                    path.clone(),
+                    Arc::new(VectorSecret {
+                        approximation_factor,
+                        secret: vector_secret.secret.clone(),
+                        options: vector_secret.options.clone(),
+                    }),
+                )
+            })
+            .collect();
+        StandaloneVectorClient {
+            config: Arc::new(config),
+            rng: client.rng,
+            security_events: SecurityEventLogger::default(),
+        }
+    }
+
+    fn with_options(
+        client: StandaloneVectorClient,
+        options: VectorSecretOptions,
+    ) -> StandaloneVectorClient {
+        let config = client
+            .config
+            .iter()
+            .map(|(path, vector_secret)| {
+                (
+                    path.clone(),
+                    VectorSecret::new_with_options(
+                        vector_secret.approximation_factor,
+                        vector_secret.secret.clone(),
+                        options.clone(),
+                    )
+                    .unwrap(),
+                )
+            })
+            .collect();
+        StandaloneVectorClient {
+            config: Arc::new(config),
+            rng: client.rng,
+            security_events: SecurityEventLogger::default(),
         }
     }
 
@@ -355,10 +611,11 @@ mod test {
         let vector_secret = VectorSecret {
             approximation_factor: 4.0f32,
             secret: Arc::new(rotatable_secret),
+            options: VectorSecretOptions::default(),
         };
 
         StandaloneVectorClient {
-            rng: Arc::new(Mutex::new(k)),
+            rng: Arc::new(ShardedRng::single(k)),
             config: Arc::new(
                 [(
                     SecretPath("secret_path".to_string()),
@@ -366,6 +623,7 @@ mod test {
                 )]
                 .into(),
             ),
+            security_events: SecurityEventLogger::default(),
         }
     }
 
@@ -394,7 +652,8 @@ mod test {
             [
                 0, 0, 0, 1, 129, 0, 10, 12, 154, 55, 68, 80, 69, 96, 99, 158, 198, 112, 183, 161,
                 18, 32, 125, 78, 5, 108, 187, 19, 103, 206, 124, 199, 184, 212, 208, 35, 61, 45, 6,
-                130, 55, 85, 125, 210, 5, 126, 145, 45, 240, 250, 63, 45, 168, 104
+                130, 55, 85, 125, 210, 5, 126, 145, 45, 240, 250, 63, 45, 168, 104, 160, 6, 2, 176,
+                6, 5, 173, 6, 0, 0, 128, 64
             ]
         );
     }
@@ -418,6 +677,193 @@ mod test {
         assert_ulps_eq!(result.plaintext_vector[..], plaintext.plaintext_vector[..]);
     }
 
+    #[tokio::test]
+    async fn encrypt_batch_roundtrip() {
+        let ironcore_alloy = get_default_client();
+        let plaintexts: PlaintextVectors = (0..100)
+            .map(|i| {
+                (
+                    format!("vector{i}"),
+                    PlaintextVector {
+                        plaintext_vector: (0..64).map(|j| (i * j) as f32 / 1000.).collect(),
+                        secret_path: SecretPath("secret_path".to_string()),
+                        derivation_path: DerivationPath("deriv_path".to_string()),
+                    },
+                )
+            })
+            .chain([(
+                "bad_path".to_string(),
+                PlaintextVector {
+                    plaintext_vector: vec![1., 2., 3.],
+                    secret_path: SecretPath("not_a_path".to_string()),
+                    derivation_path: DerivationPath("deriv_path".to_string()),
+                },
+            )])
+            .collect();
+        let result = ironcore_alloy
+            .encrypt_batch(plaintexts.clone(), &get_metadata())
+            .await
+            .unwrap();
+        assert_eq!(result.successes.len(), 100);
+        assert_eq!(result.failures.len(), 1);
+        assert!(matches!(
+            result.failures["bad_path"],
+            AlloyError::InvalidConfiguration { .. }
+        ));
+        for (id, encrypted) in result.successes {
+            let decrypted = ironcore_alloy
+                .decrypt(encrypted, &get_metadata())
+                .await
+                .unwrap();
+            assert_ulps_eq!(
+                decrypted.plaintext_vector[..],
+                plaintexts[&id].plaintext_vector[..],
+                epsilon = 1e-5
+            );
+        }
+    }
+
+    #[test]
+    fn encrypt_batch_from_a_blocked_rayon_worker() {
+        // The only worker of the caller's pool is blocked waiting on the batch, so the batch has to run elsewhere.
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let plaintexts: PlaintextVectors = [(
+            "vector".to_string(),
+            PlaintextVector {
+                plaintext_vector: vec![0.1, 0.2, 0.3],
+                secret_path: SecretPath("secret_path".to_string()),
+                derivation_path: DerivationPath("deriv_path".to_string()),
+            },
+        )]
+        .into();
+        let result = pool.install(|| {
+            runtime.block_on(get_default_client().encrypt_batch(plaintexts, &get_metadata()))
+        });
+        assert_eq!(result.unwrap().successes.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn encrypt_decrypt_typed_roundtrip() {
+        let ironcore_alloy = get_default_client();
+        let values = vec![0.1f64, -0.25, 0.5, 0.75, -1.];
+        let plaintexts: Vec<TypedVectorValues> = vec![
+            values.clone().into(),
+            values.iter().map(|v| *v as f32).collect_vec().into(),
+            values
+                .iter()
+                .map(|v| half::f16::from_f64(*v))
+                .collect_vec()
+                .into(),
+            values
+                .iter()
+                .map(|v| half::bf16::from_f64(*v))
+                .collect_vec()
+                .into(),
+        ];
+        for plaintext in plaintexts {
+            let precision = plaintext.precision();
+            let encrypted = ironcore_alloy
+                .encrypt_typed(
+                    PlaintextTypedVector {
+                        plaintext_vector: plaintext.clone(),
+                        secret_path: SecretPath("secret_path".to_string()),
+                        derivation_path: DerivationPath("deriv_path".to_string()),
+                    },
+                    &get_metadata(),
+                )
+                .await
+                .unwrap();
+            let expected_ciphertext_precision = if precision == VectorPrecision::F64 {
+                VectorPrecision::F64
+            } else {
+                VectorPrecision::F32
+            };
+            assert_eq!(
+                encrypted.encrypted_vector.precision(),
+                expected_ciphertext_precision
+            );
+            let decrypted = ironcore_alloy
+                .decrypt_typed(encrypted, precision, &get_metadata())
+                .await
+                .unwrap();
+            assert_eq!(decrypted.plaintext_vector.precision(), precision);
+            let tolerance = match precision {
+                VectorPrecision::F64 | VectorPrecision::F32 => 1e-6,
+                VectorPrecision::F16 => 1e-3,
+                VectorPrecision::Bf16 => 1e-2,
+            };
+            for (actual, expected) in decrypted
+                .plaintext_vector
+                .to_f32()
+                .unwrap()
+                .into_iter()
+                .zip(plaintext.to_f32().unwrap())
+            {
+                assert!((actual - expected).abs() < tolerance);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn decrypt_typed_f64_after_serialization() {
+        let ironcore_alloy = get_default_client();
+        let encrypted = ironcore_alloy
+            .encrypt_typed(
+                PlaintextTypedVector {
+                    plaintext_vector: vec![0.1f64, 0.2, 0.3].into(),
+                    secret_path: SecretPath("secret_path".to_string()),
+                    derivation_path: DerivationPath("deriv_path".to_string()),
+                },
+                &get_metadata(),
+            )
+            .await
+            .unwrap();
+        let TypedVectorValues::F64 { values } = encrypted.encrypted_vector.clone() else {
+            panic!("Expected F64 ciphertext.")
+        };
+        let serialized = values.iter().flat_map(|v| v.to_le_bytes()).collect_vec();
+        let deserialized = serialized
+            .chunks(8)
+            .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect_vec();
+        let restored = EncryptedTypedVector {
+            encrypted_vector: deserialized.into(),
+            ..encrypted
+        };
+        let decrypted = ironcore_alloy
+            .decrypt_typed(restored.clone(), VectorPrecision::F64, &get_metadata())
+            .await
+            .unwrap();
+        assert_eq!(decrypted.plaintext_vector.precision(), VectorPrecision::F64);
+        // a value an f32 can't hold exactly can't have come from encryption
+        let TypedVectorValues::F64 { mut values } = restored.encrypted_vector.clone() else {
+            unreachable!()
+        };
+        values[0] += 1e-9;
+        let err = ironcore_alloy
+            .decrypt_typed(
+                EncryptedTypedVector {
+                    encrypted_vector: values.into(),
+                    ..restored
+                },
+                VectorPrecision::F64,
+                &get_metadata(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            AlloyError::InvalidInput {
+                kind: ErrorKind::Serialization,
+                ..
+            }
+        ));
+    }
+
     #[tokio::test]
     async fn rotate_roundtrip() {
         let alloy = get_default_client();
@@ -461,4 +907,446 @@ mod test {
             .await
             .expect_err("the old sdk can't decrypt the value with the new tenant id");
     }
+
+    #[tokio::test]
+    async fn rotate_logs_no_data_events() {
+        let plaintext = PlaintextVector {
+            plaintext_vector: vec![1., 2., 3., 4., 5.],
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+        };
+        let encrypted = get_default_client()
+            .encrypt(plaintext, &get_metadata())
+            .await
+            .unwrap();
+        let sink = InMemorySecurityEventSink::new();
+        let alloy = StandaloneVectorClient {
+            security_events: SecurityEventLogger::new(sink.clone(), true),
+            ..get_in_rotation_client()
+        };
+        let rotated = alloy
+            .rotate_vectors(
+                [("one".to_string(), encrypted)].into(),
+                &get_metadata(),
+                Some(TenantId("new_tenant".to_string())),
+            )
+            .await
+            .unwrap();
+        assert!(rotated.failures.is_empty());
+        assert!(sink.events().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rotate_multi_tenant_roundtrip() {
+        let alloy = get_default_client();
+        let tenant_a = TenantId("tenant_a".to_string());
+        let tenant_b = TenantId("tenant_b".to_string());
+        let tenant_c = TenantId("tenant_c".to_string());
+        let plaintext = PlaintextVector {
+            plaintext_vector: vec![1., 2., 3., 4., 5.],
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+        };
+        let encrypted_a = alloy
+            .encrypt(
+                plaintext.clone(),
+                &AlloyMetadata::new_simple(tenant_a.clone()),
+            )
+            .await
+            .unwrap();
+        let encrypted_b = alloy
+            .encrypt(
+                plaintext.clone(),
+                &AlloyMetadata::new_simple(tenant_b.clone()),
+            )
+            .await
+            .unwrap();
+        let bad_path = EncryptedVector {
+            secret_path: SecretPath("not_a_path".to_string()),
+            ..encrypted_b.clone()
+        };
+        let vectors = [
+            (
+                "a".to_string(),
+                TenantEncryptedVector {
+                    encrypted_vector: encrypted_a,
+                    tenant_id: tenant_a.clone(),
+                    new_tenant_id: Some(tenant_c.clone()),
+                },
+            ),
+            (
+                "b".to_string(),
+                TenantEncryptedVector {
+                    encrypted_vector: encrypted_b,
+                    tenant_id: tenant_b.clone(),
+                    new_tenant_id: None,
+                },
+            ),
+            (
+                "bad".to_string(),
+                TenantEncryptedVector {
+                    encrypted_vector: bad_path,
+                    tenant_id: tenant_b.clone(),
+                    new_tenant_id: None,
+                },
+            ),
+        ]
+        .into();
+        let alloy_rotated_secret = get_in_rotation_client();
+        let mut result = alloy_rotated_secret
+            .rotate_vectors_multi_tenant(vectors, &get_metadata())
+            .await
+            .unwrap();
+        assert_eq!(result.failures.len(), 1);
+        assert!(result.failures.contains_key("bad"));
+        let decrypted_a = alloy_rotated_secret
+            .decrypt(
+                result.successes.remove("a").unwrap(),
+                &AlloyMetadata::new_simple(tenant_c),
+            )
+            .await
+            .unwrap();
+        assert_ulps_eq!(
+            decrypted_a.plaintext_vector[..],
+            plaintext.plaintext_vector[..]
+        );
+        let decrypted_b = alloy_rotated_secret
+            .decrypt(
+                result.successes.remove("b").unwrap(),
+                &AlloyMetadata::new_simple(tenant_b),
+            )
+            .await
+            .unwrap();
+        assert_ulps_eq!(
+            decrypted_b.plaintext_vector[..],
+            plaintext.plaintext_vector[..]
+        );
+        assert!(result.successes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn decrypt_prefers_recorded_approximation_factor() {
+        let plaintext = PlaintextVector {
+            plaintext_vector: vec![1., 2., 3., 4., 5.],
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+        };
+        let encrypted = get_default_client()
+            .encrypt(plaintext.clone(), &get_metadata())
+            .await
+            .unwrap();
+        let changed_factor_client = with_approximation_factor(get_default_client(), 2.0);
+        let result = changed_factor_client
+            .decrypt(encrypted, &get_metadata())
+            .await
+            .unwrap();
+        assert_ulps_eq!(result.plaintext_vector[..], plaintext.plaintext_vector[..]);
+    }
+
+    #[tokio::test]
+    async fn decrypt_version_one_metadata_uses_configured_factor() {
+        let encrypted = EncryptedVector {
+            encrypted_vector: vec![13681085.0, 42081104.0, 82401560.0, 19847844.0, 60127316.0],
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+            paired_icl_info: vec![
+                0, 0, 0, 1, 129, 0, 10, 12, 154, 55, 68, 80, 69, 96, 99, 158, 198, 112, 183, 161,
+                18, 32, 125, 78, 5, 108, 187, 19, 103, 206, 124, 199, 184, 212, 208, 35, 61, 45, 6,
+                130, 55, 85, 125, 210, 5, 126, 145, 45, 240, 250, 63, 45, 168, 104,
+            ],
+        };
+        let result = get_default_client()
+            .decrypt(encrypted.clone(), &get_metadata())
+            .await
+            .unwrap();
+        assert_ulps_eq!(result.plaintext_vector[..], [1., 2., 3., 4., 5.][..]);
+        with_approximation_factor(get_default_client(), 2.0)
+            .decrypt(encrypted, &get_metadata())
+            .await
+            .expect_err("a different configured factor can't decrypt version 1 metadata");
+    }
+
+    #[tokio::test]
+    async fn rotate_migrates_approximation_factor() {
+        let plaintext = PlaintextVector {
+            plaintext_vector: vec![1., 2., 3., 4., 5.],
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+        };
+        let encrypted = get_default_client()
+            .encrypt(plaintext.clone(), &get_metadata())
+            .await
+            .unwrap();
+        let changed_factor_client = with_approximation_factor(get_default_client(), 2.0);
+        let mut rotated = changed_factor_client
+            .rotate_vectors(
+                [("one".to_string(), encrypted.clone())].into(),
+                &get_metadata(),
+                None,
+            )
+            .await
+            .unwrap();
+        let rotated_vector = rotated.successes.remove("one").unwrap();
+        assert_ne!(rotated_vector.paired_icl_info, encrypted.paired_icl_info);
+        let (_, metadata_bytes) =
+            StandaloneVectorClient::decompose_key_id_header(rotated_vector.paired_icl_info.clone())
+                .unwrap();
+        assert_eq!(
+            get_vector_metadata(&metadata_bytes)
+                .unwrap()
+                .approximation_factor,
+            Some(2.0)
+        );
+        let result = changed_factor_client
+            .decrypt(rotated_vector.clone(), &get_metadata())
+            .await
+            .unwrap();
+        assert_ulps_eq!(result.plaintext_vector[..], plaintext.plaintext_vector[..]);
+
+        // rotating again with the same factor and secret is a no-op
+        let mut rotated_again = changed_factor_client
+            .rotate_vectors(
+                [("one".to_string(), rotated_vector.clone())].into(),
+                &get_metadata(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            rotated_again
+                .successes
+                .remove("one")
+                .unwrap()
+                .paired_icl_info,
+            rotated_vector.paired_icl_info
+        );
+    }
+
+    #[tokio::test]
+    async fn rotate_migrates_encryption_mode() {
+        let plaintext = PlaintextVector {
+            plaintext_vector: vec![0.1, -0.2, 0.3, -0.4, 0.5],
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+        };
+        let encrypted = get_default_client()
+            .encrypt(plaintext.clone(), &get_metadata())
+            .await
+            .unwrap();
+        let quantization_client = with_options(
+            get_default_client(),
+            VectorSecretOptions {
+                mode: VectorEncryptionMode::QuantizationFriendly,
+                accept_quantization_friendly_leakage: true,
+                ..Default::default()
+            },
+        );
+        // decryption follows the mode recorded in the metadata, not the configured one
+        let result = quantization_client
+            .decrypt(encrypted.clone(), &get_metadata())
+            .await
+            .unwrap();
+        assert_abs_diff_eq!(
+            result.plaintext_vector[..],
+            plaintext.plaintext_vector[..],
+            epsilon = 1e-5
+        );
+        let mut rotated = quantization_client
+            .rotate_vectors(
+                [("one".to_string(), encrypted)].into(),
+                &get_metadata(),
+                None,
+            )
+            .await
+            .unwrap();
+        let rotated_vector = rotated.successes.remove("one").unwrap();
+        let bound = crate::vector::quantization_friendly_value_bound(4.);
+        assert!(rotated_vector
+            .encrypted_vector
+            .iter()
+            .all(|v| v.abs() <= bound));
+        let result = get_default_client()
+            .decrypt(rotated_vector, &get_metadata())
+            .await
+            .unwrap();
+        assert_abs_diff_eq!(
+            result.plaintext_vector[..],
+            plaintext.plaintext_vector[..],
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn new_with_options_rejects_zero_block_size() {
+        let Err(err) = VectorSecret::new_with_options(
+            2.0,
+            get_default_client().config[&SecretPath("secret_path".to_string())]
+                .secret
+                .clone(),
+            VectorSecretOptions {
+                matryoshka_block_size: Some(0),
+                ..Default::default()
+            },
+        ) else {
+            panic!("a block size of zero should be rejected");
+        };
+        assert!(matches!(err, AlloyError::InvalidConfiguration { .. }));
+        assert_eq!(err.kind(), ErrorKind::InvalidVectorOptions);
+    }
+
+    #[tokio::test]
+    async fn encrypt_applies_validation() {
+        let client = with_options(
+            get_default_client(),
+            VectorSecretOptions {
+                validation: VectorValidation {
+                    reject_non_finite: true,
+                    normalization: VectorNormalization::Apply,
+                    expected_dimension: Some(2),
+                },
+                ..Default::default()
+            },
+        );
+        let plaintext = |values: Vec<f32>| PlaintextVector {
+            plaintext_vector: values,
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+        };
+        let encrypted = client
+            .encrypt(plaintext(vec![3., 4.]), &get_metadata())
+            .await
+            .unwrap();
+        let decrypted = client.decrypt(encrypted, &get_metadata()).await.unwrap();
+        assert_abs_diff_eq!(
+            decrypted.plaintext_vector[..],
+            [0.6, 0.8][..],
+            epsilon = 1e-5
+        );
+
+        let err = client
+            .encrypt(plaintext(vec![0.5, f32::NAN]), &get_metadata())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidInput { .. }));
+        assert_eq!(err.kind(), ErrorKind::NonFiniteValue);
+        assert!(err.to_string().contains("index 1"));
+
+        let batch = client
+            .encrypt_batch(
+                [
+                    ("good".to_string(), plaintext(vec![1., 0.])),
+                    ("bad".to_string(), plaintext(vec![1., 0., 0.])),
+                ]
+                .into(),
+                &get_metadata(),
+            )
+            .await
+            .unwrap();
+        assert!(batch.successes.contains_key("good"));
+        assert!(matches!(
+            batch.failures.get("bad"),
+            Some(AlloyError::InvalidInput { .. })
+        ));
+
+        let err = client
+            .generate_query_vectors(
+                [("query".to_string(), plaintext(vec![0., 0.]))].into(),
+                &get_metadata(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidInput { .. }));
+        assert_eq!(err.kind(), ErrorKind::NotNormalized);
+    }
+
+    #[tokio::test]
+    async fn embedding_model_lock_rejects_mismatches() {
+        let locked_to = |id: &str, dimension| {
+            with_options(
+                get_default_client(),
+                VectorSecretOptions {
+                    embedding_model: Some(EmbeddingModel {
+                        id: id.to_string(),
+                        dimension,
+                    }),
+                    ..Default::default()
+                },
+            )
+        };
+        let plaintext = |values: Vec<f32>| PlaintextVector {
+            plaintext_vector: values,
+            secret_path: SecretPath("secret_path".to_string()),
+            derivation_path: DerivationPath("deriv_path".to_string()),
+        };
+        let client = locked_to("small", 3);
+        let encrypted = client
+            .encrypt(plaintext(vec![0.6, 0.8, 0.]), &get_metadata())
+            .await
+            .unwrap();
+        let (_, icl_metadata_bytes) =
+            StandaloneVectorClient::decompose_key_id_header(encrypted.paired_icl_info.clone())
+                .unwrap();
+        assert_eq!(
+            get_vector_metadata(&icl_metadata_bytes).unwrap().model_id,
+            Some("small".to_string())
+        );
+        assert!(client
+            .decrypt(encrypted.clone(), &get_metadata())
+            .await
+            .is_ok());
+
+        let err = client
+            .encrypt(plaintext(vec![0.6, 0.8]), &get_metadata())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidInput { .. }));
+        assert!(err.to_string().contains("dimension 2"));
+        let err = client
+            .generate_query_vectors(
+                [("query".to_string(), plaintext(vec![1.; 4]))].into(),
+                &get_metadata(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidInput { .. }));
+
+        let err = locked_to("large", 3)
+            .decrypt(encrypted.clone(), &get_metadata())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidInput { .. }));
+        assert!(err.to_string().contains("`small`"));
+        let err = locked_to("small", 4)
+            .decrypt(encrypted, &get_metadata())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("dimension 3"));
+
+        // Vectors encrypted before the lock was added only have their dimension checked.
+        let unlocked = get_default_client()
+            .encrypt(plaintext(vec![0.6, 0.8, 0.]), &get_metadata())
+            .await
+            .unwrap();
+        assert!(client
+            .decrypt(unlocked.clone(), &get_metadata())
+            .await
+            .is_ok());
+        // and adding the lock alone doesn't make rotation re-encrypt them
+        let rotated = client
+            .rotate_vectors(
+                [("one".to_string(), unlocked.clone())].into(),
+                &get_metadata(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            rotated.successes["one"].encrypted_vector,
+            unlocked.encrypted_vector
+        );
+        assert_eq!(
+            rotated.successes["one"].paired_icl_info,
+            unlocked.paired_icl_info
+        );
+    }
 }
@@ -1,7 +1,7 @@
 use crate::{
     alloy_client_trait::AlloyClient,
-    errors::AlloyError,
-    util::{get_rng, BatchResult},
+    errors::{AlloyError, ErrorKind},
+    util::{get_rng, BatchResult, ShardedRng},
     AlloyMetadata, EncryptedBytes, FieldId, PlaintextBytes, TenantId,
 };
 use ironcore_documents::{
@@ -16,22 +16,21 @@ use ironcore_documents::{
 use itertools::Itertools;
 use protobuf::Message;
 use rand::{CryptoRng, RngCore};
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
-};
+use std::{collections::HashMap, sync::Arc};
+#[cfg(feature = "uniffi")]
 use uniffi::custom_newtype;
 
 pub type PlaintextDocument = HashMap<FieldId, PlaintextBytes>;
 
-#[derive(Debug, uniffi::Record)]
+#[derive(Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct PlaintextDocumentWithEdek {
     pub edek: EdekWithKeyIdHeader,
     pub document: PlaintextDocument,
 }
 
 impl PlaintextDocumentWithEdek {
-    #[uniffi::constructor]
+    #[cfg_attr(feature = "uniffi", uniffi::constructor)]
     pub fn new(
         edek: EdekWithKeyIdHeader,
         document: PlaintextDocument,
@@ -45,6 +44,7 @@ impl PlaintextDocumentWithEdek {
 // Note that in the case of SaaS Shield Standard, users could create this with a
 // legacy V3 EDEK, which is just the EDEK. This has to be handled manually on decrypts.
 pub struct EdekWithKeyIdHeader(pub Vec<u8>);
+#[cfg(feature = "uniffi")]
 custom_newtype!(EdekWithKeyIdHeader, Vec<u8>);
 
 impl EdekWithKeyIdHeader {
@@ -64,7 +64,8 @@ impl EdekWithKeyIdHeader {
 /// Document and EDEK (encrypted document encryption key) generated by `document_encrypt`/`documentEncrypt`.
 /// Note that `document_encrypt_deterministic`/`documentEncryptDeterministic` doesn't use this type
 /// as it prefixes an encryption header to the encrypted document map instead of using a separate EDEK.
-#[derive(Debug, Clone, uniffi::Record)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct EncryptedDocument {
     /// Encrypted Document Encryption Key used when the document was encrypted
     pub edek: EdekWithKeyIdHeader,
@@ -72,7 +73,8 @@ pub struct EncryptedDocument {
     pub document: HashMap<FieldId, EncryptedBytes>,
 }
 
-#[derive(Debug, Clone, uniffi::Record)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct RekeyEdeksBatchResult {
     pub successes: HashMap<String, EdekWithKeyIdHeader>,
     pub failures: HashMap<String, AlloyError>,
@@ -125,9 +127,8 @@ pub trait StandardDocumentOps: AlloyClient {
     ) -> Result<RekeyEdeksBatchResult, AlloyError>;
     /// Generate a prefix that could used to search a data store for documents encrypted using an identifier (KMS
     /// config id for SaaS Shield, secret id for Standalone). These bytes should be encoded into
-    /// a format matching the encoding in the data store. z85/ascii85 users should first pass these bytes through
-    /// `encode_prefix_z85` or `base85_prefix_padding`. Make sure you've read the documentation of those functions to
-    /// avoid pitfalls when encoding across byte boundaries.
+    /// a format matching the encoding in the data store. `encode_search_prefixes` does this for the common
+    /// encodings, including the z85/ascii85 pitfalls when encoding across byte boundaries.
     fn get_searchable_edek_prefix(&self, id: i32) -> Vec<u8> {
         get_prefix_bytes_for_search(ironcore_documents::v5::key_id_header::KeyIdHeader::new(
             Self::get_edek_type(),
@@ -155,6 +156,7 @@ pub(crate) fn verify_sig(
         Ok(())
     } else {
         Err(AlloyError::DecryptError {
+            kind: ErrorKind::SignatureVerificationFailed,
             msg: "EDEK signature verification failed.".to_string(),
         })
     }
@@ -163,7 +165,7 @@ pub(crate) fn verify_sig(
 /// Encrypt each of the fields of the document using the aes_dek
 pub(crate) fn encrypt_document_core<U: AsRef<[u8]>, R: RngCore + CryptoRng>(
     document: HashMap<String, U>,
-    rng: Arc<Mutex<R>>,
+    rng: Arc<ShardedRng<R>>,
     aes_dek: EncryptionKey,
     key_id_header: KeyIdHeader,
     v4_doc: icl_header_v4::V4DocumentHeader,
@@ -177,7 +179,7 @@ pub(crate) fn encrypt_document_core<U: AsRef<[u8]>, R: RngCore + CryptoRng>(
 
 pub(crate) fn encrypt_map<U: AsRef<[u8]>, R: RngCore + CryptoRng>(
     document: HashMap<String, U>,
-    rng: Arc<Mutex<R>>,
+    rng: Arc<ShardedRng<R>>,
     aes_dek: EncryptionKey,
 ) -> Result<HashMap<String, Vec<u8>>, AlloyError> {
     let encrypted_document = document
@@ -233,7 +235,7 @@ mod test {
         let rng = create_rng();
         let result = encrypt_document_core(
             [("foo".to_string(), vec![100u8])].into(),
-            Arc::new(Mutex::new(rng)),
+            Arc::new(ShardedRng::single(rng)),
             EncryptionKey([0u8; 32]),
             KeyIdHeader::new(EdekType::SaasShield, PayloadType::StandardEdek, KeyId(1)),
             Default::default(),
@@ -1,11 +1,15 @@
 use crate::{
-    errors::AlloyError,
-    standard::{EdekWithKeyIdHeader, EncryptedDocument, StandardDocumentOps},
-    util::v4_proto_from_bytes,
-    AlloyMetadata, PlaintextBytes,
+    errors::{AlloyError, ErrorKind},
+    standard::{
+        EdekWithKeyIdHeader, EncryptedDocument, PlaintextDocumentWithEdek, RekeyEdeksBatchResult,
+        StandardDocumentOps,
+    },
+    util::{collection_to_batch_result, v4_proto_from_bytes, BatchResult},
+    AlloyMetadata, PlaintextBytes, TenantId,
 };
 use bytes::Bytes;
 use ironcore_documents::{
+    aes::IvAndCiphertext,
     v4,
     v5::{
         self,
@@ -13,12 +17,31 @@ use ironcore_documents::{
         key_id_header::{self, EdekType, KeyId, KeyIdHeader},
     },
 };
+use std::collections::HashMap;
+#[cfg(feature = "uniffi")]
 use uniffi::custom_newtype;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EncryptedAttachedDocument(pub Vec<u8>);
+#[cfg(feature = "uniffi")]
 custom_newtype!(EncryptedAttachedDocument, Vec<u8>);
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct RekeyAttachedDocumentsBatchResult {
+    pub successes: HashMap<String, EncryptedAttachedDocument>,
+    pub failures: HashMap<String, AlloyError>,
+}
+
+impl From<BatchResult<EncryptedAttachedDocument>> for RekeyAttachedDocumentsBatchResult {
+    fn from(value: BatchResult<EncryptedAttachedDocument>) -> Self {
+        Self {
+            successes: value.successes,
+            failures: value.failures,
+        }
+    }
+}
+
 /// API for encrypting and decrypting documents using our standard encryption.
 pub trait StandardAttachedDocumentOps {
     /// Encrypt a field with the provided metadata.
@@ -36,13 +59,31 @@ pub trait StandardAttachedDocumentOps {
         attached_field: EncryptedAttachedDocument,
         metadata: &AlloyMetadata,
     ) -> Result<PlaintextBytes, AlloyError>;
+    /// Encrypt a field with the provided metadata, reusing the EDEK from `existing_document`.
+    /// The existing EDEK will be decrypted and used to encrypt the new field, so no new DEK is generated. This is
+    /// useful when updating a document, as the result keeps the same key ID and EDEK as the original.
+    async fn encrypt_with_existing_edek(
+        &self,
+        plaintext_field: PlaintextBytes,
+        existing_document: EncryptedAttachedDocument,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedAttachedDocument, AlloyError>;
     /// Generate a prefix that could used to search a data store for documents encrypted using an identifier (KMS
     /// config id for SaaS Shield, secret id for Standalone). These bytes should be encoded into
-    /// a format matching the encoding in the data store. z85/ascii85 users should first pass these bytes through
-    /// `encode_prefix_z85` or `base85_prefix_padding`. Make sure you've read the documentation of those functions to
-    /// avoid pitfalls when encoding across byte boundaries.
+    /// a format matching the encoding in the data store. `encode_search_prefixes` does this for the common
+    /// encodings, including the z85/ascii85 pitfalls when encoding across byte boundaries.
     /// Note that this will not work for matching values that don't use our key_id_header format, such as cloaked search.
     async fn get_searchable_edek_prefix(&self, id: i32) -> Vec<u8>;
+    /// Decrypt each EDEK on the front of the attached documents and re-encrypt it to the current secret or a new
+    /// tenant without touching the encrypted payloads. `metadata` must contain the tenant ID that the documents were
+    /// originally encrypted to. If `new_tenant_id` is empty, the EDEKs will be rekeyed to the same tenant's current
+    /// secret. Legacy V4 attached documents will be converted to the V5 attached layout.
+    async fn rekey_documents(
+        &self,
+        encrypted_documents: HashMap<String, EncryptedAttachedDocument>,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<RekeyAttachedDocumentsBatchResult, AlloyError>;
 }
 
 pub(crate) async fn encrypt_core<T: StandardDocumentOps>(
@@ -53,11 +94,7 @@ pub(crate) async fn encrypt_core<T: StandardDocumentOps>(
     // In order to call the encrypt on standard, we need a map. This is just a hardcoded string we will
     // use to encrypt.
     let hardcoded_id = "".to_string();
-    let EncryptedDocument {
-        edek: edek_with_key_id_bytes,
-        // Mutable so we can removed the hardcoded key below.
-        mut document,
-    } = standard_client
+    let encrypted_document = standard_client
         .encrypt(
             [(hardcoded_id.clone(), plaintext_field)]
                 .into_iter()
@@ -65,27 +102,35 @@ pub(crate) async fn encrypt_core<T: StandardDocumentOps>(
             metadata,
         )
         .await?;
-    let (key_id_header, edek_bytes) =
-        key_id_header::decode_version_prefixed_value(edek_with_key_id_bytes.0.into())?;
-
-    let edek = v4_proto_from_bytes(edek_bytes)?;
-
-    let edoc = document
-        .remove(&hardcoded_id)
-        .ok_or(AlloyError::EncryptError {
-            msg: "Encryption returned a document without a passed in field. This shouldn't happen."
-                .to_string(),
-        })?;
+    attach_document(encrypted_document, &hardcoded_id)
+}
 
-    Ok(EncryptedAttachedDocument(
-        AttachedDocument {
-            key_id_header,
-            edek,
-            edoc: v5::EncryptedPayload::try_from(edoc)?.to_aes_value_with_attached_iv(),
-        }
-        .write_to_bytes()?
-        .to_vec(),
-    ))
+pub(crate) async fn encrypt_with_existing_edek_core<T: StandardDocumentOps>(
+    standard_client: &T,
+    plaintext_field: Vec<u8>,
+    existing_document: EncryptedAttachedDocument,
+    metadata: &AlloyMetadata,
+) -> Result<EncryptedAttachedDocument, AlloyError> {
+    let AttachedDocument {
+        key_id_header,
+        edek,
+        ..
+    } = decode_attached_document(existing_document)?;
+    // In order to call the encrypt on standard, we need a map. This is just a hardcoded string we will
+    // use to encrypt.
+    let hardcoded_id = "".to_string();
+    let encrypted_document = standard_client
+        .encrypt_with_existing_edek(
+            PlaintextDocumentWithEdek::new(
+                EdekWithKeyIdHeader::new(key_id_header, edek),
+                [(hardcoded_id.clone(), plaintext_field)]
+                    .into_iter()
+                    .collect(),
+            ),
+            metadata,
+        )
+        .await?;
+    attach_document(encrypted_document, &hardcoded_id)
 }
 
 pub(crate) async fn decrypt_core<T: StandardDocumentOps>(
@@ -93,22 +138,11 @@ pub(crate) async fn decrypt_core<T: StandardDocumentOps>(
     attached_field: EncryptedAttachedDocument,
     metadata: &AlloyMetadata,
 ) -> Result<PlaintextBytes, AlloyError> {
-    let attached_field_bytes: Bytes = attached_field.0.into();
     let AttachedDocument {
         key_id_header,
         edek,
         edoc,
-    } = v4::attached::decode_attached_edoc(attached_field_bytes.clone())
-        .map(|(edek, edoc)| AttachedDocument {
-            key_id_header: KeyIdHeader::new(
-                EdekType::Standalone,
-                key_id_header::PayloadType::StandardEdek,
-                KeyId(0),
-            ),
-            edek,
-            edoc,
-        })
-        .or_else(|_| attached_field_bytes.try_into())?;
+    } = decode_attached_document(attached_field)?;
     // In order to call the decrypt on standard, we need a map. This is just a hardcoded string we will
     // use to decrypt.
     let hardcoded_id = "".to_string();
@@ -132,3 +166,108 @@ pub(crate) async fn decrypt_core<T: StandardDocumentOps>(
         .expect("Decryption doesn't change the structure of the fields.");
     Ok(plaintext)
 }
+
+pub(crate) async fn rekey_documents_core<T: StandardDocumentOps>(
+    standard_client: &T,
+    encrypted_documents: HashMap<String, EncryptedAttachedDocument>,
+    metadata: &AlloyMetadata,
+    new_tenant_id: Option<TenantId>,
+) -> Result<RekeyAttachedDocumentsBatchResult, AlloyError> {
+    let BatchResult {
+        successes: decoded_documents,
+        failures: decode_failures,
+    } = collection_to_batch_result(encrypted_documents, decode_attached_document);
+    let (edeks, mut edocs): (HashMap<_, _>, HashMap<_, _>) = decoded_documents
+        .into_iter()
+        .map(|(document_id, document)| {
+            (
+                (
+                    document_id.clone(),
+                    EdekWithKeyIdHeader::new(document.key_id_header, document.edek),
+                ),
+                (document_id, document.edoc),
+            )
+        })
+        .unzip();
+    let RekeyEdeksBatchResult {
+        successes: rekeyed_edeks,
+        failures: rekey_failures,
+    } = standard_client
+        .rekey_edeks(edeks, metadata, new_tenant_id)
+        .await?;
+    let rekeyed_documents = rekeyed_edeks.into_iter().filter_map(|(document_id, edek)| {
+        edocs
+            .remove(&document_id)
+            .map(|edoc| (document_id, (edek, edoc)))
+    });
+    let mut result =
+        collection_to_batch_result(rekeyed_documents, |(edek, edoc)| attach_edek(edek, edoc));
+    result.failures.extend(decode_failures);
+    result.failures.extend(rekey_failures);
+    Ok(result.into())
+}
+
+/// Split attached bytes into their parts. V4 attached documents don't have a key ID header, so a Standalone
+/// header with key ID 0 is used for them.
+fn decode_attached_document(
+    attached_field: EncryptedAttachedDocument,
+) -> Result<AttachedDocument, AlloyError> {
+    let attached_field_bytes: Bytes = attached_field.0.into();
+    Ok(
+        v4::attached::decode_attached_edoc(attached_field_bytes.clone())
+            .map(|(edek, edoc)| AttachedDocument {
+                key_id_header: KeyIdHeader::new(
+                    EdekType::Standalone,
+                    key_id_header::PayloadType::StandardEdek,
+                    KeyId(0),
+                ),
+                edek,
+                edoc,
+            })
+            .or_else(|_| attached_field_bytes.try_into())?,
+    )
+}
+
+/// Put the EDEK from `encrypted_document` on the front of its single field, which is stored under `hardcoded_id`.
+fn attach_document(
+    encrypted_document: EncryptedDocument,
+    hardcoded_id: &str,
+) -> Result<EncryptedAttachedDocument, AlloyError> {
+    let EncryptedDocument {
+        edek,
+        // Mutable so we can removed the hardcoded key below.
+        mut document,
+    } = encrypted_document;
+    let edoc = document
+        .remove(hardcoded_id)
+        .ok_or(AlloyError::EncryptError {
+            kind: ErrorKind::Other,
+            msg: "Encryption returned a document without a passed in field. This shouldn't happen."
+                .to_string(),
+        })?;
+    attach_edek(
+        edek,
+        v5::EncryptedPayload::try_from(edoc)?.to_aes_value_with_attached_iv(),
+    )
+}
+
+/// Put the EDEK (with its key ID header) on the front of the encrypted payload.
+fn attach_edek(
+    edek_with_key_id_bytes: EdekWithKeyIdHeader,
+    edoc: IvAndCiphertext,
+) -> Result<EncryptedAttachedDocument, AlloyError> {
+    let (key_id_header, edek_bytes) =
+        key_id_header::decode_version_prefixed_value(edek_with_key_id_bytes.0.into())?;
+
+    let edek = v4_proto_from_bytes(edek_bytes)?;
+
+    Ok(EncryptedAttachedDocument(
+        AttachedDocument {
+            key_id_header,
+            edek,
+            edoc,
+        }
+        .write_to_bytes()?
+        .to_vec(),
+    ))
+}
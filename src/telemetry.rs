@@ -0,0 +1,183 @@
+//! `tracing` instrumentation, enabled with the `tracing` feature, and the choke point that reports operations to the
+//! installed `MetricsRecorder`. Without the feature and a recorder, operations just run the wrapped future.
+//!
+//! Spans only ever carry identifiers and sizes: tenant ID, operation, secret path, key ID, batch size and latency.
+//! Failures are recorded by error variant alone, since some error messages are derived from plaintext values.
+
+use crate::errors::AlloyError;
+use crate::metrics::{self, OperationMetrics};
+use std::future::Future;
+use std::time::Instant;
+
+/// Description of an SDK operation or TSP request to trace.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Operation<'a> {
+    name: &'static str,
+    // Only spans record these, since they'd make metric labels unbounded.
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    tenant_id: Option<&'a str>,
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    secret_path: Option<&'a str>,
+    batch_size: Option<usize>,
+    endpoint: Option<&'a str>,
+}
+
+impl<'a> Operation<'a> {
+    pub(crate) fn new(name: &'static str) -> Self {
+        Operation {
+            name,
+            tenant_id: None,
+            secret_path: None,
+            batch_size: None,
+            endpoint: None,
+        }
+    }
+
+    pub(crate) fn tenant_id(self, tenant_id: &'a str) -> Self {
+        Operation {
+            tenant_id: Some(tenant_id),
+            ..self
+        }
+    }
+
+    pub(crate) fn secret_path(self, secret_path: &'a str) -> Self {
+        Operation {
+            secret_path: Some(secret_path),
+            ..self
+        }
+    }
+
+    pub(crate) fn batch_size(self, batch_size: usize) -> Self {
+        Operation {
+            batch_size: Some(batch_size),
+            ..self
+        }
+    }
+
+    pub(crate) fn endpoint(self, endpoint: &'a str) -> Self {
+        Operation {
+            endpoint: Some(endpoint),
+            ..self
+        }
+    }
+
+    /// Run `future` inside a span for this operation, recording its latency and whether it failed, and report it to
+    /// the installed `MetricsRecorder`.
+    pub(crate) async fn run<T, F>(self, future: F) -> Result<T, AlloyError>
+    where
+        F: Future<Output = Result<T, AlloyError>>,
+    {
+        let recorder = metrics::current_recorder();
+        let start = Instant::now();
+        let result = self.run_traced(future).await;
+        if let Some(recorder) = recorder {
+            recorder.record_operation(&OperationMetrics {
+                operation: self.name,
+                endpoint: self.endpoint,
+                batch_size: self.batch_size,
+                latency: start.elapsed(),
+                error: result.as_ref().err(),
+            });
+        }
+        result
+    }
+
+    #[cfg(feature = "tracing")]
+    async fn run_traced<T, F>(self, future: F) -> Result<T, AlloyError>
+    where
+        F: Future<Output = Result<T, AlloyError>>,
+    {
+        use tracing::Instrument;
+        let span = tracing::info_span!(
+            "alloy",
+            operation = self.name,
+            tenant_id = self.tenant_id,
+            secret_path = self.secret_path,
+            batch_size = self.batch_size,
+            endpoint = self.endpoint,
+            key_id = tracing::field::Empty,
+            latency_ms = tracing::field::Empty,
+        );
+        let start = Instant::now();
+        let result = future.instrument(span.clone()).await;
+        let latency_ms = start.elapsed().as_secs_f64() * 1000.;
+        span.record("latency_ms", latency_ms);
+        span.in_scope(|| match &result {
+            Ok(_) => tracing::debug!(latency_ms, "{} succeeded", self.name),
+            Err(e) => tracing::warn!(latency_ms, error = e.variant_name(), "{} failed", self.name),
+        });
+        result
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    async fn run_traced<T, F>(self, future: F) -> Result<T, AlloyError>
+    where
+        F: Future<Output = Result<T, AlloyError>>,
+    {
+        future.await
+    }
+}
+
+/// Record the key ID chosen for, or found on, the value being worked on in the current operation's span.
+#[cfg(feature = "tracing")]
+pub(crate) fn record_key_id(key_id: u32) {
+    tracing::Span::current().record("key_id", key_id);
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn record_key_id(_key_id: u32) {}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use crate::standalone::standard::test::new_client;
+    use crate::standard::StandardDocumentOps;
+    use crate::AlloyMetadata;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn spans_record_identifiers_but_not_plaintext() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_span_events(FmtSpan::CLOSE)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let client = new_client(Some(1));
+        let metadata = AlloyMetadata::new_simple("tenant-in-span".into());
+        let document = [("field".to_string(), b"very-secret-plaintext".to_vec())].into();
+        let encrypted = client.encrypt(document, &metadata).await.unwrap();
+        client.decrypt(encrypted, &metadata).await.unwrap();
+        let other_tenant = AlloyMetadata::new_simple("other-tenant".into());
+        let encrypted = client.encrypt([].into(), &metadata).await.unwrap();
+        assert!(client.decrypt(encrypted, &other_tenant).await.is_err());
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("operation=\"standard.encrypt\""));
+        assert!(output.contains("operation=\"standard.decrypt\""));
+        assert!(output.contains("tenant_id=\"tenant-in-span\""));
+        assert!(output.contains("key_id=1"));
+        assert!(output.contains("latency_ms="));
+        assert!(output.contains("standard.decrypt failed"));
+        assert!(!output.contains("very-secret-plaintext"));
+    }
+}
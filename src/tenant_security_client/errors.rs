@@ -4,7 +4,8 @@ use thiserror::Error;
 /// Errors originating from the Tenant Security Proxy.
 /// These errors are broken into 4 types: service errors, KMS errors,
 /// security event errors, and tenant secret errors.
-#[derive(Error, Debug, PartialEq, Eq, uniffi::Enum, Clone)]
+#[derive(Error, Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 #[non_exhaustive]
 pub enum TenantSecurityProxyError {
     Service { error: ServiceError },
@@ -14,7 +15,8 @@ pub enum TenantSecurityProxyError {
 }
 
 /// Errors communicating with the TSP
-#[derive(Debug, PartialEq, Eq, uniffi::Enum, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 #[non_exhaustive]
 pub enum ServiceError {
     UnknownError,
@@ -23,7 +25,8 @@ pub enum ServiceError {
 }
 
 /// Errors originating from or relating to the tenant's KMS
-#[derive(Debug, PartialEq, Eq, uniffi::Enum, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 #[non_exhaustive]
 pub enum KmsError {
     NoPrimaryKmsConfiguration,
@@ -39,14 +42,16 @@ pub enum KmsError {
 }
 
 /// Errors related to security events
-#[derive(Debug, PartialEq, Eq, uniffi::Enum, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 #[non_exhaustive]
 pub enum SecurityEventError {
     SecurityEventRejected,
 }
 
 /// Errors related to tenant secrets
-#[derive(Debug, PartialEq, Eq, uniffi::Enum, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
 #[non_exhaustive]
 pub enum TenantSecretError {
     SecretCreationFailed,
@@ -8,7 +8,7 @@ pub use rest::{
     BatchUnwrapKeyResponse, DerivationType, DeriveKeyChoice, DerivedKey, KeyDeriveResponse,
     SecretType, UnwrapKeyResponse, WrapKeyResponse,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
     convert::{TryFrom, TryInto},
@@ -17,7 +17,7 @@ use std::{
 };
 
 use self::rest::RekeyResponse;
-use crate::errors::AlloyError;
+use crate::errors::{AlloyError, ErrorKind};
 #[cfg(test)]
 pub use rest::TenantSecretAssignmentId;
 
@@ -34,6 +34,7 @@ impl TryFrom<String> for ApiKey {
         use AlloyError::InvalidConfiguration;
         Base64::from_str(value.as_str())
             .map_err(|_| InvalidConfiguration {
+                kind: ErrorKind::Other,
                 msg: "API_KEY was not valid Base64.".to_string(),
             })
             .and_then(|base64| {
@@ -41,6 +42,7 @@ impl TryFrom<String> for ApiKey {
                     Ok(ApiKey(value))
                 } else {
                     Err(InvalidConfiguration {
+                        kind: ErrorKind::Other,
                         msg: "API_KEY was not 16 characters.".to_string(),
                     })
                 }
@@ -125,17 +127,25 @@ impl TenantSecurityClient {
         event: &SecurityEvent,
         metadata: &RequestMetadata,
     ) -> Result<(), AlloyError> {
-        self.request
-            .log_security_event(event.to_string().as_str(), metadata)
+        self.log_security_event_text(event.to_string().as_str(), metadata)
             .await
     }
+
+    /// Log a security event that was already converted to its TSP name, like `DATA_ENCRYPT`.
+    pub(crate) async fn log_security_event_text(
+        &self,
+        event_text: &str,
+        metadata: &RequestMetadata,
+    ) -> Result<(), AlloyError> {
+        self.request.log_security_event(event_text, metadata).await
+    }
 }
 
 /// Holds metadata fields as part of an document request. Each document will have metadata that associates
 /// it to a tenant ID, which service is accessing the data, as well as optional fields for other arbitrary
 /// key/value pairs and a request ID to send to the Tenant Security Proxy.
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all(serialize = "camelCase"))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct RequestMetadata {
     pub tenant_id: TenantId,
     pub icl_fields: IclFields,
@@ -209,8 +219,8 @@ impl RequestMetadata {
 }
 
 /// Document metadata in a form that can be serialized and sent to the TSP.
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all(serialize = "camelCase"))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct IclFields {
     /// An identifier for the requesting user or service.
     requesting_id: RequestingId,
@@ -221,7 +231,8 @@ pub struct IclFields {
 }
 
 /// Unique ID of user/service that is processing data.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String")]
 pub struct RequestingId(String);
 impl RequestingId {
     pub fn new(id: String) -> Result<RequestingId, AlloyError> {
@@ -234,6 +245,7 @@ impl TryFrom<String> for RequestingId {
     fn try_from(value: String) -> Result<Self, Self::Error> {
         if value.is_empty() {
             Err(AlloyError::InvalidInput {
+                kind: ErrorKind::Other,
                 msg: "RequestingId cannot be empty.".to_string(),
             })
         } else {
@@ -1,11 +1,12 @@
 use super::errors::TenantSecurityProxyError;
 use super::rest::{
-    BatchUnwrapKeyRequest, BatchUnwrapKeyResponse, DerivationType, KeyDeriveResponse,
-    LogSecurityEventRequest, RekeyRequest, RekeyResponse, SecretType, TenantDeriveKeyRequest,
-    TspErrorResponse, UnwrapKeyRequest, UnwrapKeyResponse, WrapKeyResponse,
+    invalid_response_kind, BatchUnwrapKeyRequest, BatchUnwrapKeyResponse, DerivationType,
+    KeyDeriveResponse, LogSecurityEventRequest, RekeyRequest, RekeyResponse, SecretType,
+    TenantDeriveKeyRequest, TspErrorResponse, UnwrapKeyRequest, UnwrapKeyResponse, WrapKeyResponse,
 };
 use super::{ApiKey, RequestMetadata};
 use crate::errors::AlloyError;
+use crate::telemetry::Operation;
 use crate::{DerivationPath, SecretPath};
 use async_trait::async_trait;
 use base64_type::Base64;
@@ -55,6 +56,17 @@ impl TspRequest {
         &self,
         endpoint: String,
         post_data: A,
+    ) -> Result<Response, AlloyError> {
+        Operation::new("tsp.request")
+            .endpoint(&endpoint)
+            .run(self.make_json_request_core(&endpoint, post_data))
+            .await
+    }
+
+    async fn make_json_request_core<A: Serialize>(
+        &self,
+        endpoint: &str,
+        post_data: A,
     ) -> Result<Response, AlloyError> {
         let url = format!("{}{}{}", self.tsp_address, TSP_API_PREFIX, endpoint);
         let resp = self
@@ -71,10 +83,12 @@ impl TspRequest {
                     .json::<Value>()
                     .await
                     .map_err(|_| AlloyError::RequestError {
+                        kind: invalid_response_kind(status),
                         msg: format!(
                             "Response from the TSP URL was not valid JSON. Status: {}",
                             status.as_str()
                         ),
+                        source: None,
                     })
                     .and_then(|json| TspErrorResponse::try_from_value(json, status))?;
                 let error_variant = TenantSecurityProxyError::code_to_error(parsed_error.code);
@@ -206,17 +220,23 @@ impl TenantKeyOps for TspRequest {
         derivation_type: DerivationType,
         secret_type: SecretType,
     ) -> Result<KeyDeriveResponse, AlloyError> {
-        let post_data = serde_json::to_value(TenantDeriveKeyRequest {
-            metadata,
-            paths,
-            derivation_type,
-            secret_type,
-        })?;
-        Ok(self
-            .make_json_request(TENANT_KEY_DERIVE_ENDPOINT.to_string(), post_data)
-            .await?
-            .json::<KeyDeriveResponse>()
-            .await?)
+        Operation::new("tsp.tenant_key_derive")
+            .tenant_id(&metadata.tenant_id.0)
+            .batch_size(paths.values().map(HashSet::len).sum())
+            .run(async {
+                let post_data = serde_json::to_value(TenantDeriveKeyRequest {
+                    metadata,
+                    paths,
+                    derivation_type,
+                    secret_type,
+                })?;
+                Ok(self
+                    .make_json_request(TENANT_KEY_DERIVE_ENDPOINT.to_string(), post_data)
+                    .await?
+                    .json::<KeyDeriveResponse>()
+                    .await?)
+            })
+            .await
     }
 }
 
@@ -1,5 +1,5 @@
 use super::{DerivationPath, RequestMetadata, SecretPath};
-use crate::errors::AlloyError;
+use crate::errors::{AlloyError, ErrorKind};
 use base64_type::Base64;
 use ironcore_documents::v5::key_id_header::KeyId;
 use reqwest::StatusCode;
@@ -78,16 +78,28 @@ impl TspErrorResponse {
     pub fn try_from_value(value: Value, status: StatusCode) -> Result<Self, AlloyError> {
         serde_json::from_value::<TspErrorResponse>(value.clone()).map_err(|_| {
             AlloyError::RequestError {
+                kind: invalid_response_kind(status),
                 msg: format!(
                     "TSP gave an invalid response: `{}`. Status: {}",
                     value,
                     status.as_str()
                 ),
+                source: None,
             }
         })
     }
 }
 
+/// Kind of error for a TSP response that couldn't be understood. Overloaded or restarting TSPs and the proxies in
+/// front of them often respond with a 5xx or 429 that isn't from the TSP itself, so those are worth retrying.
+pub(crate) fn invalid_response_kind(status: StatusCode) -> ErrorKind {
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        ErrorKind::TspUnavailable
+    } else {
+        ErrorKind::InvalidTspResponse
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct WrapKeyResponse {
     pub dek: Base64,
@@ -150,8 +162,10 @@ impl KeyDeriveResponse {
             DeriveKeyChoice::InRotation => self.get_in_rotation(secret_path, deriv_path),
         }
         .ok_or_else(|| AlloyError::RequestError {
+            kind: ErrorKind::InvalidTspResponse,
             msg: "The secret path, derivation path combo didn't have the requested key."
                 .to_string(),
+            source: None,
         })
     }
 
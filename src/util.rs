@@ -1,4 +1,5 @@
 use crate::{errors::AlloyError, AlloyMetadata, FieldId, TenantId, VectorEncryptionKey};
+use futures::{channel::oneshot, stream, Future, FutureExt, StreamExt};
 use ironcore_documents::v5::key_id_header::KeyId;
 use itertools::Itertools;
 use protobuf::Message;
@@ -7,25 +8,75 @@ use rand::{
     CryptoRng, RngCore, SeedableRng,
 };
 use rand_chacha::{ChaCha20Core, ChaCha20Rng};
+use rayon::prelude::*;
 use ring::hmac::{Key as HMACKey, HMAC_SHA256, HMAC_SHA512};
 use std::{
     collections::HashMap,
-    sync::{Arc, Mutex, MutexGuard},
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock,
+    },
 };
 
 /// number of bytes that can be read from before it rngs are reseeded. 1 MiB
 const BYTES_BEFORE_RESEEDING: u64 = 1024 * 1024;
+/// Most tenants rotated at the same time by the multi-tenant rotation functions.
+const MAX_CONCURRENT_TENANT_GROUPS: usize = 8;
 
 pub(crate) type OurReseedingRng = ReseedingRng<ChaCha20Core, OsRng>;
 
 #[derive(Debug, PartialEq)]
 pub(crate) struct AuthHash(pub(crate) [u8; 32]);
 
-/// Helper function to avoid `Mutex` noise at each call site. Must be derefed to use.
-pub(crate) fn get_rng<R: RngCore + CryptoRng>(rng: &Arc<Mutex<R>>) -> MutexGuard<'_, R> {
+/// Source of the next shard index handed out to a thread the first time it asks for an RNG.
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Index of the shard this thread uses. Threads are assigned round robin so that up to as many threads as
+    /// there are shards never share a lock.
+    static SHARD_INDEX: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A set of independent RNGs, one per available core by default. Each thread always uses the same shard, so
+/// concurrent encryptions on different threads don't wait on each other for randomness. Every shard is its own
+/// CSPRNG seeded independently, so sharding doesn't weaken the randomness of any of them.
+pub(crate) struct ShardedRng<R> {
+    shards: Vec<Mutex<R>>,
+}
+
+impl<R: RngCore + CryptoRng> ShardedRng<R> {
+    /// Create one shard per available core, each built by a separate call to `new_shard`.
+    pub(crate) fn new<F: FnMut() -> R>(new_shard: F) -> Self {
+        let shard_count = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Self::with_shard_count(shard_count, new_shard)
+    }
+
+    pub(crate) fn with_shard_count<F: FnMut() -> R>(shard_count: usize, mut new_shard: F) -> Self {
+        Self {
+            shards: (0..shard_count.max(1))
+                .map(|_| Mutex::new(new_shard()))
+                .collect(),
+        }
+    }
+
+    /// A single shard wrapping `rng`. Every thread shares it, which makes the output deterministic for a seeded
+    /// `rng` at the cost of contention.
+    #[cfg(test)]
+    pub(crate) fn single(rng: R) -> Self {
+        Self {
+            shards: vec![Mutex::new(rng)],
+        }
+    }
+}
+
+/// Helper function to avoid `Mutex` noise at each call site. Locks the shard belonging to the current thread.
+/// Must be derefed to use.
+pub(crate) fn get_rng<R: RngCore + CryptoRng>(rng: &ShardedRng<R>) -> MutexGuard<'_, R> {
+    let shard = SHARD_INDEX.with(|index| index % rng.shards.len());
     // should be safe... panics if the current thread holds the lock, we'll need to test to see how the FFI makes
     // threads appear. Also panics if poisoned, which I don't think we care about cause everything is dying then.
-    rng.lock().unwrap()
+    rng.shards[shard].lock().unwrap()
 }
 
 pub(crate) fn hash256<K: AsRef<[u8]>, T: AsRef<[u8]>>(key: K, payload: T) -> [u8; 32] {
@@ -78,19 +129,18 @@ pub(crate) fn check_auth_hash<'a, A: AsRef<[u8]>, B: Iterator<Item = &'a f32>>(
     compute_auth_hash(key, approximation_factor, iv, encrypted_embedding) == auth_hash
 }
 
-pub(crate) fn create_reseeding_rng() -> Arc<Mutex<OurReseedingRng>> {
-    Arc::new(Mutex::new(ReseedingRng::new(
-        ChaCha20Core::from_entropy(),
-        BYTES_BEFORE_RESEEDING,
-        OsRng,
-    )))
+pub(crate) fn create_reseeding_rng() -> Arc<ShardedRng<OurReseedingRng>> {
+    Arc::new(ShardedRng::new(|| {
+        ReseedingRng::new(ChaCha20Core::from_entropy(), BYTES_BEFORE_RESEEDING, OsRng)
+    }))
 }
 
 /// Creates a seeded RNG that won't actually ever reseed to use in test functions from the FFI.
 #[cfg(test)]
-pub(crate) fn create_test_seeded_rng(seed: u64) -> Arc<Mutex<OurReseedingRng>> {
+pub(crate) fn create_test_seeded_rng(seed: u64) -> Arc<ShardedRng<OurReseedingRng>> {
     //Note that this will never actually reseed because the threshold is 0.
-    Arc::new(Mutex::new(ReseedingRng::new(
+    //There's only one shard so the output is the same no matter which thread the test runs on.
+    Arc::new(ShardedRng::single(ReseedingRng::new(
         ChaCha20Core::seed_from_u64(seed),
         0,
         OsRng,
@@ -127,6 +177,133 @@ where
     }
 }
 
+/// Rayon pool that batch operations run on. It belongs to the crate instead of being whichever pool the caller is on,
+/// so a caller that blocks a rayon worker while it waits for a batch can't leave the batch with no thread to run on.
+/// It's sized like rayon's global pool, including honoring `RAYON_NUM_THREADS`.
+fn batch_thread_pool() -> &'static rayon::ThreadPool {
+    static POOL: OnceLock<rayon::ThreadPool> = OnceLock::new();
+    POOL.get_or_init(|| {
+        rayon::ThreadPoolBuilder::new()
+            .thread_name(|index| format!("ironcore-alloy-batch-{index}"))
+            .build()
+            .expect("Failed to create the batch thread pool. This means there is a system misconfiguration.")
+    })
+}
+
+/// Like `collection_to_batch_result`, but applies `func` to the values in parallel on the crate's batch thread pool.
+/// Each chunk of work rayon splits off gets its own ChaCha20 RNG seeded from its thread's shard of `rng`, so
+/// shards are only locked once per chunk rather than once per value.
+/// The work is handed to the pool and awaited through a channel, so the calling task doesn't hold its
+/// executor thread while the batch is processed. This doesn't rely on a tokio runtime, so it also works under the
+/// foreign executors the FFI uses and the blocking clients. A panic in `func` is resumed on the awaiting task.
+pub(crate) async fn parallel_collection_to_batch_result<T, U, F, R>(
+    collection: HashMap<FieldId, T>,
+    rng: Arc<ShardedRng<R>>,
+    func: F,
+) -> BatchResult<U>
+where
+    T: Send + 'static,
+    U: Send + 'static,
+    F: Fn(T, &mut ChaCha20Rng) -> Result<U, AlloyError> + Send + Sync + 'static,
+    R: RngCore + CryptoRng + Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    batch_thread_pool().spawn(move || {
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let results: Vec<_> = collection
+                .into_par_iter()
+                .map_init(
+                    || {
+                        ChaCha20Rng::from_rng(&mut *get_rng(&rng))
+                            .expect("Seeding from an in-memory RNG can't fail.")
+                    },
+                    |thread_rng, (key, value)| match func(value, thread_rng) {
+                        Ok(x) => Ok((key, x)),
+                        Err(x) => Err((key, x)),
+                    },
+                )
+                .collect();
+            results
+        }));
+        // The receiver is only gone if the caller stopped waiting, in which case nobody wants the result.
+        let _ = sender.send(result);
+    });
+    let results = match receiver
+        .await
+        .expect("The rayon job always sends its result before dropping the sender.")
+    {
+        Ok(results) => results,
+        Err(panic) => std::panic::resume_unwind(panic),
+    };
+    let (successes, failures) = results.into_iter().partition_result();
+    BatchResult {
+        successes,
+        failures,
+    }
+}
+
+/// Groups `items` by the tenant they were encrypted to, calls `rotate` once per group with each item's new tenant ID,
+/// then merges every group's result into a single batch result. If a whole group fails (e.g. key derivation failed)
+/// every item in that group is reported as a failure with that error. Fields of `metadata` other than the tenant ID
+/// are shared by all groups. At most `MAX_CONCURRENT_TENANT_GROUPS` groups are rotated at once, since each one can
+/// make key derivation requests to the TSP.
+pub(crate) async fn rotate_grouped_by_tenant<T, U, F, Fut, I>(
+    items: I,
+    metadata: &AlloyMetadata,
+    rotate: F,
+) -> BatchResult<U>
+where
+    F: Fn(HashMap<FieldId, (Option<TenantId>, T)>, AlloyMetadata) -> Fut,
+    Fut: Future<Output = Result<BatchResult<U>, AlloyError>>,
+    I: IntoIterator<Item = (FieldId, (TenantId, Option<TenantId>, T))>,
+{
+    let groups = items
+        .into_iter()
+        .map(|(id, (tenant_id, new_tenant_id, value))| (tenant_id, (id, (new_tenant_id, value))))
+        .into_group_map();
+    let group_results: Vec<_> = stream::iter(groups)
+        .map(|(tenant_id, group)| {
+            let ids = group.iter().map(|(id, _)| id.clone()).collect_vec();
+            let group_metadata = AlloyMetadata {
+                tenant_id,
+                ..metadata.clone()
+            };
+            rotate(group.into_iter().collect(), group_metadata).map(|result| (ids, result))
+        })
+        .buffer_unordered(MAX_CONCURRENT_TENANT_GROUPS)
+        .collect()
+        .await;
+    merge_group_results(group_results)
+}
+
+/// Merges the results of rotating groups of items. Every item in a group that failed as a whole is reported as a
+/// failure with the group's error.
+pub(crate) fn merge_group_results<U>(
+    group_results: impl IntoIterator<Item = (Vec<FieldId>, Result<BatchResult<U>, AlloyError>)>,
+) -> BatchResult<U> {
+    group_results.into_iter().fold(
+        BatchResult {
+            successes: HashMap::new(),
+            failures: HashMap::new(),
+        },
+        |mut acc, (ids, result)| {
+            match result {
+                Ok(BatchResult {
+                    successes,
+                    failures,
+                }) => {
+                    acc.successes.extend(successes);
+                    acc.failures.extend(failures);
+                }
+                Err(e) => acc
+                    .failures
+                    .extend(ids.into_iter().map(|id| (id, e.clone()))),
+            }
+            acc
+        },
+    )
+}
+
 /// Returns `true` if the key IDs and tenant IDs are identical, otherwise `false`.
 pub(crate) fn check_rotation_no_op(
     encrypted_key_id: KeyId,
@@ -143,12 +320,92 @@ pub(crate) fn v4_proto_from_bytes<B: AsRef<[u8]>>(
     Ok(Message::parse_from_bytes(b.as_ref())?)
 }
 
+pub(crate) const Z85_ALPHABET: &[u8; 85] =
+    b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ.-:+=^!/*?&<>()[]{}@%$#";
+
+/// Every string that base64 encodings of values starting with `prefix_bytes` can start with. The last character is
+/// enumerated when `prefix_bytes` only determine some of its bits.
+pub(crate) fn base64_search_prefixes<E: base64::Engine>(
+    engine: &E,
+    prefix_bytes: &[u8],
+) -> Vec<String> {
+    // Each byte past the last full 3 byte chunk fixes one more whole character, plus 2 or 4 bits of the next one.
+    let (extension_shift, free_values): (u8, u8) = match prefix_bytes.len() % 3 {
+        0 => return vec![engine.encode(prefix_bytes)],
+        1 => (4, 16),
+        _ => (6, 4),
+    };
+    let prefix_len = prefix_bytes.len() / 3 * 4 + prefix_bytes.len() % 3 + 1;
+    (0..free_values)
+        .map(|free_bits| {
+            let mut encoded =
+                engine.encode([prefix_bytes, &[free_bits << extension_shift]].concat());
+            encoded.truncate(prefix_len);
+            encoded
+        })
+        .collect()
+}
+
+/// Strings that base85 encodings of values starting with `prefix_bytes` can start with, using `to_char` for the
+/// alphabet. `zero_shorthand` covers ascii85's `z` for chunks that are all zero.
+pub(crate) fn base85_search_prefixes<F: Fn(u32) -> char>(
+    prefix_bytes: &[u8],
+    to_char: F,
+    zero_shorthand: bool,
+) -> Vec<String> {
+    if prefix_bytes.is_empty() {
+        return vec![String::new()];
+    }
+    let digits = |mut value: u32, count: usize| {
+        let mut chars = (0..5)
+            .map(|_| {
+                let digit = value % 85;
+                value /= 85;
+                to_char(digit)
+            })
+            .collect_vec();
+        chars.reverse();
+        chars.into_iter().take(count).collect::<String>()
+    };
+    let chunk_options = prefix_bytes.chunks(4).map(|chunk| {
+        let free_bits = 8 * (4 - chunk.len() as u32);
+        let low = chunk
+            .iter()
+            .fold(0u64, |value, byte| value << 8 | *byte as u64)
+            << free_bits;
+        let high = low | ((1u64 << free_bits) - 1);
+        let mut options = if chunk.len() == 4 {
+            vec![digits(low as u32, 5)]
+        } else {
+            // Only the first `chunk.len()` characters are kept, so look at every value they can take across the
+            // range of values the free bytes allow.
+            let step = 85u64.pow(5 - chunk.len() as u32);
+            (low / step..=high / step)
+                .map(|leading| digits((leading * step) as u32, chunk.len()))
+                .collect_vec()
+        };
+        if zero_shorthand && low == 0 {
+            options.push("z".to_string());
+        }
+        options
+    });
+    chunk_options
+        .multi_cartesian_product()
+        .map(|parts| parts.concat())
+        .collect()
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::errors::ErrorKind;
+    use crate::{encode_search_prefixes, PrefixEncoding};
     use crate::{vector::EncryptionKey, vector::ScalingFactor};
     use base64::Engine;
     use bytes::Bytes;
+    use ironcore_documents::v5::key_id_header::{
+        get_prefix_bytes_for_search, EdekType, KeyIdHeader, PayloadType,
+    };
     use itertools::Itertools;
     use proptest::prelude::*;
     use std::collections::HashSet;
@@ -165,6 +422,27 @@ pub(crate) mod tests {
         );
     }
 
+    #[test]
+    fn get_rng_uses_one_shard_per_thread() {
+        let rng = ShardedRng::with_shard_count(4, rand_chacha::ChaCha20Rng::from_entropy);
+        let shard_address = |rng: &ShardedRng<_>| &*get_rng(rng) as *const _ as usize;
+        assert_eq!(shard_address(&rng), shard_address(&rng));
+        let this_thread = SHARD_INDEX.with(|index| *index);
+        let other_thread = std::thread::spawn(|| SHARD_INDEX.with(|index| *index))
+            .join()
+            .unwrap();
+        assert_ne!(this_thread, other_thread);
+    }
+
+    #[test]
+    fn seeded_rng_is_deterministic_across_threads() {
+        let first = get_rng(&create_test_seeded_rng(1)).next_u64();
+        let second = std::thread::spawn(|| get_rng(&create_test_seeded_rng(1)).next_u64())
+            .join()
+            .unwrap();
+        assert_eq!(first, second);
+    }
+
     proptest! {
         // This is to test that values written out and read by serde_json work the same as
         // just generic f32 values.
@@ -285,6 +563,93 @@ pub(crate) mod tests {
         }
     }
 
+    fn encode_with(encoding: PrefixEncoding, bytes: &[u8]) -> String {
+        match encoding {
+            PrefixEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(bytes),
+            PrefixEncoding::Base64Url => base64::engine::general_purpose::URL_SAFE.encode(bytes),
+            PrefixEncoding::Z85 => z85::encode(bytes),
+            PrefixEncoding::Ascii85 => {
+                let encoded = ascii85::encode(bytes);
+                encoded[2..encoded.len() - 2].to_string()
+            }
+            PrefixEncoding::Hex => hex::encode(bytes),
+        }
+    }
+
+    fn arb_prefix_encoding() -> impl Strategy<Value = PrefixEncoding> {
+        prop_oneof![
+            Just(PrefixEncoding::Base64),
+            Just(PrefixEncoding::Base64Url),
+            Just(PrefixEncoding::Z85),
+            Just(PrefixEncoding::Ascii85),
+            Just(PrefixEncoding::Hex),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn search_prefixes_match_encoded_values(
+            prefix_bytes in proptest::collection::vec(any::<u8>(), 0..12),
+            // z85 encodes a trailing partial chunk differently, so the rest of the value fills at least one chunk.
+            rest in proptest::collection::vec(any::<u8>(), 4..12),
+            encoding in arb_prefix_encoding(),
+        ) {
+            let prefixes = encode_search_prefixes(prefix_bytes.clone(), encoding);
+            let encoded = encode_with(encoding, &[prefix_bytes, rest].concat());
+            prop_assert!(
+                prefixes.iter().any(|prefix| encoded.starts_with(prefix)),
+                "{encoded} doesn't start with any of {prefixes:?}"
+            );
+        }
+
+        #[test]
+        fn zero_chunks_match_ascii85_shorthand(zeros in 1..9usize, rest: [u8; 4]) {
+            let prefixes = encode_search_prefixes(vec![0; zeros], PrefixEncoding::Ascii85);
+            let with_shorthand = ascii85::encode(&[vec![0; zeros], rest.to_vec()].concat())
+                .replace("!!!!!", "z");
+            prop_assert!(prefixes.iter().any(|prefix| with_shorthand[2..].starts_with(prefix)));
+        }
+
+        #[test]
+        fn search_prefixes_separate_key_ids(id in 1..u32::MAX, other_id in 1..u32::MAX, encoding in arb_prefix_encoding()) {
+            prop_assume!(id != other_id);
+            let header_prefixes = |id: u32| {
+                encode_search_prefixes(
+                    get_prefix_bytes_for_search(KeyIdHeader::new(
+                        EdekType::SaasShield,
+                        PayloadType::StandardEdek,
+                        KeyId(id),
+                    ))
+                    .into(),
+                    encoding,
+                )
+            };
+            let prefixes = header_prefixes(id);
+            let other_prefixes = header_prefixes(other_id);
+            prop_assert!(prefixes.iter().all(|prefix| !other_prefixes.contains(prefix)));
+        }
+    }
+
+    #[test]
+    fn search_prefixes_enumerate_partial_base64_characters() {
+        assert_eq!(
+            encode_search_prefixes(vec![0, 0, 0], PrefixEncoding::Base64),
+            vec!["AAAA"]
+        );
+        assert_eq!(
+            encode_search_prefixes(vec![0xff, 0xff], PrefixEncoding::Base64Url),
+            vec!["__-", "__8", "__9", "___"]
+        );
+        assert_eq!(
+            encode_search_prefixes(vec![0], PrefixEncoding::Base64).len(),
+            16
+        );
+        assert_eq!(
+            encode_search_prefixes(vec![0xab, 0x01], PrefixEncoding::Hex),
+            vec!["ab01"]
+        );
+    }
+
     // Note that this does _not_ work if you allow an id of 0
     fn encoding_produces_consistent_prefix<F, G>(
         id: u32,
@@ -327,4 +692,56 @@ pub(crate) mod tests {
         prop_assert_eq!(prefixes.len(), unique_padding_count);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn rotate_grouped_by_tenant_groups_by_original_tenant() {
+        let item = |tenant: &str, new_tenant: Option<&str>| {
+            (
+                TenantId(tenant.to_string()),
+                new_tenant.map(|t| TenantId(t.to_string())),
+                (),
+            )
+        };
+        let items = [
+            ("a-to-b".to_string(), item("a", Some("b"))),
+            ("a-to-c".to_string(), item("a", Some("c"))),
+            ("a-stays".to_string(), item("a", None)),
+            ("d-stays".to_string(), item("d", None)),
+        ];
+        let calls = Mutex::new(Vec::new());
+        let result = rotate_grouped_by_tenant(
+            items,
+            &AlloyMetadata::new_simple(TenantId("ignored".to_string())),
+            |group: HashMap<FieldId, (Option<TenantId>, ())>, metadata| {
+                calls
+                    .lock()
+                    .unwrap()
+                    .push((metadata.tenant_id.0.clone(), group.len()));
+                async move {
+                    if metadata.tenant_id.0 == "d" {
+                        Err(AlloyError::InvalidInput {
+                            kind: ErrorKind::Other,
+                            msg: "derive failed".to_string(),
+                        })
+                    } else {
+                        Ok(BatchResult {
+                            successes: group.into_keys().map(|id| (id, ())).collect(),
+                            failures: HashMap::new(),
+                        })
+                    }
+                }
+            },
+        )
+        .await;
+        let calls = calls.into_inner().unwrap();
+        assert_eq!(
+            calls.into_iter().sorted().collect_vec(),
+            vec![("a".to_string(), 3), ("d".to_string(), 1)]
+        );
+        assert_eq!(
+            result.successes.into_keys().sorted().collect_vec(),
+            vec!["a-stays", "a-to-b", "a-to-c"]
+        );
+        assert_eq!(result.failures.into_keys().collect_vec(), vec!["d-stays"]);
+    }
 }
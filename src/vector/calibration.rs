@@ -0,0 +1,526 @@
+use super::{crypto, VectorEncryptionKey, VectorEncryptionMode, VectorEncryptionParams};
+use crate::errors::{AlloyError, ErrorKind};
+use half::f16;
+use itertools::Itertools;
+use ndarray::Array1;
+use rand::{CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+/// How similarity between two embeddings is measured when finding nearest neighbors during calibration.
+/// This should match the metric used by the vector database the encrypted embeddings will be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum CalibrationMetric {
+    Euclidean,
+    Cosine,
+    DotProduct,
+}
+
+/// Search quality of one candidate approximation factor over the provided sample.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct ApproximationFactorCalibration {
+    pub approximation_factor: f32,
+    /// Average fraction of each query's `k` plaintext nearest neighbors that are also among its `k` nearest
+    /// neighbors once everything is encrypted. 1.0 means encryption didn't change any search results.
+    pub recall_at_k: f32,
+    /// Mean absolute difference between plaintext and encrypted query-to-embedding distances, in the units of
+    /// the chosen metric. Encrypted embeddings are divided by their scaling factor before comparison.
+    pub mean_distance_distortion: f32,
+    /// Largest absolute difference between plaintext and encrypted query-to-embedding distances.
+    pub max_distance_distortion: f32,
+}
+
+/// Measure how each candidate approximation factor affects nearest neighbor search over a sample of the caller's
+/// plaintext `embeddings` and `queries`. For each factor, both are encrypted with a throwaway key and the `k`
+/// nearest embeddings to each query are compared against the plaintext results.
+/// Larger factors are more secure but less accurate; pick the largest factor whose recall is acceptable.
+/// Results are returned in the same order as `candidate_factors`.
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+pub fn calibrate_approximation_factors(
+    embeddings: Vec<Vec<f32>>,
+    queries: Vec<Vec<f32>>,
+    candidate_factors: Vec<f32>,
+    k: u32,
+    metric: CalibrationMetric,
+) -> Result<Vec<ApproximationFactorCalibration>, AlloyError> {
+    calibrate_with_rng(
+        embeddings,
+        queries,
+        candidate_factors,
+        k,
+        metric,
+        &mut ChaCha20Rng::from_entropy(),
+    )
+}
+
+pub(crate) fn calibrate_with_rng<R: RngCore + CryptoRng>(
+    embeddings: Vec<Vec<f32>>,
+    queries: Vec<Vec<f32>>,
+    candidate_factors: Vec<f32>,
+    k: u32,
+    metric: CalibrationMetric,
+    rng: &mut R,
+) -> Result<Vec<ApproximationFactorCalibration>, AlloyError> {
+    let k = k as usize;
+    validate_inputs(&embeddings, &queries, &candidate_factors, k)?;
+    let key = random_key(rng);
+    let embeddings = embeddings.into_iter().map(Array1::from).collect_vec();
+    let queries = queries.into_iter().map(Array1::from).collect_vec();
+    let plaintext_distances = all_distances(&queries, &embeddings, metric);
+    let plaintext_neighbors = plaintext_distances
+        .iter()
+        .map(|distances| nearest(distances, k, metric))
+        .collect_vec();
+
+    candidate_factors
+        .into_iter()
+        .map(|approximation_factor| {
+            let encrypt_all = |values: &[Array1<f32>], rng: &mut R| {
+                values
+                    .iter()
+                    .map(|value| {
+                        crypto::encrypt(&key, approximation_factor, value.clone(), rng)
+                            .map(|result| result.ciphertext / key.scaling_factor.0)
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            let encrypted_embeddings = encrypt_all(&embeddings, rng)?;
+            let encrypted_queries = encrypt_all(&queries, rng)?;
+            let encrypted_distances =
+                all_distances(&encrypted_queries, &encrypted_embeddings, metric);
+
+            let distortions = encrypted_distances
+                .iter()
+                .flatten()
+                .zip(plaintext_distances.iter().flatten())
+                .map(|(encrypted, plaintext)| (encrypted - plaintext).abs())
+                .collect_vec();
+            Ok(ApproximationFactorCalibration {
+                approximation_factor,
+                recall_at_k: recall_at_k(&encrypted_distances, &plaintext_neighbors, k, metric),
+                mean_distance_distortion: distortions.iter().sum::<f32>()
+                    / distortions.len() as f32,
+                max_distance_distortion: distortions.into_iter().fold(0., f32::max),
+            })
+        })
+        .collect()
+}
+
+/// A way vector databases commonly compress stored embeddings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum Quantization {
+    /// Values are stored as `f32`s.
+    None,
+    /// Values are stored as IEEE 754 half precision, like pgvector's `halfvec`. Values too large for `f16` become
+    /// infinite.
+    Float16,
+    /// Values are clamped to [-range, range] and mapped to the integers -127 to 127. For `QuantizationFriendly`
+    /// embeddings `quantization_friendly_value_bound` gives a range that never clamps normalized embeddings.
+    Int8 { range: f32 },
+    /// Values are reduced to their sign, like binary quantization with Hamming distance.
+    Binary,
+}
+
+impl Quantization {
+    /// The value a database would compare after quantizing `value`. Int8 levels and signs are kept as `f32`s so
+    /// the same distance functions apply.
+    fn apply(&self, value: f32) -> f32 {
+        match self {
+            Quantization::None => value,
+            Quantization::Float16 => f16::from_f32(value).to_f32(),
+            Quantization::Int8 { range } => (value.clamp(-range, *range) / range * 127.).round(),
+            Quantization::Binary => {
+                if value >= 0. {
+                    1.
+                } else {
+                    -1.
+                }
+            }
+        }
+    }
+}
+
+/// Search quality of encrypted embeddings after quantization, for both encryption modes.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct QuantizationEvaluation {
+    pub quantization: Quantization,
+    /// Recall@k of the plaintext embeddings after the same quantization. Encrypted recall can't be expected to beat it.
+    pub plaintext_recall_at_k: f32,
+    /// Recall@k of embeddings encrypted in `VectorEncryptionMode::Scaled` after quantization.
+    pub scaled_recall_at_k: f32,
+    /// Recall@k of embeddings encrypted in `VectorEncryptionMode::QuantizationFriendly` after quantization. That mode
+    /// reveals far more about the embeddings than `Scaled`, so a better recall here isn't a reason to switch to it.
+    pub quantization_friendly_recall_at_k: f32,
+}
+
+/// Compare how well each encryption mode survives each of `quantizations`, over a sample of the caller's plaintext
+/// `embeddings` and `queries`. Everything is encrypted with a throwaway key in both modes and quantized, then the `k`
+/// nearest quantized embeddings to each quantized query are compared against the unquantized plaintext results.
+/// `Scaled` results depend on the throwaway key's scaling factor, so they vary between runs.
+/// Results are returned in the same order as `quantizations`.
+///
+/// The two modes don't offer the same protection. `QuantizationFriendly` leaves every embedding readable to anyone who
+/// recovers the keyed shuffle (see `VectorEncryptionMode::QuantizationFriendly`), which is why secret paths need
+/// `VectorSecretOptions::accept_quantization_friendly_leakage` to use it. Treat its numbers as what that trade-off
+/// would buy, not as a drop-in alternative to `Scaled`.
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+pub fn evaluate_quantization(
+    embeddings: Vec<Vec<f32>>,
+    queries: Vec<Vec<f32>>,
+    approximation_factor: f32,
+    k: u32,
+    metric: CalibrationMetric,
+    quantizations: Vec<Quantization>,
+) -> Result<Vec<QuantizationEvaluation>, AlloyError> {
+    evaluate_quantization_with_rng(
+        embeddings,
+        queries,
+        approximation_factor,
+        k,
+        metric,
+        quantizations,
+        &mut ChaCha20Rng::from_entropy(),
+    )
+}
+
+pub(crate) fn evaluate_quantization_with_rng<R: RngCore + CryptoRng>(
+    embeddings: Vec<Vec<f32>>,
+    queries: Vec<Vec<f32>>,
+    approximation_factor: f32,
+    k: u32,
+    metric: CalibrationMetric,
+    quantizations: Vec<Quantization>,
+    rng: &mut R,
+) -> Result<Vec<QuantizationEvaluation>, AlloyError> {
+    let k = k as usize;
+    validate_inputs(&embeddings, &queries, &[approximation_factor], k)?;
+    if let Some(range) = quantizations.iter().find_map(|q| match q {
+        Quantization::Int8 { range } if !range.is_finite() || *range <= 0. => Some(range),
+        _ => None,
+    }) {
+        Err(AlloyError::InvalidInput {
+            kind: ErrorKind::Other,
+            msg: format!("Int8 quantization ranges must be positive and finite, but got {range}."),
+        })?
+    }
+    let key = random_key(rng);
+    let embeddings = embeddings.into_iter().map(Array1::from).collect_vec();
+    let queries = queries.into_iter().map(Array1::from).collect_vec();
+    let plaintext_neighbors = all_distances(&queries, &embeddings, metric)
+        .iter()
+        .map(|distances| nearest(distances, k, metric))
+        .collect_vec();
+    let mut encrypt_all = |values: &[Array1<f32>], mode| {
+        values
+            .iter()
+            .map(|value| {
+                let params = VectorEncryptionParams {
+                    mode,
+                    ..VectorEncryptionParams::scaled(approximation_factor)
+                };
+                crypto::encrypt_with_params(&key, &params, value.clone(), rng)
+                    .map(|result| result.ciphertext)
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let scaled = (
+        encrypt_all(&embeddings, VectorEncryptionMode::Scaled)?,
+        encrypt_all(&queries, VectorEncryptionMode::Scaled)?,
+    );
+    let quantization_friendly = (
+        encrypt_all(&embeddings, VectorEncryptionMode::QuantizationFriendly)?,
+        encrypt_all(&queries, VectorEncryptionMode::QuantizationFriendly)?,
+    );
+    let quantized_recall = |(embeddings, queries): &(Vec<Array1<f32>>, Vec<Array1<f32>>),
+                            quantization: Quantization| {
+        let quantize = |values: &[Array1<f32>]| {
+            values
+                .iter()
+                .map(|value| value.mapv(|x| quantization.apply(x)))
+                .collect_vec()
+        };
+        let distances = all_distances(&quantize(queries), &quantize(embeddings), metric);
+        recall_at_k(&distances, &plaintext_neighbors, k, metric)
+    };
+    let plaintext = (embeddings.clone(), queries.clone());
+    Ok(quantizations
+        .into_iter()
+        .map(|quantization| QuantizationEvaluation {
+            quantization,
+            plaintext_recall_at_k: quantized_recall(&plaintext, quantization),
+            scaled_recall_at_k: quantized_recall(&scaled, quantization),
+            quantization_friendly_recall_at_k: quantized_recall(
+                &quantization_friendly,
+                quantization,
+            ),
+        })
+        .collect())
+}
+
+fn validate_inputs(
+    embeddings: &[Vec<f32>],
+    queries: &[Vec<f32>],
+    candidate_factors: &[f32],
+    k: usize,
+) -> Result<(), AlloyError> {
+    let dimension =
+        embeddings
+            .first()
+            .map(|e| e.len())
+            .ok_or_else(|| AlloyError::InvalidInput {
+                kind: ErrorKind::Other,
+                msg: "At least one embedding is required for calibration.".to_string(),
+            })?;
+    if queries.is_empty() {
+        Err(AlloyError::InvalidInput {
+            kind: ErrorKind::Other,
+            msg: "At least one query is required for calibration.".to_string(),
+        })?
+    }
+    if k == 0 || k > embeddings.len() {
+        Err(AlloyError::InvalidInput {
+            kind: ErrorKind::Other,
+            msg: format!(
+                "`k` must be between 1 and the number of embeddings ({}), but was {k}.",
+                embeddings.len()
+            ),
+        })?
+    }
+    let mismatched = |name: &str, (index, value): (usize, &Vec<f32>)| {
+        (value.len() != dimension).then(|| AlloyError::InvalidInput {
+            kind: ErrorKind::Other,
+            msg: format!(
+                "All embeddings and queries must have dimension {dimension}, but {name} {index} had dimension {}.",
+                value.len()
+            ),
+        })
+    };
+    if let Some(error) = embeddings
+        .iter()
+        .enumerate()
+        .find_map(|e| mismatched("embedding", e))
+        .or_else(|| {
+            queries
+                .iter()
+                .enumerate()
+                .find_map(|q| mismatched("query", q))
+        })
+    {
+        Err(error)?
+    }
+    if let Some(factor) = candidate_factors
+        .iter()
+        .find(|factor| !factor.is_finite() || **factor <= 0.)
+    {
+        Err(AlloyError::InvalidInput {
+            kind: ErrorKind::Other,
+            msg: format!("Approximation factors must be positive and finite, but got {factor}."),
+        })?
+    }
+    Ok(())
+}
+
+/// A key for calibration only. The scaling factor comes out of the distances again, so any non-zero one works.
+fn random_key<R: RngCore + CryptoRng>(rng: &mut R) -> VectorEncryptionKey {
+    loop {
+        let mut key_bytes = [0u8; 35];
+        rng.fill_bytes(&mut key_bytes);
+        let key = VectorEncryptionKey::unsafe_bytes_to_key(&key_bytes);
+        if key.scaling_factor.0 != 0. {
+            return key;
+        }
+    }
+}
+
+/// Distance from every query to every embedding. For `Cosine` and `DotProduct` larger values mean closer.
+pub(crate) fn all_distances(
+    queries: &[Array1<f32>],
+    embeddings: &[Array1<f32>],
+    metric: CalibrationMetric,
+) -> Vec<Vec<f32>> {
+    queries
+        .iter()
+        .map(|query| {
+            embeddings
+                .iter()
+                .map(|embedding| match metric {
+                    CalibrationMetric::Euclidean => {
+                        (query - embedding).mapv(|x| x * x).sum().sqrt()
+                    }
+                    CalibrationMetric::DotProduct => query.dot(embedding),
+                    CalibrationMetric::Cosine => {
+                        let norms = query.dot(query).sqrt() * embedding.dot(embedding).sqrt();
+                        if norms == 0. {
+                            0.
+                        } else {
+                            query.dot(embedding) / norms
+                        }
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Average fraction of each query's `expected` neighbors that are among its `k` nearest according to `distances`.
+pub(crate) fn recall_at_k(
+    distances: &[Vec<f32>],
+    expected: &[Vec<usize>],
+    k: usize,
+    metric: CalibrationMetric,
+) -> f32 {
+    let recall_sum: f32 = distances
+        .iter()
+        .zip(expected.iter())
+        .map(|(distances, expected)| {
+            let found = nearest(distances, k, metric);
+            found.iter().filter(|i| expected.contains(i)).count() as f32 / k as f32
+        })
+        .sum();
+    recall_sum / distances.len() as f32
+}
+
+/// Indices of the `k` nearest embeddings according to `distances`.
+pub(crate) fn nearest(distances: &[f32], k: usize, metric: CalibrationMetric) -> Vec<usize> {
+    let ordered = distances
+        .iter()
+        .enumerate()
+        .sorted_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(i, _)| i);
+    match metric {
+        CalibrationMetric::Euclidean => ordered.take(k).collect(),
+        CalibrationMetric::Cosine | CalibrationMetric::DotProduct => {
+            ordered.rev().take(k).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vector::quantization_friendly_value_bound;
+    use assertables::*;
+    use rand_distr::{Distribution, StandardNormal};
+
+    fn random_unit_vectors(rng: &mut ChaCha20Rng, count: usize, dimension: usize) -> Vec<Vec<f32>> {
+        (0..count)
+            .map(|_| {
+                let v: Vec<f32> = (0..dimension).map(|_| StandardNormal.sample(rng)).collect();
+                let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+                v.into_iter().map(|x| x / norm).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recall_decreases_as_factor_grows() {
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let embeddings = random_unit_vectors(&mut rng, 200, 32);
+        let queries = random_unit_vectors(&mut rng, 20, 32);
+        let results = calibrate_with_rng(
+            embeddings,
+            queries,
+            vec![0.001, 10.],
+            5,
+            CalibrationMetric::Cosine,
+            &mut rng,
+        )
+        .unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].approximation_factor, 0.001);
+        assert_eq!(results[0].recall_at_k, 1.);
+        assert_lt!(results[1].recall_at_k, results[0].recall_at_k);
+        assert_lt!(
+            results[0].mean_distance_distortion,
+            results[1].mean_distance_distortion
+        );
+        assert_le!(
+            results[1].mean_distance_distortion,
+            results[1].max_distance_distortion
+        );
+    }
+
+    #[test]
+    fn quantization_friendly_survives_fixed_range_int8() {
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let embeddings = random_unit_vectors(&mut rng, 200, 32);
+        let queries = random_unit_vectors(&mut rng, 20, 32);
+        let approximation_factor = 0.1;
+        let results = evaluate_quantization_with_rng(
+            embeddings,
+            queries,
+            approximation_factor,
+            5,
+            CalibrationMetric::Cosine,
+            vec![
+                Quantization::None,
+                Quantization::Int8 {
+                    range: quantization_friendly_value_bound(approximation_factor),
+                },
+                Quantization::Binary,
+            ],
+            &mut rng,
+        )
+        .unwrap();
+        assert_eq!(results.len(), 3);
+        let [unquantized, int8, binary] = &results[..] else {
+            panic!("Expected three results");
+        };
+        assert_eq!(unquantized.plaintext_recall_at_k, 1.);
+        assert_gt!(unquantized.quantization_friendly_recall_at_k, 0.8);
+        assert_gt!(unquantized.scaled_recall_at_k, 0.8);
+        // Scaled values are far outside the int8 range, so every value clamps to its sign.
+        assert_gt!(int8.quantization_friendly_recall_at_k, 0.8);
+        assert_lt!(
+            int8.scaled_recall_at_k,
+            int8.quantization_friendly_recall_at_k
+        );
+        assert_le!(
+            binary.quantization_friendly_recall_at_k,
+            binary.plaintext_recall_at_k + 0.1
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_int8_range() {
+        let err = evaluate_quantization(
+            vec![vec![1., 2.]],
+            vec![vec![1., 2.]],
+            1.,
+            1,
+            CalibrationMetric::Euclidean,
+            vec![Quantization::Int8 { range: 0. }],
+        )
+        .unwrap_err();
+        assert_contains!(err.to_string(), "Int8 quantization ranges");
+    }
+
+    #[test]
+    fn rejects_mismatched_dimension() {
+        let err = calibrate_approximation_factors(
+            vec![vec![1., 2.], vec![1., 2.]],
+            vec![vec![1., 2., 3.]],
+            vec![1.],
+            1,
+            CalibrationMetric::Euclidean,
+        )
+        .unwrap_err();
+        assert_contains!(err.to_string(), "query 0 had dimension 3");
+    }
+
+    #[test]
+    fn rejects_k_larger_than_sample() {
+        let err = calibrate_approximation_factors(
+            vec![vec![1., 2.]],
+            vec![vec![1., 2.]],
+            vec![1.],
+            2,
+            CalibrationMetric::Euclidean,
+        )
+        .unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidInput { .. }));
+    }
+}
@@ -1,4 +1,6 @@
-use super::{EncryptionKey, ScalingFactor, VectorEncryptionKey};
+use super::{
+    EncryptionKey, ScalingFactor, VectorEncryptionKey, VectorEncryptionMode, VectorEncryptionParams,
+};
 use crate::util;
 use crate::util::{compute_auth_hash, create_rng, AuthHash};
 use itertools::Itertools;
@@ -9,6 +11,10 @@ use rand_chacha::ChaCha20Rng;
 use rand_distr::{Distribution, StandardNormal, Uniform};
 use std::{error::Error, fmt::Display};
 
+/// Appended to the IV when authenticating quantization friendly ciphertexts so they can't be passed off as scaled ones.
+const QUANTIZATION_FRIENDLY_AUTH_DOMAIN: &[u8] = b"quantization-friendly";
+/// Appended to the IV, along with the block size, when authenticating ciphertexts encrypted in Matryoshka blocks.
+const MATRYOSHKA_AUTH_DOMAIN: &[u8] = b"matryoshka";
 const SHUFFLE_KEY:&str = "One Ring to rule them all, One Ring to find them, One Ring to bring them all, and in the darkness bind them";
 
 #[derive(Debug)]
@@ -100,13 +106,13 @@ fn calculate_normalized_vector(
     multivariate_normal_sample * uniform_point_in_ball / norm
 }
 
-fn generate_normalized_vector(
+fn generate_normalized_vector<S: AsRef<[u8]>>(
     key: &VectorEncryptionKey,
-    iv: [u8; 12],
+    seed: S,
     approximation_factor: f32,
     message_dimensionality: usize,
 ) -> Array1<f32> {
-    let mut coin_rng = create_rng(&key.key.0, iv);
+    let mut coin_rng = create_rng(&key.key.0, seed);
     // u
     let multivariate_normal_sample = sample_normal_vector(&mut coin_rng, message_dimensionality);
     // x'
@@ -122,11 +128,71 @@ fn generate_normalized_vector(
     calculate_normalized_vector(multivariate_normal_sample, uniform_point_in_ball)
 }
 
+/// λ_m for the whole message. With a Matryoshka block size each block gets its own noise vector, sampled as if the
+/// block were a message of its own, so any prefix made of whole blocks is encrypted independently of the rest.
+fn generate_noise(
+    key: &VectorEncryptionKey,
+    iv: [u8; 12],
+    params: &VectorEncryptionParams,
+    message_dimensionality: usize,
+) -> Array1<f32> {
+    match params.matryoshka_block_size {
+        None => {
+            generate_normalized_vector(key, iv, params.approximation_factor, message_dimensionality)
+        }
+        Some(block_size) => (0..message_dimensionality)
+            .step_by(block_size as usize)
+            .enumerate()
+            .flat_map(|(block_index, block_start)| {
+                let block_dimensionality =
+                    (block_size as usize).min(message_dimensionality - block_start);
+                generate_normalized_vector(
+                    key,
+                    [&iv[..], &(block_index as u32).to_be_bytes()].concat(),
+                    params.approximation_factor,
+                    block_dimensionality,
+                )
+            })
+            .collect(),
+    }
+}
+
 pub(crate) fn encrypt<R: RngCore + CryptoRng>(
     key: &VectorEncryptionKey, // K + s in the paper, key and scaling factor
     approximation_factor: f32,
     message: Array1<f32>, // m in the paper, vector
     rng: &mut R,
+) -> Result<EncryptResult, EncryptError> {
+    encrypt_with_params(
+        key,
+        &VectorEncryptionParams::scaled(approximation_factor),
+        message,
+        rng,
+    )
+}
+
+/// The IV bytes covered by the auth hash. Ciphertexts encrypted with anything other than the scheme from the paper
+/// are authenticated under a separate domain, so they can't be decrypted as if they were encrypted some other way.
+fn auth_hash_iv(iv: [u8; 12], params: &VectorEncryptionParams) -> Vec<u8> {
+    let mut auth_iv = iv.to_vec();
+    if params.mode == VectorEncryptionMode::QuantizationFriendly {
+        auth_iv.extend_from_slice(QUANTIZATION_FRIENDLY_AUTH_DOMAIN);
+    }
+    if let Some(block_size) = params.matryoshka_block_size {
+        auth_iv.extend_from_slice(MATRYOSHKA_AUTH_DOMAIN);
+        auth_iv.extend_from_slice(&block_size.to_be_bytes());
+    }
+    auth_iv
+}
+
+/// Encrypt `message` with `params`. `Scaled` mode is the scheme from the paper. `QuantizationFriendly` divides the
+/// ciphertext by the scaling factor, so the noise is the same relative to the message but the values keep the
+/// message's magnitude. The Matryoshka block size must not be zero.
+pub(crate) fn encrypt_with_params<R: RngCore + CryptoRng>(
+    key: &VectorEncryptionKey,
+    params: &VectorEncryptionParams,
+    message: Array1<f32>,
+    rng: &mut R,
 ) -> Result<EncryptResult, EncryptError> {
     if key.scaling_factor.0 == 0. || key.scaling_factor.0 == -0. {
         return Err(EncryptError::InvalidKey(
@@ -150,15 +216,25 @@ pub(crate) fn encrypt<R: RngCore + CryptoRng>(
         Array1::zeros(message.raw_dim())
     } else {
         // λ_m
-        let ball_normalized_vector =
-            generate_normalized_vector(key, iv, approximation_factor, message_dimensionality);
-        // c <-- sm + λ_m
-        key.scaling_factor.0 * message + ball_normalized_vector
+        let ball_normalized_vector = generate_noise(key, iv, params, message_dimensionality);
+        match params.mode {
+            // c <-- sm + λ_m
+            VectorEncryptionMode::Scaled => key.scaling_factor.0 * message + ball_normalized_vector,
+            // c <-- m + λ_m / s
+            VectorEncryptionMode::QuantizationFriendly => {
+                message + ball_normalized_vector / key.scaling_factor.0
+            }
+        }
     };
     if ciphertext.iter().any(|f| !f.is_finite()) {
         Err(EncryptError::OverflowError)
     } else {
-        let auth_hash = compute_auth_hash(key, &approximation_factor, iv, ciphertext.iter());
+        let auth_hash = compute_auth_hash(
+            key,
+            &params.approximation_factor,
+            auth_hash_iv(iv, params),
+            ciphertext.iter(),
+        );
         Ok(EncryptResult {
             ciphertext,
             iv,
@@ -171,6 +247,19 @@ pub(crate) fn decrypt(
     key: &VectorEncryptionKey, // K + s
     approximation_factor: f32,
     encrypted_result: EncryptResult, // n + c
+) -> Result<Array1<f32>, DecryptError> {
+    decrypt_with_params(
+        key,
+        &VectorEncryptionParams::scaled(approximation_factor),
+        encrypted_result,
+    )
+}
+
+/// Decrypt a ciphertext that was encrypted with `params`.
+pub(crate) fn decrypt_with_params(
+    key: &VectorEncryptionKey,
+    params: &VectorEncryptionParams,
+    encrypted_result: EncryptResult,
 ) -> Result<Array1<f32>, DecryptError> {
     if key.scaling_factor.0 == 0. || key.scaling_factor.0 == -0. {
         return Err(DecryptError::InvalidKey(
@@ -180,8 +269,8 @@ pub(crate) fn decrypt(
 
     if util::check_auth_hash(
         key,
-        &approximation_factor,
-        encrypted_result.iv,
+        &params.approximation_factor,
+        auth_hash_iv(encrypted_result.iv, params),
         encrypted_result.ciphertext.iter(),
         encrypted_result.auth_hash,
     ) {
@@ -192,14 +281,18 @@ pub(crate) fn decrypt(
             return Ok(Array1::zeros(encrypted_result.ciphertext.raw_dim()));
         }
         // λ_m
-        let ball_normalized_vector = generate_normalized_vector(
-            key,
-            encrypted_result.iv,
-            approximation_factor,
-            message_dimensionality,
-        );
-        // m <-- (c - λ_m) / s
-        let message = (encrypted_result.ciphertext - ball_normalized_vector) / key.scaling_factor.0;
+        let ball_normalized_vector =
+            generate_noise(key, encrypted_result.iv, params, message_dimensionality);
+        let message = match params.mode {
+            // m <-- (c - λ_m) / s
+            VectorEncryptionMode::Scaled => {
+                (encrypted_result.ciphertext - ball_normalized_vector) / key.scaling_factor.0
+            }
+            // m <-- c - λ_m / s
+            VectorEncryptionMode::QuantizationFriendly => {
+                encrypted_result.ciphertext - ball_normalized_vector / key.scaling_factor.0
+            }
+        };
         Ok(message)
     } else {
         Err(DecryptError::InvalidAuthHash)
@@ -210,8 +303,18 @@ fn create_rng_for_shuffle(key: &EncryptionKey) -> ChaCha20Rng {
     util::create_rng(&key.0, SHUFFLE_KEY.as_bytes())
 }
 
+fn create_rng_for_block_shuffle(key: &EncryptionKey, block_index: usize) -> ChaCha20Rng {
+    util::create_rng(
+        &key.0,
+        [SHUFFLE_KEY.as_bytes(), &(block_index as u32).to_be_bytes()].concat(),
+    )
+}
+
 pub(crate) fn shuffle<T: IntoIterator<Item = A>, A>(key: &EncryptionKey, input: T) -> Vec<A> {
-    let mut rng: ChaCha20Rng = create_rng_for_shuffle(key);
+    shuffle_with_rng(create_rng_for_shuffle(key), input)
+}
+
+fn shuffle_with_rng<T: IntoIterator<Item = A>, A>(mut rng: ChaCha20Rng, input: T) -> Vec<A> {
     input
         .into_iter()
         .map(|i| (rng.next_u32(), i))
@@ -221,7 +324,10 @@ pub(crate) fn shuffle<T: IntoIterator<Item = A>, A>(key: &EncryptionKey, input:
 }
 
 pub(crate) fn unshuffle<T: IntoIterator<Item = A>, A>(key: &EncryptionKey, input: T) -> Vec<A> {
-    let mut rng: ChaCha20Rng = create_rng_for_shuffle(key);
+    unshuffle_with_rng(create_rng_for_shuffle(key), input)
+}
+
+fn unshuffle_with_rng<T: IntoIterator<Item = A>, A>(mut rng: ChaCha20Rng, input: T) -> Vec<A> {
     // Because we're working with iterators, we get the indexes via enumerate and then unzip
     let (indexes_with_rand, values): (Vec<_>, Vec<_>) = input
         .into_iter()
@@ -242,6 +348,47 @@ pub(crate) fn unshuffle<T: IntoIterator<Item = A>, A>(key: &EncryptionKey, input
         .collect()
 }
 
+/// Shuffle `input` as a whole, or only within each block of `block_size` values so that values never leave their
+/// block. The last block may be shorter than `block_size`, which must not be zero.
+pub(crate) fn shuffle_blocks<A>(
+    key: &EncryptionKey,
+    input: Vec<A>,
+    block_size: Option<u32>,
+) -> Vec<A> {
+    match block_size {
+        None => shuffle(key, input),
+        Some(block_size) => map_blocks(input, block_size, |block_index, block| {
+            shuffle_with_rng(create_rng_for_block_shuffle(key, block_index), block)
+        }),
+    }
+}
+
+/// Reverse `shuffle_blocks`.
+pub(crate) fn unshuffle_blocks<A>(
+    key: &EncryptionKey,
+    input: Vec<A>,
+    block_size: Option<u32>,
+) -> Vec<A> {
+    match block_size {
+        None => unshuffle(key, input),
+        Some(block_size) => map_blocks(input, block_size, |block_index, block| {
+            unshuffle_with_rng(create_rng_for_block_shuffle(key, block_index), block)
+        }),
+    }
+}
+
+fn map_blocks<A, F: Fn(usize, Vec<A>) -> Vec<A>>(input: Vec<A>, block_size: u32, f: F) -> Vec<A> {
+    let mut input = input.into_iter().peekable();
+    let mut output = Vec::new();
+    let mut block_index = 0;
+    while input.peek().is_some() {
+        let block = input.by_ref().take(block_size as usize).collect_vec();
+        output.extend(f(block_index, block));
+        block_index += 1;
+    }
+    output
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
@@ -516,8 +663,64 @@ pub(crate) mod tests {
             let decrypt_result = decrypt(&key, approximation_factor, encrypt_result).unwrap();
             assert_ulps_eq!(message.as_slice().unwrap(), decrypt_result.as_slice().unwrap());
         }
+
+        #[test]
+        fn quantization_friendly_roundtrip(
+            arb_msg in proptest::collection::vec(-1f32..1., 1..64),
+            key: [u8; 32],
+            scaling_factor in 1..16777215
+        ) {
+            let key = VectorEncryptionKey {
+                scaling_factor: ScalingFactor(scaling_factor as f32),
+                key: EncryptionKey(key.to_vec()),
+            };
+            let approximation_factor = 5.;
+            let message: Array1<f32> = arb_msg.into();
+            let params = VectorEncryptionParams {
+                mode: VectorEncryptionMode::QuantizationFriendly,
+                ..VectorEncryptionParams::scaled(approximation_factor)
+            };
+            let encrypt_result = encrypt_with_params(
+                &key,
+                &params,
+                message.clone(),
+                &mut rand::thread_rng(),
+            )
+            .unwrap();
+            // The noise has a norm of at most approximation_factor / 4.
+            let bound = 1. + approximation_factor / 4.;
+            assert!(encrypt_result.ciphertext.iter().all(|c| c.abs() <= bound));
+            let decrypt_result = decrypt_with_params(
+                &key,
+                &params,
+                encrypt_result,
+            )
+            .unwrap();
+            approx::assert_abs_diff_eq!(
+                message.as_slice().unwrap(),
+                decrypt_result.as_slice().unwrap(),
+                epsilon = 1e-5
+            );
+        }
     );
 
+    #[test]
+    fn decrypt_in_other_mode_fails_auth() {
+        let message: Array1<f32> = vec![0.1, 0.2, 0.3].into();
+        let encrypt_result = encrypt_with_params(
+            &get_key(),
+            &VectorEncryptionParams {
+                mode: VectorEncryptionMode::QuantizationFriendly,
+                ..VectorEncryptionParams::scaled(NEW_APPROX_FACTOR)
+            },
+            message,
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        let result = decrypt(&get_key(), NEW_APPROX_FACTOR, encrypt_result).unwrap_err();
+        assert_eq!(result, DecryptError::InvalidAuthHash);
+    }
+
     fn get_key() -> VectorEncryptionKey {
         VectorEncryptionKey {
             scaling_factor: ScalingFactor(1235.),
@@ -598,4 +801,54 @@ pub(crate) mod tests {
             one.to_vec()
         );
     }
+
+    #[test]
+    fn block_shuffle_keeps_values_in_their_block() {
+        let values = (0..10).collect_vec();
+        let shuffled = shuffle_blocks(&get_key().key, values.clone(), Some(4));
+        assert_ne!(shuffled, values);
+        for (block, shuffled_block) in values.chunks(4).zip(shuffled.chunks(4)) {
+            assert_eq!(
+                shuffled_block.iter().sorted().copied().collect_vec(),
+                block.to_vec()
+            );
+        }
+        assert_eq!(unshuffle_blocks(&get_key().key, shuffled, Some(4)), values);
+    }
+
+    #[test]
+    fn matryoshka_prefix_matches_truncated_encryption() {
+        // The first block of a Matryoshka ciphertext depends only on the first block of the message, so truncated
+        // ciphertexts are as close to each other as the truncated plaintexts.
+        let params = VectorEncryptionParams {
+            matryoshka_block_size: Some(3),
+            ..VectorEncryptionParams::scaled(NEW_APPROX_FACTOR)
+        };
+        let message: Array1<f32> = vec![0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7].into();
+        let result = encrypt_with_params(
+            &get_key(),
+            &params,
+            message.clone(),
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        let first_block_noise = generate_normalized_vector(
+            &get_key(),
+            [&result.iv[..], &0u32.to_be_bytes()].concat(),
+            NEW_APPROX_FACTOR,
+            3,
+        );
+        let expected = get_key().scaling_factor.0 * message.slice(ndarray::s![..3]).to_owned()
+            + first_block_noise;
+        assert_eq!(
+            result.ciphertext.slice(ndarray::s![..3]).to_vec(),
+            expected.to_vec()
+        );
+        let decrypted = decrypt_with_params(&get_key(), &params, result).unwrap();
+        approx::assert_abs_diff_eq!(
+            decrypted.as_slice().unwrap(),
+            message.as_slice().unwrap(),
+            epsilon = 1e-5
+        );
+    }
 }
@@ -1,10 +1,12 @@
-use self::crypto::{shuffle, unshuffle, EncryptResult};
+use self::crypto::{shuffle_blocks, unshuffle_blocks, EncryptResult};
+use self::precision::{EncryptedTypedVector, PlaintextTypedVector, VectorPrecision};
+use self::validation::VectorValidation;
 use crate::{
-    errors::AlloyError,
+    errors::{AlloyError, ErrorKind},
+    telemetry::record_key_id,
     util::{self, AuthHash, BatchResult},
     AlloyMetadata, DerivationPath, Secret, SecretPath, TenantId,
 };
-use bytes::Bytes;
 use ironcore_documents::{
     v5::{
         self,
@@ -13,16 +15,23 @@ use ironcore_documents::{
     vector_encryption_metadata::VectorEncryptionMetadata,
 };
 use itertools::Itertools;
+use protobuf::UnknownValueRef;
 use rand::{CryptoRng, RngCore};
 use serde::Serialize;
 use std::collections::HashMap;
+#[cfg(feature = "uniffi")]
 use uniffi::custom_newtype;
 
+pub mod calibration;
 pub(crate) mod crypto;
+pub mod precision;
+pub mod sparse;
+pub mod validation;
 
 pub type VectorId = String;
 
-#[derive(Clone, Debug, uniffi::Record)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct EncryptedVector {
     pub encrypted_vector: Vec<f32>,
     pub secret_path: SecretPath,
@@ -30,7 +39,8 @@ pub struct EncryptedVector {
     pub paired_icl_info: Vec<u8>,
 }
 
-#[derive(Clone, Debug, uniffi::Record)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct PlaintextVector {
     pub plaintext_vector: Vec<f32>,
     pub secret_path: SecretPath,
@@ -39,7 +49,8 @@ pub struct PlaintextVector {
 pub type PlaintextVectors = HashMap<VectorId, PlaintextVector>;
 pub type EncryptedVectors = HashMap<VectorId, EncryptedVector>;
 pub type GenerateQueryResult = HashMap<VectorId, Vec<EncryptedVector>>;
-#[derive(Debug, uniffi::Record)]
+#[derive(Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
 pub struct VectorRotateResult {
     pub successes: EncryptedVectors,
     pub failures: HashMap<VectorId, AlloyError>,
@@ -52,6 +63,240 @@ impl From<BatchResult<EncryptedVector>> for VectorRotateResult {
         }
     }
 }
+impl From<VectorRotateResult> for BatchResult<EncryptedVector> {
+    fn from(value: VectorRotateResult) -> Self {
+        Self {
+            successes: value.successes,
+            failures: value.failures,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct VectorEncryptBatchResult {
+    pub successes: EncryptedVectors,
+    pub failures: HashMap<VectorId, AlloyError>,
+}
+impl From<BatchResult<EncryptedVector>> for VectorEncryptBatchResult {
+    fn from(value: BatchResult<EncryptedVector>) -> Self {
+        Self {
+            successes: value.successes,
+            failures: value.failures,
+        }
+    }
+}
+
+/// An encrypted vector along with the tenant it is currently encrypted to and the tenant it should be rotated to.
+/// If `new_tenant_id` is empty the vector will be rotated to the current secret of `tenant_id`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct TenantEncryptedVector {
+    pub encrypted_vector: EncryptedVector,
+    pub tenant_id: TenantId,
+    pub new_tenant_id: Option<TenantId>,
+}
+pub type TenantEncryptedVectors = HashMap<VectorId, TenantEncryptedVector>;
+
+/// How encrypted vector values relate to the magnitude of the plaintext values.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum VectorEncryptionMode {
+    /// Values are multiplied by the secret's scaling factor (up to 2^24) before noise is added. This hides the
+    /// magnitude of the embeddings, but the encrypted values have no fixed range.
+    #[default]
+    Scaled,
+    /// Values are not scaled at all and only have noise added, so encrypted embeddings can be stored as `f16` or
+    /// quantized to int8 or binary codes with a fixed range. Every value of an embedding whose values are within
+    /// [-1, 1] stays within `quantization_friendly_value_bound(approximation_factor)`. Distances between encrypted
+    /// embeddings are the same as in `Scaled` mode divided by the scaling factor, so search quality is unchanged.
+    ///
+    /// This gives up most of the protection of `Scaled` mode. Without the secret scaling factor, each encrypted
+    /// value is a plaintext value plus noise, and the noise vector's norm is at most `approximation_factor / 4`, which
+    /// for normalized embeddings is small next to the embedding itself. The only thing hiding the plaintext is the
+    /// keyed shuffle, which is the same for every vector encrypted with a key. Anything that doesn't depend on the
+    /// order of the values, like their distribution, norm or largest values, is revealed as is. An attacker who
+    /// recovers the shuffle, for example from a few known plaintext/ciphertext pairs or from per-dimension
+    /// statistics of the embedding model, can read every embedding encrypted with that key up to the noise. In
+    /// `Scaled` mode they would also have to learn the secret scaling factor.
+    ///
+    /// Only use this mode when the encrypted embeddings are protected some other way and the leakage is acceptable.
+    /// Secret paths can only be configured with it if `VectorSecretOptions::accept_quantization_friendly_leakage`
+    /// is set. Sparse vectors can't be encrypted in this mode.
+    QuantizationFriendly,
+}
+
+/// An embedding model that a vector secret path is locked to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct EmbeddingModel {
+    /// Identifier of the model, like `text-embedding-3-small`. It's recorded in the metadata of every vector encrypted
+    /// under the lock.
+    pub id: String,
+    /// Number of values in the model's embeddings.
+    pub dimension: u32,
+}
+
+/// Options for a vector secret path other than its approximation factor. Changing `mode` or `matryoshka_block_size`
+/// for a secret path has the same consequences as changing its approximation factor.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct VectorSecretOptions {
+    pub mode: VectorEncryptionMode,
+    /// Shuffle and add noise within blocks of this many dimensions. Encrypted Matryoshka embeddings can then be
+    /// truncated to any multiple of the block size (for example 256 or 512 dimensions with a block size of 256) and
+    /// still be searched against queries truncated the same way. Each block gets as much noise as a whole vector
+    /// would, so distances between full length vectors are less accurate than without blocks. Truncated vectors
+    /// can't be decrypted. Has to be greater than zero.
+    pub matryoshka_block_size: Option<u32>,
+    pub validation: VectorValidation,
+    /// Lock the secret path to one embedding model, so vectors from a different model can't be mixed in after a
+    /// model change. `encrypt` and `generate_query_vectors` reject vectors of any other dimension, and `decrypt`
+    /// rejects vectors whose metadata records a different dimension or model. Vectors encrypted before the lock was
+    /// added don't record a model, so only their dimension is checked. Adding or changing the lock doesn't make
+    /// rotation re-encrypt vectors that are otherwise up to date, so they keep the model they were encrypted with.
+    pub embedding_model: Option<EmbeddingModel>,
+    /// Has to be set to configure `VectorEncryptionMode::QuantizationFriendly`, to acknowledge that it leaves the
+    /// plaintext protected only by the keyed shuffle. See that mode for what it reveals.
+    pub accept_quantization_friendly_leakage: bool,
+}
+
+impl VectorSecretOptions {
+    /// Check the options when a secret path is configured with them, so mistakes fail before anything is encrypted.
+    pub(crate) fn validate(&self) -> Result<(), AlloyError> {
+        if self.mode == VectorEncryptionMode::QuantizationFriendly
+            && !self.accept_quantization_friendly_leakage
+        {
+            Err(AlloyError::InvalidConfiguration {
+                kind: ErrorKind::InvalidVectorOptions,
+                msg: "QuantizationFriendly mode reveals embeddings up to a keyed shuffle. Set `accept_quantization_friendly_leakage` to use it.".to_string(),
+            })?
+        }
+        if self.matryoshka_block_size == Some(0) {
+            Err(AlloyError::InvalidConfiguration {
+                kind: ErrorKind::InvalidVectorOptions,
+                msg: "Matryoshka block size must be greater than zero.".to_string(),
+            })?
+        }
+        Ok(())
+    }
+
+    /// Sparse vectors are always encrypted in `Scaled` mode, so secret paths configured for another mode can't be
+    /// used to encrypt them.
+    pub(crate) fn check_sparse(&self, secret_path: &SecretPath) -> Result<(), AlloyError> {
+        if self.mode != VectorEncryptionMode::Scaled {
+            Err(AlloyError::InvalidConfiguration {
+                kind: ErrorKind::InvalidVectorOptions,
+                msg: format!(
+                    "Secret path `{}` is configured for {:?} mode, but sparse vectors can only be encrypted in Scaled mode.",
+                    secret_path.0, self.mode
+                ),
+            })?
+        }
+        Ok(())
+    }
+
+    pub(crate) fn encryption_params(&self, approximation_factor: f32) -> VectorEncryptionParams {
+        VectorEncryptionParams {
+            approximation_factor,
+            mode: self.mode,
+            matryoshka_block_size: self.matryoshka_block_size,
+            model_id: self.embedding_model.as_ref().map(|model| model.id.clone()),
+        }
+    }
+
+    /// Validate and preprocess a vector that's about to be encrypted, then check it against the embedding model lock.
+    pub(crate) fn prepare_vector(
+        &self,
+        plaintext_vector: PlaintextVector,
+    ) -> Result<PlaintextVector, AlloyError> {
+        let plaintext_vector = self.validation.prepare_vector(plaintext_vector)?;
+        if let Some(model) = &self.embedding_model {
+            if plaintext_vector.plaintext_vector.len() != model.dimension as usize {
+                Err(AlloyError::InvalidInput {
+                    kind: ErrorKind::DimensionMismatch,
+                    msg: format!(
+                        "Secret path `{}` is locked to embedding model `{}` with dimension {}, but the vector has dimension {}.",
+                        plaintext_vector.secret_path.0,
+                        model.id,
+                        model.dimension,
+                        plaintext_vector.plaintext_vector.len()
+                    ),
+                })?
+            }
+        }
+        Ok(plaintext_vector)
+    }
+
+    /// Check an encrypted vector against the embedding model lock before decrypting it.
+    pub(crate) fn check_encrypted(
+        &self,
+        encrypted_vector: &EncryptedVector,
+        vector_metadata: &VectorMetadata,
+    ) -> Result<(), AlloyError> {
+        if let Some(model) = &self.embedding_model {
+            let dimension = vector_metadata
+                .dimension
+                .unwrap_or(encrypted_vector.encrypted_vector.len() as u32);
+            if dimension != model.dimension {
+                Err(AlloyError::InvalidInput {
+                    kind: ErrorKind::DimensionMismatch,
+                    msg: format!(
+                        "Secret path `{}` is locked to embedding model `{}` with dimension {}, but the vector was encrypted with dimension {dimension}.",
+                        encrypted_vector.secret_path.0, model.id, model.dimension
+                    ),
+                })?
+            }
+            if let Some(recorded_id) = vector_metadata
+                .model_id
+                .as_ref()
+                .filter(|recorded_id| **recorded_id != model.id)
+            {
+                Err(AlloyError::InvalidInput {
+                    kind: ErrorKind::EmbeddingModelMismatch,
+                    msg: format!(
+                        "Secret path `{}` is locked to embedding model `{}`, but the vector was encrypted for embedding model `{recorded_id}`.",
+                        encrypted_vector.secret_path.0, model.id
+                    ),
+                })?
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Everything other than the key that determines how a vector is encrypted. All of it is recorded in the vector's
+/// metadata.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VectorEncryptionParams {
+    pub(crate) approximation_factor: f32,
+    pub(crate) mode: VectorEncryptionMode,
+    /// Values are shuffled and noised within blocks of this many dimensions when set, so the encrypted vector can be
+    /// truncated to any multiple of it like a Matryoshka embedding.
+    pub(crate) matryoshka_block_size: Option<u32>,
+    /// Embedding model the secret path is locked to. Only recorded, it doesn't change the encryption.
+    pub(crate) model_id: Option<String>,
+}
+
+impl VectorEncryptionParams {
+    /// The parameters of the scheme from the paper, used by vectors that don't record anything else.
+    pub(crate) fn scaled(approximation_factor: f32) -> Self {
+        Self {
+            approximation_factor,
+            mode: VectorEncryptionMode::Scaled,
+            matryoshka_block_size: None,
+            model_id: None,
+        }
+    }
+}
+
+/// The largest absolute value a `QuantizationFriendly` encrypted embedding can contain when all of its plaintext
+/// values are within [-1, 1]. Use it as the range of a fixed-range scalar quantizer.
+#[cfg_attr(feature = "uniffi", uniffi::export)]
+pub fn quantization_friendly_value_bound(approximation_factor: f32) -> f32 {
+    // The noise vector's norm is at most approximation_factor / 4, so no single value of it can be larger.
+    1. + approximation_factor / 4.
+}
 
 /// Key used to for vector encryption.
 #[derive(Debug, Serialize, Clone)]
@@ -64,10 +309,12 @@ pub struct VectorEncryptionKey {
 
 #[derive(Debug, Serialize, Clone, Copy)]
 pub struct ScalingFactor(pub f32); // Based on page 135 having a size 2^30
+#[cfg(feature = "uniffi")]
 custom_newtype!(ScalingFactor, f32);
 
 #[derive(Debug, Serialize, Clone)]
 pub struct EncryptionKey(pub Vec<u8>);
+#[cfg(feature = "uniffi")]
 custom_newtype!(EncryptionKey, Vec<u8>);
 
 impl VectorEncryptionKey {
@@ -109,7 +356,8 @@ impl VectorEncryptionKey {
 
 pub trait VectorOps {
     /// Encrypt a vector embedding with the provided metadata. The provided embedding is assumed to be normalized
-    /// and its values will be shuffled as part of the encryption.
+    /// and its values will be shuffled as part of the encryption. It is first checked and preprocessed according to
+    /// the `VectorValidation` configured for its secret path, and rejected with `InvalidInput` if that fails.
     /// The same tenant ID must be provided in the metadata when decrypting the embedding.
     async fn encrypt(
         &self,
@@ -117,6 +365,19 @@ pub trait VectorOps {
         metadata: &AlloyMetadata,
     ) -> Result<EncryptedVector, AlloyError>;
 
+    /// Encrypt a batch of vector embeddings with the provided metadata. Vectors are encrypted in parallel on a rayon
+    /// thread pool owned by this crate, separate from rayon's global pool and any pool the caller runs on. That makes
+    /// this much faster than calling `encrypt` in a loop for large batches or high-dimensional embeddings. The pool
+    /// has a thread per core unless `RAYON_NUM_THREADS` says otherwise. The calling task waits for the pool without
+    /// blocking its executor thread, but a large batch keeps every pool thread busy until it's done, which competes
+    /// with other CPU work in the process.
+    /// Vectors that fail to encrypt are returned in `failures` keyed by their ID.
+    async fn encrypt_batch(
+        &self,
+        plaintext_vectors: PlaintextVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorEncryptBatchResult, AlloyError>;
+
     /// Decrypt a vector embedding that was encrypted with the provided metadata. The values of the embedding will
     /// be unshuffled to their original positions during decryption.
     async fn decrypt(
@@ -125,6 +386,24 @@ pub trait VectorOps {
         metadata: &AlloyMetadata,
     ) -> Result<PlaintextVector, AlloyError>;
 
+    /// Encrypt a vector embedding given in any supported precision. Encryption runs on `f32` values, so `f64`
+    /// embeddings are rounded to `f32` first. The encrypted vector is `F64` for `F64` embeddings and `F32` otherwise;
+    /// see `EncryptedTypedVector` for why half precision ciphertexts aren't possible.
+    async fn encrypt_typed(
+        &self,
+        plaintext_vector: PlaintextTypedVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedTypedVector, AlloyError>;
+
+    /// Decrypt a vector embedding that was encrypted with `encrypt_typed` (or `encrypt`) and return it in `precision`.
+    /// Fails if a decrypted value doesn't fit in `precision`.
+    async fn decrypt_typed(
+        &self,
+        encrypted_vector: EncryptedTypedVector,
+        precision: VectorPrecision,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextTypedVector, AlloyError>;
+
     /// Encrypt each plaintext vector with any Current and InRotation keys for the provided secret path.
     /// The resulting encrypted vectors should be used in tandem when querying the vector database.
     async fn generate_query_vectors(
@@ -135,9 +414,8 @@ pub trait VectorOps {
 
     /// Generate a prefix that could used to search a data store for documents encrypted using an identifier (KMS
     /// config id for SaaS Shield, secret id for Standalone). These bytes should be encoded into
-    /// a format matching the encoding in the data store. z85/ascii85 users should first pass these bytes through
-    /// `encode_prefix_z85` or `base85_prefix_padding`. Make sure you've read the documentation of those functions to
-    /// avoid pitfalls when encoding across byte boundaries.
+    /// a format matching the encoding in the data store. `encode_search_prefixes` does this for the common
+    /// encodings, including the z85/ascii85 pitfalls when encoding across byte boundaries.
     async fn get_in_rotation_prefix(
         &self,
         secret_path: SecretPath,
@@ -148,6 +426,10 @@ pub trait VectorOps {
     /// Rotates vectors from the in-rotation secret for their secret path to the current secret.
     /// This can also be used to rotate data from one tenant ID to a new one, which most useful when a tenant is
     /// internally migrated.
+    /// Vectors are also re-encrypted if the approximation factor recorded in their metadata doesn't match the one
+    /// currently configured for their secret path, which migrates them to the new factor. Vectors encrypted before
+    /// the factor was recorded are decrypted with the configured factor and re-encrypted to record it. Vectors are
+    /// likewise migrated to the encryption mode and Matryoshka block size configured for their secret path.
     ///
     /// WARNINGS:
     ///     * this involves decrypting then encrypting vectors. Since the vectors are full of floating point numbers,
@@ -155,82 +437,600 @@ pub trait VectorOps {
     ///       store the source vector encrypted with `standard` next to the encrypted vector. `standard` decrypt
     ///       that, `vector` encrypt it again, and replace the encrypted vector with the result.
     ///     * only one metadata and new tenant ID argument means each call to this needs to have one tenant's vectors.
+    ///       Use `rotate_vectors_multi_tenant` to rotate vectors from many tenants in one call.
     async fn rotate_vectors(
         &self,
         encrypted_vectors: EncryptedVectors,
         metadata: &AlloyMetadata,
         new_tenant_id: Option<TenantId>,
     ) -> Result<VectorRotateResult, AlloyError>;
+
+    /// Rotates vectors that may belong to many tenants. Each vector carries the tenant ID it was encrypted to and an
+    /// optional tenant ID to rotate it to. Vectors are grouped by the tenant they were encrypted to, so that tenant's
+    /// keys are derived once however many tenants its vectors move to. The tenant ID in `metadata` is ignored, but its
+    /// other values are used for every group.
+    /// The same lossiness warning as `rotate_vectors` applies.
+    async fn rotate_vectors_multi_tenant(
+        &self,
+        encrypted_vectors: TenantEncryptedVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<VectorRotateResult, AlloyError>;
 }
 
-pub(crate) fn get_iv_and_auth_hash(b: &[u8]) -> Result<([u8; 12], AuthHash), AlloyError> {
+/// Version of the vector metadata written by this library. Version 1 metadata only contains the IV and
+/// authentication hash. Version 2 adds the approximation factor and the dimension of the encrypted vector.
+const VECTOR_METADATA_VERSION: u64 = 2;
+// Version 2 values are written as extra fields on the `VectorEncryptionMetadata` proto. Readers that only know
+// about version 1 skip them as unknown fields.
+const VERSION_FIELD_NUMBER: u32 = 100;
+const APPROXIMATION_FACTOR_FIELD_NUMBER: u32 = 101;
+const DIMENSION_FIELD_NUMBER: u32 = 102;
+/// Only present on sparse vectors. Authenticates their permuted indices, which the auth hash doesn't cover.
+const INDICES_HASH_FIELD_NUMBER: u32 = 103;
+/// Only present when the vector wasn't encrypted in `VectorEncryptionMode::Scaled`.
+const ENCRYPTION_MODE_FIELD_NUMBER: u32 = 104;
+const QUANTIZATION_FRIENDLY_MODE: u64 = 1;
+/// Only present on vectors encrypted in Matryoshka blocks.
+const MATRYOSHKA_BLOCK_SIZE_FIELD_NUMBER: u32 = 105;
+/// Only present on vectors encrypted under an embedding model lock.
+const MODEL_ID_FIELD_NUMBER: u32 = 106;
+
+/// The parsed contents of the metadata stored alongside an encrypted vector, after the key ID header.
+#[derive(Debug, PartialEq)]
+pub(crate) struct VectorMetadata {
+    pub(crate) iv: [u8; 12],
+    pub(crate) auth_hash: AuthHash,
+    /// Approximation factor used to encrypt the vector. Not present in version 1 metadata.
+    pub(crate) approximation_factor: Option<f32>,
+    /// Number of values in the encrypted vector. Not present in version 1 metadata.
+    pub(crate) dimension: Option<u32>,
+    /// Authentication hash of a sparse vector's indices. Only present for sparse vectors.
+    pub(crate) indices_hash: Option<AuthHash>,
+    /// Mode the vector was encrypted in. Vectors without a recorded mode are `Scaled`.
+    pub(crate) mode: VectorEncryptionMode,
+    /// Size of the blocks the vector was shuffled and noised in, if it was encrypted for Matryoshka truncation.
+    pub(crate) matryoshka_block_size: Option<u32>,
+    /// Identifier of the embedding model the vector's secret path was locked to when it was encrypted.
+    pub(crate) model_id: Option<String>,
+}
+
+impl VectorMetadata {
+    /// Whether the vector was encrypted with exactly `params`. Version 1 metadata never matches because it doesn't
+    /// record its approximation factor. The embedding model isn't compared since it doesn't change the encryption,
+    /// and re-encrypting only to record it would add noise to the vector again.
+    pub(crate) fn was_encrypted_with(&self, params: &VectorEncryptionParams) -> bool {
+        self.approximation_factor == Some(params.approximation_factor)
+            && self.mode == params.mode
+            && self.matryoshka_block_size == params.matryoshka_block_size
+    }
+}
+
+pub(crate) fn get_vector_metadata(b: &[u8]) -> Result<VectorMetadata, AlloyError> {
     let vector_proto: VectorEncryptionMetadata = protobuf::Message::parse_from_bytes(b)?;
+    let unknown_fields = vector_proto.special_fields.unknown_fields();
+    let version = match unknown_fields.get(VERSION_FIELD_NUMBER) {
+        Some(UnknownValueRef::Varint(version)) => version,
+        Some(_) => Err(AlloyError::DecryptError {
+            kind: ErrorKind::MalformedHeader,
+            msg: "Invalid vector metadata version".to_string(),
+        })?,
+        None => 1,
+    };
+    let (approximation_factor, dimension) = if version >= 2 {
+        let approximation_factor = match unknown_fields.get(APPROXIMATION_FACTOR_FIELD_NUMBER) {
+            Some(UnknownValueRef::Fixed32(bits)) => f32::from_bits(bits),
+            _ => Err(AlloyError::DecryptError {
+                kind: ErrorKind::MalformedHeader,
+                msg: "Vector metadata was missing its approximation factor".to_string(),
+            })?,
+        };
+        let dimension = match unknown_fields.get(DIMENSION_FIELD_NUMBER) {
+            Some(UnknownValueRef::Varint(dimension)) => {
+                u32::try_from(dimension).map_err(|_| AlloyError::DecryptError {
+                    kind: ErrorKind::MalformedHeader,
+                    msg: "Invalid vector dimension in metadata".to_string(),
+                })?
+            }
+            _ => Err(AlloyError::DecryptError {
+                kind: ErrorKind::MalformedHeader,
+                msg: "Vector metadata was missing its dimension".to_string(),
+            })?,
+        };
+        (Some(approximation_factor), Some(dimension))
+    } else {
+        (None, None)
+    };
+    let indices_hash = match unknown_fields.get(INDICES_HASH_FIELD_NUMBER) {
+        Some(UnknownValueRef::LengthDelimited(hash)) => {
+            Some(AuthHash(hash.try_into().map_err(|_| {
+                AlloyError::DecryptError {
+                    kind: ErrorKind::MalformedHeader,
+                    msg: "Invalid sparse vector indices hash".to_string(),
+                }
+            })?))
+        }
+        Some(_) => Err(AlloyError::DecryptError {
+            kind: ErrorKind::MalformedHeader,
+            msg: "Invalid sparse vector indices hash".to_string(),
+        })?,
+        None => None,
+    };
+    let mode = match unknown_fields.get(ENCRYPTION_MODE_FIELD_NUMBER) {
+        None => VectorEncryptionMode::Scaled,
+        Some(UnknownValueRef::Varint(QUANTIZATION_FRIENDLY_MODE)) => {
+            VectorEncryptionMode::QuantizationFriendly
+        }
+        Some(_) => Err(AlloyError::DecryptError {
+            kind: ErrorKind::MalformedHeader,
+            msg: "Unknown vector encryption mode in metadata".to_string(),
+        })?,
+    };
+    let matryoshka_block_size = match unknown_fields.get(MATRYOSHKA_BLOCK_SIZE_FIELD_NUMBER) {
+        None => None,
+        Some(UnknownValueRef::Varint(block_size)) if block_size > 0 => Some(
+            u32::try_from(block_size).map_err(|_| AlloyError::DecryptError {
+                kind: ErrorKind::MalformedHeader,
+                msg: "Invalid Matryoshka block size in metadata".to_string(),
+            })?,
+        ),
+        Some(_) => Err(AlloyError::DecryptError {
+            kind: ErrorKind::MalformedHeader,
+            msg: "Invalid Matryoshka block size in metadata".to_string(),
+        })?,
+    };
+    let model_id = match unknown_fields.get(MODEL_ID_FIELD_NUMBER) {
+        None => None,
+        Some(UnknownValueRef::LengthDelimited(model_id)) => Some(
+            String::from_utf8(model_id.to_vec()).map_err(|_| AlloyError::DecryptError {
+                kind: ErrorKind::MalformedHeader,
+                msg: "Invalid embedding model in metadata".to_string(),
+            })?,
+        ),
+        Some(_) => Err(AlloyError::DecryptError {
+            kind: ErrorKind::MalformedHeader,
+            msg: "Invalid embedding model in metadata".to_string(),
+        })?,
+    };
     let iv = vector_proto.iv;
     let auth_hash = vector_proto.auth_hash;
-    Ok((
-        iv[..].try_into().map_err(|_| AlloyError::DecryptError {
+    Ok(VectorMetadata {
+        iv: iv[..].try_into().map_err(|_| AlloyError::DecryptError {
+            kind: ErrorKind::MalformedHeader,
             msg: "Invalid IV".to_string(),
         })?,
-        AuthHash(
+        auth_hash: AuthHash(
             auth_hash[..]
                 .try_into()
                 .map_err(|_| AlloyError::DecryptError {
+                    kind: ErrorKind::MalformedHeader,
                     msg: "Invalid authentication hash".to_string(),
                 })?,
         ),
-    ))
+        approximation_factor,
+        dimension,
+        indices_hash,
+        mode,
+        matryoshka_block_size,
+        model_id,
+    })
+}
+
+/// Build the key ID header and metadata stored alongside an encrypted vector.
+pub(crate) fn create_paired_icl_info(
+    edek_type: EdekType,
+    key_id: KeyId,
+    result: &EncryptResult,
+    params: &VectorEncryptionParams,
+    indices_hash: Option<AuthHash>,
+) -> Vec<u8> {
+    record_key_id(key_id.0);
+    let (header, mut vector_metadata) = v5::key_id_header::create_vector_metadata(
+        KeyIdHeader::new(edek_type, PayloadType::VectorMetadata, key_id),
+        result.iv.to_vec().into(),
+        result.auth_hash.0.to_vec().into(),
+    );
+    let unknown_fields = vector_metadata.special_fields.mut_unknown_fields();
+    unknown_fields.add_varint(VERSION_FIELD_NUMBER, VECTOR_METADATA_VERSION);
+    unknown_fields.add_fixed32(
+        APPROXIMATION_FACTOR_FIELD_NUMBER,
+        params.approximation_factor.to_bits(),
+    );
+    unknown_fields.add_varint(DIMENSION_FIELD_NUMBER, result.ciphertext.len() as u64);
+    if params.mode == VectorEncryptionMode::QuantizationFriendly {
+        unknown_fields.add_varint(ENCRYPTION_MODE_FIELD_NUMBER, QUANTIZATION_FRIENDLY_MODE);
+    }
+    if let Some(block_size) = params.matryoshka_block_size {
+        unknown_fields.add_varint(MATRYOSHKA_BLOCK_SIZE_FIELD_NUMBER, block_size as u64);
+    }
+    if let Some(model_id) = &params.model_id {
+        unknown_fields.add_length_delimited(MODEL_ID_FIELD_NUMBER, model_id.as_bytes().to_vec());
+    }
+    if let Some(indices_hash) = indices_hash {
+        unknown_fields.add_length_delimited(INDICES_HASH_FIELD_NUMBER, indices_hash.0.to_vec());
+    }
+    v5::key_id_header::encode_vector_metadata(header, vector_metadata).to_vec()
 }
 
 pub(crate) fn encrypt_internal<R: RngCore + CryptoRng>(
-    approximation_factor: f32,
+    params: VectorEncryptionParams,
     key: &VectorEncryptionKey,
     key_id: KeyId,
     edek_type: EdekType,
     plaintext_vector: PlaintextVector,
     rng: &mut R,
 ) -> Result<EncryptedVector, AlloyError> {
-    let result = crypto::encrypt(
+    if params.matryoshka_block_size == Some(0) {
+        Err(AlloyError::InvalidConfiguration {
+            kind: ErrorKind::InvalidVectorOptions,
+            msg: "Matryoshka block size must be greater than zero.".to_string(),
+        })?
+    }
+    let result = crypto::encrypt_with_params(
         key,
-        approximation_factor,
-        shuffle(&key.key, plaintext_vector.plaintext_vector)
-            .into_iter()
-            .collect(),
+        &params,
+        shuffle_blocks(
+            &key.key,
+            plaintext_vector.plaintext_vector,
+            params.matryoshka_block_size,
+        )
+        .into(),
         rng,
     )?;
-    let (header, vector_metadata) = v5::key_id_header::create_vector_metadata(
-        KeyIdHeader::new(edek_type, PayloadType::VectorMetadata, key_id),
-        result.iv.to_vec().into(),
-        result.auth_hash.0.to_vec().into(),
-    );
+    let paired_icl_info = create_paired_icl_info(edek_type, key_id, &result, &params, None);
     Ok(EncryptedVector {
         encrypted_vector: result.ciphertext.to_vec(),
         secret_path: plaintext_vector.secret_path,
         derivation_path: plaintext_vector.derivation_path,
-        paired_icl_info: v5::key_id_header::encode_vector_metadata(header, vector_metadata)
-            .to_vec(),
+        paired_icl_info,
     })
 }
 
+/// Decrypt the vector using `approximation_factor`. Callers should prefer the approximation factor recorded in
+/// `vector_metadata` over any configured one. The encryption mode and Matryoshka block size always come from
+/// `vector_metadata`.
 pub(crate) fn decrypt_internal(
     approximation_factor: f32,
     key: &VectorEncryptionKey,
     encrypted_vector: EncryptedVector,
-    icl_metadata_bytes: Bytes,
+    vector_metadata: VectorMetadata,
 ) -> Result<PlaintextVector, AlloyError> {
-    let (iv, auth_hash) = get_iv_and_auth_hash(&icl_metadata_bytes)?;
-    Ok(crypto::decrypt(
-        key,
+    let VectorMetadata {
+        iv,
+        auth_hash,
+        dimension,
+        indices_hash,
+        mode,
+        matryoshka_block_size,
+        ..
+    } = vector_metadata;
+    if indices_hash.is_some() {
+        Err(AlloyError::DecryptError {
+            kind: ErrorKind::HeaderTypeMismatch,
+            msg: "This is an encrypted sparse vector. Decrypt it with the sparse vector client."
+                .to_string(),
+        })?;
+    }
+    if let Some(dimension) = dimension {
+        if dimension as usize != encrypted_vector.encrypted_vector.len() {
+            Err(AlloyError::DecryptError {
+                kind: ErrorKind::DimensionMismatch,
+                msg: format!(
+                    "Encrypted vector has {} values, but its metadata recorded a dimension of {}.",
+                    encrypted_vector.encrypted_vector.len(),
+                    dimension
+                ),
+            })?;
+        }
+    }
+    let params = VectorEncryptionParams {
         approximation_factor,
+        mode,
+        matryoshka_block_size,
+        model_id: None,
+    };
+    Ok(crypto::decrypt_with_params(
+        key,
+        &params,
         EncryptResult {
             ciphertext: encrypted_vector.encrypted_vector.into(),
             iv,
             auth_hash,
         },
     )
-    .map(|r| unshuffle(&key.key, r))?)
+    .map(|r| unshuffle_blocks(&key.key, r.to_vec(), matryoshka_block_size))?)
     .map(|dec| PlaintextVector {
         plaintext_vector: dec,
         secret_path: encrypted_vector.secret_path,
         derivation_path: encrypted_vector.derivation_path,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use protobuf::Message;
+
+    #[test]
+    fn vector_metadata_roundtrip() {
+        let key = VectorEncryptionKey {
+            scaling_factor: ScalingFactor(12345.0),
+            key: EncryptionKey(vec![1; 32]),
+        };
+        let encrypted = encrypt_internal(
+            VectorEncryptionParams::scaled(1.5),
+            &key,
+            KeyId(1),
+            EdekType::Standalone,
+            PlaintextVector {
+                plaintext_vector: vec![0.1, 0.2, 0.3],
+                secret_path: SecretPath("".to_string()),
+                derivation_path: DerivationPath("".to_string()),
+            },
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        let (_, metadata_bytes) = v5::key_id_header::decode_version_prefixed_value(
+            encrypted.paired_icl_info.clone().into(),
+        )
+        .unwrap();
+        let metadata = get_vector_metadata(&metadata_bytes).unwrap();
+        assert_eq!(metadata.approximation_factor, Some(1.5));
+        assert_eq!(metadata.dimension, Some(3));
+        assert_eq!(metadata.mode, VectorEncryptionMode::Scaled);
+    }
+
+    #[test]
+    fn quantization_friendly_mode_is_recorded_and_decrypts() {
+        let key = VectorEncryptionKey {
+            scaling_factor: ScalingFactor(12345.0),
+            key: EncryptionKey(vec![1; 32]),
+        };
+        let plaintext = vec![0.1, -0.2, 0.3];
+        let encrypted = encrypt_internal(
+            VectorEncryptionParams {
+                mode: VectorEncryptionMode::QuantizationFriendly,
+                ..VectorEncryptionParams::scaled(1.5)
+            },
+            &key,
+            KeyId(1),
+            EdekType::Standalone,
+            PlaintextVector {
+                plaintext_vector: plaintext.clone(),
+                secret_path: SecretPath("".to_string()),
+                derivation_path: DerivationPath("".to_string()),
+            },
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        let bound = quantization_friendly_value_bound(1.5);
+        assert!(encrypted.encrypted_vector.iter().all(|v| v.abs() <= bound));
+        let (_, metadata_bytes) = v5::key_id_header::decode_version_prefixed_value(
+            encrypted.paired_icl_info.clone().into(),
+        )
+        .unwrap();
+        let metadata = get_vector_metadata(&metadata_bytes).unwrap();
+        assert_eq!(metadata.mode, VectorEncryptionMode::QuantizationFriendly);
+        let decrypted = decrypt_internal(1.5, &key, encrypted, metadata).unwrap();
+        approx::assert_abs_diff_eq!(
+            &decrypted.plaintext_vector[..],
+            &plaintext[..],
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn version_one_metadata_has_no_factor() {
+        let metadata_bytes = VectorEncryptionMetadata {
+            iv: vec![1; 12].into(),
+            auth_hash: vec![2; 32].into(),
+            ..Default::default()
+        }
+        .write_to_bytes()
+        .unwrap();
+        let metadata = get_vector_metadata(&metadata_bytes).unwrap();
+        assert_eq!(
+            metadata,
+            VectorMetadata {
+                iv: [1; 12],
+                auth_hash: AuthHash([2; 32]),
+                approximation_factor: None,
+                dimension: None,
+                indices_hash: None,
+                mode: VectorEncryptionMode::Scaled,
+                matryoshka_block_size: None,
+                model_id: None,
+            }
+        );
+    }
+
+    #[test]
+    fn decrypt_checks_recorded_dimension() {
+        let key = VectorEncryptionKey {
+            scaling_factor: ScalingFactor(12345.0),
+            key: EncryptionKey(vec![1; 32]),
+        };
+        let mut encrypted = encrypt_internal(
+            VectorEncryptionParams::scaled(1.5),
+            &key,
+            KeyId(1),
+            EdekType::Standalone,
+            PlaintextVector {
+                plaintext_vector: vec![0.1, 0.2, 0.3],
+                secret_path: SecretPath("".to_string()),
+                derivation_path: DerivationPath("".to_string()),
+            },
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        encrypted.encrypted_vector.pop();
+        let (_, metadata_bytes) = v5::key_id_header::decode_version_prefixed_value(
+            encrypted.paired_icl_info.clone().into(),
+        )
+        .unwrap();
+        let metadata = get_vector_metadata(&metadata_bytes).unwrap();
+        let err = decrypt_internal(1.5, &key, encrypted, metadata).unwrap_err();
+        assert!(matches!(err, AlloyError::DecryptError { .. }));
+    }
+
+    /// Encrypt every vector and truncate the ciphertexts to each prefix length, returning recall@5 against the
+    /// plaintexts truncated the same way.
+    fn truncated_recall(matryoshka_block_size: Option<u32>, prefixes: &[usize]) -> Vec<f32> {
+        use calibration::{all_distances, nearest, recall_at_k, CalibrationMetric};
+        use ndarray::Array1;
+        use rand::SeedableRng;
+        use rand_distr::{Distribution, StandardNormal};
+
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(1);
+        let key = VectorEncryptionKey {
+            scaling_factor: ScalingFactor(12345.0),
+            key: EncryptionKey(vec![1; 32]),
+        };
+        let params = VectorEncryptionParams {
+            matryoshka_block_size,
+            ..VectorEncryptionParams::scaled(0.1)
+        };
+        let mut random_vectors = |count: usize| {
+            (0..count)
+                .map(|_| {
+                    let values: Vec<f32> =
+                        (0..64).map(|_| StandardNormal.sample(&mut rng)).collect();
+                    let norm = values.iter().map(|x| x * x).sum::<f32>().sqrt();
+                    values.into_iter().map(|x| x / norm).collect_vec()
+                })
+                .collect_vec()
+        };
+        let embeddings = random_vectors(200);
+        let queries = random_vectors(20);
+        let encrypt_all = |vectors: &[Vec<f32>], rng: &mut rand_chacha::ChaCha20Rng| {
+            vectors
+                .iter()
+                .map(|vector| {
+                    let plaintext = PlaintextVector {
+                        plaintext_vector: vector.clone(),
+                        secret_path: SecretPath("".to_string()),
+                        derivation_path: DerivationPath("".to_string()),
+                    };
+                    encrypt_internal(
+                        params.clone(),
+                        &key,
+                        KeyId(1),
+                        EdekType::Standalone,
+                        plaintext,
+                        rng,
+                    )
+                    .unwrap()
+                    .encrypted_vector
+                })
+                .collect_vec()
+        };
+        let encrypted_embeddings = encrypt_all(&embeddings, &mut rng);
+        let encrypted_queries = encrypt_all(&queries, &mut rng);
+        let truncate = |vectors: &[Vec<f32>], prefix: usize| {
+            vectors
+                .iter()
+                .map(|vector| Array1::from(vector[..prefix].to_vec()))
+                .collect_vec()
+        };
+        prefixes
+            .iter()
+            .map(|&prefix| {
+                let metric = CalibrationMetric::Cosine;
+                let expected = all_distances(
+                    &truncate(&queries, prefix),
+                    &truncate(&embeddings, prefix),
+                    metric,
+                )
+                .iter()
+                .map(|distances| nearest(distances, 5, metric))
+                .collect_vec();
+                let distances = all_distances(
+                    &truncate(&encrypted_queries, prefix),
+                    &truncate(&encrypted_embeddings, prefix),
+                    metric,
+                );
+                recall_at_k(&distances, &expected, 5, metric)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matryoshka_blocks_keep_recall_at_each_prefix() {
+        for (prefix, recall) in [16, 32, 48, 64]
+            .into_iter()
+            .zip(truncated_recall(Some(16), &[16, 32, 48, 64]))
+        {
+            assert!(recall >= 0.8, "recall at prefix {prefix} was {recall}");
+        }
+        // Without blocks the values of a prefix are shuffled in from anywhere in the vector.
+        let unblocked = truncated_recall(None, &[16, 64]);
+        assert!(
+            unblocked[0] < 0.5,
+            "unblocked prefix recall was {}",
+            unblocked[0]
+        );
+        assert!(unblocked[1] >= 0.8);
+    }
+
+    #[test]
+    fn matryoshka_block_size_is_recorded_and_decrypts() {
+        let key = VectorEncryptionKey {
+            scaling_factor: ScalingFactor(12345.0),
+            key: EncryptionKey(vec![1; 32]),
+        };
+        let plaintext = (1..=10).map(|i| i as f32 / 10.).collect_vec();
+        let params = VectorEncryptionParams {
+            matryoshka_block_size: Some(4),
+            ..VectorEncryptionParams::scaled(1.5)
+        };
+        let encrypted = encrypt_internal(
+            params.clone(),
+            &key,
+            KeyId(1),
+            EdekType::Standalone,
+            PlaintextVector {
+                plaintext_vector: plaintext.clone(),
+                secret_path: SecretPath("".to_string()),
+                derivation_path: DerivationPath("".to_string()),
+            },
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        let (_, metadata_bytes) = v5::key_id_header::decode_version_prefixed_value(
+            encrypted.paired_icl_info.clone().into(),
+        )
+        .unwrap();
+        let metadata = get_vector_metadata(&metadata_bytes).unwrap();
+        assert!(metadata.was_encrypted_with(&params));
+        let decrypted = decrypt_internal(1.5, &key, encrypted, metadata).unwrap();
+        approx::assert_abs_diff_eq!(
+            &decrypted.plaintext_vector[..],
+            &plaintext[..],
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn zero_matryoshka_block_size_is_rejected() {
+        let key = VectorEncryptionKey {
+            scaling_factor: ScalingFactor(12345.0),
+            key: EncryptionKey(vec![1; 32]),
+        };
+        let err = encrypt_internal(
+            VectorEncryptionParams {
+                matryoshka_block_size: Some(0),
+                ..VectorEncryptionParams::scaled(1.5)
+            },
+            &key,
+            KeyId(1),
+            EdekType::Standalone,
+            PlaintextVector {
+                plaintext_vector: vec![0.1],
+                secret_path: SecretPath("".to_string()),
+                derivation_path: DerivationPath("".to_string()),
+            },
+            &mut rand::thread_rng(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidConfiguration { .. }));
+    }
+}
@@ -0,0 +1,350 @@
+use super::{EncryptedVector, PlaintextVector};
+use crate::{
+    errors::{AlloyError, ErrorKind},
+    DerivationPath, SecretPath,
+};
+use half::{bf16, f16};
+use itertools::Itertools;
+
+/// Floating point precisions supported by the typed vector entry points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum VectorPrecision {
+    F64,
+    F32,
+    F16,
+    Bf16,
+}
+
+/// Vector values in one of the supported precisions. Half precision values are carried as their raw bits
+/// (IEEE 754 binary16 for `F16`, bfloat16 for `Bf16`) because not every language has a native half type.
+/// Rust callers can convert from and to `half::f16`/`half::bf16` vectors directly.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum TypedVectorValues {
+    F64 { values: Vec<f64> },
+    F32 { values: Vec<f32> },
+    F16 { bits: Vec<u16> },
+    Bf16 { bits: Vec<u16> },
+}
+
+impl TypedVectorValues {
+    pub fn precision(&self) -> VectorPrecision {
+        match self {
+            TypedVectorValues::F64 { .. } => VectorPrecision::F64,
+            TypedVectorValues::F32 { .. } => VectorPrecision::F32,
+            TypedVectorValues::F16 { .. } => VectorPrecision::F16,
+            TypedVectorValues::Bf16 { .. } => VectorPrecision::Bf16,
+        }
+    }
+
+    /// The values as `f16`s, if they are half precision.
+    pub fn as_f16(&self) -> Option<Vec<f16>> {
+        match self {
+            TypedVectorValues::F16 { bits } => {
+                Some(bits.iter().copied().map(f16::from_bits).collect())
+            }
+            _ => None,
+        }
+    }
+
+    /// The values as `bf16`s, if they are bfloat16.
+    pub fn as_bf16(&self) -> Option<Vec<bf16>> {
+        match self {
+            TypedVectorValues::Bf16 { bits } => {
+                Some(bits.iter().copied().map(bf16::from_bits).collect())
+            }
+            _ => None,
+        }
+    }
+
+    /// Widen the values to the `f32`s vector encryption operates on. Every `f16` and `bf16` is exactly
+    /// representable as an `f32`. `f64`s are rounded, and any that are too large for an `f32` are rejected.
+    pub(crate) fn to_f32(&self) -> Result<Vec<f32>, AlloyError> {
+        match self {
+            TypedVectorValues::F64 { values } => values
+                .iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    let narrowed = *value as f32;
+                    if value.is_finite() && !narrowed.is_finite() {
+                        Err(AlloyError::InvalidInput {
+                            kind: ErrorKind::VectorOverflow,
+                            msg: format!(
+                                "Vector value at index {index} is too large to be encrypted."
+                            ),
+                        })
+                    } else {
+                        Ok(narrowed)
+                    }
+                })
+                .collect(),
+            TypedVectorValues::F32 { values } => Ok(values.clone()),
+            TypedVectorValues::F16 { bits } => Ok(bits
+                .iter()
+                .map(|bits| f16::from_bits(*bits).to_f32())
+                .collect()),
+            TypedVectorValues::Bf16 { bits } => Ok(bits
+                .iter()
+                .map(|bits| bf16::from_bits(*bits).to_f32())
+                .collect()),
+        }
+    }
+
+    /// Convert `f32`s to `precision`. Returns the index of the first value that doesn't fit in `precision` if there
+    /// is one, since narrowing it would silently turn it into an infinity.
+    pub(crate) fn from_f32(values: Vec<f32>, precision: VectorPrecision) -> Result<Self, usize> {
+        fn narrow<T>(
+            values: Vec<f32>,
+            to_half: fn(f32) -> T,
+            is_finite: fn(&T) -> bool,
+            to_bits: fn(T) -> u16,
+        ) -> Result<Vec<u16>, usize> {
+            values
+                .into_iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    let half = to_half(value);
+                    if value.is_finite() && !is_finite(&half) {
+                        Err(index)
+                    } else {
+                        Ok(to_bits(half))
+                    }
+                })
+                .collect()
+        }
+        Ok(match precision {
+            VectorPrecision::F64 => TypedVectorValues::F64 {
+                values: values.into_iter().map(f64::from).collect(),
+            },
+            VectorPrecision::F32 => TypedVectorValues::F32 { values },
+            VectorPrecision::F16 => TypedVectorValues::F16 {
+                bits: narrow(values, f16::from_f32, |h| h.is_finite(), f16::to_bits)?,
+            },
+            VectorPrecision::Bf16 => TypedVectorValues::Bf16 {
+                bits: narrow(values, bf16::from_f32, |h| h.is_finite(), bf16::to_bits)?,
+            },
+        })
+    }
+}
+
+impl From<Vec<f64>> for TypedVectorValues {
+    fn from(values: Vec<f64>) -> Self {
+        TypedVectorValues::F64 { values }
+    }
+}
+
+impl From<Vec<f32>> for TypedVectorValues {
+    fn from(values: Vec<f32>) -> Self {
+        TypedVectorValues::F32 { values }
+    }
+}
+
+impl From<Vec<f16>> for TypedVectorValues {
+    fn from(values: Vec<f16>) -> Self {
+        TypedVectorValues::F16 {
+            bits: values.into_iter().map(f16::to_bits).collect(),
+        }
+    }
+}
+
+impl From<Vec<bf16>> for TypedVectorValues {
+    fn from(values: Vec<bf16>) -> Self {
+        TypedVectorValues::Bf16 {
+            bits: values.into_iter().map(bf16::to_bits).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct PlaintextTypedVector {
+    pub plaintext_vector: TypedVectorValues,
+    pub secret_path: SecretPath,
+    pub derivation_path: DerivationPath,
+}
+
+/// An encrypted vector in the precision it should be stored in. Encryption scales values by the secret's
+/// scaling factor (up to 2^24), which overflows `f16` and loses too much precision in `bf16` for the
+/// authentication hash to survive, so vectors encrypted from half precision plaintexts are `F32`.
+/// Vectors encrypted from `F64` plaintexts are `F64`, which holds every encrypted `f32` value exactly.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct EncryptedTypedVector {
+    pub encrypted_vector: TypedVectorValues,
+    pub secret_path: SecretPath,
+    pub derivation_path: DerivationPath,
+    pub paired_icl_info: Vec<u8>,
+}
+
+impl TryFrom<PlaintextTypedVector> for PlaintextVector {
+    type Error = AlloyError;
+
+    fn try_from(value: PlaintextTypedVector) -> Result<Self, Self::Error> {
+        Ok(PlaintextVector {
+            plaintext_vector: value.plaintext_vector.to_f32()?,
+            secret_path: value.secret_path,
+            derivation_path: value.derivation_path,
+        })
+    }
+}
+
+impl PlaintextTypedVector {
+    /// Narrow a decrypted vector to `precision`. Decryption is approximate, so a plaintext that was near the top of
+    /// a half precision range can decrypt to a value outside of it.
+    pub(crate) fn from_decrypted(
+        value: PlaintextVector,
+        precision: VectorPrecision,
+    ) -> Result<Self, AlloyError> {
+        Ok(PlaintextTypedVector {
+            plaintext_vector: TypedVectorValues::from_f32(value.plaintext_vector, precision)
+                .map_err(|index| AlloyError::DecryptError {
+                    kind: ErrorKind::DecryptionFailed,
+                    msg: format!(
+                        "Decrypted value at index {index} is out of range for {precision:?}."
+                    ),
+                })?,
+            secret_path: value.secret_path,
+            derivation_path: value.derivation_path,
+        })
+    }
+}
+
+impl EncryptedTypedVector {
+    /// Store an encrypted vector in the precision matching a plaintext of `plaintext_precision`.
+    pub(crate) fn from_encrypted(
+        value: EncryptedVector,
+        plaintext_precision: VectorPrecision,
+    ) -> Self {
+        let encrypted_vector = match plaintext_precision {
+            VectorPrecision::F64 => TypedVectorValues::F64 {
+                values: value.encrypted_vector.into_iter().map(f64::from).collect(),
+            },
+            VectorPrecision::F32 | VectorPrecision::F16 | VectorPrecision::Bf16 => {
+                TypedVectorValues::F32 {
+                    values: value.encrypted_vector,
+                }
+            }
+        };
+        EncryptedTypedVector {
+            encrypted_vector,
+            secret_path: value.secret_path,
+            derivation_path: value.derivation_path,
+            paired_icl_info: value.paired_icl_info,
+        }
+    }
+}
+
+impl TryFrom<EncryptedTypedVector> for EncryptedVector {
+    type Error = AlloyError;
+
+    /// Encrypted `f64` values must be exactly the `f32`s encryption produced, otherwise the authentication hash
+    /// can't be checked.
+    fn try_from(value: EncryptedTypedVector) -> Result<Self, Self::Error> {
+        let encrypted_vector = match value.encrypted_vector {
+            TypedVectorValues::F64 { values } => values
+                .into_iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    let narrowed = value as f32;
+                    if f64::from(narrowed) == value {
+                        Ok(narrowed)
+                    } else {
+                        Err(AlloyError::InvalidInput {
+                            kind: ErrorKind::Serialization,
+                            msg: format!(
+                                "Encrypted vector value at index {index} was not produced by encryption."
+                            ),
+                        })
+                    }
+                })
+                .try_collect()?,
+            TypedVectorValues::F32 { values } => values,
+            TypedVectorValues::F16 { .. } | TypedVectorValues::Bf16 { .. } => {
+                Err(AlloyError::InvalidInput {
+                    kind: ErrorKind::Serialization,
+                    msg: "Encrypted vectors are never half precision.".to_string(),
+                })?
+            }
+        };
+        Ok(EncryptedVector {
+            encrypted_vector,
+            secret_path: value.secret_path,
+            derivation_path: value.derivation_path,
+            paired_icl_info: value.paired_icl_info,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assertables::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn f64_too_large_for_f32_is_rejected() {
+        let values = TypedVectorValues::from(vec![1., 1e39]);
+        let err = values.to_f32().unwrap_err();
+        assert_contains!(err.to_string(), "index 1");
+    }
+
+    #[test]
+    fn from_f32_rejects_values_out_of_half_range() {
+        assert_eq!(
+            TypedVectorValues::from_f32(vec![1., 70000.], VectorPrecision::F16),
+            Err(1)
+        );
+        assert_eq!(
+            TypedVectorValues::from_f32(vec![1., f32::MAX], VectorPrecision::Bf16),
+            Err(1)
+        );
+        assert!(TypedVectorValues::from_f32(vec![1., 70000.], VectorPrecision::Bf16).is_ok());
+    }
+
+    #[test]
+    fn half_ciphertext_is_rejected() {
+        let encrypted = EncryptedTypedVector {
+            encrypted_vector: vec![f16::ONE].into(),
+            secret_path: SecretPath("".to_string()),
+            derivation_path: DerivationPath("".to_string()),
+            paired_icl_info: vec![],
+        };
+        assert!(EncryptedVector::try_from(encrypted).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn encrypted_f64_roundtrips_exactly(
+            values in prop::collection::vec(
+                prop::num::f32::NORMAL | prop::num::f32::SUBNORMAL | prop::num::f32::ZERO,
+                0..100
+            )
+        ) {
+            let encrypted = EncryptedVector {
+                encrypted_vector: values.clone(),
+                secret_path: SecretPath("".to_string()),
+                derivation_path: DerivationPath("".to_string()),
+                paired_icl_info: vec![],
+            };
+            let typed = EncryptedTypedVector::from_encrypted(encrypted, VectorPrecision::F64);
+            let back = EncryptedVector::try_from(typed).unwrap();
+            prop_assert_eq!(
+                back.encrypted_vector.iter().map(|v| v.to_bits()).collect_vec(),
+                values.iter().map(|v| v.to_bits()).collect_vec()
+            );
+        }
+
+        #[test]
+        fn half_values_roundtrip_through_f32(bits: Vec<u16>) {
+            let values = TypedVectorValues::F16 { bits: bits.clone() };
+            let widened = values.to_f32().unwrap();
+            let narrowed = TypedVectorValues::from_f32(widened, VectorPrecision::F16).unwrap();
+            // NaN payloads may not survive, but every other value must be unchanged.
+            let expected = bits.iter().map(|b| f16::from_bits(*b)).collect_vec();
+            for (actual, expected) in narrowed.as_f16().unwrap().into_iter().zip(expected) {
+                prop_assert!(actual == expected || (actual.is_nan() && expected.is_nan()));
+            }
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use super::{
+    create_paired_icl_info,
+    crypto::{self, EncryptResult},
+    EncryptionKey, VectorEncryptionKey, VectorEncryptionParams, VectorId, VectorMetadata,
+};
+use crate::{
+    errors::{AlloyError, ErrorKind},
+    util::{hash256, AuthHash, BatchResult},
+    AlloyMetadata, DerivationPath, SecretPath, TenantId,
+};
+use ironcore_documents::v5::key_id_header::{EdekType, KeyId};
+use itertools::Itertools;
+use rand::{CryptoRng, RngCore};
+use ring::hmac::{Context as HMACContext, Key as HMACKey, HMAC_SHA256};
+use std::collections::{HashMap, HashSet};
+
+const INDEX_PERMUTATION_KEY: &str = "IronCore Alloy sparse vector index permutation";
+const INDICES_HASH_KEY: &str = "IronCore Alloy sparse vector indices authentication";
+/// Number of Feistel rounds used to permute indices.
+const PERMUTATION_ROUNDS: u8 = 8;
+
+/// A sparse embedding such as SPLADE or BM25 term weights. `values[i]` is the weight of the term at `indices[i]`.
+/// Indices must be unique but don't need to be sorted.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct PlaintextSparseVector {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+    pub secret_path: SecretPath,
+    pub derivation_path: DerivationPath,
+}
+
+/// An encrypted sparse embedding. Indices are replaced by their keyed permutation and sorted, and values are
+/// encrypted the same way dense embedding values are, so the same index in two vectors encrypted with the same
+/// key still lines up and sparse dot product search keeps working.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct EncryptedSparseVector {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+    pub secret_path: SecretPath,
+    pub derivation_path: DerivationPath,
+    pub paired_icl_info: Vec<u8>,
+}
+
+pub type PlaintextSparseVectors = HashMap<VectorId, PlaintextSparseVector>;
+pub type EncryptedSparseVectors = HashMap<VectorId, EncryptedSparseVector>;
+pub type GenerateSparseQueryResult = HashMap<VectorId, Vec<EncryptedSparseVector>>;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct SparseVectorRotateResult {
+    pub successes: EncryptedSparseVectors,
+    pub failures: HashMap<VectorId, AlloyError>,
+}
+impl From<BatchResult<EncryptedSparseVector>> for SparseVectorRotateResult {
+    fn from(value: BatchResult<EncryptedSparseVector>) -> Self {
+        Self {
+            successes: value.successes,
+            failures: value.failures,
+        }
+    }
+}
+
+pub trait SparseVectorOps {
+    /// Encrypt a sparse embedding with the provided metadata. Indices are hidden with a keyed permutation and values
+    /// are encrypted like dense embedding values, using the approximation factor configured for the secret path.
+    /// The same tenant ID must be provided in the metadata when decrypting the embedding.
+    async fn encrypt(
+        &self,
+        plaintext_vector: PlaintextSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<EncryptedSparseVector, AlloyError>;
+
+    /// Decrypt a sparse embedding that was encrypted with the provided metadata. The result is sorted by index.
+    async fn decrypt(
+        &self,
+        encrypted_vector: EncryptedSparseVector,
+        metadata: &AlloyMetadata,
+    ) -> Result<PlaintextSparseVector, AlloyError>;
+
+    /// Encrypt each sparse embedding with any Current and InRotation keys for the provided secret path.
+    /// The resulting encrypted vectors should be used in tandem when querying the vector database.
+    async fn generate_query_vectors(
+        &self,
+        vectors_to_query: PlaintextSparseVectors,
+        metadata: &AlloyMetadata,
+    ) -> Result<GenerateSparseQueryResult, AlloyError>;
+
+    /// Generate a prefix that could used to search a data store for sparse vectors encrypted using an identifier
+    /// (KMS config id for SaaS Shield, secret id for Standalone). These bytes should be encoded into
+    /// a format matching the encoding in the data store. `encode_search_prefixes` does this for the common
+    /// encodings, including the z85/ascii85 pitfalls when encoding across byte boundaries.
+    async fn get_in_rotation_prefix(
+        &self,
+        secret_path: SecretPath,
+        derivation_path: DerivationPath,
+        metadata: &AlloyMetadata,
+    ) -> Result<Vec<u8>, AlloyError>;
+
+    /// Rotates sparse vectors from the in-rotation secret for their secret path to the current secret, or from one
+    /// tenant ID to a new one. Like dense vectors, they are also re-encrypted if their recorded approximation factor
+    /// doesn't match the configured one.
+    /// The same lossiness warning as dense vector rotation applies to the values.
+    async fn rotate_vectors(
+        &self,
+        encrypted_vectors: EncryptedSparseVectors,
+        metadata: &AlloyMetadata,
+        new_tenant_id: Option<TenantId>,
+    ) -> Result<SparseVectorRotateResult, AlloyError>;
+}
+
+/// A keyed pseudorandom permutation of the `u32` index space, built as a balanced Feistel network over the two
+/// 16 bit halves of an index with HMAC-SHA256 as the round function. Unlike `shuffle`, which permutes the positions
+/// of a dense vector, this has to permute indices that are never all present at once.
+struct IndexPermutation {
+    key: HMACKey,
+}
+
+impl IndexPermutation {
+    fn new(key: &EncryptionKey) -> Self {
+        Self {
+            key: HMACKey::new(HMAC_SHA256, &hash256(&key.0, INDEX_PERMUTATION_KEY)),
+        }
+    }
+
+    fn round(&self, round: u8, half: u16) -> u16 {
+        let mut ctx = HMACContext::with_key(&self.key);
+        ctx.update(&[round]);
+        ctx.update(&half.to_be_bytes());
+        let tag = ctx.sign();
+        u16::from_be_bytes([tag.as_ref()[0], tag.as_ref()[1]])
+    }
+
+    fn permute(&self, index: u32) -> u32 {
+        let (left, right) = (0..PERMUTATION_ROUNDS).fold(
+            ((index >> 16) as u16, index as u16),
+            |(left, right), round| (right, left ^ self.round(round, right)),
+        );
+        (left as u32) << 16 | right as u32
+    }
+
+    fn unpermute(&self, index: u32) -> u32 {
+        let (left, right) = (0..PERMUTATION_ROUNDS).rev().fold(
+            ((index >> 16) as u16, index as u16),
+            |(left, right), round| (right ^ self.round(round, left), left),
+        );
+        (left as u32) << 16 | right as u32
+    }
+}
+
+/// Authenticates the permuted indices of a sparse vector. The IV ties them to the values' auth hash.
+fn compute_indices_hash(key: &VectorEncryptionKey, iv: &[u8], indices: &[u32]) -> AuthHash {
+    let hmac_key = HMACKey::new(HMAC_SHA256, &hash256(&key.key.0, INDICES_HASH_KEY));
+    let mut ctx = HMACContext::with_key(&hmac_key);
+    ctx.update(iv);
+    for index in indices {
+        ctx.update(&index.to_be_bytes());
+    }
+    AuthHash(
+        ctx.sign()
+            .as_ref()
+            .try_into() // this is safe because digest output len (SHA256_OUTPUT_LEN) == 32
+            .unwrap(),
+    )
+}
+
+fn validate(plaintext_vector: &PlaintextSparseVector) -> Result<(), AlloyError> {
+    if plaintext_vector.indices.len() != plaintext_vector.values.len() {
+        Err(AlloyError::InvalidInput {
+            kind: ErrorKind::MalformedSparseVector,
+            msg: format!(
+                "Sparse vector has {} indices but {} values.",
+                plaintext_vector.indices.len(),
+                plaintext_vector.values.len()
+            ),
+        })?
+    }
+    let mut seen = HashSet::with_capacity(plaintext_vector.indices.len());
+    if let Some(duplicate) = plaintext_vector
+        .indices
+        .iter()
+        .find(|index| !seen.insert(**index))
+    {
+        Err(AlloyError::InvalidInput {
+            kind: ErrorKind::MalformedSparseVector,
+            msg: format!("Sparse vector index {duplicate} appears more than once."),
+        })?
+    }
+    Ok(())
+}
+
+pub(crate) fn encrypt_sparse_internal<R: RngCore + CryptoRng>(
+    approximation_factor: f32,
+    key: &VectorEncryptionKey,
+    key_id: KeyId,
+    edek_type: EdekType,
+    plaintext_vector: PlaintextSparseVector,
+    rng: &mut R,
+) -> Result<EncryptedSparseVector, AlloyError> {
+    validate(&plaintext_vector)?;
+    let permutation = IndexPermutation::new(&key.key);
+    // Sorting by permuted index means the encrypted layout reveals nothing about the original order.
+    let (indices, values): (Vec<u32>, Vec<f32>) = plaintext_vector
+        .indices
+        .into_iter()
+        .map(|index| permutation.permute(index))
+        .zip(plaintext_vector.values)
+        .sorted_unstable_by_key(|(index, _)| *index)
+        .unzip();
+    let result = crypto::encrypt(key, approximation_factor, values.into(), rng)?;
+    let indices_hash = compute_indices_hash(key, &result.iv, &indices);
+    let paired_icl_info = create_paired_icl_info(
+        edek_type,
+        key_id,
+        &result,
+        &VectorEncryptionParams::scaled(approximation_factor),
+        Some(indices_hash),
+    );
+    Ok(EncryptedSparseVector {
+        indices,
+        values: result.ciphertext.to_vec(),
+        secret_path: plaintext_vector.secret_path,
+        derivation_path: plaintext_vector.derivation_path,
+        paired_icl_info,
+    })
+}
+
+/// Decrypt the sparse vector using `approximation_factor`. Callers should prefer the approximation factor recorded
+/// in `vector_metadata` over any configured one.
+pub(crate) fn decrypt_sparse_internal(
+    approximation_factor: f32,
+    key: &VectorEncryptionKey,
+    encrypted_vector: EncryptedSparseVector,
+    vector_metadata: VectorMetadata,
+) -> Result<PlaintextSparseVector, AlloyError> {
+    let VectorMetadata {
+        iv,
+        auth_hash,
+        dimension,
+        indices_hash,
+        ..
+    } = vector_metadata;
+    let indices_hash = indices_hash.ok_or_else(|| AlloyError::DecryptError {
+        kind: ErrorKind::HeaderTypeMismatch,
+        msg: "This is not an encrypted sparse vector.".to_string(),
+    })?;
+    if encrypted_vector.indices.len() != encrypted_vector.values.len()
+        || dimension.is_some_and(|dimension| dimension as usize != encrypted_vector.values.len())
+    {
+        Err(AlloyError::DecryptError {
+            kind: ErrorKind::DimensionMismatch,
+            msg: "Encrypted sparse vector's indices, values, and recorded dimension don't match."
+                .to_string(),
+        })?
+    }
+    if compute_indices_hash(key, &iv, &encrypted_vector.indices) != indices_hash {
+        Err(crypto::DecryptError::InvalidAuthHash)?
+    }
+    let values = crypto::decrypt(
+        key,
+        approximation_factor,
+        EncryptResult {
+            ciphertext: encrypted_vector.values.into(),
+            iv,
+            auth_hash,
+        },
+    )?;
+    let permutation = IndexPermutation::new(&key.key);
+    let (indices, values) = encrypted_vector
+        .indices
+        .into_iter()
+        .map(|index| permutation.unpermute(index))
+        .zip(values)
+        .sorted_unstable_by_key(|(index, _)| *index)
+        .unzip();
+    Ok(PlaintextSparseVector {
+        indices,
+        values,
+        secret_path: encrypted_vector.secret_path,
+        derivation_path: encrypted_vector.derivation_path,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vector::{get_vector_metadata, ScalingFactor};
+    use approx::assert_abs_diff_eq;
+    use ironcore_documents::v5::key_id_header::decode_version_prefixed_value;
+    use proptest::prelude::*;
+
+    fn key() -> VectorEncryptionKey {
+        VectorEncryptionKey {
+            scaling_factor: ScalingFactor(12345.0),
+            key: EncryptionKey(vec![1; 32]),
+        }
+    }
+
+    fn plaintext(indices: Vec<u32>, values: Vec<f32>) -> PlaintextSparseVector {
+        PlaintextSparseVector {
+            indices,
+            values,
+            secret_path: SecretPath("".to_string()),
+            derivation_path: DerivationPath("".to_string()),
+        }
+    }
+
+    fn metadata(encrypted: &EncryptedSparseVector) -> VectorMetadata {
+        let (_, metadata_bytes) =
+            decode_version_prefixed_value(encrypted.paired_icl_info.clone().into()).unwrap();
+        get_vector_metadata(&metadata_bytes).unwrap()
+    }
+
+    proptest! {
+        #[test]
+        fn permutation_roundtrips(index: u32) {
+            let permutation = IndexPermutation::new(&key().key);
+            prop_assert_eq!(permutation.unpermute(permutation.permute(index)), index);
+        }
+    }
+
+    #[test]
+    fn roundtrip_sorts_by_index() {
+        let encrypted = encrypt_sparse_internal(
+            1.,
+            &key(),
+            KeyId(1),
+            EdekType::Standalone,
+            plaintext(vec![7, 3, 20000], vec![0.5, 0.25, 0.125]),
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        assert!(encrypted.indices.iter().all(|i| ![7, 3, 20000].contains(i)));
+        let vector_metadata = metadata(&encrypted);
+        let decrypted = decrypt_sparse_internal(1., &key(), encrypted, vector_metadata).unwrap();
+        assert_eq!(decrypted.indices, vec![3, 7, 20000]);
+        assert_abs_diff_eq!(
+            &decrypted.values[..],
+            &[0.25, 0.5, 0.125][..],
+            epsilon = 1e-5
+        );
+    }
+
+    #[test]
+    fn same_index_permutes_the_same_across_vectors() {
+        let encrypt = |indices| {
+            encrypt_sparse_internal(
+                1.,
+                &key(),
+                KeyId(1),
+                EdekType::Standalone,
+                plaintext(indices, vec![1.]),
+                &mut rand::thread_rng(),
+            )
+            .unwrap()
+        };
+        assert_eq!(encrypt(vec![42]).indices, encrypt(vec![42]).indices);
+    }
+
+    #[test]
+    fn tampered_indices_fail_to_decrypt() {
+        let mut encrypted = encrypt_sparse_internal(
+            1.,
+            &key(),
+            KeyId(1),
+            EdekType::Standalone,
+            plaintext(vec![1, 2], vec![0.5, 0.5]),
+            &mut rand::thread_rng(),
+        )
+        .unwrap();
+        let vector_metadata = metadata(&encrypted);
+        encrypted.indices[0] ^= 1;
+        let err = decrypt_sparse_internal(1., &key(), encrypted, vector_metadata).unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidInput { .. }));
+    }
+
+    #[test]
+    fn rejects_duplicate_indices() {
+        let err = encrypt_sparse_internal(
+            1.,
+            &key(),
+            KeyId(1),
+            EdekType::Standalone,
+            plaintext(vec![1, 2, 1], vec![0.5, 0.5, 0.5]),
+            &mut rand::thread_rng(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            AlloyError::InvalidInput {
+                kind: ErrorKind::MalformedSparseVector,
+                msg: "Sparse vector index 1 appears more than once.".to_string()
+            }
+        );
+    }
+}
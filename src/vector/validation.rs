@@ -0,0 +1,155 @@
+use super::PlaintextVector;
+use crate::errors::{AlloyError, ErrorKind};
+
+/// Whether plaintext vectors must be, or should be made, L2 normalized before encryption.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum VectorNormalization {
+    /// Vectors are encrypted as they are.
+    #[default]
+    None,
+    /// Vectors whose L2 norm differs from 1 by more than `tolerance` are rejected.
+    Require { tolerance: f32 },
+    /// Vectors are divided by their L2 norm before encryption. Zero vectors are rejected.
+    Apply,
+}
+
+/// Checks and preprocessing applied to plaintext vectors before they are encrypted, including when they're
+/// re-encrypted by rotation. The default accepts every vector unchanged.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct VectorValidation {
+    /// Reject vectors containing NaN or infinite values.
+    pub reject_non_finite: bool,
+    pub normalization: VectorNormalization,
+    /// Reject vectors that don't have exactly this many values.
+    pub expected_dimension: Option<u32>,
+}
+
+impl VectorValidation {
+    /// Check `values` against this validation and return them normalized if requested.
+    pub(crate) fn prepare(&self, mut values: Vec<f32>) -> Result<Vec<f32>, AlloyError> {
+        if let Some(expected_dimension) = self.expected_dimension {
+            if values.len() != expected_dimension as usize {
+                Err(AlloyError::InvalidInput {
+                    kind: ErrorKind::DimensionMismatch,
+                    msg: format!(
+                        "Expected a vector with dimension {expected_dimension}, but it had dimension {}.",
+                        values.len()
+                    ),
+                })?
+            }
+        }
+        if self.reject_non_finite {
+            if let Some(index) = values.iter().position(|value| !value.is_finite()) {
+                Err(AlloyError::InvalidInput {
+                    kind: ErrorKind::NonFiniteValue,
+                    msg: format!(
+                        "Vector value at index {index} is {}, but only finite values are allowed.",
+                        values[index]
+                    ),
+                })?
+            }
+        }
+        let norm = || values.iter().map(|value| value * value).sum::<f32>().sqrt();
+        match self.normalization {
+            VectorNormalization::None => {}
+            VectorNormalization::Require { tolerance } => {
+                let norm = norm();
+                if norm.is_nan() || (norm - 1.).abs() > tolerance {
+                    Err(AlloyError::InvalidInput {
+                        kind: ErrorKind::NotNormalized,
+                        msg: format!(
+                            "Vector has an L2 norm of {norm}, but must be normalized to within {tolerance} of 1."
+                        ),
+                    })?
+                }
+            }
+            VectorNormalization::Apply => {
+                let norm = norm();
+                if norm == 0. {
+                    Err(AlloyError::InvalidInput {
+                        kind: ErrorKind::NotNormalized,
+                        msg: "A vector with an L2 norm of 0 can't be normalized.".to_string(),
+                    })?
+                }
+                values.iter_mut().for_each(|value| *value /= norm);
+            }
+        }
+        Ok(values)
+    }
+
+    pub(crate) fn prepare_vector(
+        &self,
+        plaintext_vector: PlaintextVector,
+    ) -> Result<PlaintextVector, AlloyError> {
+        Ok(PlaintextVector {
+            plaintext_vector: self.prepare(plaintext_vector.plaintext_vector)?,
+            ..plaintext_vector
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use assertables::*;
+
+    #[test]
+    fn default_accepts_anything() {
+        let values = vec![f32::NAN, 3., f32::INFINITY];
+        let prepared = VectorValidation::default().prepare(values.clone()).unwrap();
+        assert_eq!(prepared.len(), 3);
+        assert!(prepared[0].is_nan());
+        assert_eq!(prepared[1..], values[1..]);
+    }
+
+    #[test]
+    fn non_finite_reports_index() {
+        let validation = VectorValidation {
+            reject_non_finite: true,
+            ..Default::default()
+        };
+        let err = validation.prepare(vec![0.5, 0.5, f32::NAN]).unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidInput { .. }));
+        assert_contains!(err.to_string(), "index 2");
+        let err = validation
+            .prepare(vec![f32::NEG_INFINITY, 0.5])
+            .unwrap_err();
+        assert_contains!(err.to_string(), "index 0");
+    }
+
+    #[test]
+    fn expected_dimension_is_enforced() {
+        let validation = VectorValidation {
+            expected_dimension: Some(3),
+            ..Default::default()
+        };
+        assert!(validation.prepare(vec![1., 2., 3.]).is_ok());
+        let err = validation.prepare(vec![1., 2.]).unwrap_err();
+        assert_contains!(err.to_string(), "dimension 3");
+    }
+
+    #[test]
+    fn require_normalization_uses_tolerance() {
+        let validation = VectorValidation {
+            normalization: VectorNormalization::Require { tolerance: 0.01 },
+            ..Default::default()
+        };
+        assert!(validation.prepare(vec![0.6, 0.8]).is_ok());
+        assert!(validation.prepare(vec![0.6, 0.805]).is_ok());
+        let err = validation.prepare(vec![3., 4.]).unwrap_err();
+        assert!(matches!(err, AlloyError::InvalidInput { .. }));
+        assert!(validation.prepare(vec![f32::NAN, 0.]).is_err());
+    }
+
+    #[test]
+    fn apply_normalization_divides_by_norm() {
+        let validation = VectorValidation {
+            normalization: VectorNormalization::Apply,
+            ..Default::default()
+        };
+        assert_eq!(validation.prepare(vec![3., 4.]).unwrap(), vec![0.6, 0.8]);
+        assert!(validation.prepare(vec![0., 0.]).is_err());
+    }
+}
@@ -10,6 +10,7 @@ use std::{
     process::{Command, ExitStatus, Stdio},
     sync::Arc,
 };
+#[cfg(feature = "uniffi")]
 use uniffi::TargetLanguage;
 
 pub type TestResult = Result<(), AlloyError>;
@@ -72,6 +73,7 @@ pub(crate) fn get_dynamic_library_paths() -> Result<Vec<PathBuf>, Box<dyn Error>
     Ok(paths)
 }
 
+#[cfg(feature = "uniffi")]
 pub(crate) fn generate_bindings(
     library_path: PathBuf,
     out_dir: PathBuf,
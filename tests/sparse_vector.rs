@@ -0,0 +1,79 @@
+mod common;
+
+#[cfg(feature = "integration_tests")]
+mod tests {
+    use crate::common::{get_client, TestResult};
+    use approx::assert_abs_diff_eq;
+    use ironcore_alloy::{
+        vector::sparse::{PlaintextSparseVector, SparseVectorOps},
+        AlloyMetadata, DerivationPath, SecretPath, TenantId,
+    };
+    use std::sync::Arc;
+
+    fn get_metadata() -> Arc<AlloyMetadata> {
+        AlloyMetadata::new_simple(TenantId("tenant-gcp-l".to_string()))
+    }
+
+    fn get_plaintext() -> PlaintextSparseVector {
+        PlaintextSparseVector {
+            indices: vec![2045, 7, 30000],
+            values: vec![1.5, 0.25, 0.75],
+            secret_path: SecretPath("secret".to_string()),
+            derivation_path: DerivationPath("deriv".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn sparse_encrypt_decrypt_roundtrip() -> TestResult {
+        let metadata = get_metadata();
+        let encrypted = get_client()
+            .sparse_vector()
+            .encrypt(get_plaintext(), &metadata)
+            .await?;
+        assert_eq!(encrypted.indices.len(), 3);
+        assert_eq!(encrypted.values.len(), 3);
+        let decrypted = get_client()
+            .sparse_vector()
+            .decrypt(encrypted, &metadata)
+            .await?;
+        assert_eq!(decrypted.indices, vec![7, 2045, 30000]);
+        assert_abs_diff_eq!(
+            &decrypted.values[..],
+            &[0.25, 1.5, 0.75][..],
+            epsilon = 1e-4
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sparse_rotate_new_tenant() -> TestResult {
+        let metadata = get_metadata();
+        let encrypted = get_client()
+            .sparse_vector()
+            .encrypt(get_plaintext(), &metadata)
+            .await?;
+        let new_tenant_id = TenantId("tenant-aws-l".to_string());
+        let mut resp = get_client()
+            .sparse_vector()
+            .rotate_vectors(
+                [("vector".to_string(), encrypted)].into(),
+                &metadata,
+                Some(new_tenant_id.clone()),
+            )
+            .await?;
+        assert_eq!(resp.successes.len(), 1);
+        assert_eq!(resp.failures.len(), 0);
+        let rotated = resp.successes.remove("vector").unwrap();
+        let decrypted = get_client()
+            .sparse_vector()
+            .decrypt(rotated, &AlloyMetadata::new_simple(new_tenant_id))
+            .await?;
+        assert_eq!(decrypted.indices, vec![7, 2045, 30000]);
+        assert_abs_diff_eq!(
+            &decrypted.values[..],
+            &[0.25, 1.5, 0.75][..],
+            epsilon = 1e-4
+        );
+        Ok(())
+    }
+}
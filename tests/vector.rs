@@ -51,7 +51,7 @@ mod tests {
         let metadata = get_metadata();
         let encrypted = get_client().vector().encrypt(plaintext, &metadata).await?;
         assert_eq!(encrypted.encrypted_vector.len(), 3);
-        assert_eq!(encrypted.paired_icl_info.len(), 54);
+        assert_eq!(encrypted.paired_icl_info.len(), 66);
         assert_eq!(encrypted.secret_path.0, "secret");
         assert_eq!(encrypted.derivation_path.0, "deriv");
         Ok(())